@@ -0,0 +1,10 @@
+fn main() {
+    // link flags of N-API addons, resolved by Node.js at load time
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
+    // messages and service of `grpc` module, `protoc` must be found in `PATH`
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/filigram.proto")
+        .expect("can't compile proto/filigram.proto");
+}
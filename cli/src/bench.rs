@@ -0,0 +1,202 @@
+use clap::ValueEnum;
+use filigram_rs::image::imageops::FilterType;
+use filigram_rs::image::{Rgb, RgbImage};
+use filigram_rs::processor::{Resize, Watermark};
+use filigram_rs::{spread_watermark, Config, Options, Outcome, Rules, UnqualifiedPolicy};
+use log::info;
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Resize filter of the default chain, see `image::imageops::FilterType`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl Filter {
+    fn filter_type(self) -> FilterType {
+        match self {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Triangle => "triangle",
+            Filter::CatmullRom => "catmull-rom",
+            Filter::Gaussian => "gaussian",
+            Filter::Lanczos3 => "lanczos3",
+        }
+    }
+}
+
+/// Throughput of a run over the benchmarked images, with a configuration
+#[derive(Debug, Clone, Serialize)]
+pub struct Measure {
+    pub threads: usize,
+    pub filter: Filter,
+    /// Number of watermarked images
+    pub images: usize,
+    /// Duration of the run, in seconds
+    pub elapsed: f64,
+    pub images_s: f64,
+    /// Source megabytes (10^6 bytes) processed per second
+    pub throughput_mb_s: f64,
+}
+
+/// Write `count` JPEG images of `width` x `height` pixels into `dir`,
+/// with gradients and noise so that they don't compress to nothing
+pub fn synthetic_images(
+    dir: &Path,
+    count: usize,
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+    for i in 0..count {
+        let seed = i as u32;
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            // cheap hash of the pixel position, as noise
+            let noise = (x ^ y.rotate_left(16) ^ seed).wrapping_mul(0x9e37_79b9) >> 26;
+            Rgb([
+                (x * 255 / width) as u8 ^ noise as u8,
+                (y * 255 / height) as u8,
+                (seed * 40) as u8 ^ noise as u8,
+            ])
+        });
+        img.save(dir.join(format!("bench-{i:03}.jpg")))?;
+    }
+    Ok(())
+}
+
+/// Watermark the images of `images` with each combination of `threads`
+/// and `filters`, one run each into a scratch directory removed afterwards.
+/// Other files of `images` are skipped
+pub fn bench(
+    images: &Path,
+    threads: &[usize],
+    filters: &[Filter],
+    cfg: &Config,
+) -> Result<Vec<Measure>, Box<dyn std::error::Error>> {
+    let target = std::env::temp_dir().join(format!("filigram-bench-{}", std::process::id()));
+    let mut measures = vec![];
+    for &filter in filters {
+        for &threads in threads {
+            info!(
+                "Benchmarking {threads} threads with the {} filter",
+                filter.as_str()
+            );
+            let options = Options {
+                processors: vec![
+                    Box::new(Resize {
+                        filter: filter.filter_type(),
+                        ..Default::default()
+                    }),
+                    Box::new(Watermark),
+                ],
+                unqualified: UnqualifiedPolicy::Skip,
+                threads: Some(threads),
+                ..Default::default()
+            };
+            let report = spread_watermark(
+                &images,
+                &target.as_path(),
+                cfg,
+                &Rules::default(),
+                &options,
+                None,
+            );
+            fs::remove_dir_all(&target).ok();
+            let report = report?;
+            if report.count(Outcome::Failed) > 0 {
+                return Err(format!("{} images failed", report.count(Outcome::Failed)).into());
+            }
+
+            let metrics = report.metrics();
+            let elapsed = metrics.elapsed.as_secs_f64();
+            let images = report.count(Outcome::Watermarked);
+            measures.push(Measure {
+                threads,
+                filter,
+                images,
+                elapsed,
+                images_s: if elapsed > 0.0 {
+                    images as f64 / elapsed
+                } else {
+                    0.0
+                },
+                throughput_mb_s: metrics.throughput_mb_s,
+            });
+        }
+    }
+    Ok(measures)
+}
+
+/// Human-readable table of `measures`
+pub fn table(measures: &[Measure]) -> String {
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "{:<12} {:>7} {:>6} {:>9} {:>9} {:>8}",
+        "filter", "threads", "images", "time (s)", "images/s", "MB/s"
+    );
+    for measure in measures {
+        let _ = writeln!(
+            text,
+            "{:<12} {:>7} {:>6} {:>9.2} {:>9.1} {:>8.1}",
+            measure.filter.as_str(),
+            measure.threads,
+            measure.images,
+            measure.elapsed,
+            measure.images_s,
+            measure.throughput_mb_s
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measures() {
+        let images = std::env::temp_dir().join("filigram-bench-test");
+        fs::remove_dir_all(&images).ok();
+        synthetic_images(&images, 3, 320, 200).unwrap();
+        let img = filigram_rs::image::open(images.join("bench-002.jpg")).unwrap();
+        assert_eq!((img.width(), img.height()), (320, 200));
+
+        let filters = [Filter::Nearest, Filter::Lanczos3];
+        let measures = bench(&images, &[1, 2], &filters, &Config::default()).unwrap();
+        let configurations: Vec<_> = measures
+            .iter()
+            .map(|measure| (measure.filter, measure.threads, measure.images))
+            .collect();
+        assert_eq!(
+            configurations,
+            [
+                (Filter::Nearest, 1, 3),
+                (Filter::Nearest, 2, 3),
+                (Filter::Lanczos3, 1, 3),
+                (Filter::Lanczos3, 2, 3),
+            ]
+        );
+
+        let table = table(&measures);
+        assert_eq!(table.lines().count(), 5);
+        assert!(table.lines().nth(3).unwrap().starts_with("lanczos3"));
+    }
+}
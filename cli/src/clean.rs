@@ -0,0 +1,163 @@
+use filigram_rs::journal::JOURNAL_FILE;
+use filigram_rs::{is_watermarked, FileReport};
+use log::{info, warn};
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// Files of a JSON manifest, see `Manifest::Json`
+#[derive(Deserialize)]
+struct Manifest {
+    files: Vec<FileReport>,
+}
+
+/// Outputs written in `target` by a previous run: those listed by `manifest`
+/// (a JSON manifest) if given, by the journal of `target` if any,
+/// otherwise the images carrying the marker of filigram
+pub fn outputs(
+    target: &Path,
+    manifest: Option<&Path>,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let journal = target.join(JOURNAL_FILE);
+    let outputs = if let Some(manifest) = manifest {
+        let manifest: Manifest = serde_json::from_reader(BufReader::new(File::open(manifest)?))
+            .map_err(|e| format!("{manifest:?} is not a JSON manifest: {e}"))?;
+        reported_outputs(target, manifest.files)?
+    } else if journal.exists() {
+        let mut reports = vec![];
+        for line in BufReader::new(File::open(&journal)?).lines() {
+            // a crash may leave a truncated last line
+            if let Ok(report) = serde_json::from_str::<FileReport>(&line?) {
+                reports.push(report);
+            }
+        }
+        let mut outputs = reported_outputs(target, reports)?;
+        outputs.push(journal);
+        outputs
+    } else {
+        warn!("No manifest nor journal, only marked images of {target:?} are removed");
+        let mut outputs = vec![];
+        for entry in WalkDir::new(target) {
+            let entry = entry?;
+            if entry.file_type().is_file() && is_watermarked(entry.path())? {
+                outputs.push(entry.into_path());
+            }
+        }
+        outputs
+    };
+    Ok(outputs)
+}
+
+// Outputs of `reports` which are in `target`, others are left alone
+fn reported_outputs(
+    target: &Path,
+    reports: Vec<FileReport>,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let target = target.canonicalize()?;
+    Ok(reports
+        .into_iter()
+        .flat_map(|report| report.output.into_iter().chain(report.renditions))
+        .filter(|output| match output.canonicalize() {
+            Ok(output) if output.starts_with(&target) => true,
+            Ok(_) => {
+                warn!("{output:?} is not in {target:?}, it is left alone");
+                false
+            }
+            // already removed
+            Err(_) => false,
+        })
+        .collect())
+}
+
+/// Remove `outputs` of `target`, and the directories of `target` left empty,
+/// only list them with `dry_run`
+pub fn remove(target: &Path, outputs: &[PathBuf], dry_run: bool) -> std::io::Result<()> {
+    let target = target.canonicalize()?;
+    for output in outputs {
+        if dry_run {
+            info!("Would remove {output:?}");
+            continue;
+        }
+        let mut dir = output.parent().and_then(|dir| dir.canonicalize().ok());
+        fs::remove_file(output)?;
+        info!("Removed {output:?}");
+
+        while let Some(parent) = dir {
+            if parent == target || !parent.starts_with(&target) || fs::remove_dir(&parent).is_err()
+            {
+                break;
+            }
+            dir = parent.parent().map(Path::to_path_buf);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filigram_rs::{spread_watermark, Config, Options, Rules};
+
+    // Run over the test images into `target`, with an unrelated file added
+    fn run(target: &Path, options: &Options) {
+        fs::remove_dir_all(target).ok();
+        let rules = Rules::builder().allow_ext("jpg").build();
+        spread_watermark(
+            &Path::new("../tests/img"),
+            &target,
+            &Config::default(),
+            &rules,
+            options,
+            None,
+        )
+        .unwrap();
+        fs::write(target.join("notes.txt"), "unrelated").unwrap();
+    }
+
+    fn files(target: &Path) -> Vec<String> {
+        let mut files: Vec<_> = fs::read_dir(target)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_marked() {
+        let target = std::env::temp_dir().join("filigram-clean-marked");
+        let options = Options {
+            mark_outputs: true,
+            ..Default::default()
+        };
+        run(&target, &options);
+
+        let outputs = outputs(&target, None).unwrap();
+        assert_eq!(outputs, [target.join("test.jpg")]);
+        remove(&target, &outputs, true).unwrap();
+        assert!(target.join("test.jpg").exists());
+        remove(&target, &outputs, false).unwrap();
+        assert_eq!(
+            files(&target),
+            ["notes.txt", "test.bmp", "test.gif", "test.webp"]
+        );
+    }
+
+    #[test]
+    fn test_manifest() {
+        let target = std::env::temp_dir().join("filigram-clean-manifest");
+        let manifest = std::env::temp_dir().join("filigram-clean-manifest.json");
+        let options = Options {
+            manifest: Some(filigram_rs::Manifest::Json(manifest.clone())),
+            ..Default::default()
+        };
+        run(&target, &options);
+
+        let outputs = outputs(&target, Some(&manifest)).unwrap();
+        assert_eq!(outputs.len(), 4);
+        remove(&target, &outputs, false).unwrap();
+        assert_eq!(files(&target), ["notes.txt"]);
+    }
+}
@@ -0,0 +1,235 @@
+use filigram_rs::image::Rgba;
+use filigram_rs::{Config, Rules, TextRotation};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::env::{self, VarError};
+use std::fs;
+use std::path::Path;
+
+use crate::parse_color;
+
+/// Commented config file with default values, written by `filigram init`
+pub const TEMPLATE: &str = include_str!("filigram.toml");
+
+// Environment variables giving the fields missing from the config file,
+// with their section, their key and the kind of their value
+const ENVIRONMENT: [(&str, &str, &str, Kind); 11] = [
+    ("FILIGRAM_TEXT", "watermark", "text", Kind::Text),
+    ("FILIGRAM_ALT_TEXT", "watermark", "texts", Kind::List),
+    (
+        "FILIGRAM_ROTATE_BY_PATH",
+        "watermark",
+        "rotate_by_path",
+        Kind::Bool,
+    ),
+    ("FILIGRAM_COLOR", "watermark", "color", Kind::Text),
+    ("FILIGRAM_SCALE", "watermark", "scale", Kind::Float),
+    ("FILIGRAM_COPYRIGHT", "watermark", "copyright", Kind::Text),
+    ("FILIGRAM_ARTIST", "watermark", "artist", Kind::Text),
+    (
+        "FILIGRAM_DESCRIPTION",
+        "watermark",
+        "description",
+        Kind::Text,
+    ),
+    (
+        "FILIGRAM_EXT",
+        "rules",
+        "authorized_extensions",
+        Kind::Extensions,
+    ),
+    ("FILIGRAM_EXCLUDE_DIR", "rules", "excluded_dirs", Kind::List),
+    (
+        "FILIGRAM_EXCLUDE_FILE",
+        "rules",
+        "excluded_files",
+        Kind::List,
+    ),
+];
+
+// Kind of the value of an environment variable
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    Float,
+    Bool,
+    // comma-separated
+    List,
+    // comma-separated, as given to `--ext`
+    Extensions,
+}
+
+/// Content of a `--config` TOML file, describing the watermark
+/// and the rules of a run:
+///
+/// ```toml
+/// [watermark]
+/// text = "© ACME"
+/// color = "#ffffff80"
+///
+/// [rules]
+/// excluded_dirs = [".hidden"]
+/// authorized_extensions = ["jpg", "png"]
+/// ```
+///
+/// Missing fields take their default value
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub watermark: Watermark,
+    /// Same fields as the serialized `Rules`, see `Rules::from_file`
+    pub rules: Rules,
+}
+
+/// Fields of `Config` given in a config file, or by flags
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Watermark {
+    pub text: Option<String>,
+    /// As `#rrggbb` or `#rrggbbaa`
+    #[serde(deserialize_with = "from_color")]
+    pub color: Option<Rgba<u8>>,
+    /// Height of the text, in pixels
+    pub scale: Option<f32>,
+    pub copyright: Option<String>,
+    pub artist: Option<String>,
+    pub description: Option<String>,
+    /// Other texts, alternating with `text` from image to image
+    pub texts: Vec<String>,
+    /// Choose the text of each image by its path instead of alternating
+    pub rotate_by_path: bool,
+}
+
+impl ConfigFile {
+    /// Config file read from `path` if any, its missing fields
+    /// given by their `FILIGRAM_*` environment variable if set
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let Some(path) = path else {
+            return Self::parse("");
+        };
+        let content = fs::read_to_string(path).map_err(|e| format!("{path:?}: {e}"))?;
+        Self::parse(&content).map_err(|e| format!("{path:?}: {e}").into())
+    }
+
+    /// Config file of TOML `content`, completed by the environment, see `ConfigFile::load`
+    pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut table: toml::Table = toml::from_str(content)?;
+        for (var, section, key, kind) in ENVIRONMENT {
+            let section = table
+                .entry(section)
+                .or_insert_with(|| toml::Table::new().into());
+            // an invalid section is reported by deserialization
+            let Some(section) = section.as_table_mut().filter(|s| !s.contains_key(key)) else {
+                continue;
+            };
+            match env::var(var) {
+                Ok(value) => section.insert(key.to_owned(), from_env(var, &value, kind)?),
+                Err(VarError::NotPresent) => continue,
+                Err(e) => return Err(format!("{var}: {e}").into()),
+            };
+        }
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+}
+
+// TOML value of environment variable `var` set to `value`
+fn from_env(var: &str, value: &str, kind: Kind) -> Result<toml::Value, String> {
+    let list = || value.split(',').map(str::to_owned);
+    Ok(match kind {
+        Kind::Text => value.into(),
+        Kind::Float => value
+            .parse::<f64>()
+            .map_err(|_| format!("{var}: {value:?} is not a number"))?
+            .into(),
+        Kind::Bool => match value.to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "" | "0" | "false" | "no" | "off" => false,
+            _ => return Err(format!("{var}: {value:?} is not a boolean")),
+        }
+        .into(),
+        Kind::List => list().collect::<Vec<_>>().into(),
+        Kind::Extensions => list()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect::<Vec<_>>()
+            .into(),
+    })
+}
+
+impl Watermark {
+    /// `Config` with the given fields, defaults for the others
+    pub fn into_config(self) -> Config {
+        let mut cfg = Config::default();
+        if let Some(text) = self.text {
+            cfg.text = text;
+        }
+        if let Some(color) = self.color {
+            cfg.color = color;
+        }
+        if let Some(scale) = self.scale {
+            cfg.scale = scale.into();
+        }
+        cfg.copyright = self.copyright;
+        cfg.artist = self.artist;
+        cfg.description = self.description;
+        cfg.texts = self.texts;
+        if self.rotate_by_path {
+            cfg.rotation = TextRotation::ByPath;
+        }
+        cfg
+    }
+}
+
+fn from_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Rgba<u8>>, D::Error> {
+    let color = String::deserialize(deserializer)?;
+    parse_color(&color).map(Some).map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let file: ConfigFile = toml::from_str(
+            "[watermark]\ntext = \"© ACME\"\ncolor = \"#ff8000\"\n\n\
+             [rules]\nexcluded_dirs = [\".hidden\"]\nmax_depth = 2\n",
+        )
+        .unwrap();
+        assert_eq!(file.watermark.text.as_deref(), Some("© ACME"));
+        assert_eq!(file.watermark.color, Some(Rgba([255, 128, 0, 255])));
+        assert_eq!(file.rules.excluded_dirs, [".hidden"]);
+        assert_eq!(file.rules.max_depth, Some(2));
+        assert_eq!(
+            file.rules.authorized_extensions,
+            Rules::default().authorized_extensions
+        );
+
+        assert!(toml::from_str::<ConfigFile>("[watermark]\ncolor = \"red\"\n").is_err());
+        assert!(toml::from_str::<ConfigFile>("[watermark]\ntxt = \"© ACME\"\n").is_err());
+    }
+
+    #[test]
+    fn test_environment() {
+        // variable not set by other tests, which run in parallel
+        std::env::set_var("FILIGRAM_SCALE", "large");
+        assert!(ConfigFile::parse("").is_err());
+        let file = ConfigFile::parse("[watermark]\nscale = 40.0\n").unwrap();
+        assert_eq!(file.watermark.scale, Some(40.0));
+        std::env::set_var("FILIGRAM_SCALE", "32");
+        let file = ConfigFile::parse("[watermark]\ntext = \"© ACME\"\n").unwrap();
+        assert_eq!(file.watermark.scale, Some(32.0));
+        std::env::remove_var("FILIGRAM_SCALE");
+    }
+
+    #[test]
+    fn test_template() {
+        let file: ConfigFile = toml::from_str(TEMPLATE).unwrap();
+        let (cfg, default) = (file.watermark.into_config(), Config::default());
+        assert_eq!(cfg.text, default.text);
+        assert_eq!(cfg.color, default.color);
+        assert!((cfg.scale.y - default.scale.y).abs() < 1e-3);
+        assert_eq!(
+            file.rules.authorized_extensions,
+            Rules::default().authorized_extensions
+        );
+    }
+}
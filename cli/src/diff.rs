@@ -0,0 +1,131 @@
+use filigram_rs::journal::JOURNAL_FILE;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Status of a file differing between an input folder and its target directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Source file without output
+    Missing,
+    /// Output older than its source file
+    Stale,
+    /// Output without source file
+    Orphan,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Missing => "missing",
+            Status::Stale => "stale",
+            Status::Orphan => "orphan",
+        }
+    }
+}
+
+/// File differing between an input folder and its target directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Difference {
+    /// Path relative to the input folder and to the target directory
+    pub path: PathBuf,
+    pub status: Status,
+}
+
+/// Differences between `input` and `target`, sorted by path, files being compared
+/// by their path relative to each of them, as written by a run with the default
+/// layout and naming. The journal of an interrupted run is not an orphan
+pub fn diff(input: &Path, target: &Path) -> Result<Vec<Difference>, Box<dyn std::error::Error>> {
+    let mut differences = vec![];
+    // a target inside the input folder is not part of it
+    let nested_target = target.canonicalize()?;
+    for (relative_path, source) in files(input, &nested_target)? {
+        let output = target.join(&relative_path);
+        let status = match fs::metadata(&output) {
+            Err(_) => Status::Missing,
+            Ok(output) if output.modified()? < source.modified()? => Status::Stale,
+            Ok(_) => continue,
+        };
+        differences.push(Difference {
+            path: relative_path,
+            status,
+        });
+    }
+    for (relative_path, _) in files(target, Path::new(""))? {
+        if !input.join(&relative_path).exists() && relative_path != Path::new(JOURNAL_FILE) {
+            differences.push(Difference {
+                path: relative_path,
+                status: Status::Orphan,
+            });
+        }
+    }
+    differences.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(differences)
+}
+
+// Files of `folder`, by path relative to it, with their metadata
+fn files(
+    folder: &Path,
+    excluded: &Path,
+) -> Result<Vec<(PathBuf, fs::Metadata)>, Box<dyn std::error::Error>> {
+    let mut files = vec![];
+    let walker = WalkDir::new(folder).follow_links(true).into_iter();
+    for entry in walker.filter_entry(|entry| {
+        entry
+            .path()
+            .canonicalize()
+            .map_or(true, |path| path != excluded)
+    }) {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative_path = entry.path().strip_prefix(folder)?.to_owned();
+        files.push((relative_path, entry.metadata()?));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_differences() {
+        let root = std::env::temp_dir().join("filigram-diff");
+        fs::remove_dir_all(&root).ok();
+        let (input, target) = (root.join("input"), root.join("input/result"));
+        fs::create_dir_all(input.join("dir")).unwrap();
+        fs::create_dir_all(target.join("dir")).unwrap();
+        for name in ["done.jpg", "stale.jpg", "dir/missing.jpg"] {
+            fs::write(input.join(name), "source").unwrap();
+        }
+        for name in ["done.jpg", "stale.jpg", "dir/orphan.jpg", JOURNAL_FILE] {
+            fs::write(target.join(name), "output").unwrap();
+        }
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(target.join("stale.jpg"))
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+
+        let differences = diff(&input, &target).unwrap();
+        let differences: Vec<_> = differences
+            .iter()
+            .map(|difference| (difference.path.to_str().unwrap(), difference.status))
+            .collect();
+        assert_eq!(
+            differences,
+            [
+                ("dir/missing.jpg", Status::Missing),
+                ("dir/orphan.jpg", Status::Orphan),
+                ("stale.jpg", Status::Stale),
+            ]
+        );
+    }
+}
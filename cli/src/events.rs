@@ -0,0 +1,99 @@
+use clap::ValueEnum;
+use filigram_rs::{FileReport, Metrics, Outcome};
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+/// Format of the logs and of the events of a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable logs on stderr, with a progress bar
+    #[default]
+    Text,
+    /// One JSON object per log record on stderr, one JSON event
+    /// per processed file on stdout, then the summary of the run
+    Json,
+}
+
+/// Event of a processed file, emitted as a JSON line on stdout
+#[derive(Debug, Serialize)]
+pub struct FileEvent<'a> {
+    pub path: &'a Path,
+    pub action: Outcome,
+    pub output: Option<&'a Path>,
+    /// Processing time, in seconds
+    pub duration: f64,
+    pub error: Option<&'a str>,
+}
+
+impl<'a> From<&'a FileReport> for FileEvent<'a> {
+    fn from(report: &'a FileReport) -> Self {
+        Self {
+            path: &report.source,
+            action: report.outcome,
+            output: report.output.as_deref(),
+            duration: report.duration.as_secs_f64(),
+            error: report.error.as_deref(),
+        }
+    }
+}
+
+/// Print the event of `report` on stdout
+pub fn print_event(report: &FileReport) {
+    match serde_json::to_string(&FileEvent::from(report)) {
+        Ok(event) => println!("{event}"),
+        Err(e) => log::error!("Can't serialize the event of {:?}: {e}", report.source),
+    }
+}
+
+/// Print `metrics` of the run on stdout, as `{"summary": {...}}`
+pub fn print_summary(metrics: &Metrics) {
+    println!("{}", serde_json::json!({ "summary": metrics }));
+}
+
+/// Logger writing records in `format`, `info` level unless `RUST_LOG` is set
+pub fn init_logger(format: LogFormat) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    }
+    builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_file_event() {
+        let report = FileReport {
+            source: "photos/a.jpg".into(),
+            output: None,
+            outcome: Outcome::Failed,
+            dimensions: None,
+            duration: Duration::from_millis(250),
+            error: Some("corrupted".to_owned()),
+            source_sha256: None,
+            output_sha256: None,
+            bytes_in: 0,
+            bytes_out: 0,
+            timings: None,
+            perceptual_hash: None,
+            duplicate: None,
+            renditions: vec![],
+        };
+        assert_eq!(
+            serde_json::to_string(&FileEvent::from(&report)).unwrap(),
+            r#"{"path":"photos/a.jpg","action":"failed","output":null,"duration":0.25,"error":"corrupted"}"#
+        );
+    }
+}
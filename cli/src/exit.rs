@@ -0,0 +1,40 @@
+use std::fmt;
+use std::process::ExitCode;
+
+/// Error ending the command with its own exit status, for scripts to tell
+/// failures apart. Other errors exit with status 1, invalid flags with status 2
+#[derive(Debug)]
+pub enum Exit {
+    /// Some files failed to be processed, others were completed
+    FilesFailed(usize),
+    /// The configuration file can't be loaded, or the watermark rendered
+    Config(Box<dyn std::error::Error>),
+    /// The run was interrupted by SIGINT, after completing its files in progress
+    Interrupted { completed: usize },
+}
+
+/// Exit status of a run interrupted by SIGINT, as the shell reports it
+pub const INTERRUPTED: u8 = 130;
+
+impl Exit {
+    /// Exit status of the command
+    pub fn code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Exit::FilesFailed(_) => 3,
+            Exit::Config(_) => 4,
+            Exit::Interrupted { .. } => INTERRUPTED,
+        })
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exit::FilesFailed(count) => write!(f, "{count} files failed"),
+            Exit::Config(e) => write!(f, "Invalid configuration: {e}"),
+            Exit::Interrupted { completed } => write!(f, "Interrupted after {completed} files"),
+        }
+    }
+}
+
+impl std::error::Error for Exit {}
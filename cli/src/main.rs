@@ -0,0 +1,1018 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use filigram_rs::image::{self, DynamicImage, Rgba, RgbaImage};
+use filigram_rs::processor::{default_chain, Context, Resize, SalientWatermark};
+use filigram_rs::{
+    create_watermark_image, spread_watermark, CaseCollisions, ColorPolicy, Config, DecodeLimits,
+    Hooks, Metrics, Options, Outcome, ReadAhead, Rendition, Report, Rules,
+};
+use log::{error, info, warn};
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use config_file::{ConfigFile, Watermark, TEMPLATE};
+use events::LogFormat;
+use exit::{Exit, INTERRUPTED};
+use progress::Bars;
+
+mod bench;
+mod clean;
+mod config_file;
+mod diff;
+mod events;
+mod exit;
+mod progress;
+mod summary;
+
+/// Watermark the images of a folder, recursively
+#[derive(Debug, Parser)]
+#[command(
+    name = "filigram",
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Folder of the images to watermark
+    #[arg(required = true, env = "FILIGRAM_INPUT")]
+    input: Option<PathBuf>,
+    /// Folder where watermarked images and other files are written
+    #[arg(required = true, env = "FILIGRAM_OUTPUT")]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    settings: Settings,
+    /// Number of images processed in parallel, as many as logical cores by default
+    #[arg(long, short, env = "FILIGRAM_JOBS")]
+    jobs: Option<NonZeroUsize>,
+    /// Skip images taking more than this many megabytes once decoded, instead of decoding them
+    #[arg(long, value_name = "MB", env = "FILIGRAM_MAX_IMAGE_MEMORY")]
+    max_image_memory: Option<u64>,
+    /// Fail images of more than this many megapixels, before decoding them
+    #[arg(long, value_name = "MP", env = "FILIGRAM_MAX_MEGAPIXELS")]
+    max_megapixels: Option<u64>,
+    /// Fail images taking more than this many megabytes once decoded, before decoding them
+    #[arg(long, value_name = "MB", env = "FILIGRAM_MAX_DECODED_MEMORY")]
+    max_decoded_memory: Option<u64>,
+    /// Walk the input folder before processing its files, from the largest to the smallest
+    #[arg(long, env = "FILIGRAM_LARGEST_FIRST")]
+    largest_first: bool,
+    /// Produce byte-identical outputs from the same inputs, run after run
+    #[arg(long, env = "FILIGRAM_DETERMINISTIC")]
+    deterministic: bool,
+    /// Convert images with a wide-gamut ICC profile to sRGB, leaving the profile out
+    #[arg(long, env = "FILIGRAM_SRGB")]
+    srgb: bool,
+    /// Write an index.html gallery of the watermarked images in each output folder
+    #[arg(long, env = "FILIGRAM_GALLERY")]
+    gallery: bool,
+    /// Flag duplicate images in the report, near duplicates having perceptual hashes
+    /// differing by at most this many bits out of 64
+    #[arg(long, value_name = "BITS", env = "FILIGRAM_SIMILAR_IMAGES")]
+    similar_images: Option<u32>,
+    /// Embed the marker of filigram in watermarked JPEG and PNG images, so that
+    /// `clean` finds them and runs skipping watermarked images leave them alone
+    #[arg(long, env = "FILIGRAM_MARK_OUTPUTS")]
+    mark_outputs: bool,
+    /// Leave pixels untouched, only write the copyright and artist in the metadata of copies
+    #[arg(long, env = "FILIGRAM_METADATA_ONLY")]
+    metadata_only: bool,
+    /// Place the watermark over the least detailed region of each image,
+    /// instead of its top left corner, to keep it off faces and subjects
+    #[arg(long, env = "FILIGRAM_SALIENT_PLACEMENT")]
+    salient_placement: bool,
+    /// Other output of each watermarked image, as `SUFFIX[:SIZE[:SCALE]]`: i.e. `web:1600`
+    /// writes `photo-web.jpg` fitting in 1600x1600, `thumb:400:0.3` a thumbnail with
+    /// a watermark 0.3 times as large. All are produced from a single decode. Can be repeated
+    #[arg(
+        long,
+        value_name = "SPEC",
+        value_parser = parse_rendition,
+        env = "FILIGRAM_RENDITION",
+        value_delimiter = ','
+    )]
+    rendition: Vec<(String, Option<u32>, f32)>,
+    /// Copy files which fail, i.e. corrupt images, into `_failed/` in the output directory
+    /// with a note of their error, instead of only reporting them
+    #[arg(long, env = "FILIGRAM_QUARANTINE")]
+    quarantine: bool,
+    /// What is done with files whose output names only differ by case from another one,
+    /// which overwrite each other on macOS and Windows: `fail`, `suffix` or `skip`
+    #[arg(
+        long,
+        value_name = "POLICY",
+        value_parser = parse_case_collisions,
+        env = "FILIGRAM_CASE_COLLISIONS"
+    )]
+    case_collisions: Option<CaseCollisions>,
+    /// Threads reading images ahead of the workers processing them, and as many writing
+    /// watermarked images behind them, i.e. on network storages
+    #[arg(long, value_name = "THREADS", env = "FILIGRAM_READ_AHEAD")]
+    read_ahead: Option<NonZeroUsize>,
+    /// Do not show the progress bars
+    #[arg(long, short, env = "FILIGRAM_QUIET")]
+    quiet: bool,
+    /// Format of the logs, `json` also prints an event per processed file on stdout
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = LogFormat::Text,
+        global = true,
+        env = "FILIGRAM_LOG_FORMAT"
+    )]
+    log_format: LogFormat,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check the config file, the flags and the input folder, without processing anything
+    Validate {
+        /// Folder of the images to watermark, checked to be readable
+        #[arg(env = "FILIGRAM_INPUT")]
+        input: Option<PathBuf>,
+        #[command(flatten)]
+        settings: Settings,
+    },
+    /// Watermark a single image, or a test card, to try the watermark out
+    Preview {
+        /// Image to watermark, a neutral test card if not given
+        image: Option<PathBuf>,
+        /// Image file written, in the format of its extension
+        #[arg(long, short, default_value = "filigram-preview.png")]
+        output: PathBuf,
+        /// Open the written image with the default viewer
+        #[arg(long)]
+        open: bool,
+        #[command(flatten)]
+        settings: Settings,
+    },
+    /// Write a commented config file with default values, to start from
+    Init {
+        /// Config file to write
+        #[arg(default_value = "filigram.toml")]
+        path: PathBuf,
+        /// Overwrite the file if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove the outputs of a previous run from its target directory,
+    /// other files are left alone
+    Clean {
+        /// Target directory of the run
+        target: PathBuf,
+        /// JSON manifest of the run, listing its outputs. Otherwise, the outputs
+        /// listed by the journal of an interrupted run are removed, if any,
+        /// or else the images carrying the marker of filigram
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<PathBuf>,
+        /// Only list the files that would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the files of the input folder without output in the target directory,
+    /// the outputs older than their source file and the outputs without source file
+    Diff {
+        /// Folder of the images to watermark
+        input: PathBuf,
+        /// Target directory of a previous run
+        target: PathBuf,
+    },
+    /// Watermark a set of images with several thread counts and resize filters,
+    /// and print the throughput of each configuration
+    Bench {
+        /// Folder of the images to benchmark, synthetic images by default
+        images: Option<PathBuf>,
+        /// Number of synthetic images
+        #[arg(long, default_value_t = 20, conflicts_with = "images")]
+        count: usize,
+        /// Thread counts to benchmark, powers of two up to the number of logical cores by default
+        #[arg(long, short, value_delimiter = ',', value_name = "N,...")]
+        jobs: Vec<NonZeroUsize>,
+        /// Resize filters to benchmark, all by default
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "FILTER,...")]
+        filter: Vec<bench::Filter>,
+        #[command(flatten)]
+        settings: Settings,
+    },
+    /// Watermark the images of a folder as they are added, until interrupted
+    Watch {
+        /// Folder where images to watermark are dropped
+        #[arg(env = "FILIGRAM_INPUT")]
+        input: PathBuf,
+        /// Folder where watermarked images and other files are written
+        #[arg(env = "FILIGRAM_OUTPUT")]
+        output: PathBuf,
+        /// Seconds between two scans of the input folder,
+        /// files are processed once unchanged between two scans
+        #[arg(
+            long,
+            default_value_t = 2,
+            value_name = "SECS",
+            env = "FILIGRAM_INTERVAL"
+        )]
+        interval: u64,
+        /// Number of images processed in parallel, as many as logical cores by default
+        #[arg(long, short, env = "FILIGRAM_JOBS")]
+        jobs: Option<NonZeroUsize>,
+        #[command(flatten)]
+        settings: Settings,
+    },
+}
+
+/// Watermark customization and rules.
+/// Each flag can be given by its `FILIGRAM_*` environment variable,
+/// lists being comma-separated: flags override the config file,
+/// which overrides environment variables
+#[derive(Debug, Args)]
+struct Settings {
+    /// TOML file describing the watermark and the rules, overridden by flags
+    #[arg(long, value_name = "FILE", env = "FILIGRAM_CONFIG")]
+    config: Option<PathBuf>,
+    /// Text of the watermark
+    #[arg(long)]
+    text: Option<String>,
+    /// Other text of the watermark, alternating with `--text` from image to image,
+    /// replacing those of the config file. Can be repeated
+    #[arg(long = "alt-text", value_name = "TEXT")]
+    texts: Vec<String>,
+    /// Choose the text of each image by its path, so that it keeps it run after run
+    #[arg(long)]
+    rotate_by_path: bool,
+    /// Color of the watermark, as `#rrggbb` or `#rrggbbaa`
+    #[arg(long, value_parser = parse_color)]
+    color: Option<Rgba<u8>>,
+    /// Height of the watermark text, in pixels
+    #[arg(long)]
+    scale: Option<f32>,
+    /// Copyright Exif field of watermarked images
+    #[arg(long)]
+    copyright: Option<String>,
+    /// Artist Exif field of watermarked images
+    #[arg(long)]
+    artist: Option<String>,
+    /// ImageDescription Exif field of watermarked images
+    #[arg(long)]
+    description: Option<String>,
+    /// Extension of the images to watermark, replacing those of the config file,
+    /// all supported images by default
+    #[arg(long = "ext", value_name = "EXT", value_delimiter = ',')]
+    extensions: Vec<String>,
+    /// Name of directories to skip, replacing those of the config file
+    #[arg(long = "exclude-dir", value_name = "DIR", value_delimiter = ',')]
+    excluded_dirs: Vec<String>,
+    /// Prefix of file names to copy without watermark, replacing those of the config file
+    #[arg(long = "exclude-file", value_name = "PREFIX", value_delimiter = ',')]
+    excluded_files: Vec<String>,
+}
+
+impl Settings {
+    // Watermark customization and rules, of the config file if any, overridden by flags
+    fn settings(&self) -> Result<(Config, Rules), Box<dyn std::error::Error>> {
+        let file = ConfigFile::load(self.config.as_deref()).map_err(Exit::Config)?;
+        Ok((self.config(file.watermark), self.rules(file.rules)))
+    }
+
+    // Watermark customization, `watermark` overridden by flags
+    fn config(&self, watermark: Watermark) -> Config {
+        Watermark {
+            text: self.text.clone().or(watermark.text),
+            color: self.color.or(watermark.color),
+            scale: self.scale.or(watermark.scale),
+            copyright: self.copyright.clone().or(watermark.copyright),
+            artist: self.artist.clone().or(watermark.artist),
+            description: self.description.clone().or(watermark.description),
+            texts: if self.texts.is_empty() {
+                watermark.texts
+            } else {
+                self.texts.clone()
+            },
+            rotate_by_path: self.rotate_by_path || watermark.rotate_by_path,
+        }
+        .into_config()
+    }
+
+    // Rules, `rules` overridden by flags
+    fn rules(&self, mut rules: Rules) -> Rules {
+        if !self.extensions.is_empty() {
+            rules.authorized_extensions = self
+                .extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect();
+        }
+        if !self.excluded_dirs.is_empty() {
+            rules.excluded_dirs.clone_from(&self.excluded_dirs);
+        }
+        if !self.excluded_files.is_empty() {
+            rules.excluded_files.clone_from(&self.excluded_files);
+        }
+        rules
+    }
+}
+
+// Case collisions policy by its name
+fn parse_case_collisions(policy: &str) -> Result<CaseCollisions, String> {
+    match policy {
+        "ignore" => Ok(CaseCollisions::Ignore),
+        "fail" => Ok(CaseCollisions::Fail),
+        "suffix" => Ok(CaseCollisions::Suffix),
+        "skip" => Ok(CaseCollisions::Skip),
+        _ => Err(format!(
+            "{policy:?} is not one of `ignore`, `fail`, `suffix` or `skip`"
+        )),
+    }
+}
+
+// Rendition as `SUFFIX[:SIZE[:SCALE]]`, full resolution and unscaled watermark unless given
+fn parse_rendition(spec: &str) -> Result<(String, Option<u32>, f32), String> {
+    let mut parts = spec.split(':');
+    let suffix = parts.next().unwrap_or_default();
+    if suffix.is_empty() || suffix.contains(['/', '\\']) {
+        return Err(format!("{spec:?} has no valid suffix"));
+    }
+    let size = match parts.next() {
+        None | Some("") => None,
+        Some(size) => Some(
+            size.parse()
+                .map_err(|_| format!("{size:?} is not a size in pixels"))?,
+        ),
+    };
+    let scale = match parts.next() {
+        None => 1.0,
+        Some(scale) => scale
+            .parse()
+            .ok()
+            .filter(|scale: &f32| *scale > 0.0)
+            .ok_or_else(|| format!("{scale:?} is not a positive scale"))?,
+    };
+    if parts.next().is_some() {
+        return Err(format!("{spec:?} is not `SUFFIX[:SIZE[:SCALE]]`"));
+    }
+    Ok((suffix.to_owned(), size, scale))
+}
+
+// Color as `#rrggbb` or `#rrggbbaa`, opaque unless given
+fn parse_color(color: &str) -> Result<Rgba<u8>, String> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        return Err(format!("{color:?} is not a `#rrggbb` or `#rrggbbaa` color"));
+    }
+    let mut channels = [255; 4];
+    for (channel, i) in channels.iter_mut().zip((0..hex.len()).step_by(2)) {
+        *channel = u8::from_str_radix(&hex[i..i + 2], 16)
+            .map_err(|_| format!("{color:?} is not a `#rrggbb` or `#rrggbbaa` color"))?;
+    }
+    Ok(Rgba(channels))
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let (cfg, rules) = cli.settings.settings()?;
+    // files in progress are completed on Ctrl-C, a second one aborts them
+    let cancel = Arc::new(AtomicBool::new(false));
+    ctrlc::set_handler({
+        let cancel = cancel.clone();
+        move || {
+            if cancel.swap(true, Ordering::Relaxed) {
+                std::process::exit(INTERRUPTED.into());
+            }
+            warn!("Interrupted, completing files in progress (Ctrl-C again to abort)");
+        }
+    })?;
+    let mut options = Options {
+        cancel: Some(cancel.clone()),
+        threads: cli.jobs.map(NonZeroUsize::get),
+        max_image_memory: cli.max_image_memory.map(|mb| mb.saturating_mul(1_000_000)),
+        largest_first: cli.largest_first,
+        deterministic: cli.deterministic,
+        gallery: cli.gallery,
+        similar_images: cli.similar_images,
+        metadata_only: cli.metadata_only,
+        mark_outputs: cli.mark_outputs,
+        case_collisions: cli.case_collisions.unwrap_or_default(),
+        renditions: cli
+            .rendition
+            .iter()
+            .map(|(suffix, size, scale)| Rendition::new(suffix, *size, *scale))
+            .collect(),
+        quarantine: cli.quarantine,
+        color: if cli.srgb {
+            ColorPolicy::Srgb
+        } else {
+            ColorPolicy::Keep
+        },
+        decode_limits: DecodeLimits {
+            max_pixels: cli.max_megapixels.map(|mp| mp.saturating_mul(1_000_000)),
+            max_memory: cli
+                .max_decoded_memory
+                .map(|mb| mb.saturating_mul(1_000_000))
+                .or(DecodeLimits::default().max_memory),
+        },
+        read_ahead: cli.read_ahead.map(|threads| ReadAhead {
+            threads: threads.get(),
+            writers: threads.get(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    if cli.salient_placement {
+        options.processors = vec![
+            Box::new(Resize::default()),
+            Box::new(SalientWatermark::default()),
+        ];
+        for rendition in &mut options.renditions {
+            rendition.processors = vec![Box::new(SalientWatermark::default())];
+        }
+    }
+    let bars = if cli.quiet || cli.log_format == LogFormat::Json {
+        None
+    } else {
+        let workers = options.threads.unwrap_or_else(rayon::current_num_threads);
+        Some(Bars::new(workers)?)
+    };
+    if let Some(bars) = &bars {
+        options.hooks = bars.hooks();
+    } else if cli.log_format == LogFormat::Json {
+        options.hooks = Hooks {
+            on_report: Some(Box::new(events::print_event)),
+            ..Default::default()
+        };
+    }
+
+    let report = spread_watermark(
+        cli.input.as_ref().expect("required input"),
+        cli.output.as_ref().expect("required output"),
+        &cfg,
+        &rules,
+        &options,
+        bars.as_ref().map(|bars| &bars.overall),
+    )?;
+    if let Some(bars) = &bars {
+        bars.finish();
+    }
+
+    let concluded = conclude(&report, cli.log_format);
+    if cancel.load(Ordering::Relaxed) {
+        return Err(Box::new(Exit::Interrupted {
+            completed: report.files.len() - report.count(Outcome::Cancelled),
+        }));
+    }
+    concluded
+}
+
+// Write the config file template into `path`
+fn init(path: &Path, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if path.exists() && !force {
+        return Err(format!("{path:?} already exists, use --force to overwrite it").into());
+    }
+    fs::write(path, TEMPLATE)?;
+    info!(
+        "Config written in {path:?}, to use with `--config {}`",
+        path.display()
+    );
+    Ok(())
+}
+
+// Watch `input` into `output`, until interrupted by SIGINT
+fn watch(
+    input: &Path,
+    output: &Path,
+    interval: Duration,
+    jobs: Option<NonZeroUsize>,
+    log_format: LogFormat,
+    settings: &Settings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (cfg, rules) = settings.settings()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    ctrlc::set_handler({
+        let stop = stop.clone();
+        move || stop.store(true, Ordering::Relaxed)
+    })?;
+
+    let options = Options {
+        hooks: Hooks {
+            on_report: Some(match log_format {
+                LogFormat::Text => Box::new(|report| match &report.error {
+                    Some(e) if report.outcome == Outcome::Failed => {
+                        error!("{:?}: {e}", report.source)
+                    }
+                    _ => info!("{:?}: {}", report.source, report.outcome.as_str()),
+                }),
+                LogFormat::Json => Box::new(events::print_event),
+            }),
+            ..Default::default()
+        },
+        threads: jobs.map(NonZeroUsize::get),
+        ..Default::default()
+    };
+    info!("Watching {input:?}, press Ctrl-C to stop");
+    let metrics = filigram_rs::watch(input, output, &cfg, &rules, &options, interval, &stop)?;
+    info!("Stopped watching {input:?}");
+
+    summarize(&metrics, log_format)
+}
+
+// Log the failures and the summary of a run, an error if some files failed
+fn conclude(report: &Report, log_format: LogFormat) -> Result<(), Box<dyn std::error::Error>> {
+    for file in report.failed() {
+        error!(
+            "{:?}: {}",
+            file.source,
+            file.error.as_deref().unwrap_or("failed")
+        );
+    }
+    summarize(&report.metrics(), log_format)
+}
+
+// Log the summary of a run, an error if some files failed
+fn summarize(metrics: &Metrics, log_format: LogFormat) -> Result<(), Box<dyn std::error::Error>> {
+    match log_format {
+        LogFormat::Text => eprintln!("{}", summary::summary(metrics)),
+        LogFormat::Json => events::print_summary(metrics),
+    }
+    if metrics.count(Outcome::Failed) > 0 {
+        return Err(Box::new(Exit::FilesFailed(metrics.count(Outcome::Failed))));
+    }
+    Ok(())
+}
+
+// Print the differences between `input` and `target`, an error if there are some
+fn diff(
+    input: &Path,
+    target: &Path,
+    log_format: LogFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let differences = diff::diff(input, target)?;
+    for difference in &differences {
+        match log_format {
+            LogFormat::Text => println!(
+                "{:<8} {}",
+                difference.status.as_str(),
+                difference.path.display()
+            ),
+            LogFormat::Json => println!("{}", serde_json::to_string(difference)?),
+        }
+    }
+    if !differences.is_empty() {
+        return Err(format!("{} files differ", differences.len()).into());
+    }
+    info!("{target:?} is up to date with {input:?}");
+    Ok(())
+}
+
+// Benchmark the images of `images`, or `count` synthetic ones,
+// with each of `jobs` and `filters`
+fn bench(
+    images: Option<&Path>,
+    count: usize,
+    jobs: &[NonZeroUsize],
+    filters: &[bench::Filter],
+    log_format: LogFormat,
+    settings: &Settings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (cfg, _) = settings.settings()?;
+    let threads = if jobs.is_empty() {
+        let cores = rayon::current_num_threads();
+        let mut threads: Vec<_> = std::iter::successors(Some(1), |n| Some(n * 2))
+            .take_while(|n| *n < cores)
+            .collect();
+        threads.push(cores);
+        threads
+    } else {
+        jobs.iter().map(|n| n.get()).collect()
+    };
+    let filters = if filters.is_empty() {
+        bench::Filter::value_variants()
+    } else {
+        filters
+    };
+
+    let measures = match images {
+        Some(images) => bench::bench(images, &threads, filters, &cfg)?,
+        None => {
+            let images =
+                std::env::temp_dir().join(format!("filigram-bench-images-{}", std::process::id()));
+            info!("Writing {count} synthetic images");
+            let measures = bench::synthetic_images(&images, count, 3000, 2000)
+                .and_then(|()| bench::bench(&images, &threads, filters, &cfg));
+            fs::remove_dir_all(&images).ok();
+            measures?
+        }
+    };
+    match log_format {
+        LogFormat::Text => print!("{}", bench::table(&measures)),
+        LogFormat::Json => {
+            for measure in &measures {
+                println!("{}", serde_json::to_string(measure)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Check `settings` as a run would load them, and `input` if given
+fn validate(input: Option<&Path>, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let (cfg, _) = settings.settings()?;
+    create_watermark_image(&cfg)
+        .map_err(|e| Exit::Config(format!("Can't render the watermark: {e}").into()))?;
+    if let Some(input) = input {
+        fs::read_dir(input).map_err(|e| format!("Can't read input folder {input:?}: {e}"))?;
+    }
+    info!("Configuration is valid");
+    Ok(())
+}
+
+// Watermark `image`, or a test card, into `output` as a run would
+fn preview(
+    image: Option<&Path>,
+    output: &Path,
+    open: bool,
+    settings: &Settings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (cfg, _) = settings.settings()?;
+    let watermark = create_watermark_image(&cfg)?;
+    let (mut img, path) = match image {
+        Some(image) => (image::open(image)?, image),
+        None => (test_card(), Path::new("test card")),
+    };
+    let ctx = Context {
+        path,
+        watermark: &watermark,
+    };
+    for processor in default_chain() {
+        img = processor.process(img, &ctx)?;
+    }
+    img.save(output)?;
+    info!("Preview written in {output:?}");
+
+    if open {
+        open_file(output)?;
+    }
+    Ok(())
+}
+
+// Checkerboard of a light and a dark gray, to see the watermark over both
+fn test_card() -> DynamicImage {
+    RgbaImage::from_fn(500, 500, |x, y| {
+        if (x / 50 + y / 50) % 2 == 0 {
+            Rgba([192, 192, 192, 255])
+        } else {
+            Rgba([96, 96, 96, 255])
+        }
+    })
+    .into()
+}
+
+// Open `path` with the default application of the platform
+fn open_file(path: &Path) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    command.arg(path).spawn()?;
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    events::init_logger(cli.log_format);
+    let result = match &cli.command {
+        Some(Command::Validate { input, settings }) => validate(input.as_deref(), settings),
+        Some(Command::Preview {
+            image,
+            output,
+            open,
+            settings,
+        }) => preview(image.as_deref(), output, *open, settings),
+        Some(Command::Init { path, force }) => init(path, *force),
+        Some(Command::Clean {
+            target,
+            manifest,
+            dry_run,
+        }) => clean::outputs(target, manifest.as_deref())
+            .and_then(|outputs| Ok(clean::remove(target, &outputs, *dry_run)?)),
+        Some(Command::Diff { input, target }) => diff(input, target, cli.log_format),
+        Some(Command::Bench {
+            images,
+            count,
+            jobs,
+            filter,
+            settings,
+        }) => bench(
+            images.as_deref(),
+            *count,
+            jobs,
+            filter,
+            cli.log_format,
+            settings,
+        ),
+        Some(Command::Watch {
+            input,
+            output,
+            interval,
+            jobs,
+            settings,
+        }) => watch(
+            input,
+            output,
+            Duration::from_secs(*interval),
+            *jobs,
+            cli.log_format,
+            settings,
+        ),
+        None => run(&cli),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{err}");
+            match err.downcast_ref::<Exit>() {
+                Some(exit) => exit.code(),
+                None => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_color() {
+        assert_eq!(parse_color("#ff8000"), Ok(Rgba([255, 128, 0, 255])));
+        assert_eq!(parse_color("0000006e"), Ok(Rgba([0, 0, 0, 110])));
+        assert!(parse_color("#ff80").is_err());
+        assert!(parse_color("#gg8000").is_err());
+        assert!(parse_color("#ff80é0").is_err());
+    }
+
+    #[test]
+    fn test_flags() {
+        let cli = Cli::parse_from([
+            "filigram",
+            "photos",
+            "out",
+            "--text",
+            "© ACME",
+            "--color",
+            "#ffffff80",
+            "--ext",
+            "jpg",
+            "--exclude-file",
+            "background",
+            "-j",
+            "4",
+        ]);
+        assert_eq!(cli.jobs, NonZeroUsize::new(4));
+        assert!(Cli::try_parse_from(["filigram", "photos", "out", "--jobs", "0"]).is_err());
+        let (cfg, rules) = cli.settings.settings().unwrap();
+        assert_eq!(cfg.text, "© ACME");
+        assert_eq!(cfg.color, Rgba([255, 255, 255, 128]));
+        assert!(rules.is_file_qualified(&Path::new("photos/a.jpg")));
+        assert!(!rules.is_file_qualified(&Path::new("photos/a.png")));
+        assert!(!rules.is_file_qualified(&Path::new("photos/background.jpg")));
+    }
+
+    #[test]
+    fn test_rendition() {
+        assert_eq!(
+            parse_rendition("web:1600"),
+            Ok(("web".to_owned(), Some(1600), 1.0))
+        );
+        assert_eq!(
+            parse_rendition("thumb:400:0.3"),
+            Ok(("thumb".to_owned(), Some(400), 0.3))
+        );
+        assert_eq!(
+            parse_rendition("full::2"),
+            Ok(("full".to_owned(), None, 2.0))
+        );
+        assert!(parse_rendition(":400").is_err());
+        assert!(parse_rendition("web:large").is_err());
+        assert!(parse_rendition("web:400:0").is_err());
+        assert!(parse_rendition("web:400:1:2").is_err());
+    }
+
+    #[test]
+    fn test_config_error() {
+        let cli = Cli::parse_from(["filigram", "photos", "out", "--config", "missing.toml"]);
+        let err = cli.settings.settings().unwrap_err();
+        let exit = err.downcast_ref::<Exit>().unwrap();
+        assert!(matches!(exit, Exit::Config(_)));
+        assert_eq!(exit.code(), ExitCode::from(4));
+    }
+
+    #[test]
+    fn test_overridden_file() {
+        let file: ConfigFile = toml::from_str(
+            "[watermark]\ntext = \"© ACME\"\nartist = \"Jane\"\n\n\
+             [rules]\nauthorized_extensions = [\"png\"]\nexcluded_dirs = [\".hidden\"]\n",
+        )
+        .unwrap();
+        let cli = Cli::parse_from([
+            "filigram", "photos", "out", "--text", "© Jane", "--ext", "JPG",
+        ]);
+        let cfg = cli.settings.config(file.watermark);
+        assert_eq!(cfg.text, "© Jane");
+        assert_eq!(cfg.artist.as_deref(), Some("Jane"));
+        let rules = cli.settings.rules(file.rules);
+        assert_eq!(rules.authorized_extensions, ["jpg"]);
+        assert_eq!(rules.excluded_dirs, [".hidden"]);
+    }
+
+    #[test]
+    fn test_validate_command() {
+        let cli = Cli::parse_from(["filigram", "validate", "--config", "filigram.toml"]);
+        let Some(Command::Validate { input, settings }) = cli.command else {
+            panic!("not a validate command: {cli:?}");
+        };
+        assert_eq!(input, None);
+        assert_eq!(settings.config, Some(PathBuf::from("filigram.toml")));
+        assert!(validate(None, &settings).is_err());
+
+        let cli = Cli::parse_from(["filigram", "validate", "--color", "#ff8000"]);
+        let Some(Command::Validate { settings, .. }) = cli.command else {
+            panic!("not a validate command: {cli:?}");
+        };
+        assert!(validate(None, &settings).is_ok());
+        assert!(validate(Some(Path::new("missing")), &settings).is_err());
+
+        assert!(Cli::try_parse_from(["filigram", "photos"]).is_err());
+        assert!(Cli::try_parse_from(["filigram", "validate", "--color", "red"]).is_err());
+    }
+
+    #[test]
+    fn test_preview_command() {
+        let output = std::env::temp_dir().join("filigram-preview-test.png");
+        let cli = Cli::parse_from([
+            "filigram",
+            "preview",
+            "--output",
+            output.to_str().unwrap(),
+            "--text",
+            "© ACME",
+        ]);
+        let Some(Command::Preview {
+            image,
+            output,
+            open,
+            settings,
+        }) = cli.command
+        else {
+            panic!("not a preview command: {cli:?}");
+        };
+        assert_eq!(image, None);
+        assert!(!open);
+        preview(None, &output, false, &settings).unwrap();
+        let img = image::open(&output).unwrap();
+        assert_eq!((img.width(), img.height()), (500, 500));
+        assert_ne!(img.to_rgba8(), test_card().to_rgba8());
+
+        assert!(preview(Some(Path::new("missing.jpg")), &output, false, &settings).is_err());
+    }
+
+    #[test]
+    fn test_log_format() {
+        let cli = Cli::parse_from(["filigram", "photos", "out", "--log-format", "json"]);
+        assert_eq!(cli.log_format, LogFormat::Json);
+        let cli = Cli::parse_from(["filigram", "validate", "--log-format", "json"]);
+        assert_eq!(cli.log_format, LogFormat::Json);
+        assert_eq!(
+            Cli::parse_from(["filigram", "photos", "out"]).log_format,
+            LogFormat::Text
+        );
+        assert!(Cli::try_parse_from(["filigram", "photos", "out", "--log-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_watch_command() {
+        let cli = Cli::parse_from(["filigram", "watch", "drop", "out", "--text", "© ACME"]);
+        let Some(Command::Watch {
+            input,
+            interval,
+            jobs,
+            settings,
+            ..
+        }) = cli.command
+        else {
+            panic!("not a watch command: {cli:?}");
+        };
+        assert_eq!(input, PathBuf::from("drop"));
+        assert_eq!((interval, jobs), (2, None));
+        assert_eq!(settings.text.as_deref(), Some("© ACME"));
+        assert!(Cli::try_parse_from(["filigram", "watch", "drop"]).is_err());
+    }
+
+    #[test]
+    fn test_diff_command() {
+        let cli = Cli::parse_from(["filigram", "diff", "photos", "out"]);
+        let Some(Command::Diff { input, target }) = cli.command else {
+            panic!("not a diff command: {cli:?}");
+        };
+        assert_eq!((input, target), ("photos".into(), "out".into()));
+        assert!(Cli::try_parse_from(["filigram", "diff", "photos"]).is_err());
+        assert!(diff(
+            Path::new("../tests/img"),
+            Path::new("missing"),
+            LogFormat::Text
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_bench_command() {
+        let cli = Cli::parse_from([
+            "filigram",
+            "bench",
+            "--jobs",
+            "1,4",
+            "--filter",
+            "nearest,catmull-rom",
+        ]);
+        let Some(Command::Bench {
+            images,
+            count,
+            jobs,
+            filter,
+            ..
+        }) = cli.command
+        else {
+            panic!("not a bench command: {cli:?}");
+        };
+        assert_eq!((images, count), (None, 20));
+        assert_eq!(jobs, [NonZeroUsize::MIN, NonZeroUsize::new(4).unwrap()]);
+        assert_eq!(filter, [bench::Filter::Nearest, bench::Filter::CatmullRom]);
+        assert!(Cli::try_parse_from(["filigram", "bench", "photos", "--count", "5"]).is_err());
+        assert!(Cli::try_parse_from(["filigram", "bench", "--filter", "bicubic"]).is_err());
+    }
+
+    #[test]
+    fn test_environment() {
+        // variables not set by other tests, which run in parallel
+        std::env::set_var("FILIGRAM_COPYRIGHT", "© ACME");
+        std::env::set_var("FILIGRAM_DESCRIPTION", "Preview");
+        std::env::set_var("FILIGRAM_ALT_TEXT", "Proof,Draft");
+        std::env::set_var("FILIGRAM_EXCLUDE_DIR", ".cache,.trash");
+        std::env::set_var("FILIGRAM_INTERVAL", "10");
+        let cli = Cli::parse_from(["filigram", "photos", "out", "--description", "Final"]);
+        let file = ConfigFile::parse("[watermark]\ncopyright = \"© Jane\"\n").unwrap();
+        let cfg = cli.settings.config(file.watermark);
+        assert_eq!(cfg.copyright.as_deref(), Some("© Jane"));
+        assert_eq!(cfg.description.as_deref(), Some("Final"));
+        assert_eq!(cfg.texts, ["Proof", "Draft"]);
+        assert_eq!(file.rules.excluded_dirs, [".cache", ".trash"]);
+
+        let cli = Cli::parse_from(["filigram", "validate"]);
+        let Some(Command::Validate { settings, .. }) = cli.command else {
+            panic!("not a validate command: {cli:?}");
+        };
+        let (cfg, _) = settings.settings().unwrap();
+        assert_eq!(cfg.copyright.as_deref(), Some("© ACME"));
+        assert_eq!(cfg.description.as_deref(), Some("Preview"));
+
+        let cli = Cli::parse_from(["filigram", "watch", "photos", "out"]);
+        let Some(Command::Watch { interval, .. }) = cli.command else {
+            panic!("not a watch command: {cli:?}");
+        };
+        assert_eq!(interval, 10);
+        for var in [
+            "FILIGRAM_COPYRIGHT",
+            "FILIGRAM_DESCRIPTION",
+            "FILIGRAM_ALT_TEXT",
+            "FILIGRAM_EXCLUDE_DIR",
+            "FILIGRAM_INTERVAL",
+        ] {
+            std::env::remove_var(var);
+        }
+    }
+
+    #[test]
+    fn test_init_command() {
+        let path = std::env::temp_dir().join("filigram-init-test.toml");
+        fs::remove_file(&path).ok();
+        let cli = Cli::parse_from(["filigram", "init", path.to_str().unwrap()]);
+        let Some(Command::Init { path, force }) = cli.command else {
+            panic!("not an init command: {cli:?}");
+        };
+        init(&path, force).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), TEMPLATE);
+        assert!(init(&path, false).is_err());
+        assert!(init(&path, true).is_ok());
+
+        let cli = Cli::parse_from(["filigram", "init"]);
+        assert!(
+            matches!(cli.command, Some(Command::Init { path, .. }) if path == Path::new("filigram.toml"))
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use filigram_rs::indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use filigram_rs::Hooks;
+use std::time::Duration;
+
+/// Progress bars of a run: the overall progression of the walk,
+/// then a bar per worker with the file it processes and for how long
+#[derive(Debug)]
+pub struct Bars {
+    /// Overall progression, given to `spread_watermark`
+    pub overall: ProgressBar,
+    workers: Vec<ProgressBar>,
+}
+
+impl Bars {
+    /// Bars of a run with `workers` threads
+    pub fn new(workers: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        let multi = MultiProgress::new();
+        let overall = multi.add(
+            ProgressBar::new(0).with_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        "[{elapsed_precise}] [{bar:40.blue}] {pos}/{len} ({eta_precise} left)",
+                    )?
+                    .progress_chars("#>-"),
+            ),
+        );
+        overall.enable_steady_tick(Duration::from_millis(250));
+
+        let style =
+            ProgressStyle::default_spinner().template("  {spinner} [{elapsed:>4}] {wide_msg}")?;
+        let workers = (0..workers)
+            .map(|_| {
+                let bar = multi.add(ProgressBar::new_spinner().with_style(style.clone()));
+                bar.set_message("idle");
+                bar.enable_steady_tick(Duration::from_millis(250));
+                bar
+            })
+            .collect();
+        Ok(Self { overall, workers })
+    }
+
+    /// Hooks showing each file on the bar of the worker processing it
+    pub fn hooks(&self) -> Hooks {
+        let (started, done) = (self.workers.clone(), self.workers.clone());
+        Hooks {
+            on_file_start: Some(Box::new(move |path| {
+                if let Some(bar) = rayon::current_thread_index().and_then(|i| started.get(i)) {
+                    bar.reset_elapsed();
+                    bar.set_message(path.display().to_string());
+                }
+            })),
+            on_file_done: Some(Box::new(move |_, _| {
+                if let Some(bar) = rayon::current_thread_index().and_then(|i| done.get(i)) {
+                    bar.set_message("idle");
+                }
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Leave the overall bar, remove the bars of workers
+    pub fn finish(&self) {
+        self.overall.finish();
+        for bar in &self.workers {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filigram_rs::Outcome;
+    use std::path::Path;
+
+    #[test]
+    fn test_worker_bars() {
+        let bars = Bars::new(2).unwrap();
+        let hooks = bars.hooks();
+        // a single worker, so that the file is started and done on the same bar
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let path = Path::new("photos/a.jpg");
+
+        pool.install(|| hooks.on_file_start.as_ref().unwrap()(path));
+        let messages = || {
+            bars.workers
+                .iter()
+                .map(ProgressBar::message)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(messages(), ["photos/a.jpg", "idle"]);
+        pool.install(|| hooks.on_file_done.as_ref().unwrap()(path, Outcome::Copied));
+        assert_eq!(messages(), ["idle", "idle"]);
+    }
+}
@@ -0,0 +1,77 @@
+use filigram_rs::{Metrics, Outcome};
+use std::fmt::Write;
+
+// Outcomes always shown, others only when some files have them
+const OUTCOMES: [Outcome; 4] = [
+    Outcome::Watermarked,
+    Outcome::Copied,
+    Outcome::Skipped,
+    Outcome::Failed,
+];
+
+/// Human-readable summary of a run, printed once it's done
+pub fn summary(metrics: &Metrics) -> String {
+    let count = |outcome| {
+        metrics
+            .outcomes
+            .iter()
+            .find(|(other, _)| *other == outcome)
+            .map_or(0, |(_, count)| *count)
+    };
+    let counts = OUTCOMES
+        .into_iter()
+        .map(|outcome| (outcome, count(outcome)))
+        .chain(
+            metrics
+                .outcomes
+                .iter()
+                .filter(|(outcome, _)| !OUTCOMES.contains(outcome))
+                .copied(),
+        )
+        .map(|(outcome, count)| format!("{count} {}", outcome.as_str()))
+        .collect::<Vec<_>>();
+
+    let mut text = String::new();
+    let _ = writeln!(text, "Files: {}", counts.join(", "));
+    let _ = writeln!(
+        text,
+        "Size:  {:.1} MB read, {:.1} MB written",
+        metrics.bytes_in as f64 / 1e6,
+        metrics.bytes_out as f64 / 1e6
+    );
+    let _ = write!(
+        text,
+        "Time:  {:.1} s, {:.1} MB/s",
+        metrics.elapsed.as_secs_f64(),
+        metrics.throughput_mb_s
+    );
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_text() {
+        let metrics = Metrics {
+            outcomes: vec![
+                (Outcome::Copied, 3),
+                (Outcome::Watermarked, 12),
+                (Outcome::Linked, 1),
+            ],
+            bytes_in: 24_500_000,
+            bytes_out: 8_120_000,
+            elapsed: Duration::from_millis(12_300),
+            throughput_mb_s: 2.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            summary(&metrics),
+            "Files: 12 watermarked, 3 copied, 0 skipped, 0 failed, 1 linked\n\
+             Size:  24.5 MB read, 8.1 MB written\n\
+             Time:  12.3 s, 2.0 MB/s"
+        );
+    }
+}
@@ -1,8 +1,7 @@
-use filigram_rs::{config::Config, rules::Rules, spread_watermark};
+use filigram_rs::{config::Config, options::Options, rules::Rules, spread_watermark, Outcome};
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{info, warn};
 use std::{path::PathBuf, time::Duration};
-use walkdir::WalkDir;
 
 static RESULT_PATH: &str = "./result";
 static INPUT_PATH: &str = "./data/input";
@@ -13,6 +12,35 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Starting program");
 
+    // let's define some rules
+    let rules = Rules::builder()
+        .exclude_dir(".hidden")
+        .exclude_file("background")
+        .allow_ext("jpg")
+        .allow_ext("jpeg")
+        .allow_ext("png")
+        .allow_ext("bmp")
+        .allow_ext("gif")
+        .build();
+
+    // i.e.: `tar c photos | cargo run --example filigram --features tar -- --pipe | tar x`
+    #[cfg(feature = "tar")]
+    if std::env::args().any(|arg| arg == "--pipe") {
+        let report = filigram_rs::watermark_tar(
+            std::io::stdin(),
+            std::io::stdout(),
+            &Config::default(),
+            &rules,
+            &Options::default(),
+        )?;
+        info!(
+            "Watermarked {} images in {} secs",
+            report.count(Outcome::Watermarked),
+            report.elapsed.as_secs()
+        );
+        return Ok(());
+    }
+
     let input = PathBuf::from(INPUT_PATH).canonicalize()?;
     let target_dir = PathBuf::from(RESULT_PATH).canonicalize()?;
 
@@ -25,21 +53,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("from: {input:?}");
     info!("to:   {target_dir:?}");
 
-    // let's define some rules
-    let rules = Rules {
-        excluded_dirs: vec![".hidden".to_string()],
-        authorized_extensions: vec![
-            "jpg".to_string(),
-            "jpeg".to_string(),
-            "png".to_string(),
-            "bmp".to_string(),
-            "gif".to_string(),
-        ],
-        excluded_files: vec!["background".to_string()],
-    };
-
     // default parameters
     let cfg = Config::default();
+    let options = Options::default();
 
     let progress = ProgressBar::new(0).with_style(
         ProgressStyle::default_bar()
@@ -49,19 +65,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     progress.enable_steady_tick(Duration::from_millis(250));
 
     // start the watermarking parallelized process
-    spread_watermark(&input, &target_dir, &cfg, &rules, Some(&progress))?;
+    let report = spread_watermark(&input, &target_dir, &cfg, &rules, &options, Some(&progress))?;
 
     progress.finish();
 
-    let nb_images = WalkDir::new(input)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| !entry.path().is_dir())
-        .count();
     info!(
         "Watermarked {} images in {} secs",
-        nb_images,
-        progress.elapsed().as_secs()
+        report.count(Outcome::Watermarked),
+        report.elapsed.as_secs()
     );
 
     Ok(())
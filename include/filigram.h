@@ -0,0 +1,68 @@
+/* C API of filigram-rs, see `src/ffi.rs`.
+ * Build the shared library with
+ * `cargo rustc --release --features ffi --crate-type cdylib`. */
+
+#ifndef FILIGRAM_H
+#define FILIGRAM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status returned by the functions below, the message of the last error
+ * of the calling thread is given by `filigram_last_error` */
+typedef enum FiligramError {
+    FILIGRAM_OK = 0,
+    /* A null pointer or a string which is not UTF-8 */
+    FILIGRAM_INVALID_ARGUMENT = 1,
+    /* A file can't be read or written */
+    FILIGRAM_IO = 2,
+    /* The image can't be decoded, processed or encoded */
+    FILIGRAM_IMAGE = 3,
+    /* Unexpected failure of filigram */
+    FILIGRAM_PANIC = 4,
+} FiligramError;
+
+/* Customization of the watermark, null strings take their default value,
+ * or leave Exif fields as they are */
+typedef struct FiligramConfig {
+    const char *text;
+    /* Red, green, blue and alpha channels */
+    uint8_t color[4];
+    /* Height of the watermark text, in pixels */
+    float scale;
+    const char *copyright;
+    const char *artist;
+    const char *description;
+} FiligramConfig;
+
+/* Fill `config` with the default customization of the watermark */
+void filigram_config_default(FiligramConfig *config);
+
+/* Apply a watermark to image `src` and write it to `dst`, in the format
+ * of its extension. A null `config` stands for the default one */
+FiligramError filigram_watermark_file(const char *src, const char *dst,
+                                      const FiligramConfig *config);
+
+/* Apply a watermark to image `input` of `input_len` bytes. On success, `output`
+ * and `output_len` are set to the watermarked image, encoded in the format
+ * of `input`, to free with `filigram_free_bytes` */
+FiligramError filigram_watermark_bytes(const uint8_t *input, size_t input_len,
+                                       const FiligramConfig *config,
+                                       uint8_t **output, size_t *output_len);
+
+/* Free an image returned by `filigram_watermark_bytes` */
+void filigram_free_bytes(uint8_t *output, size_t output_len);
+
+/* Message of the last error of the calling thread, null if none.
+ * It is valid until the next call of this API on this thread */
+const char *filigram_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FILIGRAM_H */
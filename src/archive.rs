@@ -0,0 +1,641 @@
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+#[cfg(any(feature = "zip", feature = "s3"))]
+use std::fs;
+#[cfg(feature = "zip")]
+use std::fs::File;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+#[cfg(any(feature = "zip", feature = "tar"))]
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    sync::Mutex,
+};
+#[cfg(feature = "tar")]
+use std::{sync::mpsc, time::SystemTime};
+#[cfg(feature = "walkdir")]
+use walkdir::WalkDir;
+#[cfg(feature = "zip")]
+use zip::{write::SimpleFileOptions, DateTime, ZipArchive, ZipWriter};
+
+use crate::hooks::{Event, Outcome};
+use crate::layout::CaseCollisions;
+use crate::options::{Options, UnqualifiedPolicy};
+use crate::report::{FileReport, Report};
+use crate::run::{hex, Qualification, Run};
+use crate::similar::flag_duplicates;
+#[cfg(any(feature = "zip", feature = "s3"))]
+use crate::sink::FsSink;
+use crate::sink::OutputSink;
+use crate::trace::{debug, error, RunSpan};
+use crate::ProgressBar;
+#[cfg(feature = "walkdir")]
+use crate::{check_dir, rules::SymlinkPolicy};
+use crate::{config::Config, rules::Rules};
+
+/// Reader of the content of an entry, run by the worker handling it
+pub(crate) type Reader<'a> =
+    Box<dyn FnOnce() -> Result<Vec<u8>, Box<dyn std::error::Error>> + Send + 'a>;
+
+/// Entry path, relative to the root of the source, its size if known beforehand, and its reader
+pub(crate) type Entry<'a> = (PathBuf, Option<u64>, Reader<'a>);
+
+// Preallocated capacity of the content of an archive entry at most,
+// as the size in its header can't be trusted
+#[cfg(any(feature = "zip", feature = "tar"))]
+const MAX_PREALLOCATION: u64 = 64 << 20;
+
+/// Whether `path` is a ZIP archive, judging by its extension
+#[cfg(feature = "zip")]
+pub(crate) fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+#[cfg(feature = "zip")]
+impl OutputSink for Mutex<ZipWriter<File>> {
+    fn write_file(&self, name: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        // entry names use forward slashes, whatever the platform
+        let name = name
+            .components()
+            .map(|comp| comp.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        // dated 1980-01-01 whatever the features of `zip`, for reproducible archives
+        let entry = SimpleFileOptions::default().last_modified_time(DateTime::default());
+        let mut writer = self.lock().expect("poisoned lock");
+        writer.start_file(name, entry)?;
+        writer.write_all(data)?;
+        Ok(())
+    }
+}
+
+// Entries of a tar stream, dated when written unless the run is deterministic
+#[cfg(feature = "tar")]
+struct TarSink<W: Write> {
+    builder: Mutex<tar::Builder<W>>,
+    deterministic: bool,
+}
+
+#[cfg(feature = "tar")]
+impl<W: Write + Send> OutputSink for TarSink<W> {
+    fn write_file(&self, name: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        if !self.deterministic {
+            header.set_mtime(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            );
+        }
+        let mut builder = self.builder.lock().expect("poisoned lock");
+        builder.append_data(&mut header, name, data)?;
+        Ok(())
+    }
+}
+
+// Entries kept until the end of the run, then written in the order of their names,
+// so that an archive doesn't depend on the order in which workers complete
+#[cfg(any(feature = "zip", feature = "tar"))]
+#[derive(Default)]
+struct SortedSink(Mutex<BTreeMap<PathBuf, Vec<u8>>>);
+
+#[cfg(any(feature = "zip", feature = "tar"))]
+impl OutputSink for SortedSink {
+    fn write_file(&self, name: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut entries = self.0.lock().expect("poisoned lock");
+        entries.insert(name.to_owned(), data.to_vec());
+        Ok(())
+    }
+}
+
+/// Run `spread` on archive `sink`, with its entries sorted by name
+/// for deterministic runs, see `Options::deterministic`
+#[cfg(any(feature = "zip", feature = "tar"))]
+fn sorted(
+    options: &Options,
+    sink: &dyn OutputSink,
+    spread: impl FnOnce(&dyn OutputSink) -> Vec<FileReport>,
+) -> Result<Vec<FileReport>, Box<dyn std::error::Error>> {
+    if !options.deterministic {
+        return Ok(spread(sink));
+    }
+    let sorted = SortedSink::default();
+    let files = spread(&sorted);
+    for (name, data) in sorted.0.into_inner().expect("poisoned lock") {
+        sink.write_file(&name, &data)?;
+    }
+    Ok(files)
+}
+
+/// Watermark the images of ZIP archive `source` into `target`,
+/// see `with_sink` and `spread_entries`
+#[cfg(feature = "zip")]
+pub(crate) fn spread_zip(
+    source: &Path,
+    target: &Path,
+    cfg: &Config,
+    rules: &Rules,
+    options: &Options,
+    progress: Option<&ProgressBar>,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let run = Run::new(cfg, rules, options)?;
+    let mut archive = ZipArchive::new(File::open(source)?)?;
+
+    let entries =
+        (0..archive.len()).filter_map(|index| read_zip_entry(&run, source, &mut archive, index));
+    let files = with_sink(target, options, |sink| {
+        spread_entries(&run, source, target, entries, sink, progress)
+    })?;
+    finish(options, files, start)
+}
+
+/// Run `spread` with the sink of `target`: objects under a `s3://bucket/prefix`
+/// URI (`s3` feature), a ZIP archive if its extension is "zip" (`zip` feature),
+/// or a directory otherwise
+#[cfg(any(feature = "zip", feature = "s3"))]
+pub(crate) fn with_sink(
+    target: &Path,
+    options: &Options,
+    spread: impl FnOnce(&dyn OutputSink) -> Vec<FileReport>,
+) -> Result<Vec<FileReport>, Box<dyn std::error::Error>> {
+    #[cfg(feature = "s3")]
+    if let Some(uri) = crate::s3::S3Uri::parse(target) {
+        return Ok(spread(&crate::s3::S3Sink::new(uri)?));
+    }
+    #[cfg(feature = "zip")]
+    if is_zip(target) {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let sink = Mutex::new(ZipWriter::new(File::create(target)?));
+        let files = sorted(options, &sink, spread)?;
+        sink.into_inner().expect("poisoned lock").finish()?;
+        return Ok(files);
+    }
+
+    fs::create_dir_all(target)?;
+    Ok(spread(&FsSink::new(target)))
+}
+
+/// Watermark images of folder `source` into `sink`, i.e. to stream them to an object storage
+/// or an in-memory store. See `spread_watermark` for the meaning of `cfg`, `rules` and `options`.
+///
+/// Files are read by the workers processing them. As for archives, byte-identical images are
+/// all watermarked and options relying on the filesystem of the target (journal, attributes,
+/// links, gallery) don't apply. Reported outputs are relative to the root of the sink
+#[cfg(feature = "walkdir")]
+pub fn spread_watermark_to(
+    source: &Path,
+    sink: &dyn OutputSink,
+    cfg: &Config,
+    rules: &Rules,
+    options: &Options,
+    progress: Option<&ProgressBar>,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    check_dir(source)?;
+    let run = Run::new(cfg, rules, options)?;
+    let entries = local_entries(source, rules);
+    let files = spread_entries(&run, source, Path::new(""), entries, sink, progress);
+    finish(options, files, start)
+}
+
+/// Watermark the images of tar stream `input` into tar stream `output`,
+/// i.e. to be used as `tar c photos | filigram | tar x`.
+/// Only regular files are handled, other entries (directories, links...)
+/// are left out. Nothing is written on disk, but the manifest if any.
+///
+/// Entries are read in the background while the previous ones are processed,
+/// see `spread_watermark` for the meaning of `cfg`, `rules` and `options`.
+/// Reported sources and outputs are the paths of the entries
+#[cfg(feature = "tar")]
+pub fn watermark_tar<R: Read + Send, W: Write + Send>(
+    input: R,
+    output: W,
+    cfg: &Config,
+    rules: &Rules,
+    options: &Options,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let run = Run::new(cfg, rules, options)?;
+    let sink = TarSink {
+        builder: Mutex::new(tar::Builder::new(output)),
+        deterministic: options.deterministic,
+    };
+
+    // tar entries borrow their archive, which can't be shared with workers:
+    // entries are read by a dedicated thread and sent to workers
+    let (sender, receiver) = mpsc::sync_channel(run.workers());
+    let (files, read) = std::thread::scope(|scope| {
+        let reader = scope.spawn(|| read_tar_entries(&run, input, sender));
+        let files = sorted(options, &sink, |sink| {
+            spread_entries(
+                &run,
+                Path::new(""),
+                Path::new(""),
+                receiver.into_iter(),
+                sink,
+                None,
+            )
+        });
+        (files, reader.join().expect("tar reader panicked"))
+    });
+    read?;
+    let files = files?;
+
+    sink.builder
+        .into_inner()
+        .expect("poisoned lock")
+        .into_inner()?;
+    finish(options, files, start)
+}
+
+/// Watermark or copy `entries` on the workers of `run` into `sink`.
+///
+/// Byte-identical images are all watermarked, unqualified files
+/// are copied unless `UnqualifiedPolicy::Skip` is set,
+/// and options relying on the filesystem (journal, attributes,
+/// links) don't apply.
+pub(crate) fn spread_entries<'a>(
+    run: &Run,
+    source: &Path,
+    target: &Path,
+    entries: impl Iterator<Item = Entry<'a>> + Send,
+    sink: &dyn OutputSink,
+    progress: Option<&ProgressBar>,
+) -> Vec<FileReport> {
+    let (rules, options) = (run.rules, run.options);
+    let span = RunSpan::new(source, target);
+    options.emit(|| Event::Started {
+        source: source.to_owned(),
+        target: target.to_owned(),
+    });
+
+    let entries = entries
+        .filter(|(relative_path, _, _)| is_within_depth(rules, relative_path))
+        .inspect(|_| {
+            if let Some(progress) = progress {
+                progress.inc_length(1);
+            }
+        });
+    run.map(entries, |(relative_path, size, input)| {
+        let start = Instant::now();
+        let path = source.join(&relative_path);
+        let span = span.file(&path);
+        debug!("entry: {path:?}");
+        if run.cancelled() {
+            debug!("cancelled: {path:?}");
+            return FileReport::new(&path, Outcome::Cancelled, None);
+        }
+        options.hooks.file_start(&path);
+
+        // files left out by their name or size are not even read
+        let left_out = size.filter(|&size| run.is_left_out(&path, &relative_path, size));
+        let result = match left_out {
+            Some(size) => {
+                debug!("skipping {path:?}");
+                Ok(FileReport {
+                    bytes_in: size,
+                    ..FileReport::new(&path, Outcome::Skipped, None)
+                })
+            }
+            None => options
+                .layout
+                .output_path(&path, &relative_path)
+                .and_then(|name| {
+                    let input = input()?;
+                    process_entry(run, sink, target, &path, &relative_path, &name, input)
+                }),
+        };
+
+        let mut report = match result {
+            Ok(report) => report,
+            Err(e) => {
+                error!("Error processing: {path:?} - {e}");
+                options.hooks.error(&path, e.as_ref());
+                options.emit(|| Event::Error {
+                    path: path.clone(),
+                    error: e.to_string(),
+                });
+                FileReport {
+                    error: Some(e.to_string()),
+                    ..FileReport::new(&path, Outcome::Failed, None)
+                }
+            }
+        };
+        report.duration = start.elapsed();
+        span.record(&report);
+        options.hooks.file_done(&path, report.outcome);
+        options.hooks.report(&report);
+        options.emit(|| Event::FileDone(report.clone()));
+        if let Some(progress) = progress {
+            progress.inc(1);
+        }
+
+        report
+    })
+}
+
+/// Report of the whole run, written as manifest if required
+pub(crate) fn finish(
+    options: &Options,
+    mut files: Vec<FileReport>,
+    start: Instant,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    files.sort_by(|a, b| a.source.cmp(&b.source));
+    if let Some(max_distance) = options.similar_images {
+        flag_duplicates(&mut files, max_distance);
+    }
+    let report = Report {
+        files,
+        elapsed: start.elapsed(),
+    };
+    options.emit(|| Event::Finished(report.metrics()));
+    if let Some(manifest) = &options.manifest {
+        report.write_manifest(manifest)?;
+    }
+    Ok(report)
+}
+
+// Entry at `relative_path` is within `Rules::max_depth`, entries beyond it are ignored
+fn is_within_depth(rules: &Rules, relative_path: &Path) -> bool {
+    rules
+        .max_depth
+        .is_none_or(|max| relative_path.components().count() <= max)
+}
+
+// Entry at `relative_path` of `size` bytes in `source` is not to be read,
+// being ignored or left out by `Run::is_left_out`
+#[cfg(any(feature = "zip", feature = "tar"))]
+fn is_unread(run: &Run, source: &Path, relative_path: &Path, size: u64) -> bool {
+    !is_within_depth(run.rules, relative_path)
+        || run.is_left_out(&source.join(relative_path), relative_path, size)
+}
+
+// Content of archive entry `input` of `size` bytes, read up to its size only
+#[cfg(any(feature = "zip", feature = "tar"))]
+fn read_entry(input: impl Read, size: u64) -> std::io::Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(size.min(MAX_PREALLOCATION) as usize);
+    input
+        .take(size.saturating_add(1))
+        .read_to_end(&mut content)?;
+    if content.len() as u64 > size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("entry larger than its declared size of {size} bytes"),
+        ));
+    }
+    Ok(content)
+}
+
+/// Entry path relative to the archive root, if it stays under it
+pub(crate) fn relative_path(path: &Path) -> Option<PathBuf> {
+    let mut relative_path = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::Normal(name) => relative_path.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(relative_path).filter(|path| path.file_name().is_some())
+}
+
+/// Files of local directory `folder`, read by workers
+#[cfg(feature = "walkdir")]
+pub(crate) fn local_entries<'a>(
+    folder: &'a Path,
+    rules: &Rules,
+) -> impl Iterator<Item = Entry<'a>> + Send {
+    let skip_links = rules.symlinks == SymlinkPolicy::Skip;
+    WalkDir::new(folder)
+        .follow_links(rules.symlinks == SymlinkPolicy::Follow)
+        .into_iter()
+        .filter(move |entry| {
+            !(skip_links && entry.as_ref().is_ok_and(|entry| entry.path_is_symlink()))
+        })
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .map_or(true, |entry| !entry.file_type().is_dir())
+        })
+        .map(move |entry| -> Entry {
+            let relative = |path: &Path| {
+                path.strip_prefix(folder)
+                    .expect("can't strip prefix")
+                    .to_owned()
+            };
+            match entry {
+                Ok(entry) => {
+                    let size = entry.metadata().ok().map(|metadata| metadata.len());
+                    let path = entry.into_path();
+                    (
+                        relative(&path),
+                        size,
+                        Box::new(move || Ok(std::fs::read(path)?)),
+                    )
+                }
+                Err(e) => (
+                    e.path().map(relative).unwrap_or_default(),
+                    None,
+                    Box::new(move || Err(e.into())),
+                ),
+            }
+        })
+}
+
+// Reader of an entry whose content has already been read
+#[cfg(any(feature = "zip", feature = "tar"))]
+fn loaded<'a>(content: Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>) -> Reader<'a> {
+    Box::new(move || content.map_err(|e| e as Box<dyn std::error::Error>))
+}
+
+// Relative path and content of file entry `index` of archive `source`,
+// `None` for directories. Entries not to be handled by `run` are not read
+#[cfg(feature = "zip")]
+fn read_zip_entry(
+    run: &Run,
+    source: &Path,
+    archive: &mut ZipArchive<File>,
+    index: usize,
+) -> Option<Entry<'static>> {
+    let entry = match archive.by_index(index) {
+        Ok(entry) => entry,
+        Err(e) => {
+            let name = PathBuf::from(format!("#{index}"));
+            return Some((name, None, loaded(Err(e.into()))));
+        }
+    };
+    if entry.is_dir() {
+        return None;
+    }
+    let Some(relative_path) = relative_path(Path::new(entry.name())) else {
+        let name = PathBuf::from(entry.name());
+        return Some((
+            name,
+            None,
+            loaded(Err(format!("unsafe entry name: {:?}", entry.name()).into())),
+        ));
+    };
+
+    let size = entry.size();
+    let content = match is_unread(run, source, &relative_path, size) {
+        true => Err("entry left unread".into()),
+        false => read_entry(entry, size).map_err(|e| e.into()),
+    };
+    Some((relative_path, Some(size), loaded(content)))
+}
+
+// Send to workers the regular files of tar stream `input`,
+// entries not to be handled by `run` are skipped unread
+#[cfg(feature = "tar")]
+fn read_tar_entries(
+    run: &Run,
+    input: impl Read,
+    sender: mpsc::SyncSender<Entry<'static>>,
+) -> std::io::Result<()> {
+    let mut archive = tar::Archive::new(input);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let entry = match relative_path(&path) {
+            Some(relative_path) => {
+                let size = entry.size();
+                let content = match is_unread(run, Path::new(""), &relative_path, size) {
+                    true => Err("entry left unread".into()),
+                    false => read_entry(entry, size).map_err(|e| e.into()),
+                };
+                (relative_path, Some(size), loaded(content))
+            }
+            None => (
+                path.clone(),
+                None,
+                loaded(Err(format!("unsafe entry name: {path:?}").into())),
+            ),
+        };
+        if sender.send(entry).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Watermark or copy entry `path` into `sink` as `name`, or a variant of it
+fn process_entry(
+    run: &Run,
+    sink: &dyn OutputSink,
+    target: &Path,
+    path: &Path,
+    relative_path: &Path,
+    name: &Path,
+    input: Vec<u8>,
+) -> Result<FileReport, Box<dyn std::error::Error>> {
+    let options = run.options;
+    let input = Bytes::from(input);
+
+    let qualification = run.qualify(path, relative_path, input.len() as u64, None, || {
+        Ok(Cursor::new(&input))
+    });
+    // `ffmpeg` reads and writes files, videos of archives are not watermarked
+    #[cfg(feature = "ffmpeg")]
+    let qualification = match qualification {
+        Qualification::Qualified if crate::video::is_video(path) => Qualification::Unqualified,
+        qualification => qualification,
+    };
+    if qualification == Qualification::Qualified {
+        if let Some(reason) = run.exceeds_memory(path, || Ok(Cursor::new(&input))) {
+            debug!("skipping {path:?}: {reason}");
+            return Ok(FileReport {
+                error: Some(reason),
+                bytes_in: input.len() as u64,
+                ..FileReport::new(path, Outcome::Skipped, None)
+            });
+        }
+    }
+    let name = &if qualification == Qualification::Qualified
+        || (qualification == Qualification::Unqualified
+            && options.unqualified != UnqualifiedPolicy::Skip)
+    {
+        match run.claim(name) {
+            Ok(name) => name,
+            Err(reason) if options.case_collisions == CaseCollisions::Skip => {
+                debug!("skipping {path:?}: {reason}");
+                return Ok(FileReport {
+                    error: Some(reason),
+                    bytes_in: input.len() as u64,
+                    ..FileReport::new(path, Outcome::Skipped, None)
+                });
+            }
+            Err(reason) => return Err(reason.into()),
+        }
+    } else {
+        name.to_owned()
+    };
+    let (mut report, name, output, renditions) = if qualification == Qualification::Qualified {
+        debug!("watermarking {path:?}");
+
+        let watermark = run.select_watermark(relative_path);
+        let (output, encoded, renditions) = run.watermark(
+            path,
+            watermark,
+            input.clone(),
+            &options.renditions,
+            |width, height| run.watermarked_path(name, width, height),
+        )?;
+        let report = FileReport {
+            dimensions: Some((output.width, output.height)),
+            timings: output.timings,
+            perceptual_hash: output.perceptual_hash.map(|hash| format!("{hash:016x}")),
+            renditions: output
+                .renditions
+                .into_iter()
+                .map(|(_, path)| target.join(path))
+                .collect(),
+            ..FileReport::new(path, Outcome::Watermarked, Some(target.join(&output.path)))
+        };
+        (report, output.path, encoded, renditions)
+    } else if qualification == Qualification::Skipped
+        || options.unqualified == UnqualifiedPolicy::Skip
+    {
+        debug!("skipping {path:?}");
+        return Ok(FileReport {
+            bytes_in: input.len() as u64,
+            ..FileReport::new(path, Outcome::Skipped, None)
+        });
+    } else {
+        debug!("copying {path:?}");
+
+        let name = run.output_path(name.to_owned());
+        let report = FileReport::new(path, Outcome::Copied, Some(target.join(&name)));
+        (report, name, input.clone(), vec![])
+    };
+
+    if let Some(parent) = name.parent() {
+        sink.create_dir_all(parent)?;
+    }
+    sink.write_file(&name, &output)?;
+    for (rendition_path, encoded) in renditions {
+        sink.write_file(&rendition_path, &encoded)?;
+        options.buffer_pool.recycle(encoded);
+    }
+    report.bytes_in = input.len() as u64;
+    report.bytes_out = output.len() as u64;
+    if options.checksums || report.perceptual_hash.is_some() {
+        report.source_sha256 = Some(hex(&Sha256::digest(&input)));
+    }
+    if options.checksums {
+        report.output_sha256 = Some(hex(&Sha256::digest(&output)));
+    }
+    options.buffer_pool.recycle(output);
+    Ok(report)
+}
@@ -0,0 +1,140 @@
+use image::{Pixel, Rgba, RgbaImage};
+
+/// Overlay `watermark` on the top left corner of `img`, with the same result
+/// as `image::imageops::overlay`. Rows are blended with AVX2 instructions
+/// when the CPU supports them, two pixels at a time
+pub(crate) fn overlay(img: &mut RgbaImage, watermark: &RgbaImage) {
+    let width = img.width().min(watermark.width()) as usize * 4;
+    let height = img.height().min(watermark.height()) as usize;
+    if width == 0 || height == 0 {
+        return;
+    }
+    let (img_stride, watermark_stride) = (img.width() as usize * 4, watermark.width() as usize * 4);
+
+    let rows = img
+        .chunks_exact_mut(img_stride)
+        .zip(watermark.chunks_exact(watermark_stride))
+        .take(height);
+    for (row, watermark_row) in rows {
+        blend_row(&mut row[..width], &watermark_row[..width]);
+    }
+}
+
+// Blend `watermark` over `row`, both RGBA8 pixels of the same length
+fn blend_row(row: &mut [u8], watermark: &[u8]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 is supported by the CPU
+        unsafe { avx2::blend_row(row, watermark) };
+        return;
+    }
+    blend_pixels(row, watermark);
+}
+
+// Blend each pixel of `watermark` over the one of `row`, as `Rgba::blend` does
+fn blend_pixels(row: &mut [u8], watermark: &[u8]) {
+    for (bg, fg) in row.chunks_exact_mut(4).zip(watermark.chunks_exact(4)) {
+        let mut pixel = *Rgba::from_slice(bg);
+        pixel.blend(Rgba::from_slice(fg));
+        bg.copy_from_slice(&pixel.0);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    /// Blend `watermark` over `row` two pixels at a time, with the same
+    /// float operations in the same order as `Rgba::blend`, so that
+    /// results are identical
+    ///
+    /// # Safety
+    /// The CPU must support AVX2
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn blend_row(row: &mut [u8], watermark: &[u8]) {
+        let max = _mm256_set1_ps(255.0);
+        let one = _mm256_set1_ps(1.0);
+        let mut pairs = row.chunks_exact_mut(8).zip(watermark.chunks_exact(8));
+        for (bg, fg) in &mut pairs {
+            // most pixels of a watermark are transparent
+            if fg[3] == 0 && fg[7] == 0 {
+                continue;
+            }
+
+            let (bg_v, fg_v) = (load(bg, max), load(fg, max));
+            // alpha of each pixel, in all of its channels
+            let bg_a = _mm256_shuffle_ps::<0xFF>(bg_v, bg_v);
+            let fg_a = _mm256_shuffle_ps::<0xFF>(fg_v, fg_v);
+
+            let alpha = _mm256_sub_ps(_mm256_add_ps(bg_a, fg_a), _mm256_mul_ps(bg_a, fg_a));
+            let out = _mm256_add_ps(
+                _mm256_mul_ps(fg_v, fg_a),
+                _mm256_mul_ps(_mm256_mul_ps(bg_v, bg_a), _mm256_sub_ps(one, fg_a)),
+            );
+            let out = _mm256_blend_ps::<0b1000_1000>(_mm256_div_ps(out, alpha), alpha);
+            let out = _mm256_cvttps_epi32(_mm256_mul_ps(max, out));
+
+            // narrow to bytes, each 128-bit lane starting with its pixel
+            let out = _mm256_packus_epi32(out, out);
+            let out = _mm256_packus_epi16(out, out);
+            let blended = [
+                _mm256_extract_epi32::<0>(out).to_le_bytes(),
+                _mm256_extract_epi32::<4>(out).to_le_bytes(),
+            ];
+
+            for ((bg, fg), blended) in bg.chunks_exact_mut(4).zip(fg.chunks_exact(4)).zip(blended) {
+                match fg[3] {
+                    0 => {}
+                    255 => bg.copy_from_slice(fg),
+                    _ => bg.copy_from_slice(&blended),
+                }
+            }
+        }
+        let remainder = row.len() / 8 * 8;
+        super::blend_pixels(&mut row[remainder..], &watermark[remainder..]);
+    }
+
+    // Channels of the first two pixels of `pixels`, divided by `max`
+    #[target_feature(enable = "avx2")]
+    unsafe fn load(pixels: &[u8], max: __m256) -> __m256 {
+        let bytes = _mm_loadl_epi64(pixels.as_ptr().cast());
+        _mm256_div_ps(_mm256_cvtepi32_ps(_mm256_cvtepu8_epi32(bytes)), max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Image whose channels are spread over the whole range, with some
+    // fully transparent and opaque pixels
+    fn noise(width: u32, height: u32, seed: u32) -> RgbaImage {
+        let mut state = seed;
+        RgbaImage::from_fn(width, height, |_, _| {
+            let mut channels = [0; 4];
+            for channel in &mut channels {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                *channel = (state >> 24) as u8;
+            }
+            match channels[0] % 8 {
+                0 => channels[3] = 0,
+                1 => channels[3] = 255,
+                _ => {}
+            }
+            Rgba(channels)
+        })
+    }
+
+    #[test]
+    fn test_like_overlay() {
+        for (img, watermark) in [((61, 40), (37, 50)), ((20, 20), (45, 9))] {
+            let watermark = noise(watermark.0, watermark.1, 7);
+            let mut img = noise(img.0, img.1, 3);
+            let mut expected = img.clone();
+            image::imageops::overlay(&mut expected, &watermark, 0, 0);
+
+            overlay(&mut img, &watermark);
+            assert_eq!(img, expected);
+        }
+    }
+}
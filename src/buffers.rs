@@ -0,0 +1,100 @@
+use bytes::Bytes;
+use image::DynamicImage;
+use std::cell::RefCell;
+
+thread_local! {
+    // buffers released by the files processed on this thread, for the next ones
+    static BUFFERS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sizing of the buffers kept by each worker between the files it processes,
+/// to read, decode and encode images without allocating them for each file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPool {
+    /// Buffers kept by each worker, 0 disables the pool
+    pub buffers: usize,
+    /// Largest capacity of a kept buffer, in bytes: larger buffers are freed
+    pub max_capacity: usize,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self {
+            buffers: 4,
+            max_capacity: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl BufferPool {
+    /// Empty buffer of at least `capacity` bytes, the largest one kept by this worker if any
+    pub(crate) fn take(&self, capacity: usize) -> Vec<u8> {
+        let buffer = BUFFERS.with(|buffers| {
+            let mut buffers = buffers.borrow_mut();
+            let largest = (0..buffers.len()).max_by_key(|i| buffers[*i].capacity())?;
+            Some(buffers.swap_remove(largest))
+        });
+        let mut buffer = buffer.unwrap_or_default();
+        buffer.clear();
+        buffer.reserve(capacity);
+        buffer
+    }
+
+    /// Keep `buffer` for the next files of this worker, unless there are enough already
+    pub(crate) fn put(&self, buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > self.max_capacity {
+            return;
+        }
+        BUFFERS.with(|buffers| {
+            let mut buffers = buffers.borrow_mut();
+            if buffers.len() < self.buffers {
+                buffers.push(buffer);
+            }
+        });
+    }
+
+    /// Keep the buffer of `bytes`, if not shared
+    pub(crate) fn recycle(&self, bytes: Bytes) {
+        if bytes.is_unique() {
+            self.put(bytes.into());
+        }
+    }
+
+    /// Keep the pixels of `img`, for 8-bit images
+    pub(crate) fn recycle_image(&self, img: DynamicImage) {
+        match img {
+            DynamicImage::ImageLuma8(img) => self.put(img.into_raw()),
+            DynamicImage::ImageLumaA8(img) => self.put(img.into_raw()),
+            DynamicImage::ImageRgb8(img) => self.put(img.into_raw()),
+            DynamicImage::ImageRgba8(img) => self.put(img.into_raw()),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let pool = BufferPool {
+            buffers: 1,
+            max_capacity: 1000,
+        };
+        let buffer = pool.take(100);
+        let ptr = buffer.as_ptr();
+        pool.put(buffer);
+        pool.put(Vec::with_capacity(10));
+
+        let buffer = pool.take(50);
+        assert_eq!(buffer.as_ptr(), ptr);
+        assert!(buffer.is_empty());
+        // the buffer of capacity 10 wasn't kept, the pool being full
+        let other = pool.take(0);
+        assert_eq!(other.capacity(), 0);
+
+        pool.put(Vec::with_capacity(2000));
+        assert_eq!(pool.take(0).capacity(), 0);
+    }
+}
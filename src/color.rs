@@ -0,0 +1,93 @@
+use image::{DynamicImage, ImageBuffer, Pixel};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformExecutor, TransformOptions};
+use std::sync::Arc;
+
+/// Color management of watermarked images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorPolicy {
+    /// Keep pixels as decoded, along with the ICC profile of the source
+    /// as set by `MetadataPolicy::icc_profile`
+    #[default]
+    Keep,
+    /// Convert images with an RGB ICC profile (i.e. Display P3, Adobe RGB) to sRGB
+    /// before watermarking them, and leave the profile out of their output,
+    /// as viewers assume sRGB without one. Other images are kept as is
+    Srgb,
+}
+
+/// Convert `img` from the color space of ICC profile `icc` to sRGB.
+/// Returns `false` if it was left as is: a profile which isn't RGB,
+/// or an image which isn't 8-bit or 16-bit RGB(A)
+pub(crate) fn to_srgb(
+    img: &mut DynamicImage,
+    icc: &[u8],
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let profile = ColorProfile::new_from_slice(icc)?;
+    if profile.color_space != DataColorSpace::Rgb {
+        return Ok(false);
+    }
+    let srgb = ColorProfile::new_srgb();
+    let options = TransformOptions::default();
+    match img {
+        DynamicImage::ImageRgb8(img) => transform(
+            img,
+            profile.create_transform_8bit(Layout::Rgb, &srgb, Layout::Rgb, options)?,
+        )?,
+        DynamicImage::ImageRgba8(img) => transform(
+            img,
+            profile.create_transform_8bit(Layout::Rgba, &srgb, Layout::Rgba, options)?,
+        )?,
+        DynamicImage::ImageRgb16(img) => transform(
+            img,
+            profile.create_transform_16bit(Layout::Rgb, &srgb, Layout::Rgb, options)?,
+        )?,
+        DynamicImage::ImageRgba16(img) => transform(
+            img,
+            profile.create_transform_16bit(Layout::Rgba, &srgb, Layout::Rgba, options)?,
+        )?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// Apply `transform` to the channels of `img`
+fn transform<P: Pixel>(
+    img: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    transform: Arc<dyn TransformExecutor<P::Subpixel> + Send + Sync>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    P::Subpixel: Default,
+{
+    let mut converted = vec![P::Subpixel::default(); img.len()];
+    transform.transform(img, &mut converted)?;
+    img.copy_from_slice(&converted);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_display_p3() {
+        let icc = ColorProfile::new_display_p3().encode().unwrap();
+        let mut img = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([200, 60, 40])));
+        assert!(to_srgb(&mut img, &icc).unwrap());
+        // a saturated red of Display P3 is out of the sRGB gamut
+        let Rgb([r, g, b]) = *img.as_rgb8().unwrap().get_pixel(0, 0);
+        assert!(r > 200 && g < 60 && b < 40, "{:?}", (r, g, b));
+
+        let icc = ColorProfile::new_srgb().encode().unwrap();
+        let mut img = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([200, 60, 40])));
+        assert!(to_srgb(&mut img, &icc).unwrap());
+        let Rgb(channels) = *img.as_rgb8().unwrap().get_pixel(0, 0);
+        assert!(channels
+            .iter()
+            .zip([200u8, 60, 40])
+            .all(|(channel, expected)| channel.abs_diff(expected) <= 1));
+
+        let mut gray = DynamicImage::new_luma8(4, 4);
+        assert!(!to_srgb(&mut gray, &icc).unwrap());
+    }
+}
@@ -1,6 +1,8 @@
 use ab_glyph::PxScale;
 use image::Rgba;
 
+use crate::exif::{ARTIST, COPYRIGHT, IMAGE_DESCRIPTION};
+
 /// Customization of the watermark.
 /// Basically you can choose the `text`,
 /// the `color` and the `scale` (size) of
@@ -10,6 +12,28 @@ pub struct Config {
     pub text: String,
     pub color: image::Rgba<u8>,
     pub scale: PxScale,
+    /// Copyright Exif field set on watermarked images, replacing the one of the source
+    pub copyright: Option<String>,
+    /// Artist Exif field set on watermarked images, replacing the one of the source
+    pub artist: Option<String>,
+    /// ImageDescription Exif field set on watermarked images, replacing the one of the source
+    pub description: Option<String>,
+    /// Other texts of the watermark, i.e. a URL or the name of a client: the text of each image
+    /// is one of `text` and these, chosen by `rotation`
+    pub texts: Vec<String>,
+    /// How the text of each image is chosen when there are other `texts`
+    pub rotation: TextRotation,
+}
+
+/// How the text of each image is chosen among `Config::text` and `Config::texts`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextRotation {
+    /// One after the other, in the order images are watermarked, starting over with each run
+    #[default]
+    RoundRobin,
+    /// By a hash of the path of the image relative to its input folder,
+    /// so that an image gets the same text run after run
+    ByPath,
 }
 
 impl Default for Config {
@@ -25,6 +49,30 @@ impl Default for Config {
             text: "© Copyright Filigram".to_owned(),
             color: Rgba([0_u8, 0_u8, 0_u8, 110_u8]),
             scale,
+            copyright: None,
+            artist: None,
+            description: None,
+            texts: vec![],
+            rotation: TextRotation::default(),
         }
     }
 }
+
+impl Config {
+    /// Texts of the watermark: `text`, then the other `texts`
+    pub(crate) fn all_texts(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.text.as_str()).chain(self.texts.iter().map(String::as_str))
+    }
+
+    /// Exif fields to set on watermarked images, with their tag
+    pub(crate) fn exif_fields(&self) -> Vec<(u16, String)> {
+        [
+            (COPYRIGHT, &self.copyright),
+            (ARTIST, &self.artist),
+            (IMAGE_DESCRIPTION, &self.description),
+        ]
+        .into_iter()
+        .filter_map(|(tag, value)| Some((tag, value.clone()?)))
+        .collect()
+    }
+}
@@ -0,0 +1,191 @@
+use bytes::Bytes;
+use c2pa::{create_signer, Builder, Error as C2paError};
+use serde_json::json;
+use std::fmt;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::trace::debug;
+
+pub use c2pa::SigningAlg;
+
+/// Name and version of the software recorded as claim generator
+const GENERATOR: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Signer of the C2PA manifest attached to each watermarked image.
+///
+/// The manifest asserts that a watermark was applied, when and by whom.
+/// It is signed with the certificate of the user, so that viewers can
+/// tell who vouches for the image
+#[derive(Clone)]
+pub struct ContentCredentials {
+    /// PEM certificate chain of the signer, its own certificate first
+    pub certificate: Vec<u8>,
+    /// PEM private key of the signer, matching `algorithm`
+    pub private_key: Vec<u8>,
+    /// Signing algorithm, i.e. `SigningAlg::Es256` for a P-256 key
+    pub algorithm: SigningAlg,
+    /// URL of a RFC 3161 time-stamping authority countersigning manifests
+    pub timestamp_authority: Option<String>,
+    /// Creator of the images, recorded as their author
+    pub creator: Option<String>,
+}
+
+impl ContentCredentials {
+    /// Credentials signing with `certificate` and `private_key`, both PEM encoded
+    pub fn new(certificate: Vec<u8>, private_key: Vec<u8>, algorithm: SigningAlg) -> Self {
+        Self {
+            certificate,
+            private_key,
+            algorithm,
+            timestamp_authority: None,
+            creator: None,
+        }
+    }
+}
+
+// the private key is not printed
+impl fmt::Debug for ContentCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContentCredentials")
+            .field("algorithm", &self.algorithm)
+            .field("timestamp_authority", &self.timestamp_authority)
+            .field("creator", &self.creator)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Attach a signed C2PA manifest to `encoded`, the content of `output`,
+/// without the date of the watermark if `deterministic`.
+/// Formats C2PA does not support are left untouched
+pub(crate) fn sign(
+    credentials: &ContentCredentials,
+    output: &Path,
+    encoded: Bytes,
+    deterministic: bool,
+) -> Result<Bytes, Box<dyn std::error::Error>> {
+    let Ok(format) = image::guess_format(&encoded) else {
+        return Ok(encoded);
+    };
+    let signer = create_signer::from_keys(
+        &credentials.certificate,
+        &credentials.private_key,
+        credentials.algorithm,
+        credentials.timestamp_authority.clone(),
+    )?;
+    let mut builder =
+        Builder::from_json(&manifest(credentials, output, deterministic).to_string())?;
+    let mut signed = Cursor::new(vec![]);
+    match builder.sign(
+        signer.as_ref(),
+        format.to_mime_type(),
+        &mut Cursor::new(&encoded),
+        &mut signed,
+    ) {
+        Ok(_) => Ok(signed.into_inner().into()),
+        Err(C2paError::UnsupportedType) => {
+            debug!("{output:?}: no content credentials for {format:?} images");
+            Ok(encoded)
+        }
+        Err(err) => Err(format!("{output:?}: cannot sign content credentials: {err}").into()),
+    }
+}
+
+// Manifest definition of `output`: the watermark action, with its date unless `deterministic`,
+// and the author
+fn manifest(
+    credentials: &ContentCredentials,
+    output: &Path,
+    deterministic: bool,
+) -> serde_json::Value {
+    let mut action = json!({
+        "action": "c2pa.watermarked",
+        "softwareAgent": GENERATOR,
+    });
+    if !deterministic {
+        action["when"] = timestamp(SystemTime::now()).into();
+    }
+    let mut assertions = vec![json!({
+        "label": "c2pa.actions",
+        "data": { "actions": [action] }
+    })];
+    if let Some(creator) = &credentials.creator {
+        assertions.push(json!({
+            "label": "stds.schema-org.CreativeWork",
+            "data": {
+                "@context": "https://schema.org",
+                "@type": "CreativeWork",
+                "author": [{ "@type": "Person", "name": creator }]
+            }
+        }));
+    }
+    json!({
+        "claim_generator": GENERATOR,
+        "title": output.file_name().map(|name| name.to_string_lossy()),
+        "assertions": assertions,
+    })
+}
+
+// RFC 3339 UTC timestamp of `time`, to the second
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let (era, doe) = (z / 146_097, z % 146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamp() {
+        let at = |secs| super::timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn test_manifest() {
+        let mut credentials = ContentCredentials::new(vec![], vec![], SigningAlg::Es256);
+        let manifest = super::manifest(&credentials, Path::new("out/photo.jpg"), false);
+        assert_eq!(manifest["title"], "photo.jpg");
+        assert_eq!(manifest["assertions"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            manifest["assertions"][0]["data"]["actions"][0]["action"],
+            "c2pa.watermarked"
+        );
+        assert!(manifest["assertions"][0]["data"]["actions"][0]["when"].is_string());
+
+        credentials.creator = Some("Jane Doe".into());
+        let manifest = super::manifest(&credentials, Path::new("photo.jpg"), true);
+        assert!(manifest["assertions"][0]["data"]["actions"][0]["when"].is_null());
+        assert_eq!(
+            manifest["assertions"][1]["data"]["author"][0]["name"],
+            "Jane Doe"
+        );
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let credentials = ContentCredentials::new(vec![], vec![], SigningAlg::Es256);
+        let jpeg = Bytes::from(std::fs::read("data/original.jpg").unwrap());
+        assert!(sign(&credentials, Path::new("photo.jpg"), jpeg, false).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::hooks::Outcome;
+use crate::metrics::Timings;
+use crate::rendition::rendition_path;
+use crate::trace::debug;
+
+/// How byte-identical images met during a run are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Every image is watermarked, even duplicates
+    #[default]
+    Process,
+    /// Duplicates are hard links to the output already produced
+    Hardlink,
+    /// Duplicates are reflinks (copy-on-write clones) of the output
+    /// already produced, or plain copies if the filesystem doesn't support it
+    Reflink,
+}
+
+// Content hash, source extension, and watermark with its text
+type Key = ([u8; 32], String, (usize, usize));
+
+/// Watermarked image written on disk
+#[derive(Debug, Clone)]
+pub(crate) struct Output {
+    pub(crate) path: PathBuf,
+    /// Suffix and path of each rendition, see `Options::renditions`
+    pub(crate) renditions: Vec<(String, PathBuf)>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) timings: Option<Timings>,
+    /// Perceptual hash of the source, see `Options::similar_images`
+    pub(crate) perceptual_hash: Option<u64>,
+}
+
+/// Outputs already produced during a run, indexed by source content
+#[derive(Debug, Default)]
+pub(crate) struct Duplicates {
+    outputs: Mutex<HashMap<Key, Arc<Mutex<Option<Output>>>>>,
+}
+
+impl Duplicates {
+    /// Run `watermark` to produce the output of `path`,
+    /// unless an identical source has already been watermarked with `watermark_index`
+    /// and the same text (see `Run::select_watermark`):
+    /// then the existing output is linked to `output_path(width, height)`, with its renditions.
+    /// When two identical sources are handled concurrently,
+    /// one waits for the other to complete.
+    pub(crate) fn watermark_once(
+        &self,
+        policy: DuplicatePolicy,
+        path: &Path,
+        watermark_index: (usize, usize),
+        output_path: impl Fn(u32, u32) -> PathBuf,
+        watermark: impl FnOnce() -> Result<Output, Box<dyn std::error::Error>>,
+    ) -> Result<(Outcome, Output), Box<dyn std::error::Error>> {
+        if policy == DuplicatePolicy::Process {
+            return Ok((Outcome::Watermarked, watermark()?));
+        }
+
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let key = (hash_file(path)?, extension, watermark_index);
+        let slot = self
+            .outputs
+            .lock()
+            .expect("poisoned lock")
+            .entry(key)
+            .or_default()
+            .clone();
+
+        let mut produced = slot.lock().expect("poisoned lock");
+        if let Some(original) = produced.as_ref() {
+            debug!("duplicate of {:?}: {path:?}", original.path);
+
+            let path = output_path(original.width, original.height);
+            let output = Output {
+                renditions: original
+                    .renditions
+                    .iter()
+                    .map(|(suffix, _)| (suffix.clone(), rendition_path(&path, suffix)))
+                    .collect(),
+                path,
+                timings: None,
+                ..original.clone()
+            };
+            let originals = std::iter::once(&original.path)
+                .chain(original.renditions.iter().map(|(_, path)| path));
+            let links =
+                std::iter::once(&output.path).chain(output.renditions.iter().map(|(_, path)| path));
+            for (original, link) in originals.zip(links) {
+                if link.exists() {
+                    fs::remove_file(link)?;
+                }
+                match policy {
+                    DuplicatePolicy::Hardlink => fs::hard_link(original, link)?,
+                    DuplicatePolicy::Reflink => {
+                        reflink_copy::reflink_or_copy(original, link)?;
+                    }
+                    DuplicatePolicy::Process => unreachable!(),
+                }
+            }
+            return Ok((Outcome::Deduplicated, output));
+        }
+
+        let output = watermark()?;
+        *produced = Some(output.clone());
+        Ok((Outcome::Watermarked, output))
+    }
+}
+
+/// SHA-256 of the content of file at `path`
+pub(crate) fn hash_file(path: &Path) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
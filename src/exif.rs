@@ -0,0 +1,669 @@
+/// Tag of the Copyright field
+pub const COPYRIGHT: u16 = 0x8298;
+/// Tag of the Artist field
+pub const ARTIST: u16 = 0x013b;
+/// Tag of the ImageDescription field
+pub const IMAGE_DESCRIPTION: u16 = 0x010e;
+/// Tag of the Software field
+pub const SOFTWARE: u16 = 0x0131;
+/// Tag of the Make field, the manufacturer of the camera
+pub(crate) const MAKE: u16 = 0x010f;
+/// Tag of the Model field, the model of the camera
+pub(crate) const MODEL: u16 = 0x0110;
+/// Tag of the DateTime field, when the image was last changed
+pub(crate) const DATE_TIME: u16 = 0x0132;
+/// Tag of the DateTimeOriginal field, in the Exif IFD, when the photo was taken
+pub(crate) const DATE_TIME_ORIGINAL: u16 = 0x9003;
+/// Tag of the Orientation field
+pub(crate) const ORIENTATION: u16 = 0x0112;
+/// Tag of the Compression field
+pub(crate) const COMPRESSION: u16 = 0x0103;
+/// Tag of the offset of the JPEG thumbnail, in the second IFD
+pub(crate) const THUMBNAIL_OFFSET: u16 = 0x0201;
+/// Tag of the length of the JPEG thumbnail, in the second IFD
+pub(crate) const THUMBNAIL_LENGTH: u16 = 0x0202;
+/// Tag of the pointer to the Exif IFD
+pub(crate) const EXIF_IFD: u16 = 0x8769;
+/// Tag of the pointer to the GPS IFD
+pub(crate) const GPS_IFD: u16 = 0x8825;
+
+// Exif without any field, little endian
+const EMPTY: &[u8] = b"II\x2a\x00\x08\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+
+// Type of ASCII values
+const ASCII: u16 = 2;
+// Type of SHORT values
+const SHORT: u16 = 3;
+// Type of LONG values
+const LONG: u16 = 4;
+
+/// Value of ASCII field `tag` in the first IFD of `exif`, raw Exif data
+/// starting with its TIFF header ("II" or "MM")
+pub(crate) fn read_ascii(exif: &[u8], tag: u16) -> Option<String> {
+    let tiff = Tiff::new(exif)?;
+    tiff.ascii(tiff.u32(4)? as usize, tag)
+}
+
+/// Value of ASCII field `tag` in the Exif IFD of `exif`, raw Exif data
+pub(crate) fn read_exif_ascii(exif: &[u8], tag: u16) -> Option<String> {
+    let tiff = Tiff::new(exif)?;
+    let pointer = tiff.entry(tiff.u32(4)? as usize, EXIF_IFD)?;
+    tiff.ascii(tiff.u32(pointer + 8)? as usize, tag)
+}
+
+/// `exif`, raw Exif data (or new Exif data if `None` or invalid),
+/// with ASCII `fields` (tag and value) set in its first IFD.
+/// Existing data is kept as is, the updated IFD is appended to it
+/// so that offsets of other IFDs and values remain valid
+pub(crate) fn set_ascii(exif: Option<&[u8]>, fields: &[(u16, String)]) -> Vec<u8> {
+    let tiff = exif
+        .and_then(Tiff::new)
+        .unwrap_or_else(|| Tiff::new(EMPTY).expect("valid empty Exif"));
+    let mut data = tiff.data.to_vec();
+
+    let entries = fields
+        .iter()
+        .map(|(tag, value)| {
+            let mut value = value.as_bytes().to_vec();
+            value.push(0);
+            let count = value.len() as u32;
+            (*tag, put_entry(&tiff, &mut data, *tag, ASCII, count, value))
+        })
+        .collect();
+    append_first_ifd(
+        &tiff,
+        data,
+        |tag| !fields.iter().any(|(field, _)| *field == tag),
+        entries,
+    )
+}
+
+/// `exif`, raw Exif data, with the Orientation field of its first IFD (if any)
+/// set to 1, the normal orientation, i.e. once pixels have been rotated accordingly
+pub(crate) fn reset_orientation(exif: &[u8]) -> Vec<u8> {
+    let mut data = exif.to_vec();
+    let Some(tiff) = Tiff::new(exif) else {
+        return data;
+    };
+    let entry = tiff
+        .u32(4)
+        .and_then(|ifd| tiff.entry(ifd as usize, ORIENTATION))
+        .filter(|&entry| tiff.u16(entry + 2) == Some(SHORT));
+    if let Some(value) = entry.and_then(|entry| data.get_mut(entry + 8..entry + 10)) {
+        value.copy_from_slice(&tiff.put_u16(1));
+    }
+    data
+}
+
+/// `exif`, raw Exif data, without GPS data: the pointer to the GPS IFD is removed
+/// from the first IFD in place, the GPS IFD and its values are zeroed.
+/// Invalid Exif data and Exif data without GPS IFD are returned as is
+pub(crate) fn strip_gps(exif: &[u8]) -> Vec<u8> {
+    let mut data = exif.to_vec();
+    let Some(tiff) = Tiff::new(exif) else {
+        return data;
+    };
+    let (Some(ifd), Some(next)) = (tiff.u32(4), tiff.next_ifd()) else {
+        return data;
+    };
+    let Some(pointer) = tiff.entry(ifd as usize, GPS_IFD) else {
+        return data;
+    };
+    let Some(gps) = tiff.u32(pointer + 8) else {
+        return data;
+    };
+
+    let gps = gps as usize;
+    let entries = tiff.u16(gps).unwrap_or_default() as usize;
+    for entry in (0..entries).map(|i| gps + 2 + i * 12) {
+        let (Some(kind), Some(count), Some(offset)) = (
+            tiff.u16(entry + 2),
+            tiff.u32(entry + 4),
+            tiff.u32(entry + 8),
+        ) else {
+            continue;
+        };
+        let len = type_size(kind).saturating_mul(count as usize);
+        // values of 4 bytes or less are inlined in the entry, zeroed with the IFD
+        if len > 4 {
+            erase(&mut data, offset as usize, len);
+        }
+    }
+    erase(&mut data, gps, 2 + entries * 12 + 4);
+
+    // following entries and the pointer to the next IFD move up over the removed entry
+    let count = tiff.u16(ifd as usize).unwrap_or_default();
+    data[ifd as usize..ifd as usize + 2].copy_from_slice(&tiff.put_u16(count - 1));
+    data[pointer..next - 8].copy_from_slice(&exif[pointer + 12..next + 4]);
+    erase(&mut data, next - 8, 12);
+    data
+}
+
+/// `exif`, raw Exif data (or new Exif data if `None` or invalid), with the GPS data
+/// of `gps`, raw Exif data, in place of its own. Without GPS IFD in `gps`,
+/// GPS data of `exif` is removed
+pub(crate) fn replace_gps(exif: Option<&[u8]>, gps: &[u8]) -> Vec<u8> {
+    let stripped = exif.map(strip_gps);
+    let tiff = stripped
+        .as_deref()
+        .and_then(Tiff::new)
+        .unwrap_or_else(|| Tiff::new(EMPTY).expect("valid empty Exif"));
+    let mut data = tiff.data.to_vec();
+    let Some(from) = Tiff::new(gps) else {
+        return data;
+    };
+    let Some(ifd) = from
+        .u32(4)
+        .and_then(|ifd| from.entry(ifd as usize, GPS_IFD))
+        .and_then(|entry| from.u32(entry + 8))
+    else {
+        return data;
+    };
+
+    // entries and values of the GPS IFD of `gps`, relocated in `data`
+    let ifd = ifd as usize;
+    let count = from.u16(ifd).unwrap_or_default() as usize;
+    let mut entries = vec![];
+    for entry in (0..count).map(|i| ifd + 2 + i * 12) {
+        let (Some(tag), Some(kind), Some(count)) =
+            (from.u16(entry), from.u16(entry + 2), from.u32(entry + 4))
+        else {
+            continue;
+        };
+        let len = type_size(kind).saturating_mul(count as usize);
+        let offset = match len {
+            // unknown type
+            0 => continue,
+            1..=4 => entry + 8,
+            _ => from.u32(entry + 8).unwrap_or(u32::MAX) as usize,
+        };
+        let Some(value) = from.data.get(offset..offset.saturating_add(len)) else {
+            continue;
+        };
+        let mut value = value.to_vec();
+        if from.big_endian != tiff.big_endian {
+            // rationals are pairs of 4-byte integers
+            let size = match kind {
+                5 | 10 => 4,
+                _ => type_size(kind),
+            };
+            value.chunks_mut(size).for_each(|value| value.reverse());
+        }
+        entries.push(put_entry(&tiff, &mut data, tag, kind, count, value));
+    }
+
+    data.resize(data.len() + data.len() % 2, 0);
+    let gps_ifd = data.len() as u32;
+    data.extend(tiff.put_u16(entries.len() as u16));
+    for entry in entries {
+        data.extend(entry);
+    }
+    data.extend(tiff.put_u32(0));
+
+    let pointer = put_entry(
+        &tiff,
+        &mut data,
+        GPS_IFD,
+        LONG,
+        1,
+        tiff.put_u32(gps_ifd).to_vec(),
+    );
+    append_first_ifd(&tiff, data, |tag| tag != GPS_IFD, vec![(GPS_IFD, pointer)])
+}
+
+/// JPEG thumbnail of `exif`, raw Exif data, described by its second IFD
+pub(crate) fn thumbnail(exif: &[u8]) -> Option<&[u8]> {
+    let (offset, len) = Tiff::new(exif)?.thumbnail()?;
+    exif.get(offset..offset.checked_add(len)?)
+}
+
+/// `exif`, raw Exif data, without its second IFD: the pointer to it is removed from
+/// the first IFD, the second IFD and the thumbnail it describes are zeroed, and cut off
+/// if the thumbnail ends the data. Invalid Exif data and Exif data without second IFD
+/// are returned as is
+pub(crate) fn strip_thumbnail(exif: &[u8]) -> Vec<u8> {
+    let mut data = exif.to_vec();
+    let Some(tiff) = Tiff::new(exif) else {
+        return data;
+    };
+    let (Some(pointer), Some(ifd)) = (tiff.next_ifd(), tiff.second_ifd()) else {
+        return data;
+    };
+
+    let entries = tiff.u16(ifd).unwrap_or_default() as usize;
+    erase(&mut data, ifd, 2 + entries * 12 + 4);
+    erase(&mut data, pointer, 4);
+    if let Some((offset, len)) = tiff.thumbnail() {
+        erase(&mut data, offset, len);
+        if offset.saturating_add(len) == data.len() {
+            data.truncate(offset);
+        }
+    }
+    data
+}
+
+/// `exif`, raw Exif data, with `thumbnail`, a JPEG image, in place of its own thumbnail,
+/// described by a new second IFD. Both take the space of the former ones when they fit
+/// in it, and are appended otherwise, the thumbnail last
+pub(crate) fn replace_thumbnail(exif: &[u8], thumbnail: &[u8]) -> Vec<u8> {
+    let mut data = strip_thumbnail(exif);
+    let Some(tiff) = Tiff::new(exif) else {
+        return data;
+    };
+    let Some(pointer) = tiff.next_ifd() else {
+        return data;
+    };
+
+    // 3 entries, all inlined
+    let len = 2 + 3 * 12 + 4;
+    let ifd = match tiff.second_ifd() {
+        Some(ifd)
+            if 2 + tiff.u16(ifd).unwrap_or_default() as usize * 12 + 4 >= len
+                && ifd + len <= data.len() =>
+        {
+            ifd
+        }
+        _ => {
+            data.resize(data.len() + data.len() % 2, 0);
+            data.resize(data.len() + len, 0);
+            data.len() - len
+        }
+    };
+    let offset = match tiff.thumbnail() {
+        Some((offset, len)) if thumbnail.len() <= len && offset + thumbnail.len() <= data.len() => {
+            data[offset..offset + thumbnail.len()].copy_from_slice(thumbnail);
+            offset
+        }
+        _ => {
+            data.resize(data.len() + data.len() % 2, 0);
+            data.extend(thumbnail);
+            data.len() - thumbnail.len()
+        }
+    };
+    // JPEG compression
+    let entries = [
+        (COMPRESSION, SHORT, tiff.put_u16(6).to_vec()),
+        (THUMBNAIL_OFFSET, LONG, tiff.put_u32(offset as u32).to_vec()),
+        (
+            THUMBNAIL_LENGTH,
+            LONG,
+            tiff.put_u32(thumbnail.len() as u32).to_vec(),
+        ),
+    ]
+    .map(|(tag, kind, value)| put_entry(&tiff, &mut data, tag, kind, 1, value));
+
+    let mut bytes = tiff.put_u16(entries.len() as u16).to_vec();
+    for entry in entries {
+        bytes.extend(entry);
+    }
+    bytes.extend(tiff.put_u32(0));
+    data[ifd..ifd + len].copy_from_slice(&bytes);
+    data[pointer..pointer + 4].copy_from_slice(&tiff.put_u32(ifd as u32));
+    data
+}
+
+// Zero `len` bytes of `data` from offset `from`, as far as `data` goes
+fn erase(data: &mut [u8], from: usize, len: usize) {
+    let to = from.saturating_add(len).min(data.len());
+    if let Some(bytes) = data.get_mut(from..to) {
+        bytes.fill(0);
+    }
+}
+
+// Raw IFD entry of field `tag`, of TIFF type `kind`, with `count` values `value`
+// (in the byte order of `tiff`) appended to `data` if they do not fit in the entry
+fn put_entry(
+    tiff: &Tiff,
+    data: &mut Vec<u8>,
+    tag: u16,
+    kind: u16,
+    count: u32,
+    mut value: Vec<u8>,
+) -> Vec<u8> {
+    let mut entry = tiff.put_u16(tag).to_vec();
+    entry.extend(tiff.put_u16(kind));
+    entry.extend(tiff.put_u32(count));
+    if value.len() <= 4 {
+        value.resize(4, 0);
+        entry.extend(value);
+    } else {
+        // values start on a word boundary
+        data.resize(data.len() + data.len() % 2, 0);
+        entry.extend(tiff.put_u32(data.len() as u32));
+        data.extend(value);
+    }
+    entry
+}
+
+// `data`, raw Exif data based on `tiff`, with a new first IFD appended and referenced
+// by the header: entries of the first IFD of `tiff` to `keep` (by tag) and `entries`
+// (tag and raw entry), so that offsets of other IFDs and values remain valid
+fn append_first_ifd(
+    tiff: &Tiff,
+    mut data: Vec<u8>,
+    keep: impl Fn(u16) -> bool,
+    mut entries: Vec<(u16, Vec<u8>)>,
+) -> Vec<u8> {
+    let ifd = tiff.u32(4).unwrap_or_default() as usize;
+    let count = tiff.u16(ifd).unwrap_or_default() as usize;
+    entries.extend((0..count).filter_map(|i| {
+        let entry = ifd + 2 + i * 12;
+        let tag = tiff.u16(entry)?;
+        if !keep(tag) {
+            return None;
+        }
+        Some((tag, tiff.data.get(entry..entry + 12)?.to_vec()))
+    }));
+    entries.sort_by_key(|(tag, _)| *tag);
+    let next_ifd = tiff.u32(ifd + 2 + count * 12).unwrap_or_default();
+
+    data.resize(data.len() + data.len() % 2, 0);
+    let new_ifd = tiff.put_u32(data.len() as u32);
+    data.extend(tiff.put_u16(entries.len() as u16));
+    for (_, entry) in entries {
+        data.extend(entry);
+    }
+    data.extend(tiff.put_u32(next_ifd));
+    data[4..8].copy_from_slice(&new_ifd);
+    data
+}
+
+// Size in bytes of a value of TIFF type `kind`
+fn type_size(kind: u16) -> usize {
+    match kind {
+        // BYTE, ASCII, SBYTE, UNDEFINED
+        1 | 2 | 6 | 7 => 1,
+        // SHORT, SSHORT
+        3 | 8 => 2,
+        // LONG, SLONG, FLOAT, IFD
+        4 | 9 | 11 | 13 => 4,
+        // RATIONAL, SRATIONAL, DOUBLE
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+// TIFF data, with its byte order
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(..2)? {
+            b"II" => false,
+            b"MM" => true,
+            _ => return None,
+        };
+        let tiff = Self { data, big_endian };
+        (tiff.u16(2)? == 42).then_some(tiff)
+    }
+
+    // Offset of the entry of `tag` in the IFD at `ifd`
+    fn entry(&self, ifd: usize, tag: u16) -> Option<usize> {
+        let entries = self.u16(ifd)? as usize;
+        (0..entries)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| self.u16(entry) == Some(tag))
+    }
+
+    // Offset of the pointer to the second IFD, at the end of the first IFD
+    fn next_ifd(&self) -> Option<usize> {
+        let ifd = self.u32(4)? as usize;
+        let pointer = ifd + 2 + self.u16(ifd)? as usize * 12;
+        self.u32(pointer).map(|_| pointer)
+    }
+
+    // Offset of the second IFD, if any
+    fn second_ifd(&self) -> Option<usize> {
+        let ifd = self.u32(self.next_ifd()?)? as usize;
+        (ifd != 0).then_some(ifd)
+    }
+
+    // Offset and length of the thumbnail described by the second IFD, if any
+    fn thumbnail(&self) -> Option<(usize, usize)> {
+        let ifd = self.second_ifd()?;
+        let value = |tag| self.u32(self.entry(ifd, tag)? + 8);
+        Some((
+            value(THUMBNAIL_OFFSET)? as usize,
+            value(THUMBNAIL_LENGTH)? as usize,
+        ))
+    }
+
+    // Value of ASCII field `tag` in the IFD at `ifd`
+    fn ascii(&self, ifd: usize, tag: u16) -> Option<String> {
+        let entry = self.entry(ifd, tag)?;
+        if self.u16(entry + 2)? != ASCII {
+            return None;
+        }
+        let count = self.u32(entry + 4)? as usize;
+        // values of 4 bytes or less are inlined in the entry
+        let offset = if count <= 4 {
+            entry + 8
+        } else {
+            self.u32(entry + 8)? as usize
+        };
+        let value = self.data.get(offset..offset.checked_add(count)?)?;
+        let value = String::from_utf8_lossy(value);
+        Some(value.trim_end_matches('\0').replace('\0', " "))
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn put_u16(&self, value: u16) -> [u8; 2] {
+        match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        }
+    }
+
+    fn put_u32(&self, value: u32) -> [u8; 4] {
+        match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_ascii, replace_gps, reset_orientation, set_ascii, strip_gps, Tiff};
+    use super::{replace_thumbnail, strip_thumbnail, thumbnail, THUMBNAIL_OFFSET};
+    use super::{ARTIST, COPYRIGHT, GPS_IFD, IMAGE_DESCRIPTION, MODEL, ORIENTATION};
+
+    #[test]
+    fn test_read_ascii() {
+        // little endian, one IFD with Artist inlined and Copyright at offset 26
+        let mut exif = b"II\x2a\x00\x08\x00\x00\x00\x02\x00".to_vec();
+        exif.extend(b"\x3b\x01\x02\x00\x04\x00\x00\x00Bob\x00");
+        exif.extend(b"\x98\x82\x02\x00\x0c\x00\x00\x00\x26\x00\x00\x00");
+        exif.extend(b"\x00\x00\x00\x00");
+        exif.extend(b"(c) Studio\x00\x00");
+
+        assert_eq!(read_ascii(&exif, ARTIST).as_deref(), Some("Bob"));
+        assert_eq!(read_ascii(&exif, COPYRIGHT).as_deref(), Some("(c) Studio"));
+        assert_eq!(read_ascii(&exif, 0x010e), None);
+        assert_eq!(read_ascii(&exif[..20], COPYRIGHT), None);
+        assert_eq!(read_ascii(b"not exif", COPYRIGHT), None);
+    }
+
+    #[test]
+    fn test_set_ascii() {
+        let exif = set_ascii(None, &[(COPYRIGHT, "(c) Studio".to_string())]);
+        assert_eq!(read_ascii(&exif, COPYRIGHT).as_deref(), Some("(c) Studio"));
+
+        let exif = set_ascii(
+            Some(&exif),
+            &[
+                (ARTIST, "Bob".to_string()),
+                (IMAGE_DESCRIPTION, "Sunset over the bay".to_string()),
+                (COPYRIGHT, "(c) Other Studio".to_string()),
+            ],
+        );
+        assert_eq!(read_ascii(&exif, ARTIST).as_deref(), Some("Bob"));
+        assert_eq!(
+            read_ascii(&exif, IMAGE_DESCRIPTION).as_deref(),
+            Some("Sunset over the bay")
+        );
+        assert_eq!(
+            read_ascii(&exif, COPYRIGHT).as_deref(),
+            Some("(c) Other Studio")
+        );
+
+        // with the Exif of a camera, kept as is
+        let input = std::fs::read("data/exif/notes.jpg").unwrap();
+        let jpeg = img_parts::jpeg::Jpeg::from_bytes(input.into()).unwrap();
+        let camera_exif = img_parts::ImageEXIF::exif(&jpeg).unwrap();
+        let exif = set_ascii(Some(&camera_exif), &[(ARTIST, "Bob".to_string())]);
+        assert_eq!(read_ascii(&exif, ARTIST).as_deref(), Some("Bob"));
+        assert_eq!(exif[..camera_exif.len()][8..], camera_exif[8..]);
+    }
+
+    #[test]
+    fn test_strip_gps() {
+        // little endian, one IFD with Artist inlined and the GPS IFD at offset 38,
+        // with GPSLatitudeRef inlined and GPSLatitude at offset 68
+        let mut exif = b"II\x2a\x00\x08\x00\x00\x00\x02\x00".to_vec();
+        exif.extend(b"\x3b\x01\x02\x00\x04\x00\x00\x00Bob\x00");
+        exif.extend(b"\x25\x88\x04\x00\x01\x00\x00\x00\x26\x00\x00\x00");
+        exif.extend(b"\x00\x00\x00\x00");
+        exif.extend(b"\x02\x00");
+        exif.extend(b"\x01\x00\x02\x00\x02\x00\x00\x00N\x00\x00\x00");
+        exif.extend(b"\x02\x00\x05\x00\x03\x00\x00\x00\x44\x00\x00\x00");
+        exif.extend(b"\x00\x00\x00\x00");
+        exif.extend([[48, 0, 0, 0, 1, 0, 0, 0]; 3].concat());
+
+        let stripped = strip_gps(&exif);
+        let tiff = Tiff::new(&stripped).unwrap();
+        assert_eq!(tiff.entry(tiff.u32(4).unwrap() as usize, GPS_IFD), None);
+        assert_eq!(read_ascii(&stripped, ARTIST).as_deref(), Some("Bob"));
+        // the first IFD is rewritten in place
+        assert_eq!(stripped.len(), exif.len());
+        assert_eq!(tiff.u32(4), Some(8));
+        assert!(stripped[26..exif.len()].iter().all(|byte| *byte == 0));
+
+        // with the Exif of a camera
+        let input = std::fs::read("data/exif/notes.jpg").unwrap();
+        let jpeg = img_parts::jpeg::Jpeg::from_bytes(input.into()).unwrap();
+        let camera_exif = img_parts::ImageEXIF::exif(&jpeg).unwrap();
+        let tiff = Tiff::new(&camera_exif).unwrap();
+        assert!(tiff.entry(tiff.u32(4).unwrap() as usize, GPS_IFD).is_some());
+        let stripped = strip_gps(&camera_exif);
+        let tiff = Tiff::new(&stripped).unwrap();
+        assert_eq!(tiff.entry(tiff.u32(4).unwrap() as usize, GPS_IFD), None);
+        assert_eq!(stripped.len(), camera_exif.len());
+        assert_eq!(
+            read_ascii(&stripped, MODEL).as_deref(),
+            Some("COOLPIX P6000")
+        );
+        assert!(thumbnail(&stripped).is_some());
+
+        assert_eq!(strip_gps(b"not exif"), b"not exif");
+    }
+
+    #[test]
+    fn test_reset_orientation() {
+        // big endian, one IFD with Orientation 6 (rotated 90° clockwise)
+        let mut exif = b"MM\x00\x2a\x00\x00\x00\x08\x00\x01".to_vec();
+        exif.extend(b"\x01\x12\x00\x03\x00\x00\x00\x01\x00\x06\x00\x00");
+        exif.extend(b"\x00\x00\x00\x00");
+
+        let reset = reset_orientation(&exif);
+        let tiff = Tiff::new(&reset).unwrap();
+        let entry = tiff.entry(8, ORIENTATION).unwrap();
+        assert_eq!(tiff.u16(entry + 8), Some(1));
+        assert_eq!(reset[..entry + 8], exif[..entry + 8]);
+
+        let exif = set_ascii(None, &[(ARTIST, "Bob".to_string())]);
+        assert_eq!(reset_orientation(&exif), exif);
+    }
+
+    #[test]
+    fn test_replace_gps() {
+        // big endian, with GPSLatitudeRef inlined and GPSLatitude at offset 56
+        let mut gps = b"MM\x00\x2a\x00\x00\x00\x08\x00\x01".to_vec();
+        gps.extend(b"\x88\x25\x00\x04\x00\x00\x00\x01\x00\x00\x00\x1a");
+        gps.extend(b"\x00\x00\x00\x00");
+        gps.extend(b"\x00\x02");
+        gps.extend(b"\x00\x01\x00\x02\x00\x00\x00\x02S\x00\x00\x00");
+        gps.extend(b"\x00\x02\x00\x05\x00\x00\x00\x03\x00\x00\x00\x38");
+        gps.extend(b"\x00\x00\x00\x00");
+        gps.extend([[0, 0, 0, 33, 0, 0, 0, 1]; 3].concat());
+
+        let camera = set_ascii(None, &[(ARTIST, "Bob".to_string())]);
+        let exif = replace_gps(Some(&camera), &gps);
+        assert_eq!(read_ascii(&exif, ARTIST).as_deref(), Some("Bob"));
+
+        // little endian as `camera`
+        let tiff = Tiff::new(&exif).unwrap();
+        let pointer = tiff.entry(tiff.u32(4).unwrap() as usize, GPS_IFD).unwrap();
+        let ifd = tiff.u32(pointer + 8).unwrap() as usize;
+        let latitude_ref = tiff.entry(ifd, 1).unwrap();
+        assert_eq!(exif[latitude_ref + 8..latitude_ref + 10], *b"S\x00");
+        let latitude = tiff.entry(ifd, 2).unwrap();
+        let latitude = tiff.u32(latitude + 8).unwrap() as usize;
+        let values = (0..6)
+            .map(|i| tiff.u32(latitude + i * 4).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, [33, 1, 33, 1, 33, 1]);
+
+        // replaced again, without any GPS data
+        let exif = replace_gps(Some(&exif), &camera);
+        let tiff = Tiff::new(&exif).unwrap();
+        assert_eq!(tiff.entry(tiff.u32(4).unwrap() as usize, GPS_IFD), None);
+        assert_eq!(read_ascii(&exif, ARTIST).as_deref(), Some("Bob"));
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let input = std::fs::read("data/exif/notes.jpg").unwrap();
+        let jpeg = img_parts::jpeg::Jpeg::from_bytes(input.into()).unwrap();
+        let camera_exif = img_parts::ImageEXIF::exif(&jpeg).unwrap();
+        assert_eq!(thumbnail(&camera_exif).map(<[u8]>::len), Some(6307));
+        let tiff = Tiff::new(&camera_exif).unwrap();
+        let offset = tiff.u32(tiff.entry(4454, THUMBNAIL_OFFSET).unwrap() + 8);
+        assert_eq!(offset, Some(4548));
+
+        // the thumbnail ends the data, cut off
+        let stripped = strip_thumbnail(&camera_exif);
+        assert_eq!(thumbnail(&stripped), None);
+        assert_eq!(stripped.len(), 4548);
+        assert!(stripped[4454..4532].iter().all(|byte| *byte == 0));
+        assert_eq!(
+            read_ascii(&stripped, MODEL).as_deref(),
+            Some("COOLPIX P6000")
+        );
+
+        // in the space of the former second IFD and thumbnail
+        let replaced = replace_thumbnail(&camera_exif, b"\xff\xd8 new thumbnail");
+        assert_eq!(thumbnail(&replaced), Some(&b"\xff\xd8 new thumbnail"[..]));
+        let tiff = Tiff::new(&replaced).unwrap();
+        assert_eq!(tiff.second_ifd(), Some(4454));
+        assert_eq!(replaced.len(), 4548 + 16);
+
+        // larger than the former one, appended
+        let large = [0xff; 7000];
+        let replaced = replace_thumbnail(&replaced, &large);
+        assert_eq!(thumbnail(&replaced), Some(&large[..]));
+        assert_eq!(replaced.len(), 4548 + 7000);
+
+        let exif = set_ascii(None, &[(ARTIST, "Bob".to_string())]);
+        assert_eq!(thumbnail(&exif), None);
+        assert_eq!(strip_thumbnail(&exif), exif);
+    }
+}
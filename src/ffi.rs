@@ -0,0 +1,258 @@
+use ab_glyph::PxScale;
+use image::Rgba;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use crate::config::Config;
+use crate::options::Options;
+use crate::{watermark_bytes, watermark_file};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Status returned by the functions of the C API, declared in `include/filigram.h`.
+/// The message of the last error of the calling thread is given by `filigram_last_error`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FiligramError {
+    Ok = 0,
+    /// A null pointer or a string which is not UTF-8
+    InvalidArgument = 1,
+    /// A file can't be read or written
+    Io = 2,
+    /// The image can't be decoded, processed or encoded
+    Image = 3,
+    /// Unexpected failure of filigram
+    Panic = 4,
+}
+
+/// Customization of the watermark, see `Config`.
+/// Null strings take their default value, or leave Exif fields as they are
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FiligramConfig {
+    pub text: *const c_char,
+    /// Red, green, blue and alpha channels
+    pub color: [u8; 4],
+    /// Height of the watermark text, in pixels
+    pub scale: f32,
+    pub copyright: *const c_char,
+    pub artist: *const c_char,
+    pub description: *const c_char,
+}
+
+/// Fill `config` with the default customization of the watermark
+///
+/// # Safety
+/// `config` must be null or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn filigram_config_default(config: *mut FiligramConfig) {
+    let default = Config::default();
+    if let Some(config) = config.as_mut() {
+        *config = FiligramConfig {
+            text: ptr::null(),
+            color: default.color.0,
+            scale: default.scale.y,
+            copyright: ptr::null(),
+            artist: ptr::null(),
+            description: ptr::null(),
+        };
+    }
+}
+
+/// Apply a watermark to image `src` and write it to `dst`, in the format of its extension.
+/// A null `config` stands for the default one
+///
+/// # Safety
+/// `src` and `dst` must be null or nul-terminated strings, `config` must be null
+/// or point to a config whose strings are null or nul-terminated
+#[no_mangle]
+pub unsafe extern "C" fn filigram_watermark_file(
+    src: *const c_char,
+    dst: *const c_char,
+    config: *const FiligramConfig,
+) -> FiligramError {
+    guard(|| {
+        let src = string(src)?.ok_or_else(|| invalid("null source path"))?;
+        let dst = string(dst)?.ok_or_else(|| invalid("null destination path"))?;
+        let cfg = config_of(config)?;
+        watermark_file(Path::new(&src), Path::new(&dst), &cfg, &Options::default())
+            .map_err(|e| fail(e.as_ref()))
+    })
+}
+
+/// Apply a watermark to image `input` of `input_len` bytes. On success, `output`
+/// and `output_len` are set to the watermarked image, encoded in the format of `input`,
+/// to free with `filigram_free_bytes`. A null `config` stands for the default one
+///
+/// # Safety
+/// `input` must be valid for reads of `input_len` bytes, `output` and `output_len`
+/// must be valid for writes, `config` as for `filigram_watermark_file`
+#[no_mangle]
+pub unsafe extern "C" fn filigram_watermark_bytes(
+    input: *const u8,
+    input_len: usize,
+    config: *const FiligramConfig,
+    output: *mut *mut u8,
+    output_len: *mut usize,
+) -> FiligramError {
+    guard(|| {
+        if input.is_null() || output.is_null() || output_len.is_null() {
+            return Err(invalid("null input or output"));
+        }
+        let input = std::slice::from_raw_parts(input, input_len);
+        let cfg = config_of(config)?;
+        let watermarked = watermark_bytes(input, &cfg, &Options::default())
+            .map_err(|e| fail(e.as_ref()))?
+            .into_boxed_slice();
+        *output_len = watermarked.len();
+        *output = Box::into_raw(watermarked).cast();
+        Ok(())
+    })
+}
+
+/// Free an image returned by `filigram_watermark_bytes`
+///
+/// # Safety
+/// `output` and `output_len` must be those returned by `filigram_watermark_bytes`,
+/// not freed yet, or `output` must be null
+#[no_mangle]
+pub unsafe extern "C" fn filigram_free_bytes(output: *mut u8, output_len: usize) {
+    if !output.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            output, output_len,
+        )));
+    }
+}
+
+/// Message of the last error of the calling thread, null if none.
+/// It is valid until the next call of the C API on this thread
+#[no_mangle]
+pub extern "C" fn filigram_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+// Run `f`, recording its error or panic as the last error
+fn guard(f: impl FnOnce() -> Result<(), FiligramError>) -> FiligramError {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => FiligramError::Ok,
+        Ok(Err(code)) => code,
+        Err(_) => {
+            set_last_error("filigram panicked".to_owned());
+            FiligramError::Panic
+        }
+    }
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn invalid(message: &str) -> FiligramError {
+    set_last_error(message.to_owned());
+    FiligramError::InvalidArgument
+}
+
+// Code of error `e`, recorded as the last error
+fn fail(e: &(dyn std::error::Error + 'static)) -> FiligramError {
+    set_last_error(e.to_string());
+    if e.is::<std::io::Error>() {
+        FiligramError::Io
+    } else {
+        FiligramError::Image
+    }
+}
+
+// String of `s`, `None` if null
+unsafe fn string(s: *const c_char) -> Result<Option<String>, FiligramError> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    let s = CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| invalid("string is not UTF-8"))?;
+    Ok(Some(s.to_owned()))
+}
+
+unsafe fn config_of(config: *const FiligramConfig) -> Result<Config, FiligramError> {
+    let default = Config::default();
+    let Some(config) = config.as_ref() else {
+        return Ok(default);
+    };
+    Ok(Config {
+        text: string(config.text)?.unwrap_or(default.text),
+        color: Rgba(config.color),
+        scale: PxScale::from(config.scale),
+        copyright: string(config.copyright)?,
+        artist: string(config.artist)?,
+        description: string(config.description)?,
+        ..default
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watermark() {
+        let input = std::fs::read("tests/img/test.jpg").unwrap();
+        let text = CString::new("© ACME").unwrap();
+        let mut config = std::mem::MaybeUninit::uninit();
+        let (mut output, mut output_len) = (ptr::null_mut(), 0);
+        unsafe {
+            filigram_config_default(config.as_mut_ptr());
+            let mut config = config.assume_init();
+            assert_eq!(config.color, [0, 0, 0, 110]);
+            config.text = text.as_ptr();
+
+            let status = filigram_watermark_bytes(
+                input.as_ptr(),
+                input.len(),
+                &config,
+                &mut output,
+                &mut output_len,
+            );
+            assert_eq!(status, FiligramError::Ok);
+            let watermarked = std::slice::from_raw_parts(output, output_len);
+            assert_eq!(
+                image::guess_format(watermarked).unwrap(),
+                image::ImageFormat::Jpeg
+            );
+            assert_eq!(image::load_from_memory(watermarked).unwrap().width(), 500);
+            filigram_free_bytes(output, output_len);
+
+            let status = filigram_watermark_bytes(
+                b"not an image".as_ptr(),
+                12,
+                ptr::null(),
+                &mut output,
+                &mut output_len,
+            );
+            assert_eq!(status, FiligramError::Image);
+            assert!(!filigram_last_error().is_null());
+        }
+    }
+
+    #[test]
+    fn test_watermark_file() {
+        std::fs::create_dir("tmp").ok();
+        let src = CString::new("tests/img/missing.bmp").unwrap();
+        let dst = CString::new("tmp/ffi.png").unwrap();
+        unsafe {
+            let status = filigram_watermark_file(src.as_ptr(), dst.as_ptr(), ptr::null());
+            assert_eq!(status, FiligramError::Io);
+            let src = CString::new("tests/img/test.bmp").unwrap();
+            let status = filigram_watermark_file(src.as_ptr(), dst.as_ptr(), ptr::null());
+            assert_eq!(status, FiligramError::Ok);
+            let status = filigram_watermark_file(ptr::null(), dst.as_ptr(), ptr::null());
+            assert_eq!(status, FiligramError::InvalidArgument);
+        }
+        assert!(image::open("tmp/ffi.png").is_ok());
+    }
+}
@@ -0,0 +1,115 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::graphics::encode_thumbnail;
+use crate::hooks::Outcome;
+use crate::report::FileReport;
+use crate::trace::debug;
+
+/// Name of the gallery written in each output directory, see `Options::gallery`
+pub const GALLERY_INDEX: &str = "index.html";
+
+/// Write a gallery of the watermarked outputs of `files` in each directory holding some:
+/// an HTML page with their thumbnails, inlined, linking to the outputs
+pub(crate) fn write_galleries(files: &[FileReport]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut directories: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+    let outputs = files
+        .iter()
+        .filter(|file| matches!(file.outcome, Outcome::Watermarked | Outcome::Deduplicated))
+        .filter_map(|file| file.output.as_deref());
+    for output in outputs {
+        if let Some(directory) = output.parent() {
+            directories.entry(directory).or_default().push(output);
+        }
+    }
+
+    for (directory, mut outputs) in directories {
+        // files processed again by `watch` are reported twice
+        outputs.sort();
+        outputs.dedup();
+        fs::write(directory.join(GALLERY_INDEX), gallery(directory, &outputs))?;
+    }
+    Ok(())
+}
+
+// HTML page of the gallery of `outputs`, in `directory`
+fn gallery(directory: &Path, outputs: &[&Path]) -> String {
+    let title = escape(&directory.file_name().unwrap_or_default().to_string_lossy());
+    let mut html = format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; }}
+figure {{ display: inline-block; margin: 8px; width: 160px; text-align: center; }}
+figcaption {{ overflow-wrap: anywhere; font-size: small; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+"
+    );
+    for output in outputs {
+        let name = output.file_name().unwrap_or_default().to_string_lossy();
+        let thumbnail = match thumbnail(output) {
+            Ok(thumbnail) => format!("<img src=\"data:image/jpeg;base64,{thumbnail}\" alt=\"\">"),
+            Err(e) => {
+                debug!("no thumbnail of {output:?} in the gallery: {e}");
+                String::new()
+            }
+        };
+        html.push_str(&format!(
+            "<figure><a href=\"{}\">{thumbnail}<figcaption>{}</figcaption></a></figure>\n",
+            escape(&percent_encode(&name)),
+            escape(&name),
+        ));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+// JPEG thumbnail of image `path`, in base64
+fn thumbnail(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let img = image::open(path)?;
+    Ok(STANDARD.encode(encode_thumbnail(&img)?))
+}
+
+// `text` with the characters special to HTML escaped
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// `name` as a relative URL, with the bytes other than unreserved characters percent-encoded
+fn percent_encode(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_links() {
+        assert_eq!(percent_encode("a b&é.jpg"), "a%20b%26%C3%A9.jpg");
+        assert_eq!(escape("<\"a\" & b>"), "&lt;&quot;a&quot; &amp; b&gt;");
+
+        let html = gallery(Path::new("out/2024"), &[Path::new("out/2024/a b.png")]);
+        assert!(html.contains("<title>2024</title>"));
+        assert!(html.contains("<a href=\"a%20b.png\"><figcaption>a b.png</figcaption></a>"));
+    }
+}
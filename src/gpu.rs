@@ -0,0 +1,635 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImage, GenericImageView, RgbaImage};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::LazyLock;
+use wgpu::util::DeviceExt;
+
+use crate::trace::debug;
+
+// Blend of the watermark over the image, one invocation per pixel,
+// as `image::imageops::overlay` does
+const OVERLAY_SHADER: &str = r"
+struct Params {
+    width: u32,
+    height: u32,
+    // uniforms are laid out by 16 bytes
+    padding: vec2<u32>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> image: array<u32>;
+@group(0) @binding(2) var<storage, read> watermark: array<u32>;
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+    let i = id.y * params.width + id.x;
+    let fg = unpack4x8unorm(watermark[i]);
+    let bg = unpack4x8unorm(image[i]);
+    if (fg.a == 0.0) {
+        return;
+    }
+    if (bg.a == 0.0) {
+        image[i] = watermark[i];
+        return;
+    }
+    let alpha = bg.a + fg.a - bg.a * fg.a;
+    let rgb = (fg.rgb * fg.a + bg.rgb * bg.a * (1.0 - fg.a)) / alpha;
+    image[i] = pack4x8unorm(vec4<f32>(rgb, alpha));
+}
+";
+
+// Resampling of the image, one invocation per pixel of the resized image,
+// with the kernels and windows of `image::imageops::resize`
+const RESIZE_SHADER: &str = r"
+struct Params {
+    width: u32,
+    height: u32,
+    new_width: u32,
+    new_height: u32,
+    // kernel of the filter, as numbered in `kernel`
+    kind: u32,
+    support: f32,
+    // uniforms are laid out by 16 bytes
+    padding: vec2<u32>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> image: array<u32>;
+@group(0) @binding(2) var<storage, read_write> resized: array<u32>;
+
+const PI: f32 = 3.14159265358979;
+
+fn sinc(t: f32) -> f32 {
+    if (t == 0.0) {
+        return 1.0;
+    }
+    return sin(t * PI) / (t * PI);
+}
+
+fn kernel(x: f32) -> f32 {
+    let a = abs(x);
+    switch params.kind {
+        // box
+        case 0u: {
+            return 1.0;
+        }
+        // triangle
+        case 1u: {
+            return max(1.0 - a, 0.0);
+        }
+        // Catmull-Rom cubic spline
+        case 2u: {
+            if (a < 1.0) {
+                return (9.0 * a * a * a - 15.0 * a * a + 6.0) / 6.0;
+            }
+            if (a < 2.0) {
+                return (-3.0 * a * a * a + 15.0 * a * a - 24.0 * a + 12.0) / 6.0;
+            }
+            return 0.0;
+        }
+        // gaussian with a standard deviation of 0.5
+        case 3u: {
+            return exp(-2.0 * a * a) / (sqrt(2.0 * PI) * 0.5);
+        }
+        // lanczos with a window of 3
+        default: {
+            if (a < 3.0) {
+                return sinc(a) * sinc(a / 3.0);
+            }
+            return 0.0;
+        }
+    }
+}
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.new_width || id.y >= params.new_height) {
+        return;
+    }
+    let size = vec2<f32>(f32(params.width), f32(params.height));
+    let ratio = size / vec2<f32>(f32(params.new_width), f32(params.new_height));
+    let scale = max(ratio, vec2<f32>(1.0));
+    let support = params.support * scale;
+    let center = (vec2<f32>(id.xy) + 0.5) * ratio;
+    let left = clamp(floor(center - support), vec2<f32>(0.0), size - 1.0);
+    let right = clamp(ceil(center + support), left + 1.0, size);
+    let first = vec2<u32>(left);
+    let end = vec2<u32>(right);
+    // the kernel is centered on pixels
+    let origin = center - 0.5;
+
+    var sum_x = 0.0;
+    for (var x = first.x; x < end.x; x++) {
+        sum_x += kernel((f32(x) - origin.x) / scale.x);
+    }
+    var sum_y = 0.0;
+    var color = vec4<f32>(0.0);
+    for (var y = first.y; y < end.y; y++) {
+        let weight_y = kernel((f32(y) - origin.y) / scale.y);
+        sum_y += weight_y;
+        for (var x = first.x; x < end.x; x++) {
+            let weight = weight_y * kernel((f32(x) - origin.x) / scale.x);
+            color += unpack4x8unorm(image[y * params.width + x]) * weight;
+        }
+    }
+    resized[id.y * params.new_width + id.x] = pack4x8unorm(color / (sum_x * sum_y));
+}
+";
+
+// Side of the workgroups of the shaders
+const WORKGROUP_SIZE: u32 = 16;
+
+// Most images composited in one submission, and most bytes of their pixels
+const BATCH_LEN: usize = 64;
+const BATCH_BYTES: usize = 256 << 20;
+
+// Queue of the GPU thread, if the machine has an adapter, shared by all workers
+static GPU: LazyLock<Option<Queue>> = LazyLock::new(Queue::new);
+
+// Images of the workers are sent to the GPU thread, which collects those queued
+// meanwhile into a single submission
+struct Queue {
+    jobs: Sender<Job>,
+    // largest buffer of pixels a shader can bind
+    max_binding: u64,
+}
+
+impl Queue {
+    fn new() -> Option<Self> {
+        let gpu = Gpu::new()?;
+        let max_binding = u64::from(gpu.device.limits().max_storage_buffer_binding_size)
+            .min(gpu.device.limits().max_buffer_size);
+        let (jobs, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("filigram-gpu".to_string())
+            .spawn(move || gpu.serve(receiver))
+            .inspect_err(|e| debug!("no GPU thread, compositing on CPU: {e}"))
+            .ok()?;
+        Some(Self { jobs, max_binding })
+    }
+
+    // Run `task` on the GPU thread, waiting for its resulting image
+    fn run(&self, task: Task) -> Result<RgbaImage, String> {
+        let bytes = task.bytes() as u64;
+        if bytes > self.max_binding {
+            return Err(format!("{bytes} bytes of pixels exceed the GPU limit"));
+        }
+        let (result, receiver) = mpsc::sync_channel(1);
+        self.jobs
+            .send(Job { task, result })
+            .map_err(|_| "GPU thread stopped".to_string())?;
+        receiver
+            .recv()
+            .map_err(|_| "GPU thread stopped".to_string())?
+    }
+}
+
+// Work of a shader
+enum Task {
+    // Blend `watermark` over `image`, both of the same dimensions
+    Overlay {
+        image: RgbaImage,
+        watermark: RgbaImage,
+    },
+    // Resample `image` to `width` x `height` with `filter`
+    Resize {
+        image: RgbaImage,
+        width: u32,
+        height: u32,
+        filter: FilterType,
+    },
+}
+
+impl Task {
+    // Bytes of pixels of the largest buffer of this task
+    fn bytes(&self) -> usize {
+        match self {
+            Task::Overlay { image, .. } => image.as_raw().len(),
+            Task::Resize {
+                image,
+                width,
+                height,
+                ..
+            } => (*width as usize * *height as usize * 4).max(image.as_raw().len()),
+        }
+    }
+}
+
+// Task of a worker, with where to send the resulting image
+struct Job {
+    task: Task,
+    result: mpsc::SyncSender<Result<RgbaImage, String>>,
+}
+
+// Buffers of a job recorded in a submission
+struct Recorded {
+    job: Job,
+    output: wgpu::Buffer,
+    width: u32,
+    height: u32,
+}
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    overlay: wgpu::ComputePipeline,
+    resize: wgpu::ComputePipeline,
+}
+
+impl Gpu {
+    fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()));
+        let Some(adapter) = adapter else {
+            debug!("no GPU adapter, compositing on CPU");
+            return None;
+        };
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))
+                .inspect_err(|e| debug!("no GPU device, compositing on CPU: {e}"))
+                .ok()?;
+        debug!("compositing on GPU {}", adapter.get_info().name);
+
+        let pipeline = |label, shader: &str| {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(shader.into()),
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: &module,
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let overlay = pipeline("overlay", OVERLAY_SHADER);
+        let resize = pipeline("resize", RESIZE_SHADER);
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            debug!("no GPU pipelines, compositing on CPU: {e}");
+            return None;
+        }
+        Some(Self {
+            device,
+            queue,
+            overlay,
+            resize,
+        })
+    }
+
+    // Run jobs until all workers are gone: each batch gathers the jobs queued
+    // while the previous one was running
+    fn serve(self, jobs: Receiver<Job>) {
+        while let Ok(job) = jobs.recv() {
+            let mut bytes = job.task.bytes();
+            let mut batch = vec![job];
+            while batch.len() < BATCH_LEN && bytes < BATCH_BYTES {
+                let Ok(job) = jobs.try_recv() else {
+                    break;
+                };
+                bytes += job.task.bytes();
+                batch.push(job);
+            }
+            self.submit(batch);
+        }
+    }
+
+    // Run `batch` in a single submission, sending their results to the workers
+    fn submit(&self, batch: Vec<Job>) {
+        let len = batch.len();
+        self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        let recorded: Vec<_> = batch
+            .into_iter()
+            .map(|job| self.record(&mut encoder, job))
+            .collect();
+        self.queue.submit([encoder.finish()]);
+        let validation = pollster::block_on(self.device.pop_error_scope());
+        let memory = pollster::block_on(self.device.pop_error_scope());
+        if let Some(e) = validation.or(memory) {
+            for recorded in recorded {
+                recorded.job.result.send(Err(e.to_string())).ok();
+            }
+            return;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        for (i, recorded) in recorded.iter().enumerate() {
+            let sender = sender.clone();
+            recorded
+                .output
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    sender.send((i, result)).ok();
+                });
+        }
+        self.device.poll(wgpu::Maintain::Wait);
+        let mut mapped = vec![None; len];
+        for (i, result) in receiver.try_iter() {
+            mapped[i] = Some(result);
+        }
+
+        for (recorded, mapped) in recorded.into_iter().zip(mapped) {
+            let result = match mapped {
+                Some(Ok(())) => {
+                    let pixels = recorded.output.slice(..).get_mapped_range().to_vec();
+                    RgbaImage::from_raw(recorded.width, recorded.height, pixels)
+                        .ok_or_else(|| "GPU output of unexpected size".to_string())
+                }
+                Some(Err(e)) => Err(e.to_string()),
+                None => Err("GPU output not mapped".to_string()),
+            };
+            recorded.job.result.send(result).ok();
+        }
+    }
+
+    // Record the compute pass of `job` in `encoder`, and the copy of its result
+    // to a buffer the CPU can read
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, job: Job) -> Recorded {
+        let buffer = |label, contents: &[u8], usage| {
+            self.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents,
+                    usage,
+                })
+        };
+        let words = |values: &[u32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect()
+        };
+        let (pipeline, params, input, pixels, (width, height)) = match &job.task {
+            Task::Overlay { image, watermark } => {
+                let (width, height) = image.dimensions();
+                let params = buffer(
+                    "params",
+                    &words(&[width, height, 0, 0]),
+                    wgpu::BufferUsages::UNIFORM,
+                );
+                let pixels = buffer(
+                    "image",
+                    image.as_raw(),
+                    wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                );
+                let watermark =
+                    buffer("watermark", watermark.as_raw(), wgpu::BufferUsages::STORAGE);
+                (&self.overlay, params, watermark, pixels, (width, height))
+            }
+            Task::Resize {
+                image,
+                width,
+                height,
+                filter,
+            } => {
+                let (filter, support) = match filter {
+                    FilterType::Nearest => (0, 0.0f32),
+                    FilterType::Triangle => (1, 1.0),
+                    FilterType::CatmullRom => (2, 2.0),
+                    FilterType::Gaussian => (3, 3.0),
+                    FilterType::Lanczos3 => (4, 3.0),
+                };
+                let params = buffer(
+                    "params",
+                    &words(&[
+                        image.width(),
+                        image.height(),
+                        *width,
+                        *height,
+                        filter,
+                        support.to_bits(),
+                        0,
+                        0,
+                    ]),
+                    wgpu::BufferUsages::UNIFORM,
+                );
+                let input = buffer("image", image.as_raw(), wgpu::BufferUsages::STORAGE);
+                let pixels = self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("resized"),
+                    size: u64::from(*width) * u64::from(*height) * 4,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                });
+                (&self.resize, params, input, pixels, (*width, *height))
+            }
+        };
+        let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("output"),
+            size: pixels.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // bindings as laid out by both shaders, the pixels they write are at 1 for
+        // the overlay and at 2 for the resize
+        let (first, second) = match &job.task {
+            Task::Overlay { .. } => (&pixels, &input),
+            Task::Resize { .. } => (&input, &pixels),
+        };
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: first.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: second.as_entire_binding(),
+                },
+            ],
+        });
+
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE),
+                height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        encoder.copy_buffer_to_buffer(&pixels, 0, &output, 0, pixels.size());
+        Recorded {
+            job,
+            output,
+            width,
+            height,
+        }
+    }
+}
+
+/// Overlay `watermark` on the top left corner of `img` on the GPU, for RGB and RGBA
+/// images, with the same result as `image::imageops::overlay` give or take a level
+/// of rounding. Only the pixels covered by the watermark are copied and transferred,
+/// in the submission of the images queued by all workers. Returns `false` when no GPU
+/// is available, the image has another pixel type or compositing failed, `img` is
+/// then unchanged
+pub(crate) fn overlay(img: &mut DynamicImage, watermark: &RgbaImage) -> bool {
+    let Some(gpu) = &*GPU else {
+        return false;
+    };
+    if !matches!(
+        img,
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)
+    ) {
+        return false;
+    }
+    let width = img.width().min(watermark.width());
+    let height = img.height().min(watermark.height());
+    if width == 0 || height == 0 {
+        return true;
+    }
+
+    let task = Task::Overlay {
+        image: img.view(0, 0, width, height).to_image(),
+        watermark: watermark.view(0, 0, width, height).to_image(),
+    };
+    match gpu.run(task) {
+        // converted back to RGB by dropping the alpha channel, opaque over an opaque image
+        Ok(covered) => img.copy_from(&covered, 0, 0).is_ok(),
+        Err(e) => {
+            debug!("GPU compositing failed, falling back to CPU: {e}");
+            false
+        }
+    }
+}
+
+/// `img` resized to exactly `width` x `height` with `filter` on the GPU, for RGB
+/// and RGBA images, with the same result as `DynamicImage::resize_exact` give or take
+/// a level of rounding, in the submission of the images queued by all workers.
+/// `None` when no GPU is available, the image has another pixel type, is empty
+/// or resizing failed
+pub(crate) fn resize(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+) -> Option<DynamicImage> {
+    let gpu = GPU.as_ref()?;
+    let image = match img {
+        DynamicImage::ImageRgb8(_) => img.to_rgba8(),
+        DynamicImage::ImageRgba8(rgba) => rgba.clone(),
+        _ => return None,
+    };
+    if [image.width(), image.height(), width, height].contains(&0) {
+        return None;
+    }
+
+    let task = Task::Resize {
+        image,
+        width,
+        height,
+        filter,
+    };
+    match gpu.run(task) {
+        Ok(resized) => Some(match img {
+            DynamicImage::ImageRgb8(_) => DynamicImage::ImageRgba8(resized).to_rgb8().into(),
+            _ => resized.into(),
+        }),
+        Err(e) => {
+            debug!("GPU resizing failed, falling back to CPU: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::graphics::create_watermark_image;
+    use image::{Rgb, RgbImage, Rgba};
+
+    #[test]
+    fn test_overlay_like_cpu() {
+        let watermark = create_watermark_image(&Config::default()).unwrap();
+        let rgba = RgbaImage::from_pixel(600, 300, Rgba([10, 200, 30, 255]));
+        let mut cpu = rgba.clone();
+        image::imageops::overlay(&mut cpu, &watermark, 0, 0);
+
+        // without GPU, there is nothing to compare
+        let mut img = DynamicImage::ImageRgba8(rgba);
+        if overlay(&mut img, &watermark) {
+            let close = img
+                .as_bytes()
+                .iter()
+                .zip(cpu.as_raw())
+                .all(|(gpu, cpu)| gpu.abs_diff(*cpu) <= 1);
+            assert!(close);
+        }
+    }
+
+    #[test]
+    fn test_resize_like_cpu() {
+        let img: DynamicImage =
+            RgbImage::from_fn(300, 200, |x, y| Rgb([x as u8, y as u8, (x * y) as u8])).into();
+        let filters = [
+            FilterType::Nearest,
+            FilterType::Triangle,
+            FilterType::CatmullRom,
+            FilterType::Gaussian,
+            FilterType::Lanczos3,
+        ];
+        // down and up
+        for (width, height) in [(120, 90), (500, 310)] {
+            for filter in filters {
+                let cpu = img.resize_exact(width, height, filter);
+                // without GPU, there is nothing to compare
+                let Some(gpu) = resize(&img, width, height, filter) else {
+                    continue;
+                };
+                assert_eq!(gpu.color(), cpu.color());
+                assert_eq!((gpu.width(), gpu.height()), (width, height));
+                let close = gpu
+                    .as_bytes()
+                    .iter()
+                    .zip(cpu.as_bytes())
+                    .all(|(gpu, cpu)| gpu.abs_diff(*cpu) <= 2);
+                assert!(close, "{filter:?} to {width}x{height}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        // images of concurrent workers, collected in the same submissions
+        let watermark = create_watermark_image(&Config::default()).unwrap();
+        std::thread::scope(|scope| {
+            for i in 0..8u8 {
+                let watermark = &watermark;
+                scope.spawn(move || {
+                    let rgb = RgbImage::from_pixel(400 + u32::from(i), 300, Rgb([i * 30, 100, 50]));
+                    let img = DynamicImage::ImageRgb8(rgb);
+                    let mut resized = resize(&img, 200, 150, FilterType::Triangle);
+                    if let Some(resized) = &mut resized {
+                        assert!(overlay(resized, watermark));
+                        let cpu = img.resize_exact(200, 150, FilterType::Triangle);
+                        let mut cpu = cpu.to_rgba8();
+                        image::imageops::overlay(&mut cpu, watermark, 0, 0);
+                        let close = resized
+                            .to_rgba8()
+                            .as_raw()
+                            .iter()
+                            .zip(cpu.as_raw())
+                            .all(|(gpu, cpu)| gpu.abs_diff(*cpu) <= 2);
+                        assert!(close);
+                    }
+                });
+            }
+        });
+    }
+}
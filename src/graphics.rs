@@ -1,35 +1,353 @@
 use ab_glyph::FontRef;
-use image::imageops::{overlay, FilterType};
-use image::ImageReader;
-use image::{ImageBuffer, Rgba, RgbaImage};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::metadata::Orientation;
+use image::{ColorType, ImageDecoder, ImageFormat, ImageReader};
+use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
 use imageproc::drawing::draw_text_mut;
 use imageproc::geometric_transformations::{rotate_about_center, Interpolation};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 use crate::config::Config;
+use crate::options::DecodeLimits;
+use crate::processor::{default_chain, Context, Processor};
+
+// Quality of deterministic JPEG encodings, the default of `image`
+const JPEG_QUALITY: u8 = 75;
+
+// Largest number of scaled watermarks kept by a `WatermarkCache`
+const CACHED_WATERMARKS: usize = 16;
+
+// Index of a watermark in its run and of its text, then its final width and height
+type WatermarkKey = ((usize, usize), (u32, u32));
 
 pub fn create_watermark_image(cfg: &Config) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    render_watermark(cfg, &cfg.text)
+}
+
+/// Watermark of `cfg` with `text`, one of its texts (see `Config::texts`)
+pub(crate) fn render_watermark(
+    cfg: &Config,
+    text: &str,
+) -> Result<RgbaImage, Box<dyn std::error::Error>> {
     let mut img: RgbaImage = ImageBuffer::new(500, 500);
 
     // font for watermark
     let font_bytes = include_bytes!("../fonts/Roboto-Bold.ttf");
     let font = FontRef::try_from_slice(font_bytes)?;
 
-    draw_text_mut(&mut img, cfg.color, 0, 210, cfg.scale, &font, &cfg.text);
+    draw_text_mut(&mut img, cfg.color, 0, 210, cfg.scale, &font, text);
 
     // rotate to render text in diagonal
     img = rotate_about_center(&img, 0.8, Interpolation::Bicubic, Rgba([255, 0, 0, 0]));
     Ok(img)
 }
 
+/// Watermarks of a run scaled to the sizes its images need, i.e. by `Rendition::watermark_scale`,
+/// so that they are resized once then shared by the workers of the run.
+/// Once `CACHED_WATERMARKS` are kept, the least recently used one is evicted
+#[derive(Debug, Default)]
+pub(crate) struct WatermarkCache {
+    // least recently used first
+    scaled: Mutex<Vec<(WatermarkKey, Arc<RgbaImage>)>>,
+}
+
+impl WatermarkCache {
+    /// `rendered`, the watermark of index `watermark` in the run, scaled by `scale`
+    pub(crate) fn scaled(
+        &self,
+        watermark: (usize, usize),
+        rendered: &Arc<RgbaImage>,
+        scale: f32,
+    ) -> Arc<RgbaImage> {
+        let size = (
+            ((rendered.width() as f32 * scale).round() as u32).max(1),
+            ((rendered.height() as f32 * scale).round() as u32).max(1),
+        );
+        if size == rendered.dimensions() {
+            return rendered.clone();
+        }
+        let key = (watermark, size);
+        if let Some(scaled) = self.get(&key) {
+            return scaled;
+        }
+
+        // resized without the lock, concurrent workers may resize it twice
+        let scaled = Arc::new(image::imageops::resize(
+            &**rendered,
+            size.0,
+            size.1,
+            image::imageops::FilterType::Triangle,
+        ));
+        let mut cache = self.scaled.lock().expect("poisoned lock");
+        if !cache.iter().any(|(cached, _)| *cached == key) {
+            if cache.len() >= CACHED_WATERMARKS {
+                cache.remove(0);
+            }
+            cache.push((key, scaled.clone()));
+        }
+        scaled
+    }
+
+    // Scaled watermark of `key`, marked as the most recently used
+    fn get(&self, key: &WatermarkKey) -> Option<Arc<RgbaImage>> {
+        let mut cache = self.scaled.lock().expect("poisoned lock");
+        let i = cache.iter().position(|(cached, _)| cached == key)?;
+        let entry = cache.remove(i);
+        let scaled = entry.1.clone();
+        cache.push(entry);
+        Some(scaled)
+    }
+}
+
+/// Watermark of `cfg` on a transparent canvas of `canvas_size` (width, height),
+/// where the default processors put it, i.e. for GUIs to preview settings live
+pub fn preview_watermark(
+    cfg: &Config,
+    canvas_size: (u32, u32),
+) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    let watermark = render_watermark(cfg, &cfg.text)?;
+    let mut canvas = RgbaImage::new(canvas_size.0, canvas_size.1);
+    image::imageops::overlay(&mut canvas, &watermark, 0, 0);
+    Ok(canvas)
+}
+
+/// `img` as watermarked with `cfg` by the default processors, without writing any file,
+/// i.e. for GUIs to preview settings live on a sample image
+pub fn preview_on_image(
+    cfg: &Config,
+    img: &DynamicImage,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    let watermark = render_watermark(cfg, &cfg.text)?;
+    transform_image(Path::new(""), img.clone(), &default_chain(), &watermark)
+}
+
+/// Watermark image `src` into `dst` with the default processors, see `process_image`
 pub fn overlay_watermark<P: AsRef<Path>>(
     src: P,
     dst: P,
     watermark_img: &RgbaImage,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut img = ImageReader::open(src)?.decode()?;
-    img = img.resize_exact(500, 500, FilterType::Nearest);
-    overlay(&mut img, watermark_img, 0, 0);
-    img.save(dst)?;
+    process_image(src, dst, &default_chain(), watermark_img)
+}
+
+/// Decode `src` within the default `DecodeLimits`, run it through `processors`
+/// in order and save the result to `dst`
+pub fn process_image<P: AsRef<Path>>(
+    src: P,
+    dst: P,
+    processors: &[Box<dyn Processor>],
+    watermark_img: &RgbaImage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let input = BufReader::new(File::open(&src)?);
+    let decoder = decoder(src.as_ref(), input, &DecodeLimits::default())?;
+    let img = DynamicImage::from_decoder(decoder)?;
+    transform_image(src.as_ref(), img, processors, watermark_img)?.save(dst)?;
     Ok(())
 }
+
+/// Decode `input`, the content of file `src`, with the format given by its magic bytes
+/// or by its extension, failing beyond `limits`. The pixels of 8-bit images are decoded into `buffer`
+pub(crate) fn decode_image(
+    src: &Path,
+    input: &[u8],
+    mut buffer: Vec<u8>,
+    limits: &DecodeLimits,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    let decoder = decoder(src, Cursor::new(input), limits)?;
+    let (width, height) = decoder.dimensions();
+    let color = decoder.color_type();
+    if !matches!(
+        color,
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8
+    ) {
+        return Ok(DynamicImage::from_decoder(decoder)?);
+    }
+
+    buffer.resize(decoder.total_bytes() as usize, 0);
+    decoder.read_image(&mut buffer)?;
+    let img = match color {
+        ColorType::L8 => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLuma8),
+        ColorType::La8 => {
+            ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageLumaA8)
+        }
+        ColorType::Rgb8 => {
+            ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgb8)
+        }
+        _ => ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8),
+    };
+    Ok(img.ok_or("Decoded image doesn't fit its dimensions")?)
+}
+
+/// Decode `input` like `decode_image`, then rotate and flip it according to its orientation
+/// (i.e. the Exif orientation of a photo taken in portrait), which is returned
+pub(crate) fn decode_oriented(
+    src: &Path,
+    input: &[u8],
+    limits: &DecodeLimits,
+) -> Result<(DynamicImage, Orientation), Box<dyn std::error::Error>> {
+    let mut decoder = decoder(src, Cursor::new(input), limits)?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok((img, orientation))
+}
+
+/// Encode `img` in `format` into `output`. Deterministic encodings pin the parameters
+/// of the JPEG and PNG encoders, instead of following the defaults of `image`
+/// which may change between its versions
+pub(crate) fn encode(
+    img: &DynamicImage,
+    format: ImageFormat,
+    output: &mut Cursor<Vec<u8>>,
+    deterministic: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        ImageFormat::Jpeg if deterministic => {
+            img.write_with_encoder(JpegEncoder::new_with_quality(output, JPEG_QUALITY))?
+        }
+        ImageFormat::Png if deterministic => img.write_with_encoder(
+            PngEncoder::new_with_quality(output, CompressionType::Default, FilterType::Adaptive),
+        )?,
+        _ => img.write_to(output, format)?,
+    }
+    Ok(())
+}
+
+/// `img` encoded as a JPEG thumbnail of at most 160x120, the usual size of Exif thumbnails
+pub(crate) fn encode_thumbnail(img: &DynamicImage) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut thumbnail = Cursor::new(vec![]);
+    img.thumbnail(160, 120)
+        .to_rgb8()
+        .write_to(&mut thumbnail, ImageFormat::Jpeg)?;
+    Ok(thumbnail.into_inner())
+}
+
+/// Dimensions of image `input`, the content of file `src`, read from its header
+pub(crate) fn read_dimensions(
+    src: &Path,
+    input: impl BufRead + Seek,
+) -> Result<(u32, u32), Box<dyn std::error::Error>> {
+    Ok(reader(src, input)?.into_dimensions()?)
+}
+
+/// Size of image `input`, the content of file `src`, once decoded, read from its header
+pub(crate) fn read_decoded_size(
+    src: &Path,
+    input: impl BufRead + Seek,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    Ok(reader(src, input)?.into_decoder()?.total_bytes())
+}
+
+/// Raw Exif data of image `input`, the content of file `src`, if any
+pub(crate) fn read_exif(
+    src: &Path,
+    input: impl BufRead + Seek,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    Ok(reader(src, input)?.into_decoder()?.exif_metadata()?)
+}
+
+/// ICC profile of image `input`, the content of file `src`, if any
+pub(crate) fn read_icc(
+    src: &Path,
+    input: impl BufRead + Seek,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    Ok(reader(src, input)?.into_decoder()?.icc_profile()?)
+}
+
+/// Format of image `input` given by its magic bytes, `None` if not recognized
+pub(crate) fn sniff_format(input: impl BufRead + Seek) -> Option<ImageFormat> {
+    ImageReader::new(input).with_guessed_format().ok()?.format()
+}
+
+/// Reader of `input`, the content of file `src`, with the format given by its magic bytes,
+/// or by the extension of `src` for formats without any (i.e. TGA)
+pub(crate) fn reader<R: BufRead + Seek>(
+    src: &Path,
+    input: R,
+) -> Result<ImageReader<R>, Box<dyn std::error::Error>> {
+    let mut reader = ImageReader::new(input).with_guessed_format()?;
+    if reader.format().is_none() {
+        reader.set_format(ImageFormat::from_path(src)?);
+    }
+    Ok(reader)
+}
+
+// Decoder of `input`, the content of file `src`, once its header is checked against `limits`
+fn decoder<'a, R: BufRead + Seek + 'a>(
+    src: &Path,
+    input: R,
+    limits: &DecodeLimits,
+) -> Result<impl ImageDecoder + 'a, Box<dyn std::error::Error>> {
+    let mut reader = reader(src, input)?;
+    reader.limits(limits.image_limits());
+    let decoder = reader.into_decoder()?;
+    limits.check(decoder.dimensions(), decoder.total_bytes())?;
+    Ok(decoder)
+}
+
+/// Run `img`, decoded from `src`, through `processors` in order
+pub(crate) fn transform_image(
+    src: &Path,
+    mut img: DynamicImage,
+    processors: &[Box<dyn Processor>],
+    watermark_img: &RgbaImage,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    let ctx = Context {
+        path: src,
+        watermark: watermark_img,
+    };
+    for processor in processors {
+        img = processor.process(img, &ctx)?;
+    }
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_previews() {
+        let cfg = Config::default();
+        let preview = preview_watermark(&cfg, (300, 200)).unwrap();
+        assert_eq!(preview.dimensions(), (300, 200));
+        assert!(preview.pixels().any(|pixel| pixel.0[3] > 0));
+
+        let white =
+            |width, height| image::RgbImage::from_pixel(width, height, image::Rgb([255; 3]));
+        let preview = preview_on_image(&cfg, &white(800, 600).into()).unwrap();
+        assert_eq!((preview.width(), preview.height()), (500, 500));
+        assert_ne!(preview.to_rgb8(), white(500, 500));
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache = WatermarkCache::default();
+        let rendered = Arc::new(create_watermark_image(&Config::default()).unwrap());
+        assert!(Arc::ptr_eq(
+            &cache.scaled((0, 0), &rendered, 1.0),
+            &rendered
+        ));
+
+        let half = cache.scaled((0, 0), &rendered, 0.5);
+        assert_eq!(half.dimensions(), (250, 250));
+        assert!(Arc::ptr_eq(&half, &cache.scaled((0, 0), &rendered, 0.5)));
+        // another text of the same size
+        assert!(!Arc::ptr_eq(&half, &cache.scaled((0, 1), &rendered, 0.5)));
+
+        // the least recently used one is evicted, not the whole cache
+        let other = cache.scaled((1, 0), &rendered, 0.5);
+        for i in 4..=CACHED_WATERMARKS {
+            cache.scaled((2, 0), &rendered, i as f32 / 100.0);
+        }
+        cache.scaled((0, 0), &rendered, 0.5);
+        cache.scaled((2, 0), &rendered, 0.02);
+        cache.scaled((2, 0), &rendered, 0.03);
+        assert!(Arc::ptr_eq(&half, &cache.scaled((0, 0), &rendered, 0.5)));
+        assert!(!Arc::ptr_eq(&other, &cache.scaled((1, 0), &rendered, 0.5)));
+    }
+}
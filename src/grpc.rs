@@ -0,0 +1,161 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::config::Config;
+use crate::jobs::{Job, Registry};
+use crate::options::Options;
+use crate::rules::Rules;
+use crate::spread_watermark;
+
+/// Messages, client and server generated from `proto/filigram.proto`
+pub mod proto {
+    tonic::include_proto!("filigram");
+}
+
+use proto::jobs_server::{Jobs, JobsServer};
+use proto::{
+    CancelJobRequest, CancelJobResponse, JobState, Progress, StreamProgressRequest,
+    SubmitJobRequest, SubmitJobResponse,
+};
+
+// Interval between two polls of the progress of a streamed job
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `Jobs` service, running folder jobs in the background
+pub struct JobService {
+    cfg: Arc<Config>,
+    jobs: Registry,
+}
+
+// Progress of `job` so far
+fn job_progress(job: &Job) -> Progress {
+    let (state, report, error) = match job.result() {
+        None => (JobState::Running, String::new(), String::new()),
+        Some(Ok(report)) => {
+            let state = if job.cancel.load(Ordering::Relaxed) {
+                JobState::Cancelled
+            } else {
+                JobState::Done
+            };
+            let report = serde_json::to_string(&report).unwrap_or_default();
+            (state, report, String::new())
+        }
+        Some(Err(e)) => (JobState::Failed, String::new(), e),
+    };
+    Progress {
+        state: state.into(),
+        processed: job.progress.position(),
+        total: job.progress.length().unwrap_or_default(),
+        report,
+        error,
+    }
+}
+
+/// gRPC service of folder jobs, watermarking with `cfg`, see `proto/filigram.proto`:
+/// - `SubmitJob`: runs `spread_watermark` in the background, with paths of the server
+/// - `StreamProgress`: progress of a job, streamed whenever it changes until the job is over,
+///   the last message holds the `Report` of the run
+/// - `CancelJob`: stops the walk of a job, see `Options::cancel`
+///
+/// Jobs are forgotten an hour after they are over
+pub fn service(cfg: Config) -> JobsServer<JobService> {
+    JobsServer::new(JobService {
+        cfg: Arc::new(cfg),
+        jobs: Registry::default(),
+    })
+}
+
+/// Serve the `Jobs` service on `addr`, until the process ends
+pub async fn serve(addr: SocketAddr, cfg: Config) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(service(cfg))
+        .serve(addr)
+        .await
+}
+
+impl JobService {
+    fn job(&self, id: u64) -> Result<Arc<Job>, Status> {
+        let job = self.jobs.get(id);
+        job.ok_or_else(|| Status::not_found(format!("No job {id}")))
+    }
+}
+
+#[tonic::async_trait]
+impl Jobs for JobService {
+    async fn submit_job(
+        &self,
+        request: Request<SubmitJobRequest>,
+    ) -> Result<Response<SubmitJobResponse>, Status> {
+        let request = request.into_inner();
+        let rules: Rules = if request.rules.is_empty() {
+            Rules::default()
+        } else {
+            serde_json::from_str(&request.rules)
+                .map_err(|e| Status::invalid_argument(format!("Invalid rules: {e}")))?
+        };
+
+        let cfg = self.cfg.clone();
+        let id = self.jobs.submit(move |job| {
+            let options = Options {
+                cancel: Some(job.cancel.clone()),
+                ..Default::default()
+            };
+            spread_watermark(
+                &PathBuf::from(request.input),
+                &PathBuf::from(request.output),
+                &cfg,
+                &rules,
+                &options,
+                Some(&job.progress),
+            )
+        });
+        Ok(Response::new(SubmitJobResponse { id }))
+    }
+
+    type StreamProgressStream = ReceiverStream<Result<Progress, Status>>;
+
+    async fn stream_progress(
+        &self,
+        request: Request<StreamProgressRequest>,
+    ) -> Result<Response<Self::StreamProgressStream>, Status> {
+        let job = self.job(request.into_inner().id)?;
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let progress = job_progress(&job);
+                let over = progress.state() != JobState::Running;
+                if last.as_ref() != Some(&progress) {
+                    // the client is gone
+                    if sender.send(Ok(progress.clone())).await.is_err() {
+                        break;
+                    }
+                    last = Some(progress);
+                }
+                if over {
+                    break;
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<CancelJobRequest>,
+    ) -> Result<Response<CancelJobResponse>, Status> {
+        let job = self.job(request.into_inner().id)?;
+        let running = job.is_running();
+        if running {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+        Ok(Response::new(CancelJobResponse { cancelled: running }))
+    }
+}
@@ -0,0 +1,130 @@
+use crate::metadata::Metadata;
+use crate::metrics::Metrics;
+use crate::report::FileReport;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Outcome of the processing of a single file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// File has been watermarked
+    Watermarked,
+    /// File is identical to an already watermarked one,
+    /// its output has been linked to the existing output
+    Deduplicated,
+    /// File has been copied to destination without any change
+    Copied,
+    /// File has been linked in destination,
+    /// see `SymlinkPolicy` and `UnqualifiedPolicy`
+    Linked,
+    /// File has been left out, nothing is written in destination
+    Skipped,
+    /// File processing failed, see `Hooks::on_error` for details
+    Failed,
+    /// File has not been processed, the run was cancelled before, see `Options::cancel`
+    Cancelled,
+}
+
+impl Outcome {
+    /// Name of the outcome, as serialized
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Watermarked => "watermarked",
+            Outcome::Deduplicated => "deduplicated",
+            Outcome::Copied => "copied",
+            Outcome::Linked => "linked",
+            Outcome::Skipped => "skipped",
+            Outcome::Failed => "failed",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Event of a run sent on `Options::events`, i.e. for a GUI to drive its own progress
+/// from another thread. Unlike hooks, events are received on the thread of the caller
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The run started, with its input folder (empty for `watermark_files`
+    /// and `spread_watermark_roots`) and target directory
+    Started { source: PathBuf, target: PathBuf },
+    /// A file has been processed, whatever the outcome, with its report
+    FileDone(FileReport),
+    /// The processing of a file failed, sent just before its `FileDone`
+    Error { path: PathBuf, error: String },
+    /// All files have been processed, with the metrics of the run
+    Finished(Metrics),
+}
+
+/// Called with the source path of a file
+pub type FileHook = Box<dyn Fn(&Path) + Send + Sync>;
+/// Called with the source path of a file and the outcome of its processing
+pub type DoneHook = Box<dyn Fn(&Path, Outcome) + Send + Sync>;
+/// Called with the source path of a file and the error that occurred
+pub type ErrorHook = Box<dyn Fn(&Path, &dyn std::error::Error) + Send + Sync>;
+
+/// Called with the report of a processed file
+pub type ReportHook = Box<dyn Fn(&FileReport) + Send + Sync>;
+
+/// Called with the source path of a watermarked file and the metadata of its output
+pub type MetadataHook = Box<dyn Fn(&Path, &mut Metadata) + Send + Sync>;
+
+/// Per-file lifecycle callbacks.
+/// Every hook is optional, and is called from
+/// the worker thread processing the file,
+/// hence the `Send + Sync` bounds.
+#[derive(Default)]
+pub struct Hooks {
+    /// Called before a file is processed
+    pub on_file_start: Option<FileHook>,
+    /// Called once a file has been processed, whatever the outcome
+    pub on_file_done: Option<DoneHook>,
+    /// Called when the processing of a file failed,
+    /// just before `on_file_done` is called with `Outcome::Failed`
+    pub on_error: Option<ErrorHook>,
+    /// Called just after `on_file_done`, with the complete report of the file
+    /// (output, duration, error...)
+    pub on_report: Option<ReportHook>,
+    /// Called before a watermarked image is written, to edit its metadata
+    /// (see `Metadata::set_field`) in the same pass
+    pub on_metadata: Option<MetadataHook>,
+}
+
+impl Hooks {
+    pub(crate) fn file_start(&self, path: &Path) {
+        if let Some(hook) = &self.on_file_start {
+            hook(path);
+        }
+    }
+
+    pub(crate) fn file_done(&self, path: &Path, outcome: Outcome) {
+        if let Some(hook) = &self.on_file_done {
+            hook(path, outcome);
+        }
+    }
+
+    pub(crate) fn report(&self, report: &FileReport) {
+        if let Some(hook) = &self.on_report {
+            hook(report);
+        }
+    }
+
+    pub(crate) fn error(&self, path: &Path, error: &dyn std::error::Error) {
+        if let Some(hook) = &self.on_error {
+            hook(path, error);
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_file_start", &self.on_file_start.is_some())
+            .field("on_file_done", &self.on_file_done.is_some())
+            .field("on_error", &self.on_error.is_some())
+            .field("on_report", &self.on_report.is_some())
+            .field("on_metadata", &self.on_metadata.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,95 @@
+use ignore::gitignore::Gitignore;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::trace::warn;
+
+/// Name of the ignore files honored when `Rules::ignore_files` is set
+pub const IGNORE_FILE: &str = ".filigramignore";
+
+/// Ignore files of the directories met during a run, loaded once
+#[derive(Debug, Default)]
+pub(crate) struct Ignores {
+    loaded: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+}
+
+impl Ignores {
+    /// File `path`, at `relative_path` under the input folder, is ignored
+    /// by the ignore files of the input folder or of one of its subdirectories.
+    /// As with gitignore, deeper files take precedence and
+    /// files of an ignored directory can't be re-included
+    pub(crate) fn is_ignored(&self, path: &Path, relative_path: &Path) -> bool {
+        let depth = relative_path.components().count();
+        let Some(root) = path.ancestors().nth(depth) else {
+            return false;
+        };
+
+        // each directory down to the file itself, matched by the ignore files above it
+        let mut current = root.to_owned();
+        for (i, comp) in relative_path.components().enumerate() {
+            current.push(comp);
+            let is_dir = i + 1 < depth;
+            let ignored = current
+                .ancestors()
+                .skip(1)
+                .take(i + 1)
+                .filter_map(|dir| self.load(dir))
+                .find_map(|gitignore| {
+                    let matched = gitignore.matched(&current, is_dir);
+                    (!matched.is_none()).then(|| matched.is_ignore())
+                });
+            if ignored == Some(true) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Ignore file of `dir`, `None` if there is none
+    fn load(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let mut loaded = self.loaded.lock().expect("poisoned lock");
+        loaded
+            .entry(dir.to_owned())
+            .or_insert_with(|| {
+                let file = dir.join(IGNORE_FILE);
+                if !file.is_file() {
+                    return None;
+                }
+                let (gitignore, error) = Gitignore::new(&file);
+                if let Some(e) = error {
+                    warn!("invalid patterns in {file:?}: {e}");
+                }
+                Some(Arc::new(gitignore))
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ignores, IGNORE_FILE};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_is_ignored() {
+        let root = Path::new("tmp/ignores");
+        fs::remove_dir_all(root).ok();
+        fs::create_dir_all(root.join("raw/keep")).unwrap();
+        fs::create_dir_all(root.join("shoot")).unwrap();
+        fs::write(root.join(IGNORE_FILE), "raw/\n*.png\n").unwrap();
+        fs::write(root.join("shoot").join(IGNORE_FILE), "!cover.png\n").unwrap();
+        fs::write(root.join("raw/keep").join(IGNORE_FILE), "!*\n").unwrap();
+
+        let ignores = Ignores::default();
+        let is_ignored =
+            |relative: &str| ignores.is_ignored(&root.join(relative), relative.as_ref());
+        assert!(!is_ignored("pic.jpg"));
+        assert!(is_ignored("pic.png"));
+        assert!(is_ignored("raw/pic.jpg"));
+        assert!(is_ignored("shoot/pic.png"));
+        assert!(!is_ignored("shoot/cover.png"));
+        assert!(is_ignored("raw/keep/pic.jpg"));
+    }
+}
@@ -0,0 +1,177 @@
+use indicatif::{ProgressBar, ProgressDrawTarget};
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+#[cfg(feature = "grpc")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::report::Report;
+
+// How long finished jobs are kept for their status to be queried
+const RETENTION: Duration = Duration::from_secs(3600);
+
+/// Folder job of the HTTP or gRPC service, running in the background
+pub(crate) struct Job {
+    /// Progress of the run
+    pub(crate) progress: ProgressBar,
+    /// Set to stop the run, see `Options::cancel`
+    #[cfg(feature = "grpc")]
+    pub(crate) cancel: Arc<AtomicBool>,
+    // set once the run is over, with the time it ended
+    result: Mutex<Option<(Result<Report, String>, Instant)>>,
+}
+
+impl Job {
+    fn new() -> Self {
+        Self {
+            progress: ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden()),
+            #[cfg(feature = "grpc")]
+            cancel: Arc::new(AtomicBool::new(false)),
+            result: Mutex::new(None),
+        }
+    }
+
+    /// Report of the run or its error, once over
+    pub(crate) fn result(&self) -> Option<Result<Report, String>> {
+        let result = self.result.lock().unwrap();
+        result.as_ref().map(|(result, _)| result.clone())
+    }
+
+    /// The run is not over yet
+    #[cfg(feature = "grpc")]
+    pub(crate) fn is_running(&self) -> bool {
+        self.result.lock().unwrap().is_none()
+    }
+
+    // The job is over since longer than `retention`
+    fn is_expired(&self, retention: Duration) -> bool {
+        let result = self.result.lock().unwrap();
+        result
+            .as_ref()
+            .is_some_and(|(_, end)| end.elapsed() > retention)
+    }
+}
+
+/// Jobs of a service by id, finished ones being evicted after a while
+pub(crate) struct Registry {
+    jobs: Mutex<HashMap<u64, Arc<Job>>>,
+    next_id: AtomicU64,
+    retention: Duration,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            retention: RETENTION,
+        }
+    }
+}
+
+impl Registry {
+    /// Start a job running `run` on a blocking thread of the runtime, return its id.
+    /// The job fails with the error of `run`, or if it panics
+    pub(crate) fn submit<F>(&self, run: F) -> u64
+    where
+        F: FnOnce(&Job) -> Result<Report, Box<dyn std::error::Error>> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let job = Arc::new(Job::new());
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.retain(|_, job| !job.is_expired(self.retention));
+            jobs.insert(id, job.clone());
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let result = match catch_unwind(AssertUnwindSafe(|| run(&job))) {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(panic) => Err(format!("job panicked: {}", panic_message(&*panic))),
+            };
+            *job.result.lock().unwrap() = Some((result, Instant::now()));
+        });
+        id
+    }
+
+    /// Job `id`, unless unknown or evicted
+    pub(crate) fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+}
+
+// Message given to `panic!`, if any
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Job, Registry};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Wait for job `id` of `registry` to be over
+    fn wait(registry: &Registry, id: u64) -> Arc<Job> {
+        let job = registry.get(id).unwrap();
+        while job.result().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        job
+    }
+
+    #[test]
+    fn test_panic() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let registry = Registry::default();
+
+        let id = registry.submit(|_| panic!("boom"));
+        let job = wait(&registry, id);
+        assert_eq!(job.result().unwrap().unwrap_err(), "job panicked: boom");
+
+        let id = registry.submit(|_| Err("no folder".into()));
+        let job = wait(&registry, id);
+        assert_eq!(job.result().unwrap().unwrap_err(), "no folder");
+    }
+
+    #[test]
+    fn test_eviction() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let registry = Registry {
+            retention: Duration::ZERO,
+            ..Default::default()
+        };
+
+        let first = registry.submit(|_| Err("failed".into()));
+        wait(&registry, first);
+        std::thread::sleep(Duration::from_millis(10));
+        let release = Arc::new(AtomicBool::new(false));
+        let second = registry.submit({
+            let release = release.clone();
+            move |_| {
+                while !release.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err("released".into())
+            }
+        });
+        assert!(registry.get(first).is_none());
+
+        // running jobs are kept
+        registry.submit(|_| Err("failed".into()));
+        assert!(registry.get(second).is_some());
+        release.store(true, Ordering::Relaxed);
+        wait(&registry, second);
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::hooks::Outcome;
+use crate::report::FileReport;
+
+/// Name of the journal file written in target directory, see `Options::journal`
+pub const JOURNAL_FILE: &str = ".filigram-journal.jsonl";
+
+/// Files completed during previous (interrupted) runs and the current one,
+/// persisted as one JSON `FileReport` per line
+#[derive(Debug)]
+pub(crate) struct Journal {
+    path: PathBuf,
+    folder: PathBuf,
+    file: Mutex<File>,
+    // reports of previous runs, by source path relative to input folder
+    done: HashMap<PathBuf, FileReport>,
+}
+
+impl Journal {
+    /// Open the journal of `target_dir`, loading reports of
+    /// files of `folder` already completed
+    pub(crate) fn open(folder: &Path, target_dir: &Path) -> std::io::Result<Self> {
+        let path = target_dir.join(JOURNAL_FILE);
+
+        let mut done = HashMap::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                // a crash may leave a truncated last line
+                let Ok(report) = serde_json::from_str::<FileReport>(&line?) else {
+                    continue;
+                };
+                if let Ok(relative_path) = report.source.strip_prefix(folder) {
+                    done.insert(relative_path.to_owned(), report);
+                }
+            }
+        }
+
+        fs::create_dir_all(target_dir)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            folder: folder.to_owned(),
+            file: Mutex::new(file),
+            done,
+        })
+    }
+
+    /// Report of a previous run for file `path`, if completed
+    pub(crate) fn done(&self, path: &Path) -> Option<&FileReport> {
+        let relative_path = path.strip_prefix(&self.folder).ok()?;
+        self.done.get(relative_path)
+    }
+
+    /// Reports of files completed by previous runs
+    pub(crate) fn reports(&self) -> impl Iterator<Item = &FileReport> {
+        self.done.values()
+    }
+
+    /// Record `report` if its file is completed, failed and cancelled files are left to retry
+    pub(crate) fn record(&self, report: &FileReport) -> std::io::Result<()> {
+        if matches!(report.outcome, Outcome::Failed | Outcome::Cancelled) {
+            return Ok(());
+        }
+
+        let mut line = serde_json::to_vec(report)?;
+        line.push(b'\n');
+        // a single write per line keeps lines whole between workers
+        self.file.lock().expect("poisoned lock").write_all(&line)
+    }
+
+    /// Remove the journal, once all files are completed
+    pub(crate) fn remove(self) -> std::io::Result<()> {
+        drop(self.file);
+        fs::remove_file(self.path)
+    }
+}
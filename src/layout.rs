@@ -0,0 +1,180 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Layout of the files written in target directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Mirror the hierarchy of the input folder
+    #[default]
+    Mirror,
+    /// Write all files directly in target directory.
+    /// On name collision, files are renamed with a counter
+    /// i.e.: "a/photo.jpg" and "b/photo.jpg" are written as "photo.jpg" and "photo-1.jpg"
+    Flat,
+}
+
+impl Layout {
+    /// Path of the output of file `path`, at `relative_path` in its input folder,
+    /// relative to target directory. Only the normal components of `relative_path` are kept,
+    /// so that absolute paths given to `watermark_files` are written under target directory.
+    /// Fails for a path without file name, i.e. ".." or "/"
+    pub(crate) fn output_path(
+        self,
+        path: &Path,
+        relative_path: &Path,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let output = match self {
+            Layout::Mirror => relative_path
+                .components()
+                .filter(|comp| matches!(comp, Component::Normal(_)))
+                .collect(),
+            Layout::Flat => path.file_name().map(PathBuf::from).unwrap_or_default(),
+        };
+        if output.as_os_str().is_empty() {
+            return Err(format!("can't retrieve filename of {path:?}").into());
+        }
+        Ok(output)
+    }
+}
+
+/// Where the files of each input folder are written in target directory,
+/// see `spread_watermark_roots`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Roots {
+    /// Write files of each folder under a subdirectory named after it
+    /// i.e.: files of "a/photos" and "b/videos" are written
+    /// under "photos" and "videos" in target directory
+    #[default]
+    Subdirectories,
+    /// Write files of all folders directly in target directory,
+    /// as if they were a single folder
+    Merged,
+}
+
+/// What is done with a file whose output path only differs by case from the output
+/// of another file of the run, i.e. "Photo.JPG" and "photo.jpg" of the same folder,
+/// which overwrite each other when the target directory is on a case-insensitive
+/// filesystem (macOS, Windows). The first file met is written as usual
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseCollisions {
+    /// Write it anyway, as on a case-sensitive filesystem
+    #[default]
+    Ignore,
+    /// Fail it, with the path it collides with
+    Fail,
+    /// Rename it with a counter, i.e. "photo-1.jpg"
+    Suffix,
+    /// Skip it, with the path it collides with
+    Skip,
+}
+
+/// Paths of the files already written during a run
+#[derive(Debug, Default)]
+pub(crate) struct Names {
+    taken: Mutex<HashSet<PathBuf>>,
+    // paths claimed by `claim_folded`, by their lowercase form
+    folded: Mutex<HashMap<PathBuf, PathBuf>>,
+}
+
+impl Names {
+    /// Mark `path` as taken, i.e. written by a previous run
+    pub(crate) fn insert(&self, path: PathBuf) {
+        self.taken.lock().expect("poisoned lock").insert(path);
+    }
+
+    /// Reserve `path` or, if already taken, the first free
+    /// path amongst "stem-1.ext", "stem-2.ext"...
+    pub(crate) fn reserve(&self, path: PathBuf) -> PathBuf {
+        let mut taken = self.taken.lock().expect("poisoned lock");
+        if taken.insert(path.clone()) {
+            return path;
+        }
+
+        numbered(&path)
+            .find(|candidate| taken.insert(candidate.clone()))
+            .expect("no free name")
+    }
+
+    /// Claim `path`, unless another path with the same lowercase form has been claimed:
+    /// then that path is returned. A path can be claimed several times
+    pub(crate) fn claim_folded(&self, path: &Path) -> Option<PathBuf> {
+        let mut folded = self.folded.lock().expect("poisoned lock");
+        match folded.entry(fold(path)) {
+            Entry::Occupied(entry) if entry.get() != path => Some(entry.get().clone()),
+            Entry::Occupied(_) => None,
+            Entry::Vacant(entry) => {
+                entry.insert(path.to_owned());
+                None
+            }
+        }
+    }
+
+    /// Claim `path` as `claim_folded` or, if another path with the same lowercase form
+    /// has been claimed, the first free path amongst "stem-1.ext", "stem-2.ext"...
+    pub(crate) fn reserve_folded(&self, path: &Path) -> PathBuf {
+        if self.claim_folded(path).is_none() {
+            return path.to_owned();
+        }
+        numbered(path)
+            .find(|candidate| self.claim_folded(candidate).is_none())
+            .expect("no free name")
+    }
+}
+
+// "stem-1.ext", "stem-2.ext"... in the directory of `path`
+fn numbered(path: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    let stem = path.file_stem().unwrap_or_default().to_owned();
+    let extension = path.extension().map(|ext| ext.to_owned());
+    (1..).map(move |i| {
+        let mut name = OsString::from(&stem);
+        name.push(format!("-{i}"));
+        if let Some(extension) = &extension {
+            name.push(".");
+            name.push(extension);
+        }
+        path.with_file_name(name)
+    })
+}
+
+// Lowercase form of `path`, as compared by case-insensitive filesystems
+fn fold(path: &Path) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Names;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_reserve() {
+        let names = Names::default();
+        let reserve = |path: &str| names.reserve(PathBuf::from(path));
+        assert_eq!(reserve("out/photo.jpg"), PathBuf::from("out/photo.jpg"));
+        assert_eq!(reserve("out/photo.jpg"), PathBuf::from("out/photo-1.jpg"));
+        assert_eq!(
+            reserve("out/photo-1.jpg"),
+            PathBuf::from("out/photo-1-1.jpg")
+        );
+        assert_eq!(reserve("out/photo.jpg"), PathBuf::from("out/photo-2.jpg"));
+        assert_eq!(reserve("out/README"), PathBuf::from("out/README"));
+        assert_eq!(reserve("out/README"), PathBuf::from("out/README-1"));
+    }
+
+    #[test]
+    fn test_reserve_folded() {
+        let names = Names::default();
+        let reserve = |path: &str| names.reserve_folded(path.as_ref());
+        assert_eq!(reserve("out/Photo.JPG"), PathBuf::from("out/Photo.JPG"));
+        assert_eq!(reserve("out/Photo.JPG"), PathBuf::from("out/Photo.JPG"));
+        assert_eq!(reserve("out/photo.jpg"), PathBuf::from("out/photo-1.jpg"));
+        assert_eq!(reserve("OUT/photo.jpg"), PathBuf::from("OUT/photo-2.jpg"));
+        assert_eq!(
+            names.claim_folded("out/PHOTO-1.JPG".as_ref()),
+            Some(PathBuf::from("out/photo-1.jpg"))
+        );
+    }
+}
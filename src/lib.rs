@@ -1,22 +1,122 @@
-use core::sync::atomic::{AtomicU64, Ordering};
-use img_parts::webp::WebP;
-use img_parts::{jpeg::Jpeg, png::Png};
-use img_parts::{ImageEXIF, ImageICC};
-use log::{debug, error};
-use rayon::prelude::*;
-use std::fs::OpenOptions;
-use std::{fs, path::Path};
+use bytes::Bytes;
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "walkdir")]
+use std::sync::{mpsc, Mutex};
+use std::time::Instant;
+#[cfg(feature = "walkdir")]
 use walkdir::WalkDir;
 
+use gallery::write_galleries;
+use journal::Journal;
+use run::{Run, Writes};
+use similar::flag_duplicates;
+#[cfg(feature = "walkdir")]
+use trace::warn;
+use trace::{debug, error, FileSpan, RunSpan};
+
+// `zip` and `s3` features imply `walkdir`
+#[cfg(any(feature = "walkdir", feature = "tar"))]
+mod archive;
+mod blend;
+mod buffers;
+mod color;
 pub mod config;
+#[cfg(feature = "c2pa")]
+mod credentials;
+mod dedup;
+mod exif;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod gallery;
+#[cfg(feature = "gpu")]
+mod gpu;
 mod graphics;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hooks;
+#[cfg(feature = "walkdir")]
+mod ignores;
+#[cfg(any(feature = "server", feature = "grpc"))]
+mod jobs;
+pub mod journal;
+pub mod layout;
+pub mod metadata;
+pub mod metrics;
+pub mod naming;
+// `napi` only registers the exports outside of tests, leaving them unused there
+#[cfg(all(feature = "node", not(test)))]
+mod node;
+pub mod options;
+#[cfg(feature = "walkdir")]
+pub mod plan;
+pub mod processor;
+#[cfg(not(feature = "indicatif"))]
+mod progress;
+mod quarantine;
+pub mod rendition;
+pub mod report;
 pub mod rules;
+mod run;
+#[cfg(feature = "s3")]
+mod s3;
+pub mod saliency;
+#[cfg(feature = "server")]
+pub mod server;
+mod similar;
+pub mod sink;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod trace;
+#[cfg(feature = "ffmpeg")]
+mod video;
+#[cfg(feature = "walkdir")]
+mod watch;
 
-pub use config::Config;
-pub use graphics::{create_watermark_image, overlay_watermark};
+#[cfg(feature = "walkdir")]
+pub use archive::spread_watermark_to;
+#[cfg(feature = "tar")]
+pub use archive::watermark_tar;
+pub use buffers::BufferPool;
+pub use color::ColorPolicy;
+pub use config::{Config, TextRotation};
+#[cfg(feature = "c2pa")]
+pub use credentials::{ContentCredentials, SigningAlg};
+pub use dedup::DuplicatePolicy;
+pub use gallery::GALLERY_INDEX;
+pub use graphics::{create_watermark_image, overlay_watermark, process_image};
+pub use graphics::{preview_on_image, preview_watermark};
+pub use hooks::{Event, Hooks, Outcome};
+#[cfg(feature = "walkdir")]
+pub use ignores::IGNORE_FILE;
+pub use image;
+#[cfg(feature = "indicatif")]
 pub use indicatif;
-pub use rules::Rules;
+pub use layout::{CaseCollisions, Layout, Roots};
+pub use metadata::{is_watermarked, read_metadata, ImageMetadata, Metadata, MetadataError};
+pub use metadata::{MetadataAction, MetadataPolicy, ThumbnailAction};
+pub use metrics::{Metrics, Timings};
+pub use naming::Naming;
+pub use options::{
+    DecodeLimits, NestedTargetPolicy, Options, ReadAhead, Sample, SidecarPolicy, UnqualifiedPolicy,
+};
+#[cfg(feature = "walkdir")]
+pub use plan::{apply_plan, plan_watermark, Action, Plan, PlannedFile};
+pub use processor::Processor;
+#[cfg(not(feature = "indicatif"))]
+pub use progress::ProgressBar;
+pub use quarantine::{ERROR_NOTE_EXTENSION, QUARANTINE_DIR};
+pub use regex;
+pub use rendition::Rendition;
+pub use report::{Duplicate, FileReport, Manifest, Report};
+pub use rules::{Predicate, Rules, RulesBuilder, SymlinkPolicy, DEFAULT_EXTENSIONS};
+pub use sink::{FsSink, OutputSink};
+#[cfg(feature = "ffmpeg")]
+pub use video::VIDEO_EXTENSIONS;
+#[cfg(feature = "walkdir")]
+pub use watch::watch;
 
+#[cfg(feature = "indicatif")]
 use indicatif::ProgressBar;
 
 /// Apply recursively a watermark.
@@ -24,204 +124,729 @@ use indicatif::ProgressBar;
 /// Input `folder` will be traversed, output data will be written in `target_dir`.
 /// The watermark is customized through the `Config` struct.
 /// The choice of which files/dirs are read or skipped is defined in `Rules` struct.
-/// The progression is reported through a given `ProgressBar` struct.
+/// The run itself is driven by `Options`, e.g. to be notified of each file processed
+/// or to customize the chain of `Processor`s applied to each image.
+/// The progression is reported through a given `ProgressBar` struct,
+/// whose length grows as the input folder is walked.
+/// Once done, a `Report` describes what happened to each file.
+///
+/// With the `zip` feature, `folder` may also be a ZIP archive,
+/// and `target_dir` a ZIP archive to create (see its ".zip" extension)
+/// instead of a directory.
+/// With the `s3` feature, `folder` or `target_dir` may be
+/// a `s3://bucket/prefix` URI.
+/// Outputs may be written to other stores with `spread_watermark_to`.
 ///
-/// The processing is multithreaded thanks to `rayon` crate
+/// With the `rayon` feature, the processing is multithreaded thanks to `rayon` crate,
+/// files are processed while the walk goes on
+#[cfg(feature = "walkdir")]
 pub fn spread_watermark<P: AsRef<Path> + std::fmt::Debug + Sync>(
     folder: &P,
     target_dir: &P,
     cfg: &Config,
     rules: &Rules,
+    options: &Options,
     progress: Option<&ProgressBar>,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    #[cfg(feature = "s3")]
+    if s3::is_s3(folder.as_ref(), target_dir.as_ref()) {
+        return s3::spread_s3(
+            folder.as_ref(),
+            target_dir.as_ref(),
+            cfg,
+            rules,
+            options,
+            progress,
+        );
+    }
+    #[cfg(feature = "zip")]
+    if folder.as_ref().is_file() && archive::is_zip(folder.as_ref()) {
+        return archive::spread_zip(
+            folder.as_ref(),
+            target_dir.as_ref(),
+            cfg,
+            rules,
+            options,
+            progress,
+        );
+    }
+
+    let start = Instant::now();
+    let (folder, target_dir) = (folder.as_ref(), target_dir.as_ref());
+    check_dir(folder)?;
+
+    let span = RunSpan::new(folder, target_dir);
+    let run = Run::new(cfg, rules, options)?.quarantined_in(target_dir);
+    let journal = open_journal(&run, folder, target_dir)?;
+    options.emit(|| Event::Started {
+        source: folder.to_owned(),
+        target: target_dir.to_owned(),
+    });
+    let roots = [(folder.to_owned(), target_dir.to_owned())];
+    let files = spread_roots(&run, &span, journal.as_ref(), &roots, progress)?;
+
+    complete(options, journal, files, start)
+}
+
+/// Apply recursively a watermark to several input `folders` in one run,
+/// sharing the rendering of the watermark, the progress bar and the report.
+///
+/// Files of each folder are written in `target_dir` as in `spread_watermark`,
+/// either under a subdirectory named after the folder, or all merged together,
+/// see `Options::roots`. When merged, files with the same path relative
+/// to their folder overwrite each other, unless `Layout::Flat` is used
+#[cfg(feature = "walkdir")]
+pub fn spread_watermark_roots<P, T>(
+    folders: &[P],
+    target_dir: &T,
+    cfg: &Config,
+    rules: &Rules,
+    options: &Options,
+    progress: Option<&ProgressBar>,
+) -> Result<Report, Box<dyn std::error::Error>>
+where
+    P: AsRef<Path>,
+    T: AsRef<Path> + ?Sized,
+{
+    let start = Instant::now();
+    let target_dir = target_dir.as_ref();
+
+    let mut roots: Vec<(PathBuf, PathBuf)> = Vec::with_capacity(folders.len());
+    for folder in folders {
+        let folder = folder.as_ref();
+        check_dir(folder)?;
+
+        let target = match options.roots {
+            Roots::Merged => target_dir.to_owned(),
+            Roots::Subdirectories => {
+                let name = folder
+                    .canonicalize()?
+                    .file_name()
+                    .ok_or_else(|| format!("Path {folder:?} has no name for a subdirectory"))?
+                    .to_owned();
+                let target = target_dir.join(name);
+                if let Some((other, _)) = roots.iter().find(|(_, other)| *other == target) {
+                    return Err(format!(
+                        "Paths {other:?} and {folder:?} would both be written in {target:?}"
+                    )
+                    .into());
+                }
+                target
+            }
+        };
+        roots.push((folder.to_owned(), target));
+    }
+
+    let span = RunSpan::new(Path::new(""), target_dir);
+    let run = Run::new(cfg, rules, options)?.quarantined_in(target_dir);
+    let journal = open_journal(&run, Path::new(""), target_dir)?;
+    options.emit(|| Event::Started {
+        source: PathBuf::new(),
+        target: target_dir.to_owned(),
+    });
+    let files = spread_roots(&run, &span, journal.as_ref(), &roots, progress)?;
+
+    complete(options, journal, files, start)
+}
+
+/// Apply a watermark to each file of `paths`, instead of walking a folder.
+///
+/// With `Layout::Mirror`, outputs are written in `target_dir` under
+/// the path of their source, i.e. "photos/pic.jpg" and "/data/pic.jpg"
+/// are written as "photos/pic.jpg" and "data/pic.jpg" in `target_dir`.
+/// With `Layout::Flat`, they are written directly in `target_dir`.
+/// Otherwise files are handled as in `spread_watermark`,
+/// `rules.max_depth` aside
+pub fn watermark_files<I, T>(
+    paths: I,
+    target_dir: &T,
+    cfg: &Config,
+    rules: &Rules,
+    options: &Options,
+    progress: Option<&ProgressBar>,
+) -> Result<Report, Box<dyn std::error::Error>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<Path> + Send,
+    I::IntoIter: Send,
+    T: AsRef<Path> + ?Sized,
+{
+    let start = Instant::now();
+    let target_dir = target_dir.as_ref();
+    let span = RunSpan::new(Path::new(""), target_dir);
+    let run = Run::new(cfg, rules, options)?.quarantined_in(target_dir);
+    let journal = open_journal(&run, Path::new(""), target_dir)?;
+    options.emit(|| Event::Started {
+        source: PathBuf::new(),
+        target: target_dir.to_owned(),
+    });
+    fs::create_dir_all(target_dir)?;
+
+    let paths = paths
+        .into_iter()
+        .filter(|path| !(rules.symlinks == SymlinkPolicy::Skip && path.as_ref().is_symlink()))
+        .inspect(|_| {
+            if let Some(progress) = progress {
+                progress.inc_length(1);
+            }
+        });
+    let files = run.map(paths, |path| {
+        let path = path.as_ref();
+        let target_path = options.layout.output_path(path, path).map(|output| {
+            let target_path = target_dir.join(output);
+            // a failure surfaces when the output is written
+            if let Some(parent) = target_path.parent() {
+                fs::create_dir_all(parent).ok();
+            }
+            target_path
+        });
+
+        let report = handle_file(&run, &span, journal.as_ref(), path, path, target_path, None);
+        if let Some(progress) = progress {
+            progress.inc(1);
+        }
+        report
+    });
+
+    complete(options, journal, files, start)
+}
+
+/// Apply a watermark to image `input`, encoded in the same format.
+/// The image goes through `Options::processors` and gets the metadata
+/// of `input` as in `spread_watermark`, other options about files and runs
+/// (layout, naming, renditions, journal...) don't apply
+pub fn watermark_bytes(
+    input: &[u8],
+    cfg: &Config,
+    options: &Options,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let rules = Rules::default();
+    let run = Run::new(cfg, &rules, options)?;
+    let input = input.to_vec().into();
+    let (_, output, _) = run.watermark(Path::new(""), (0, 0), input, &[], |_, _| PathBuf::new())?;
+    Ok(output.into())
+}
+
+/// Apply a watermark to image `src` and write it to `dst`, in the format
+/// of its extension, as `watermark_bytes` does
+pub fn watermark_file<P: AsRef<Path>, T: AsRef<Path>>(
+    src: P,
+    dst: T,
+    cfg: &Config,
+    options: &Options,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if !folder.as_ref().is_dir() {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    let rules = Rules::default();
+    let run = Run::new(cfg, &rules, options)?;
+    let input = fs::read(src)?.into();
+    let (_, output, _) = run.watermark(src, (0, 0), input, &[], |_, _| dst.to_owned())?;
+    fs::write(dst, output)?;
+    Ok(())
+}
+
+// Error if `folder` is not a directory
+#[cfg(feature = "walkdir")]
+fn check_dir(folder: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if !folder.is_dir() {
         return Err(Box::new(std::io::Error::new(
             std::io::ErrorKind::NotFound,
             format!("Path {folder:?} is not a directory as required"),
         )));
     }
+    Ok(())
+}
 
-    let watermark_img = create_watermark_image(cfg)?;
+// Walk each root folder and process its files into its target directory
+#[cfg(feature = "walkdir")]
+fn spread_roots(
+    run: &Run,
+    span: &RunSpan,
+    journal: Option<&Journal>,
+    roots: &[(PathBuf, PathBuf)],
+    progress: Option<&ProgressBar>,
+) -> Result<Vec<FileReport>, Box<dyn std::error::Error>> {
+    let (rules, options) = (run.rules, run.options);
 
-    let counter = AtomicU64::new(0);
-    let entries = WalkDir::new(folder)
-        .into_iter()
-        .collect::<Result<Vec<walkdir::DirEntry>, walkdir::Error>>()?;
-    let nb_entries = entries.len() as u64;
-    if let Some(progress) = progress {
-        progress.set_length(nb_entries);
-    }
-
-    // create directory structure first
-    entries
-        .par_iter()
-        .filter(|entry| entry.path().is_dir())
-        .for_each(|entry| {
-            let path = entry.path();
-            let relative_path = path.strip_prefix(folder).expect("can't strip prefix");
-            let new_dir = target_dir.as_ref().join(relative_path);
-            fs::create_dir_all(new_dir).expect("error creating dir");
-
-            if progress.is_some() {
-                counter.fetch_add(1, Ordering::Relaxed);
+    if options.layout == Layout::Flat {
+        for (_, target_dir) in roots {
+            fs::create_dir_all(target_dir)?;
+        }
+    }
+
+    let nested_targets = nested_targets(roots, options.nested_target)?;
+    let walk = |folder| walk_folder(folder, rules, options.deterministic, &nested_targets);
+
+    // Entries are streamed to workers while the walk goes on,
+    // so the progress length grows as entries are discovered.
+    // The walk stops at first error, which is returned once workers are done.
+    let walk_error: Mutex<Option<Box<dyn std::error::Error + Send + Sync>>> = Mutex::new(None);
+    let entries = roots
+        .iter()
+        .flat_map(|(folder, target_dir)| walk(folder).map(move |entry| (folder, target_dir, entry)))
+        .filter(|(_, _, entry)| {
+            !(rules.symlinks == SymlinkPolicy::Skip
+                && entry.as_ref().is_ok_and(|entry| entry.path_is_symlink()))
+        })
+        .map_while(|(folder, target_dir, entry)| {
+            entry
+                .map(|entry| (folder, target_dir, entry))
+                .map_err(|e| *walk_error.lock().expect("poisoned lock") = Some(e.into()))
+                .ok()
+        })
+        .take_while(|_| !run.cancelled())
+        .inspect(|_| {
+            if let Some(progress) = progress {
+                progress.inc_length(1);
+            }
+        })
+        // directories are created by the walk itself, before their content is yielded
+        .filter(|(folder, target_dir, entry)| {
+            if !entry.file_type().is_dir() {
+                return true;
+            }
+            // dirs at max depth are not traversed, don't create them
+            if options.layout == Layout::Mirror
+                && rules.max_depth.is_none_or(|max| entry.depth() < max)
+            {
+                let relative_path = entry
+                    .path()
+                    .strip_prefix(folder)
+                    .expect("can't strip prefix");
+                if let Err(e) = fs::create_dir_all(target_dir.join(relative_path)) {
+                    *walk_error.lock().expect("poisoned lock") = Some(e.into());
+                }
+            }
+            if let Some(progress) = progress {
+                progress.inc(1);
             }
+            false
+        });
+    let entries: Box<dyn Iterator<Item = _> + Send> = if options.largest_first {
+        let mut entries: Vec<_> = entries.collect();
+        // entries whose size is unknown fail as soon as they are processed
+        entries.sort_by_cached_key(|(_, _, entry)| {
+            std::cmp::Reverse(entry.metadata().map_or(u64::MAX, |metadata| metadata.len()))
         });
+        Box::new(entries.into_iter())
+    } else {
+        Box::new(entries)
+    };
+
+    // handle files, on the workers of the run
+    let done = || {
+        if let Some(progress) = progress {
+            progress.inc(1);
+        }
+    };
+    let files = match options.read_ahead {
+        Some(read_ahead) => map_read_ahead(run, span, journal, entries, read_ahead, done),
+        None => run.map(entries, |entry| {
+            let (path, relative_path) = entry_paths(&entry);
+            let target_path = entry_target(options, &entry);
+            let report = handle_file(run, span, journal, path, relative_path, target_path, None);
+            done();
+            report
+        }),
+    };
 
-    if let Some(progress) = progress {
-        let c = counter.fetch_add(1, Ordering::Relaxed);
-        progress.set_position(c);
+    match walk_error.into_inner().expect("poisoned lock") {
+        Some(e) => Err(e),
+        None => Ok(files),
     }
+}
 
-    // handle files
-    entries
-        .into_par_iter()
-        .filter(|entry| !entry.path().is_dir())
-        .for_each(|entry| {
-            let path = entry.path();
-            debug!("entry: {path:?}");
+// Walk of `folder` according to `rules`, sorted by file name if `sorted`,
+// leaving out `nested_targets`
+#[cfg(feature = "walkdir")]
+fn walk_folder<'a>(
+    folder: &Path,
+    rules: &'a Rules,
+    sorted: bool,
+    nested_targets: &'a [PathBuf],
+) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> + Send + 'a {
+    let mut walker = WalkDir::new(folder).follow_links(rules.symlinks == SymlinkPolicy::Follow);
+    if let Some(max_depth) = rules.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+    if sorted {
+        walker = walker.sort_by_file_name();
+    }
+    walker.into_iter().filter_entry(move |entry| {
+        if rules.skip_hidden && is_hidden(entry) {
+            return false;
+        }
+        !nested_targets.iter().any(|target| target == entry.path())
+    })
+}
 
-            let relative_path = path.strip_prefix(folder).expect("can't strip prefix");
-            let target_path = target_dir.as_ref().join(relative_path);
+// Entry met by the walk of a root folder, with the folder and its target directory
+#[cfg(feature = "walkdir")]
+type Entry<'r> = (&'r PathBuf, &'r PathBuf, walkdir::DirEntry);
 
-            if rules.is_file_qualified(&path) {
-                debug!("watermarking {path:?}");
+// Path of `entry`, and relative to its folder
+#[cfg(feature = "walkdir")]
+fn entry_paths<'e>((folder, _, entry): &'e Entry) -> (&'e Path, &'e Path) {
+    let path = entry.path();
+    let relative_path = path.strip_prefix(folder).expect("can't strip prefix");
+    (path, relative_path)
+}
 
-                if let Err(e) = overlay_watermark(path, &target_path, &watermark_img) {
-                    error!("Error watermarking: {:?} - {}", path, e.to_string());
-                } else {
-                    recopy_metadata(&path, &target_path.as_path())
-                        .expect("cannot recopy properties");
+// Output path of `entry` in its target directory
+#[cfg(feature = "walkdir")]
+fn entry_target(options: &Options, entry: &Entry) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let (path, relative_path) = entry_paths(entry);
+    let output = options.layout.output_path(path, relative_path)?;
+    Ok(entry.1.join(output))
+}
+
+// File processed by a worker, whose outputs are left to a writer thread
+#[cfg(feature = "walkdir")]
+struct Pending {
+    path: PathBuf,
+    relative_path: PathBuf,
+    start: Instant,
+    report: FileReport,
+    writes: Writes,
+}
+
+// Map `entries` to their reports on the workers of `run`, calling `done` for each file:
+// the content of the files worth it is read ahead by reader threads, and outputs are
+// written behind the workers by writer threads, see `ReadAhead`
+#[cfg(feature = "walkdir")]
+fn map_read_ahead<'r>(
+    run: &Run,
+    span: &RunSpan,
+    journal: Option<&Journal>,
+    entries: impl Iterator<Item = Entry<'r>> + Send,
+    read_ahead: ReadAhead,
+    done: impl Fn() + Sync,
+) -> Vec<FileReport> {
+    let entries = Mutex::new(entries);
+    let (sender, receiver) = mpsc::sync_channel(read_ahead.files);
+    let (writes, pending) = mpsc::sync_channel::<Pending>(read_ahead.outputs);
+    let pending = Mutex::new(pending);
+    std::thread::scope(|scope| {
+        for _ in 0..read_ahead.threads.max(1) {
+            let (entries, sender) = (&entries, sender.clone());
+            scope.spawn(move || loop {
+                let Some(entry) = entries.lock().expect("poisoned lock").next() else {
+                    break;
+                };
+                let (path, relative_path) = entry_paths(&entry);
+                // a file which can't be read fails once processed
+                let content = run
+                    .reads_ahead(path, relative_path)
+                    .then(|| run.read(path).ok())
+                    .flatten();
+                if sender.send((entry, content)).is_err() {
+                    break;
                 }
-            } else {
-                debug!("copying {path:?}");
+            });
+        }
+        // the workers are done once all readers are
+        drop(sender);
+
+        let writers: Vec<_> = (0..read_ahead.writers)
+            .map(|_| {
+                let (pending, done) = (&pending, &done);
+                scope.spawn(move || {
+                    let mut reports = vec![];
+                    loop {
+                        // the writers are done once all workers are
+                        let next = pending.lock().expect("poisoned lock").recv();
+                        let Ok(Pending {
+                            path,
+                            relative_path,
+                            start,
+                            report,
+                            writes,
+                        }) = next
+                        else {
+                            break;
+                        };
+                        let span = span.file(&path);
+                        let result = run.complete_file(&path, report, writes);
+                        let report =
+                            finish_file(run, &span, journal, &path, &relative_path, start, result);
+                        done();
+                        reports.push(report);
+                    }
+                    reports
+                })
+            })
+            .collect();
+
+        let writes = (read_ahead.writers > 0).then_some(writes);
+        let mut files: Vec<_> = run
+            .map(receiver.into_iter(), |(entry, content)| {
+                let start = Instant::now();
+                let (path, relative_path) = entry_paths(&entry);
+                let target_path = entry_target(run.options, &entry);
+                let span = span.file(path);
+                let result =
+                    match prepare_file(run, journal, path, relative_path, target_path, content) {
+                        Prepared::Done(report) => {
+                            done();
+                            return Some(report);
+                        }
+                        Prepared::Failed(e) => Err(e),
+                        Prepared::Ready(report, written) => match &writes {
+                            Some(writes) => {
+                                let pending = Pending {
+                                    path: path.to_owned(),
+                                    relative_path: relative_path.to_owned(),
+                                    start,
+                                    report,
+                                    writes: written,
+                                };
+                                match writes.send(pending) {
+                                    Ok(()) => return None,
+                                    // a writer panicked, written by the worker
+                                    Err(mpsc::SendError(pending)) => {
+                                        run.complete_file(path, pending.report, pending.writes)
+                                    }
+                                }
+                            }
+                            // without writers, outputs are written by the worker
+                            None => run.complete_file(path, report, written),
+                        },
+                    };
+                let report = finish_file(run, &span, journal, path, relative_path, start, result);
+                done();
+                Some(report)
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        drop(writes);
+        for writer in writers {
+            files.extend(writer.join().expect("writer thread panicked"));
+        }
+        files
+    })
+}
+
+// Entry under a root folder whose name starts with a dot
+#[cfg(feature = "walkdir")]
+fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
 
-                fs::copy(path, target_path).expect("error copying a file");
+// Target directories located inside a root folder, as met by the walk of the folder
+#[cfg(feature = "walkdir")]
+fn nested_targets(
+    roots: &[(PathBuf, PathBuf)],
+    policy: NestedTargetPolicy,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut nested_targets = vec![];
+    for (folder, _) in roots {
+        let absolute_folder = absolute(folder)?;
+        for (_, target_dir) in roots {
+            let Ok(relative_path) = absolute(target_dir)?
+                .strip_prefix(&absolute_folder)
+                .map(Path::to_path_buf)
+            else {
+                continue;
+            };
+            if relative_path.as_os_str().is_empty() {
+                return Err(format!("Target {target_dir:?} is the input folder {folder:?}").into());
+            }
+            if policy == NestedTargetPolicy::Fail {
+                return Err(
+                    format!("Target {target_dir:?} is inside input folder {folder:?}").into(),
+                );
             }
 
-            // Progress update
-            if let Some(progress) = progress {
-                let c = counter.fetch_add(1, Ordering::Relaxed);
-                if nb_entries < 1000 || c % 100 == 0 {
-                    progress.set_position(c);
-                }
+            warn!("Target {target_dir:?} is inside input folder {folder:?}, it won't be traversed");
+            nested_targets.push(folder.join(relative_path));
+        }
+    }
+    Ok(nested_targets)
+}
+
+// Canonical form of `path`, which may not exist yet
+#[cfg(feature = "walkdir")]
+fn absolute(path: &Path) -> std::io::Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = vec![];
+    loop {
+        match existing.canonicalize() {
+            Ok(canonical) => {
+                return Ok(canonical.join(missing.into_iter().rev().collect::<PathBuf>()))
             }
-        });
+            Err(e) => {
+                let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+                    return Err(e);
+                };
+                missing.push(name);
+                existing = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+            }
+        }
+    }
+}
 
-    Ok(())
+// Journal of `target_dir` if enabled, previous outputs are reserved in `run`
+fn open_journal(
+    run: &Run,
+    folder: &Path,
+    target_dir: &Path,
+) -> Result<Option<Journal>, Box<dyn std::error::Error>> {
+    if !run.options.journal {
+        return Ok(None);
+    }
+    let journal = Journal::open(folder, target_dir)?;
+    run.reserve_outputs(journal.reports());
+    Ok(Some(journal))
 }
 
-// Recopy file's metadata from original file (`from`) to watermarked one (`to`)
-fn recopy_metadata<P: AsRef<Path> + ?Sized + std::fmt::Debug>(
-    from: &P,
-    to: &P,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let input = fs::read(from).expect("cannot read image");
-    let output = fs::read(to).expect("cannot read target image");
-
-    match from
-        .as_ref()
-        .extension()
-        .unwrap()
-        .to_string_lossy()
-        .to_lowercase()
-        .as_str()
-    {
-        "png" => {
-            let input_png = Png::from_bytes(input.into()).expect("unable to get as png");
-
-            let mut output_png = Png::from_bytes(output.into()).expect("unable to get as png");
-            output_png.set_exif(input_png.exif());
-            output_png.set_icc_profile(input_png.icc_profile());
-
-            let output_file = OpenOptions::new()
-                .write(true)
-                .open(to)
-                .expect("unable to open png as File");
-            output_png
-                .encoder()
-                .write_to(&output_file)
-                .expect("cannot write to output png file");
+// Process file `path` into `target_path`, unless completed by a previous run.
+// A file without output path fails like a file which can't be processed
+fn handle_file(
+    run: &Run,
+    span: &RunSpan,
+    journal: Option<&Journal>,
+    path: &Path,
+    relative_path: &Path,
+    target_path: Result<PathBuf, Box<dyn std::error::Error>>,
+    content: Option<Bytes>,
+) -> FileReport {
+    let start = Instant::now();
+    let span = span.file(path);
+    let result = match prepare_file(run, journal, path, relative_path, target_path, content) {
+        Prepared::Done(report) => return report,
+        Prepared::Failed(e) => Err(e),
+        Prepared::Ready(report, writes) => run.complete_file(path, report, writes),
+    };
+    finish_file(run, &span, journal, path, relative_path, start, result)
+}
+
+// File prepared by a worker, see `prepare_file`
+enum Prepared {
+    // Completed by a previous run, or cancelled
+    Done(FileReport),
+    Failed(Box<dyn std::error::Error>),
+    // Its outputs left to write, see `Run::complete_file`
+    Ready(FileReport, Writes),
+}
+
+// First stage of `handle_file`, up to the outputs of `path` to write
+fn prepare_file(
+    run: &Run,
+    journal: Option<&Journal>,
+    path: &Path,
+    relative_path: &Path,
+    target_path: Result<PathBuf, Box<dyn std::error::Error>>,
+    content: Option<Bytes>,
+) -> Prepared {
+    debug!("entry: {path:?}");
+
+    if let Some(done) = journal.and_then(|j| j.done(path)) {
+        debug!("already completed: {path:?}");
+        return Prepared::Done(done.clone());
+    }
+    if run.cancelled() {
+        debug!("cancelled: {path:?}");
+        return Prepared::Done(FileReport::new(path, Outcome::Cancelled, None));
+    }
+    run.options.hooks.file_start(path);
+
+    let prepared = target_path
+        .and_then(|target_path| run.prepare_file(path, relative_path, &target_path, content));
+    match prepared {
+        Ok((report, writes)) => Prepared::Ready(report, writes),
+        Err(e) => Prepared::Failed(e),
+    }
+}
+
+// Last stage of `handle_file`: report of `path` processed since `start` into `result`,
+// recorded in the journal and passed to hooks. Errors are quarantined
+fn finish_file(
+    run: &Run,
+    span: &FileSpan,
+    journal: Option<&Journal>,
+    path: &Path,
+    relative_path: &Path,
+    start: Instant,
+    result: Result<FileReport, Box<dyn std::error::Error>>,
+) -> FileReport {
+    if let (Err(e), Some(quarantine)) = (&result, run.quarantine()) {
+        match quarantine::quarantine(quarantine, path, relative_path, e.as_ref()) {
+            Ok(copy) => debug!("quarantined {path:?} in {copy:?}"),
+            Err(e) => error!("Error quarantining {path:?} - {e}"),
         }
-        "jpg" | "jpeg" => {
-            let input_jpg = Jpeg::from_bytes(input.into()).expect("unable to get as jpeg");
-
-            let mut output_jpg = Jpeg::from_bytes(output.into()).expect("unable to get as jpeg");
-            output_jpg.set_exif(input_jpg.exif());
-            output_jpg.set_icc_profile(input_jpg.icc_profile());
-
-            let output_file = OpenOptions::new()
-                .write(true)
-                .open(to)
-                .expect("unable to open jpeg as File");
-            output_jpg
-                .encoder()
-                .write_to(&output_file)
-                .expect("cannot write to output jpg file");
+    }
+    record_file(run, span, journal, path, start, result)
+}
+
+// Report of `path` processed since `start` into `result`, as `finish_file` but
+// without quarantining errors, recorded in the journal and passed to hooks
+fn record_file(
+    run: &Run,
+    span: &FileSpan,
+    journal: Option<&Journal>,
+    path: &Path,
+    start: Instant,
+    result: Result<FileReport, Box<dyn std::error::Error>>,
+) -> FileReport {
+    let options = run.options;
+    let mut report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Error processing: {path:?} - {e}");
+            options.hooks.error(path, e.as_ref());
+            options.emit(|| Event::Error {
+                path: path.to_owned(),
+                error: e.to_string(),
+            });
+            FileReport {
+                error: Some(e.to_string()),
+                ..FileReport::new(path, Outcome::Failed, None)
+            }
         }
-        "webp" => {
-            let input_webp = WebP::from_bytes(input.into()).expect("unable to get as webp");
-
-            let mut output_webp = WebP::from_bytes(output.into()).expect("unable to get as webp");
-            output_webp.set_exif(input_webp.exif());
-            output_webp.set_icc_profile(input_webp.icc_profile());
-
-            let output_file = OpenOptions::new()
-                .write(true)
-                .open(to)
-                .expect("unable to open webp as File");
-            output_webp
-                .encoder()
-                .write_to(&output_file)
-                .expect("cannot write to output webp file");
+    };
+    report.duration = start.elapsed();
+    span.record(&report);
+    if let Some(journal) = journal {
+        if let Err(e) = journal.record(&report) {
+            error!("Error recording {path:?} in journal - {e}");
         }
-        other => error!("Extension ({other}) not supported to get Exif metadata: {from:?}"),
     }
-    Ok(())
+    options.hooks.file_done(path, report.outcome);
+    options.hooks.report(&report);
+    options.emit(|| Event::FileDone(report.clone()));
+    report
 }
 
-#[cfg(test)]
-mod tests {
-    use super::recopy_metadata;
-    use img_parts::jpeg::Jpeg;
-    use img_parts::ImageEXIF;
-
-    #[test]
-    fn test_exif_read_maker_note() {
-        let input = std::fs::read("data/exif/notes.jpg").unwrap();
-        let jpg = Jpeg::from_bytes(input.into()).unwrap();
-        let exif = jpg.exif().unwrap();
-        assert!(exif.starts_with(b"II"));
-        // println!("{exif:?}");
-        let exif = exif.to_vec();
-        let exif_contains = |note: &[u8]| exif.windows(note.len()).any(|window| window == note);
-        assert!(exif_contains(b"COOLPIX P6000V1.0"));
-        assert!(exif_contains(b"NIKON\0COOLPIX P6000"));
-        assert!(exif_contains(b"Nikon Transfer 1.1 W\0:2008:11:01 21:15:08"));
-    }
-
-    #[test]
-    fn test_exif_read_comments() {
-        let input = std::fs::read("data/exif/comments.jpg").unwrap();
-        let jpg = Jpeg::from_bytes(input.into()).unwrap();
-        let exif = jpg.exif().unwrap();
-        // comment added on Windows (Exif field `winxp-comments`)
-        let comment = b"B\0A\0T\0A\0I\0L\0L\0O\0N\0 \0A\0I\0R\0 \x001\x002\0.\x001\x001\08\0 \0S\0E\0C\0T\0E\0U\0R\0 \0A\0I\0R\0 \x005\x001\0 \0C\0U\0I\0V\0R\0E\0 \0\xe0\0 \0p\0r\0i\0o\0r\0i\0 \0m\0a\0i\0s\0 \0n\0o\0n\0 \0d\0o\0r\0\xe9\0\0\0";
-        assert!(exif.ends_with(comment));
-    }
-
-    #[test]
-    fn test_exif_write_comments() {
-        let input = "data/exif/comments.jpg";
-        let work = "data/exif/test_output.jpg";
-        std::fs::copy(input, work).unwrap();
-        recopy_metadata(input, work).unwrap();
-
-        let output_raw = std::fs::read(work).unwrap();
-        let jpg = Jpeg::from_bytes(output_raw.into()).unwrap();
-        let exif = jpg.exif().unwrap();
-        // comment added on Windows (Exif field `winxp-comments`)
-        let comment = b"B\0A\0T\0A\0I\0L\0L\0O\0N\0 \0A\0I\0R\0 \x001\x002\0.\x001\x001\08\0 \0S\0E\0C\0T\0E\0U\0R\0 \0A\0I\0R\0 \x005\x001\0 \0C\0U\0I\0V\0R\0E\0 \0\xe0\0 \0p\0r\0i\0o\0r\0i\0 \0m\0a\0i\0s\0 \0n\0o\0n\0 \0d\0o\0r\0\xe9\0\0\0";
-        assert!(exif.ends_with(comment));
-        std::fs::remove_file(work).unwrap();
+// Report of the run, once all files are processed
+fn complete(
+    options: &Options,
+    journal: Option<Journal>,
+    mut files: Vec<FileReport>,
+    start: Instant,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    files.sort_by(|a, b| a.source.cmp(&b.source));
+    if let Some(max_distance) = options.similar_images {
+        flag_duplicates(&mut files, max_distance);
+    }
+    let report = Report {
+        files,
+        elapsed: start.elapsed(),
+    };
+    options.emit(|| Event::Finished(report.metrics()));
+    if let Some(journal) = journal {
+        if report.count(Outcome::Failed) == 0 && report.count(Outcome::Cancelled) == 0 {
+            journal.remove()?;
+        }
+    }
+    if options.gallery {
+        write_galleries(&report.files)?;
+    }
+    if let Some(manifest) = &options.manifest {
+        report.write_manifest(manifest)?;
     }
+    Ok(report)
 }
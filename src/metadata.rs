@@ -0,0 +1,1190 @@
+use bytes::Bytes;
+use image::{DynamicImage, ImageDecoder, ImageFormat};
+use img_parts::jpeg::{markers, JpegSegment};
+use img_parts::png::PngChunk;
+use img_parts::riff::{RiffChunk, RiffContent};
+use img_parts::webp::{WebP, CHUNK_VP8L, CHUNK_VP8X, CHUNK_XMP};
+use img_parts::{jpeg::Jpeg, png::Png};
+use img_parts::{DynImage, ImageEXIF, ImageICC};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::exif;
+pub use crate::exif::{ARTIST, COPYRIGHT, IMAGE_DESCRIPTION, SOFTWARE};
+use crate::graphics::{encode_thumbnail, reader};
+use crate::trace::{debug, error};
+
+/// Marker embedded in outputs, see `Options::mark_outputs`
+const MARKER: &[u8] = b"watermarked by filigram-rs";
+// Marker as the content of a PNG text chunk, with its keyword
+const PNG_MARKER: &[u8] = b"Comment\0watermarked by filigram-rs";
+
+// Kinds of PNG text chunks
+const PNG_TEXTS: [[u8; 4]; 3] = [*b"tEXt", *b"zTXt", *b"iTXt"];
+// Prefix of a PNG text chunk with a comment, its keyword
+const PNG_COMMENT: &[u8] = b"Comment\0";
+
+// Prefix of the APP1 segment of a JPEG image with its XMP packet
+const JPEG_XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+// Prefix of the iTXt chunk of a PNG image with its XMP packet: keyword,
+// no compression, no language and no translated keyword
+const PNG_XMP: &[u8] = b"XML:com.adobe.xmp\0\0\0\0\0";
+// Flags of the VP8X chunk of a WebP image: an alpha channel, an XMP chunk
+const WEBP_ALPHA: u8 = 0b0001_0000;
+const WEBP_XMP: u8 = 0b0000_0100;
+// Largest Exif data of a JPEG image: its APP1 segment holds 65533 bytes,
+// "Exif\0\0" included
+const JPEG_MAX_EXIF: usize = 65533 - 6;
+
+/// Error reading or writing the metadata of an image
+#[derive(Debug)]
+pub enum MetadataError {
+    /// Format of the image, given by its magic bytes, has no metadata support
+    /// (only JPEG, PNG and WebP have), `None` if not recognized
+    UnsupportedFormat(Option<ImageFormat>),
+    /// Content is not a valid container of this format
+    InvalidContainer(ImageFormat, String),
+    /// Exif data of this size, even without its thumbnail, doesn't fit in a JPEG image
+    ExifTooLarge(usize),
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataError::UnsupportedFormat(Some(format)) => {
+                write!(f, "metadata of {format:?} images is not supported")
+            }
+            MetadataError::UnsupportedFormat(None) => write!(f, "unknown image format"),
+            MetadataError::InvalidContainer(format, error) => {
+                write!(f, "invalid {format:?} image: {error}")
+            }
+            MetadataError::ExifTooLarge(len) => write!(
+                f,
+                "Exif data of {len} bytes exceeds the {JPEG_MAX_EXIF} bytes of a JPEG image"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+/// What is done with a category of metadata of source images, see `MetadataPolicy`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum MetadataAction {
+    /// Copy it into watermarked images
+    #[default]
+    Keep,
+    /// Leave it out of watermarked images
+    Strip,
+    /// Write these data in watermarked images instead, see `MetadataPolicy` for their content
+    Replace(Vec<u8>),
+}
+
+/// What is done with the thumbnail embedded in the Exif data of source images,
+/// which shows the image without its watermark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThumbnailAction {
+    /// Copy it into watermarked images
+    Keep,
+    /// Leave it out of watermarked images
+    Strip,
+    /// Replace it by a thumbnail of the watermarked image
+    #[default]
+    Regenerate,
+}
+
+/// Metadata of source images written in watermarked images, by category,
+/// i.e. to comply with what may ship in files delivered to a client
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MetadataPolicy {
+    /// Exif data, replaced by raw Exif data starting with its TIFF header ("II" or "MM")
+    pub exif: MetadataAction,
+    /// ICC profile, replaced by another ICC profile (i.e. sRGB)
+    pub icc_profile: MetadataAction,
+    /// XMP packet of JPEG, PNG and WebP images, replaced by another XMP packet
+    pub xmp: MetadataAction,
+    /// GPS data of Exif data (i.e. where photos were taken), other Exif fields are kept.
+    /// Replaced by the GPS data of raw Exif data, i.e. `Metadata::exif` of a reference image.
+    /// Applies after `exif`
+    pub gps: MetadataAction,
+    /// Comments of JPEG images and text chunks of PNG images
+    /// (i.e. software, creation time), replaced by a single comment
+    pub comments: MetadataAction,
+    /// Thumbnail of Exif data, regenerated by default
+    pub thumbnail: ThumbnailAction,
+}
+
+/// Summary of the metadata of an image, see `read_metadata`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageMetadata {
+    /// Format, given by the magic bytes of the image or by its extension
+    pub format: ImageFormat,
+    /// Width and height
+    pub dimensions: (u32, u32),
+    /// The image has Exif data
+    pub has_exif: bool,
+    /// Date and time the photo was taken, as written by the camera, i.e. `2008:11:01 21:15:08`,
+    /// or date and time the image was last changed if unknown
+    pub capture_date: Option<String>,
+    /// Make and model of the camera, i.e. `NIKON COOLPIX P6000`
+    pub camera: Option<String>,
+    /// Description of the ICC profile, i.e. `sRGB IEC61966-2.1`
+    pub icc_name: Option<String>,
+}
+
+/// Read the metadata of the image at `path`, with the same parsing as `spread_watermark`,
+/// i.e. to build reports over a source tree
+pub fn read_metadata(path: &Path) -> Result<ImageMetadata, Box<dyn std::error::Error>> {
+    let reader = reader(path, BufReader::new(File::open(path)?))?;
+    let format = reader.format().ok_or("unknown image format")?;
+    let mut decoder = reader.into_decoder()?;
+    let exif = decoder.exif_metadata()?;
+    let icc_profile = decoder.icc_profile()?;
+
+    let exif = exif.as_deref();
+    let capture_date = exif.and_then(|exif| {
+        exif::read_exif_ascii(exif, exif::DATE_TIME_ORIGINAL)
+            .or_else(|| exif::read_ascii(exif, exif::DATE_TIME))
+    });
+    let camera = exif.and_then(|exif| {
+        let model = exif::read_ascii(exif, exif::MODEL)?;
+        match exif::read_ascii(exif, exif::MAKE) {
+            // models often start with the make, i.e. `Canon EOS 5D`
+            Some(make) if !model.starts_with(&make) => Some(format!("{make} {model}")),
+            _ => Some(model),
+        }
+    });
+    Ok(ImageMetadata {
+        format,
+        dimensions: decoder.dimensions(),
+        has_exif: exif.is_some(),
+        capture_date,
+        camera,
+        icc_name: icc_profile.as_deref().and_then(icc_description),
+    })
+}
+
+/// The image at `path` carries the marker of filigram (see `Options::mark_outputs`),
+/// i.e. it has been written by a previous run
+pub fn is_watermarked(path: &Path) -> std::io::Result<bool> {
+    Ok(is_marked(std::fs::read(path)?.into()))
+}
+
+/// Metadata of an image: Exif data, ICC profile, XMP packet, comments of JPEG images
+/// and text chunks of PNG images.
+/// i.e. to bump the Software field of an output:
+/// `Metadata::read(path)?.set_field(SOFTWARE, "studio 2.0").write_to(path)?`
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    exif: Option<Bytes>,
+    icc_profile: Option<Bytes>,
+    // XMP packet of a JPEG, PNG or WebP image
+    xmp: Option<Bytes>,
+    // comment segments of a JPEG image
+    comments: Vec<Bytes>,
+    // text chunks of a PNG image (i.e. software, comments, creation time)
+    texts: Vec<PngChunk>,
+}
+
+impl Metadata {
+    /// Read metadata of the JPEG, PNG or WebP image at `path`
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(parse_metadata(std::fs::read(path)?.into())?)
+    }
+
+    /// Value of ASCII Exif field `tag`, see `COPYRIGHT`, `ARTIST`, `IMAGE_DESCRIPTION` and `SOFTWARE`
+    pub fn field(&self, tag: u16) -> Option<String> {
+        exif::read_ascii(self.exif.as_ref()?, tag)
+    }
+
+    /// Set ASCII Exif field `tag` to `value`, creating Exif data if needed
+    pub fn set_field(&mut self, tag: u16, value: &str) -> &mut Self {
+        let exif = exif::set_ascii(self.exif.as_deref(), &[(tag, value.to_string())]);
+        self.exif = Some(exif.into());
+        self
+    }
+
+    /// Raw Exif data, starting with its TIFF header
+    pub fn exif(&self) -> Option<&[u8]> {
+        self.exif.as_deref()
+    }
+
+    /// XMP packet
+    pub fn xmp(&self) -> Option<&[u8]> {
+        self.xmp.as_deref()
+    }
+
+    /// Set the XMP packet
+    pub fn set_xmp(&mut self, xmp: Option<Vec<u8>>) -> &mut Self {
+        self.xmp = xmp.map(Bytes::from);
+        self
+    }
+
+    /// Write this metadata in the JPEG, PNG or WebP image at `path`,
+    /// replacing its own metadata
+    pub fn write_to(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let output = std::fs::read(path)?;
+        let output = write_metadata(output.into(), self.clone())?;
+        std::fs::write(path, output)?;
+        Ok(())
+    }
+
+    // Apply `policy` to this metadata of a source image
+    fn apply(&mut self, policy: &MetadataPolicy) {
+        // `None` to keep data, `Some(data)` to set it
+        let apply = |action: &MetadataAction| match action {
+            MetadataAction::Keep => None,
+            MetadataAction::Strip => Some(None),
+            MetadataAction::Replace(data) => Some(Some(Bytes::from(data.clone()))),
+        };
+
+        if let Some(exif) = apply(&policy.exif) {
+            self.exif = exif;
+        }
+        if let Some(icc_profile) = apply(&policy.icc_profile) {
+            self.icc_profile = icc_profile;
+        }
+        if let Some(xmp) = apply(&policy.xmp) {
+            self.xmp = xmp;
+        }
+        match &policy.gps {
+            MetadataAction::Keep => {}
+            MetadataAction::Strip => {
+                self.exif = self.exif.take().map(|exif| exif::strip_gps(&exif).into());
+            }
+            MetadataAction::Replace(gps) => {
+                self.exif = Some(exif::replace_gps(self.exif.as_deref(), gps).into());
+            }
+        }
+        if let Some(comment) = apply(&policy.comments) {
+            self.comments = comment.iter().cloned().collect();
+            self.texts = comment
+                .map(|comment| PngChunk::new(*b"tEXt", [PNG_COMMENT, &comment].concat().into()))
+                .into_iter()
+                .collect();
+        }
+    }
+}
+
+// Read metadata of `input`, the content of an image
+fn parse_metadata(input: Bytes) -> Result<Metadata, MetadataError> {
+    let format = container(&input)?;
+    let invalid =
+        |error: img_parts::Error| MetadataError::InvalidContainer(format, error.to_string());
+    match format {
+        ImageFormat::Png => {
+            let input_png = Png::from_bytes(input).map_err(invalid)?;
+            let (xmp, texts): (Vec<_>, Vec<_>) = input_png
+                .chunks()
+                .iter()
+                .filter(|chunk| PNG_TEXTS.contains(&chunk.kind()))
+                .filter(|chunk| chunk.contents() != PNG_MARKER)
+                .cloned()
+                .partition(|chunk| chunk.contents().starts_with(PNG_XMP));
+            Ok(Metadata {
+                exif: input_png.exif(),
+                icc_profile: input_png.icc_profile(),
+                xmp: xmp
+                    .first()
+                    .map(|chunk| chunk.contents().slice(PNG_XMP.len()..)),
+                comments: vec![],
+                texts,
+            })
+        }
+        ImageFormat::Jpeg => {
+            let input_jpg = Jpeg::from_bytes(input).map_err(invalid)?;
+            let xmp = input_jpg
+                .segments_by_marker(markers::APP1)
+                .find(|segment| segment.contents().starts_with(JPEG_XMP))
+                .map(|segment| segment.contents().slice(JPEG_XMP.len()..));
+            let comments = input_jpg
+                .segments_by_marker(markers::COM)
+                .map(|segment| segment.contents().clone())
+                .filter(|comment| comment != MARKER)
+                .collect();
+            Ok(Metadata {
+                exif: input_jpg.exif(),
+                icc_profile: input_jpg.icc_profile(),
+                xmp,
+                comments,
+                texts: vec![],
+            })
+        }
+        _ => {
+            let input_webp = WebP::from_bytes(input).map_err(invalid)?;
+            Ok(Metadata {
+                exif: input_webp.exif(),
+                icc_profile: input_webp.icc_profile(),
+                xmp: input_webp
+                    .chunk_by_id(CHUNK_XMP)
+                    .and_then(|chunk| chunk.content().data().cloned()),
+                comments: vec![],
+                texts: vec![],
+            })
+        }
+    }
+}
+
+// Set `metadata` in `output`, the encoded bytes of an image
+fn write_metadata(output: Bytes, metadata: Metadata) -> Result<Bytes, MetadataError> {
+    let Metadata {
+        exif,
+        icc_profile,
+        xmp,
+        comments,
+        texts,
+    } = metadata;
+
+    let format = container(&output)?;
+    let invalid =
+        |error: img_parts::Error| MetadataError::InvalidContainer(format, error.to_string());
+    match format {
+        ImageFormat::Png => {
+            let mut output_png = Png::from_bytes(output).map_err(invalid)?;
+            output_png.set_exif(exif);
+            output_png.set_icc_profile(icc_profile);
+            output_png
+                .chunks_mut()
+                .retain(|chunk| !PNG_TEXTS.contains(&chunk.kind()));
+            if let Some(xmp) = xmp {
+                let xmp = PngChunk::new(*b"iTXt", [PNG_XMP, &xmp].concat().into());
+                insert_chunk(&mut output_png, xmp);
+            }
+            for text in texts {
+                insert_chunk(&mut output_png, text);
+            }
+            Ok(output_png.encoder().bytes())
+        }
+        ImageFormat::Jpeg => {
+            let mut output_jpg = Jpeg::from_bytes(output).map_err(invalid)?;
+            let exif = exif.map(|exif| fit_exif(format, exif.to_vec()));
+            output_jpg.set_exif(exif.transpose()?.map(Bytes::from));
+            output_jpg.set_icc_profile(icc_profile);
+            output_jpg.segments_mut().retain(|segment| {
+                segment.marker() != markers::COM
+                    && !(segment.marker() == markers::APP1
+                        && segment.contents().starts_with(JPEG_XMP))
+            });
+            if let Some(xmp) = xmp {
+                let xmp = [JPEG_XMP, &xmp].concat();
+                let xmp = JpegSegment::new_with_contents(markers::APP1, xmp.into());
+                insert_segment(&mut output_jpg, xmp);
+            }
+            for comment in comments {
+                let comment = JpegSegment::new_with_contents(markers::COM, comment);
+                insert_segment(&mut output_jpg, comment);
+            }
+            Ok(output_jpg.encoder().bytes())
+        }
+        _ => {
+            let mut output_webp = WebP::from_bytes(output).map_err(invalid)?;
+            output_webp.set_exif(exif);
+            output_webp.set_icc_profile(icc_profile);
+            set_webp_xmp(&mut output_webp, xmp);
+            Ok(output_webp.encoder().bytes())
+        }
+    }
+}
+
+// Set the XMP chunk of `webp`, once its Exif data and ICC profile are set:
+// `img-parts` only turns images into the extended format (VP8X chunk) for them
+fn set_webp_xmp(webp: &mut WebP, xmp: Option<Bytes>) {
+    webp.remove_chunks_by_id(CHUNK_XMP);
+    let flags = webp
+        .chunk_by_id(CHUNK_VP8X)
+        .and_then(|vp8x| vp8x.content().data())
+        .map(|vp8x| vp8x.to_vec());
+    let mut vp8x = match (flags, &xmp) {
+        (Some(vp8x), _) => vp8x,
+        (None, None) => return,
+        (None, Some(_)) => {
+            let Some((width, height)) = webp.dimensions() else {
+                return;
+            };
+            let mut vp8x = vec![0; 4];
+            vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+            vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+            vp8x
+        }
+    };
+    if vp8x.is_empty() {
+        return;
+    }
+
+    // the alpha flag of a lossless image is the one of its bitstream, in its header:
+    // 14 bits of width, 14 bits of height then the alpha bit, after the signature byte
+    let lossless_alpha = webp
+        .chunk_by_id(CHUNK_VP8L)
+        .and_then(|vp8l| vp8l.content().data())
+        .and_then(|vp8l| vp8l.get(1..5))
+        .is_some_and(|header| header[3] & 0b0001_0000 != 0);
+    if lossless_alpha {
+        vp8x[0] |= WEBP_ALPHA;
+    }
+    match &xmp {
+        Some(_) => vp8x[0] |= WEBP_XMP,
+        None => vp8x[0] &= !WEBP_XMP,
+    }
+    webp.remove_chunks_by_id(CHUNK_VP8X);
+    let vp8x = RiffChunk::new(CHUNK_VP8X, RiffContent::Data(vp8x.into()));
+    webp.chunks_mut().insert(0, vp8x);
+    if let Some(xmp) = xmp {
+        // last, after the image data and Exif data
+        webp.chunks_mut()
+            .push(RiffChunk::new(CHUNK_XMP, RiffContent::Data(xmp)));
+    }
+}
+
+/// Copy metadata of `input`, the content of original file `from`, into `output`,
+/// the encoded bytes of the watermarked image to be written at `to`, according to `policy`
+pub(crate) fn embed_metadata(
+    from: &Path,
+    input: Bytes,
+    to: &Path,
+    output: Bytes,
+    policy: &MetadataPolicy,
+) -> Bytes {
+    let embedded = parse_metadata(input).and_then(|mut metadata| {
+        metadata.apply(policy);
+        write_metadata(output.clone(), metadata)
+    });
+    match embedded {
+        Ok(embedded) => embedded,
+        Err(err @ MetadataError::UnsupportedFormat(_)) => {
+            debug!("metadata of {from:?} not copied into {to:?}: {err}");
+            output
+        }
+        Err(err) => {
+            error!("metadata of {from:?} not copied into {to:?}: {err}");
+            output
+        }
+    }
+}
+
+/// `output`, the encoded bytes of an image to be written at `to`,
+/// with its metadata edited by `edit`. Formats without metadata support are left as is
+pub(crate) fn edit_metadata(to: &Path, output: Bytes, edit: impl FnOnce(&mut Metadata)) -> Bytes {
+    let edited = parse_metadata(output.clone()).and_then(|mut metadata| {
+        edit(&mut metadata);
+        write_metadata(output.clone(), metadata)
+    });
+    match edited {
+        Ok(edited) => edited,
+        Err(err) => {
+            debug!("metadata of {to:?} not edited: {err}");
+            output
+        }
+    }
+}
+
+/// Set ASCII Exif `fields` (tag and value) in `output`, the encoded bytes of an image,
+/// creating its Exif data if needed. Formats without Exif support are left as is
+pub(crate) fn set_exif_fields(
+    output: Bytes,
+    fields: &[(u16, String)],
+) -> Result<Bytes, MetadataError> {
+    map_exif(output, |exif| {
+        Some(exif::set_ascii(exif.as_deref(), fields))
+    })
+}
+
+/// Reset the Exif orientation of `output`, the encoded bytes of an image
+/// whose pixels have been rotated according to it. Images without Exif data are left as is
+pub(crate) fn reset_orientation(output: Bytes) -> Result<Bytes, MetadataError> {
+    map_exif(output, |exif| Some(exif::reset_orientation(&exif?)))
+}
+
+/// Apply `action` to the Exif thumbnail of `output`, the encoded bytes of `img`.
+/// Images without Exif thumbnail are left as is
+pub(crate) fn process_thumbnail(
+    output: Bytes,
+    img: &DynamicImage,
+    action: ThumbnailAction,
+) -> Result<Bytes, MetadataError> {
+    if action == ThumbnailAction::Keep {
+        return Ok(output);
+    }
+    map_exif(output, |exif| {
+        let exif = exif.filter(|exif| exif::thumbnail(exif).is_some())?;
+        let thumbnail = match action {
+            ThumbnailAction::Regenerate => encode_thumbnail(img).ok(),
+            _ => None,
+        };
+        Some(match thumbnail {
+            Some(thumbnail) => exif::replace_thumbnail(&exif, &thumbnail),
+            None => exif::strip_thumbnail(&exif),
+        })
+    })
+}
+
+// `output`, the encoded bytes of an image, with its Exif data replaced by `map` of it,
+// left as is if `map` returns `None` or for formats without Exif support
+fn map_exif(
+    output: Bytes,
+    map: impl FnOnce(Option<Bytes>) -> Option<Vec<u8>>,
+) -> Result<Bytes, MetadataError> {
+    let Ok(Some(mut image)) = DynImage::from_bytes(output.clone()) else {
+        return Ok(output);
+    };
+    let Some(exif) = map(image.exif()) else {
+        return Ok(output);
+    };
+    let exif = fit_exif(container(&output)?, exif)?;
+    image.set_exif(Some(exif.into()));
+    Ok(image.encoder().bytes())
+}
+
+// `exif`, raw Exif data to be written in an image of `format`: JPEG images hold
+// `JPEG_MAX_EXIF` bytes at most, larger Exif data loses its thumbnail to fit
+fn fit_exif(format: ImageFormat, exif: Vec<u8>) -> Result<Vec<u8>, MetadataError> {
+    if format != ImageFormat::Jpeg || exif.len() <= JPEG_MAX_EXIF {
+        return Ok(exif);
+    }
+    let stripped = exif::strip_thumbnail(&exif);
+    if stripped.len() > JPEG_MAX_EXIF {
+        return Err(MetadataError::ExifTooLarge(exif.len()));
+    }
+    debug!(
+        "Exif data of {} bytes stripped of its thumbnail to fit in a JPEG image",
+        exif.len()
+    );
+    Ok(stripped)
+}
+
+/// Embed the marker of filigram in `output`, the encoded bytes of an image:
+/// as a comment segment in a JPEG image, as a text chunk in a PNG image.
+/// Other formats are left unmarked
+pub(crate) fn mark(output: Bytes) -> Bytes {
+    match container(&output) {
+        Ok(ImageFormat::Jpeg) => {
+            let Ok(mut jpeg) = Jpeg::from_bytes(output.clone()) else {
+                return output;
+            };
+            let comment = JpegSegment::new_with_contents(markers::COM, Bytes::from_static(MARKER));
+            insert_segment(&mut jpeg, comment);
+            jpeg.encoder().bytes()
+        }
+        Ok(ImageFormat::Png) => {
+            let Ok(mut png) = Png::from_bytes(output.clone()) else {
+                return output;
+            };
+            insert_chunk(
+                &mut png,
+                PngChunk::new(*b"tEXt", Bytes::from_static(PNG_MARKER)),
+            );
+            png.encoder().bytes()
+        }
+        _ => output,
+    }
+}
+
+/// `input`, the content of an image, carries the marker of filigram
+pub(crate) fn is_marked(input: Bytes) -> bool {
+    match container(&input) {
+        Ok(ImageFormat::Jpeg) => Jpeg::from_bytes(input).is_ok_and(|jpeg| {
+            jpeg.segments_by_marker(markers::COM)
+                .any(|segment| segment.contents() == MARKER)
+        }),
+        Ok(ImageFormat::Png) => Png::from_bytes(input).is_ok_and(|png| {
+            png.chunks_by_type(*b"tEXt")
+                .any(|chunk| chunk.contents() == PNG_MARKER)
+        }),
+        _ => false,
+    }
+}
+
+/// XMP packet `xmp`, or a new one, with the rights of the image: `copyright` as `dc:rights`,
+/// `artist` as `dc:creator`, the image being marked as copyrighted
+pub(crate) fn xmp_rights(
+    xmp: Option<&[u8]>,
+    copyright: Option<&str>,
+    artist: Option<&str>,
+) -> Vec<u8> {
+    let mut description = String::from(
+        "<rdf:Description rdf:about=\"\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:xmpRights=\"http://ns.adobe.com/xap/1.0/rights/\">\
+         <xmpRights:Marked>True</xmpRights:Marked>",
+    );
+    if let Some(copyright) = copyright {
+        description.push_str(&format!(
+            "<dc:rights><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:rights>",
+            xml_escape(copyright)
+        ));
+    }
+    if let Some(artist) = artist {
+        description.push_str(&format!(
+            "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+            xml_escape(artist)
+        ));
+    }
+    description.push_str("</rdf:Description>");
+
+    // the description is added to those of the packet, before the end of its RDF
+    let end = b"</rdf:RDF>";
+    if let Some(xmp) = xmp {
+        if let Some(at) = xmp.windows(end.len()).rposition(|window| window == end) {
+            return [&xmp[..at], description.as_bytes(), &xmp[at..]].concat();
+        }
+    }
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+         {description}</rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>"
+    )
+    .into_bytes()
+}
+
+// `text` with the characters special to XML escaped
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Insert `segment` in `jpeg`, after the last APPn segment,
+// as APPn segments must come first (i.e. JFIF, Exif)
+fn insert_segment(jpeg: &mut Jpeg, segment: JpegSegment) {
+    let segments = jpeg.segments_mut();
+    let at = segments
+        .iter()
+        .rposition(|segment| (markers::APP0..=markers::APP15).contains(&segment.marker()))
+        .map_or(0, |at| at + 1);
+    segments.insert(at, segment);
+}
+
+// Insert ancillary `chunk` in `png`, before its end
+fn insert_chunk(png: &mut Png, chunk: PngChunk) {
+    let chunks = png.chunks_mut();
+    let at = chunks
+        .iter()
+        .position(|chunk| &chunk.kind() == b"IEND")
+        .unwrap_or(chunks.len());
+    chunks.insert(at, chunk);
+}
+
+// Description of `icc`, an ICC profile, from its `desc` tag:
+// ASCII text in version 2 profiles, localized UTF-16 texts (the first one is read) in version 4
+fn icc_description(icc: &[u8]) -> Option<String> {
+    let u32_at = |offset: usize| -> Option<usize> {
+        let bytes = icc.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize)
+    };
+
+    // tag table after the header of 128 bytes: signature, offset and size of each tag
+    let (offset, size) = (0..u32_at(128)?)
+        .map(|i| 132 + i * 12)
+        .find(|&entry| icc.get(entry..entry + 4) == Some(b"desc"))
+        .and_then(|entry| Some((u32_at(entry + 4)?, u32_at(entry + 8)?)))?;
+    let desc = icc.get(offset..offset.checked_add(size)?)?;
+    let text = match desc.get(..4)? {
+        b"desc" => {
+            let len = u32_at(offset + 8)?;
+            String::from_utf8_lossy(desc.get(12..12usize.checked_add(len)?)?).into_owned()
+        }
+        b"mluc" => {
+            let (len, at) = (u32_at(offset + 20)?, u32_at(offset + 24)?);
+            let utf16 = desc
+                .get(at..at.checked_add(len)?)?
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&utf16)
+        }
+        _ => return None,
+    };
+    let text = text.trim_end_matches('\0').trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+// Container of `content` with metadata support, given by its magic bytes
+// whatever the extension of its file: JPEG, PNG or WebP
+fn container(content: &[u8]) -> Result<ImageFormat, MetadataError> {
+    match image::guess_format(content) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => Ok(format),
+        Ok(format) => Err(MetadataError::UnsupportedFormat(Some(format))),
+        Err(_) => Err(MetadataError::UnsupportedFormat(None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{embed_metadata, insert_chunk, is_marked, mark, MetadataPolicy, PNG_TEXTS};
+    use img_parts::jpeg::Jpeg;
+    use img_parts::ImageEXIF;
+
+    #[test]
+    fn test_exif_read_maker_note() {
+        let input = std::fs::read("data/exif/notes.jpg").unwrap();
+        let jpg = Jpeg::from_bytes(input.into()).unwrap();
+        let exif = jpg.exif().unwrap();
+        assert!(exif.starts_with(b"II"));
+        // println!("{exif:?}");
+        let exif = exif.to_vec();
+        let exif_contains = |note: &[u8]| exif.windows(note.len()).any(|window| window == note);
+        assert!(exif_contains(b"COOLPIX P6000V1.0"));
+        assert!(exif_contains(b"NIKON\0COOLPIX P6000"));
+        assert!(exif_contains(b"Nikon Transfer 1.1 W\0:2008:11:01 21:15:08"));
+    }
+
+    #[test]
+    fn test_exif_read_comments() {
+        let input = std::fs::read("data/exif/comments.jpg").unwrap();
+        let jpg = Jpeg::from_bytes(input.into()).unwrap();
+        let exif = jpg.exif().unwrap();
+        // comment added on Windows (Exif field `winxp-comments`)
+        let comment = b"B\0A\0T\0A\0I\0L\0L\0O\0N\0 \0A\0I\0R\0 \x001\x002\0.\x001\x001\08\0 \0S\0E\0C\0T\0E\0U\0R\0 \0A\0I\0R\0 \x005\x001\0 \0C\0U\0I\0V\0R\0E\0 \0\xe0\0 \0p\0r\0i\0o\0r\0i\0 \0m\0a\0i\0s\0 \0n\0o\0n\0 \0d\0o\0r\0\xe9\0\0\0";
+        assert!(exif.ends_with(comment));
+    }
+
+    #[test]
+    fn test_exif_write_comments() {
+        let from = std::path::Path::new("data/exif/comments.jpg");
+        let input = std::fs::read(from).unwrap();
+        let output = std::fs::read("tests/img/test.jpg").unwrap();
+        let output = embed_metadata(
+            from,
+            input.into(),
+            "test_output.jpg".as_ref(),
+            output.into(),
+            &MetadataPolicy::default(),
+        );
+
+        let jpg = Jpeg::from_bytes(output).unwrap();
+        let exif = jpg.exif().unwrap();
+        // comment added on Windows (Exif field `winxp-comments`)
+        let comment = b"B\0A\0T\0A\0I\0L\0L\0O\0N\0 \0A\0I\0R\0 \x001\x002\0.\x001\x001\08\0 \0S\0E\0C\0T\0E\0U\0R\0 \0A\0I\0R\0 \x005\x001\0 \0C\0U\0I\0V\0R\0E\0 \0\xe0\0 \0p\0r\0i\0o\0r\0i\0 \0m\0a\0i\0s\0 \0n\0o\0n\0 \0d\0o\0r\0\xe9\0\0\0";
+        assert!(exif.ends_with(comment));
+    }
+
+    #[test]
+    fn test_mark() {
+        let mut png = std::io::Cursor::new(vec![]);
+        image::RgbaImage::new(4, 4)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let jpg = std::fs::read("tests/img/test.jpg").unwrap();
+
+        for output in [png.into_inner(), jpg] {
+            assert!(!is_marked(output.clone().into()));
+            let marked = mark(output.into());
+            assert!(is_marked(marked.clone()));
+            image::load_from_memory(&marked).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_png_texts() {
+        use bytes::Bytes;
+        use img_parts::png::{Png, PngChunk};
+
+        let encode = || {
+            let mut png = std::io::Cursor::new(vec![]);
+            image::RgbaImage::new(4, 4)
+                .write_to(&mut png, image::ImageFormat::Png)
+                .unwrap();
+            Png::from_bytes(png.into_inner().into()).unwrap()
+        };
+        let texts = [
+            PngChunk::new(*b"tEXt", Bytes::from_static(b"Software\0GIMP")),
+            PngChunk::new(*b"iTXt", Bytes::from_static(b"Comment\0\0\0\0\0Sunset")),
+        ];
+        let mut input = encode();
+        for text in texts.clone() {
+            insert_chunk(&mut input, text);
+        }
+
+        let path = std::path::Path::new("test.png");
+        let output = embed_metadata(
+            path,
+            input.encoder().bytes(),
+            path,
+            encode().encoder().bytes(),
+            &MetadataPolicy::default(),
+        );
+        let output = Png::from_bytes(output).unwrap();
+        let output_texts = output
+            .chunks()
+            .iter()
+            .filter(|chunk| PNG_TEXTS.contains(&chunk.kind()))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert!(output_texts == texts);
+    }
+
+    #[test]
+    fn test_webp_metadata() {
+        use img_parts::webp::WebP;
+        use img_parts::ImageICC;
+
+        let encode = || {
+            let mut webp = std::io::Cursor::new(vec![]);
+            image::RgbaImage::new(4, 4)
+                .write_to(&mut webp, image::ImageFormat::WebP)
+                .unwrap();
+            webp.into_inner()
+        };
+        let from = std::path::Path::new("data/exif/comments.jpg");
+        let exif = Jpeg::from_bytes(std::fs::read(from).unwrap().into())
+            .unwrap()
+            .exif()
+            .unwrap();
+        let mut input = WebP::from_bytes(encode().into()).unwrap();
+        input.set_exif(Some(exif.clone()));
+        input.set_icc_profile(Some(bytes::Bytes::from_static(b"icc profile")));
+
+        let path = std::path::Path::new("test.webp");
+        let output = embed_metadata(
+            path,
+            input.encoder().bytes(),
+            path,
+            encode().into(),
+            &MetadataPolicy::default(),
+        );
+        image::load_from_memory(&output).unwrap();
+        let output = WebP::from_bytes(output).unwrap();
+        assert_eq!(output.exif(), Some(exif));
+        assert_eq!(output.icc_profile().as_deref(), Some(&b"icc profile"[..]));
+    }
+
+    #[test]
+    fn test_webp_xmp() {
+        use super::{parse_metadata, set_webp_xmp, MetadataAction, WEBP_ALPHA, WEBP_XMP};
+        use img_parts::webp::{WebP, CHUNK_VP8X};
+
+        let encode = || {
+            let mut webp = std::io::Cursor::new(vec![]);
+            image::RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 0, 100]))
+                .write_to(&mut webp, image::ImageFormat::WebP)
+                .unwrap();
+            bytes::Bytes::from(webp.into_inner())
+        };
+        let xmp = bytes::Bytes::from_static(b"<x:xmpmeta/>");
+        let mut input = WebP::from_bytes(encode()).unwrap();
+        set_webp_xmp(&mut input, Some(xmp.clone()));
+        let input = input.encoder().bytes();
+
+        let path = std::path::Path::new("test.webp");
+        let output = embed_metadata(path, input.clone(), path, encode(), &Default::default());
+        let img = image::load_from_memory(&output).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 100]);
+        assert_eq!(parse_metadata(output.clone()).unwrap().xmp, Some(xmp));
+        let flags = WebP::from_bytes(output)
+            .unwrap()
+            .chunk_by_id(CHUNK_VP8X)
+            .unwrap()
+            .content()
+            .data()
+            .unwrap()[0];
+        assert_eq!(flags & (WEBP_ALPHA | WEBP_XMP), WEBP_ALPHA | WEBP_XMP);
+
+        let policy = MetadataPolicy {
+            xmp: MetadataAction::Strip,
+            ..Default::default()
+        };
+        let output = embed_metadata(path, input, path, encode(), &policy);
+        image::load_from_memory(&output).unwrap();
+        assert_eq!(parse_metadata(output).unwrap().xmp, None);
+    }
+
+    #[test]
+    fn test_edit_fields() {
+        use super::{Metadata, ARTIST, SOFTWARE};
+
+        let path = std::path::PathBuf::from("tmp/metadata/edit_fields.jpg");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::copy("data/exif/notes.jpg", &path).unwrap();
+
+        let mut metadata = Metadata::read(&path).unwrap();
+        assert_eq!(
+            metadata.field(SOFTWARE).as_deref(),
+            Some("Nikon Transfer 1.1 W")
+        );
+        metadata
+            .set_field(SOFTWARE, "filigram")
+            .set_field(ARTIST, "Bob");
+        metadata.write_to(&path).unwrap();
+
+        let metadata = Metadata::read(&path).unwrap();
+        assert_eq!(metadata.field(SOFTWARE).as_deref(), Some("filigram"));
+        assert_eq!(metadata.field(ARTIST).as_deref(), Some("Bob"));
+        image::open(&path).unwrap();
+
+        assert!(Metadata::read("tests/img/test.bmp".as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_metadata_policy() {
+        use super::{MetadataAction, MetadataPolicy, ThumbnailAction, ARTIST};
+        use img_parts::jpeg::{markers, JpegSegment};
+        use img_parts::ImageICC;
+
+        let from = std::path::Path::new("data/exif/notes.jpg");
+        let mut input = Jpeg::from_bytes(std::fs::read(from).unwrap().into()).unwrap();
+        input.set_icc_profile(Some(bytes::Bytes::from_static(b"icc profile")));
+        let xmp = [super::JPEG_XMP, b"<x:xmpmeta/>"].concat();
+        input
+            .segments_mut()
+            .insert(1, JpegSegment::new_with_contents(markers::APP1, xmp.into()));
+        input.segments_mut().insert(
+            2,
+            JpegSegment::new_with_contents(markers::COM, bytes::Bytes::from_static(b"Sunset")),
+        );
+        let input = input.encoder().bytes();
+        let output = std::fs::read("tests/img/test.jpg").unwrap();
+        let embed = |to: &str, output: Vec<u8>, policy: &MetadataPolicy| {
+            let to = std::path::Path::new(to);
+            let output = embed_metadata(from, input.clone(), to, output.into(), policy);
+            image::load_from_memory(&output).unwrap();
+            super::parse_metadata(output).unwrap()
+        };
+
+        let kept = embed("test.jpg", output.clone(), &MetadataPolicy::default());
+        assert_eq!(kept.exif, Jpeg::from_bytes(input.clone()).unwrap().exif());
+        assert_eq!(kept.icc_profile.as_deref(), Some(&b"icc profile"[..]));
+        assert_eq!(kept.xmp(), Some(&b"<x:xmpmeta/>"[..]));
+        assert_eq!(kept.comments, [&b"Sunset"[..]]);
+
+        let policy = MetadataPolicy {
+            exif: MetadataAction::Replace(crate::exif::set_ascii(
+                None,
+                &[(ARTIST, "Bob".to_string())],
+            )),
+            icc_profile: MetadataAction::Strip,
+            xmp: MetadataAction::Strip,
+            gps: MetadataAction::Keep,
+            comments: MetadataAction::Replace(b"Delivered".to_vec()),
+            thumbnail: ThumbnailAction::Keep,
+        };
+        let replaced = embed("test.jpg", output, &policy);
+        assert_eq!(replaced.field(ARTIST).as_deref(), Some("Bob"));
+        assert_eq!(replaced.icc_profile, None);
+        assert_eq!(replaced.xmp(), None);
+        assert_eq!(replaced.comments, [&b"Delivered"[..]]);
+
+        // into a PNG image
+        let mut png = std::io::Cursor::new(vec![]);
+        image::RgbaImage::new(4, 4)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+        let replaced = embed("test.png", png.clone(), &policy);
+        assert_eq!(replaced.xmp(), None);
+        assert_eq!(replaced.texts.len(), 1);
+        assert_eq!(replaced.texts[0].contents(), &b"Comment\0Delivered"[..]);
+        let kept = embed("test.png", png, &MetadataPolicy::default());
+        assert_eq!(kept.xmp(), Some(&b"<x:xmpmeta/>"[..]));
+        assert!(kept.texts.is_empty());
+    }
+
+    #[test]
+    fn test_read_metadata() {
+        use super::{icc_description, read_metadata, ImageMetadata};
+
+        let metadata = read_metadata("data/exif/notes.jpg".as_ref()).unwrap();
+        assert_eq!(
+            metadata,
+            ImageMetadata {
+                format: image::ImageFormat::Jpeg,
+                dimensions: (640, 480),
+                has_exif: true,
+                capture_date: Some("2008:10:22 16:38:20".to_string()),
+                camera: Some("NIKON COOLPIX P6000".to_string()),
+                icc_name: None,
+            }
+        );
+        let metadata = read_metadata("tests/img/test.bmp".as_ref()).unwrap();
+        assert_eq!(metadata.format, image::ImageFormat::Bmp);
+        assert!(!metadata.has_exif && metadata.camera.is_none());
+
+        // header, tag table with `desc` at offset 144 and its content
+        let icc = |desc: &[u8]| {
+            let mut icc = vec![0; 128];
+            icc.extend(1u32.to_be_bytes());
+            icc.extend(b"desc\0\0\0\x90");
+            icc.extend((desc.len() as u32).to_be_bytes());
+            icc.extend(desc);
+            icc
+        };
+        let v2 = [&b"desc\0\0\0\0\0\0\0\x05sRGB\0"[..], &[0; 79]].concat();
+        assert_eq!(icc_description(&icc(&v2)).as_deref(), Some("sRGB"));
+        let mut v4 = b"mluc\0\0\0\0\0\0\0\x01\0\0\0\x0cenUS\0\0\0\x08\0\0\0\x1c".to_vec();
+        v4.extend("P3 D".encode_utf16().flat_map(u16::to_be_bytes));
+        assert_eq!(icc_description(&icc(&v4)).as_deref(), Some("P3 D"));
+        assert_eq!(icc_description(&icc(b"text")), None);
+        assert_eq!(icc_description(b"not icc"), None);
+    }
+
+    #[test]
+    fn test_misnamed_container() {
+        use super::{Metadata, MetadataError, MetadataPolicy};
+        use image::ImageFormat;
+
+        // JPEG image with a PNG extension, its metadata is copied as JPEG metadata
+        let from = std::path::Path::new("data/exif/notes.jpg");
+        let input = std::fs::read(from).unwrap();
+        let output = std::fs::read("tests/img/test.jpg").unwrap();
+        let output = embed_metadata(
+            "misnamed.png".as_ref(),
+            input.clone().into(),
+            "misnamed.png".as_ref(),
+            output.into(),
+            &MetadataPolicy::default(),
+        );
+        let exif = Jpeg::from_bytes(input.into()).unwrap().exif();
+        assert_eq!(Jpeg::from_bytes(output).unwrap().exif(), exif);
+
+        let error = Metadata::read("tests/img/test.bmp".as_ref()).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MetadataError>(),
+            Some(MetadataError::UnsupportedFormat(Some(ImageFormat::Bmp)))
+        ));
+        let error = super::parse_metadata(b"\xff\xd8\xff truncated".to_vec().into()).unwrap_err();
+        assert!(matches!(
+            error,
+            MetadataError::InvalidContainer(ImageFormat::Jpeg, _)
+        ));
+    }
+
+    #[test]
+    fn test_process_thumbnail() {
+        use super::{process_thumbnail, MetadataPolicy, ThumbnailAction};
+
+        let from = std::path::Path::new("data/exif/notes.jpg");
+        let img = image::open("tests/img/test.jpg").unwrap();
+        let output = embed_metadata(
+            from,
+            std::fs::read(from).unwrap().into(),
+            "test.jpg".as_ref(),
+            std::fs::read("tests/img/test.jpg").unwrap().into(),
+            &MetadataPolicy::default(),
+        );
+        let exif =
+            |output: &bytes::Bytes| Jpeg::from_bytes(output.clone()).unwrap().exif().unwrap();
+        let source_thumbnail = crate::exif::thumbnail(&exif(&output)).unwrap().to_vec();
+
+        let kept = process_thumbnail(output.clone(), &img, ThumbnailAction::Keep).unwrap();
+        assert_eq!(kept, output);
+
+        let stripped = process_thumbnail(output.clone(), &img, ThumbnailAction::Strip).unwrap();
+        assert_eq!(crate::exif::thumbnail(&exif(&stripped)), None);
+
+        let regenerated = process_thumbnail(output, &img, ThumbnailAction::Regenerate).unwrap();
+        let exif = exif(&regenerated);
+        let thumbnail = crate::exif::thumbnail(&exif).unwrap();
+        assert_ne!(thumbnail, source_thumbnail);
+        let thumbnail = image::load_from_memory(thumbnail).unwrap();
+        assert!(thumbnail.width() <= 160 && thumbnail.height() <= 120);
+        image::load_from_memory(&regenerated).unwrap();
+    }
+
+    #[test]
+    fn test_exif_size_limit() {
+        use super::{fit_exif, process_thumbnail, MetadataError, ThumbnailAction, JPEG_MAX_EXIF};
+        use crate::exif::{replace_thumbnail, thumbnail};
+        use image::ImageFormat;
+
+        // Exif of a camera padded up to the size limit of JPEG images, to an even length
+        // as appended thumbnails start on a word boundary
+        let input = std::fs::read("data/exif/notes.jpg").unwrap();
+        let mut exif = Jpeg::from_bytes(input.into())
+            .unwrap()
+            .exif()
+            .unwrap()
+            .to_vec();
+        exif.resize(JPEG_MAX_EXIF - 1, 0);
+        let mut jpeg =
+            Jpeg::from_bytes(std::fs::read("tests/img/test.jpg").unwrap().into()).unwrap();
+        jpeg.set_exif(Some(exif.clone().into()));
+        let output = jpeg.encoder().bytes();
+
+        let img = image::open("tests/img/test.jpg").unwrap();
+        let regenerated = process_thumbnail(output, &img, ThumbnailAction::Regenerate).unwrap();
+        let regenerated_exif = Jpeg::from_bytes(regenerated.clone())
+            .unwrap()
+            .exif()
+            .unwrap();
+        assert!(regenerated_exif.len() <= JPEG_MAX_EXIF);
+        image::load_from_memory(&regenerated).unwrap();
+
+        // a larger thumbnail doesn't fit, dropped
+        let large = replace_thumbnail(&exif, &[0xff; 8000]);
+        assert!(large.len() > JPEG_MAX_EXIF);
+        let fitted = fit_exif(ImageFormat::Jpeg, large.clone()).unwrap();
+        assert!(fitted.len() <= JPEG_MAX_EXIF);
+        assert_eq!(thumbnail(&fitted), None);
+        assert_eq!(fit_exif(ImageFormat::Png, large.clone()).unwrap(), large);
+
+        let mut padded = large;
+        padded.resize(padded.len() + 10, 0);
+        assert!(matches!(
+            fit_exif(ImageFormat::Jpeg, padded),
+            Err(MetadataError::ExifTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_exif_fields_size_limit() {
+        use super::{
+            parse_metadata, set_exif_fields, write_metadata, MetadataError, JPEG_MAX_EXIF,
+        };
+        use crate::exif::COPYRIGHT;
+
+        // Exif without thumbnail, a few bytes under the size limit of JPEG images
+        let mut exif = crate::exif::set_ascii(None, &[(COPYRIGHT, "(c) Studio".to_string())]);
+        exif.resize(JPEG_MAX_EXIF - 8, 0);
+        let mut jpeg =
+            Jpeg::from_bytes(std::fs::read("tests/img/test.jpg").unwrap().into()).unwrap();
+        jpeg.set_exif(Some(exif.into()));
+        let output = jpeg.encoder().bytes();
+
+        let fields = [(COPYRIGHT, "(c) Other Studio".to_string())];
+        let error = set_exif_fields(output.clone(), &fields).unwrap_err();
+        assert!(matches!(error, MetadataError::ExifTooLarge(_)));
+
+        let mut metadata = parse_metadata(output.clone()).unwrap();
+        metadata.set_field(COPYRIGHT, "(c) Other Studio");
+        let error = write_metadata(output, metadata).unwrap_err();
+        assert!(matches!(error, MetadataError::ExifTooLarge(_)));
+    }
+
+    #[test]
+    fn test_gps_size_limit() {
+        use super::{MetadataAction, MetadataPolicy, JPEG_MAX_EXIF};
+        use crate::exif::strip_gps;
+
+        // Exif of a camera, with GPS data, padded up to the size limit of JPEG images
+        let input = std::fs::read("data/exif/notes.jpg").unwrap();
+        let mut exif = Jpeg::from_bytes(input.into())
+            .unwrap()
+            .exif()
+            .unwrap()
+            .to_vec();
+        exif.resize(JPEG_MAX_EXIF, 0);
+        let mut jpeg =
+            Jpeg::from_bytes(std::fs::read("tests/img/test.jpg").unwrap().into()).unwrap();
+        jpeg.set_exif(Some(exif.clone().into()));
+        let input = jpeg.encoder().bytes();
+        let output = std::fs::read("tests/img/test.jpg").unwrap();
+
+        let embed = |gps| {
+            let policy = MetadataPolicy {
+                gps,
+                ..Default::default()
+            };
+            let to = "large.jpg".as_ref();
+            let embedded = embed_metadata(to, input.clone(), to, output.clone().into(), &policy);
+            Jpeg::from_bytes(embedded).unwrap().exif()
+        };
+        // stripped in place, kept within the limit
+        let stripped = embed(MetadataAction::Strip).unwrap();
+        assert_eq!(stripped.len(), exif.len());
+        assert_eq!(strip_gps(&stripped), stripped);
+        // replaced, too large for the output: its metadata is left out
+        assert_eq!(embed(MetadataAction::Replace(exif.clone())), None);
+    }
+}
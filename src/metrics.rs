@@ -0,0 +1,294 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "walkdir")]
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::hooks::Outcome;
+use crate::report::{as_secs, from_secs, FileReport, Report};
+
+// Files whose processing time is kept by `Totals` for percentiles, the latest ones
+#[cfg(feature = "walkdir")]
+const RECENT_FILES: usize = 10_000;
+
+/// Time spent in each stage of the watermarking of a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+    /// Reading and decoding the source image
+    #[serde(serialize_with = "as_secs", deserialize_with = "from_secs")]
+    pub decode: Duration,
+    /// Running the chain of processors (resizing, compositing...)
+    #[serde(serialize_with = "as_secs", deserialize_with = "from_secs")]
+    pub process: Duration,
+    /// Encoding, embedding metadata and writing the output image
+    #[serde(serialize_with = "as_secs", deserialize_with = "from_secs")]
+    pub encode: Duration,
+}
+
+/// Aggregated metrics of a run, see `Report::metrics`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metrics {
+    /// Number of files, by outcome
+    pub outcomes: Vec<(Outcome, usize)>,
+    /// Total size of source files
+    pub bytes_in: u64,
+    /// Total size of output files
+    pub bytes_out: u64,
+    /// Total duration of the run
+    #[serde(serialize_with = "as_secs")]
+    pub elapsed: Duration,
+    /// Source megabytes (10^6 bytes) processed per second
+    pub throughput_mb_s: f64,
+    /// Median processing time of a file
+    #[serde(serialize_with = "as_secs")]
+    pub p50: Duration,
+    /// 95th percentile of the processing time of a file
+    #[serde(serialize_with = "as_secs")]
+    pub p95: Duration,
+    /// Longest processing time of a file
+    #[serde(serialize_with = "as_secs")]
+    pub max: Duration,
+    /// Sum of the time spent in each stage by all workers
+    pub stages: Timings,
+}
+
+impl Metrics {
+    pub(crate) fn new(report: &Report) -> Self {
+        let mut metrics = Metrics::default();
+        for file in &report.files {
+            metrics.add(file);
+        }
+        let durations = report.files.iter().map(|file| file.duration).collect();
+        metrics.complete(durations, report.elapsed)
+    }
+
+    /// Number of files processed with the given `outcome`
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.outcomes
+            .iter()
+            .find(|(other, _)| *other == outcome)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    // Sum up `file` with the files added so far
+    fn add(&mut self, file: &FileReport) {
+        match self
+            .outcomes
+            .iter_mut()
+            .find(|(outcome, _)| *outcome == file.outcome)
+        {
+            Some((_, count)) => *count += 1,
+            None => self.outcomes.push((file.outcome, 1)),
+        }
+        self.bytes_in += file.bytes_in;
+        self.bytes_out += file.bytes_out;
+        if let Some(timings) = &file.timings {
+            self.stages.decode += timings.decode;
+            self.stages.process += timings.process;
+            self.stages.encode += timings.encode;
+        }
+        self.max = self.max.max(file.duration);
+    }
+
+    // Metrics of a run of `elapsed` once all its files are added,
+    // with the percentiles of `durations`
+    fn complete(mut self, mut durations: Vec<Duration>, elapsed: Duration) -> Self {
+        durations.sort();
+        self.p50 = percentile(&durations, 50);
+        self.p95 = percentile(&durations, 95);
+        self.elapsed = elapsed;
+        if !elapsed.is_zero() {
+            self.throughput_mb_s = self.bytes_in as f64 / 1e6 / elapsed.as_secs_f64();
+        }
+        self
+    }
+
+    /// Metrics in Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+            let _ = writeln!(text, "# HELP filigram_{name} {help}");
+            let _ = writeln!(text, "# TYPE filigram_{name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(text, "filigram_{name}{labels} {value}");
+            }
+        };
+
+        let outcomes = self
+            .outcomes
+            .iter()
+            .map(|(outcome, count)| (format!("{{outcome=\"{}\"}}", outcome.as_str()), count))
+            .collect::<Vec<_>>();
+        metric(
+            "files_total",
+            "counter",
+            "Number of files processed, by outcome",
+            &outcomes
+                .iter()
+                .map(|(labels, count)| (labels.as_str(), count.to_string()))
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "read_bytes_total",
+            "counter",
+            "Total size of source files",
+            &[("", self.bytes_in.to_string())],
+        );
+        metric(
+            "written_bytes_total",
+            "counter",
+            "Total size of output files",
+            &[("", self.bytes_out.to_string())],
+        );
+        metric(
+            "run_duration_seconds",
+            "gauge",
+            "Duration of the run",
+            &[("", self.elapsed.as_secs_f64().to_string())],
+        );
+        metric(
+            "throughput_megabytes_per_second",
+            "gauge",
+            "Source megabytes processed per second",
+            &[("", self.throughput_mb_s.to_string())],
+        );
+        metric(
+            "file_duration_seconds",
+            "gauge",
+            "Processing time of a file, by quantile",
+            &[
+                ("{quantile=\"0.5\"}", self.p50.as_secs_f64().to_string()),
+                ("{quantile=\"0.95\"}", self.p95.as_secs_f64().to_string()),
+                ("{quantile=\"1\"}", self.max.as_secs_f64().to_string()),
+            ],
+        );
+        metric(
+            "stage_duration_seconds_total",
+            "counter",
+            "Time spent in each stage by all workers",
+            &[
+                (
+                    "{stage=\"decode\"}",
+                    self.stages.decode.as_secs_f64().to_string(),
+                ),
+                (
+                    "{stage=\"process\"}",
+                    self.stages.process.as_secs_f64().to_string(),
+                ),
+                (
+                    "{stage=\"encode\"}",
+                    self.stages.encode.as_secs_f64().to_string(),
+                ),
+            ],
+        );
+        text
+    }
+}
+
+/// Metrics summed up as files are processed, for runs which don't keep
+/// the reports of their files, see `watch`
+#[cfg(feature = "walkdir")]
+#[derive(Default)]
+pub(crate) struct Totals {
+    metrics: Metrics,
+    // processing times of the latest files, for percentiles
+    durations: VecDeque<Duration>,
+}
+
+#[cfg(feature = "walkdir")]
+impl Totals {
+    /// Sum up `file`, whose report can then be dropped
+    pub(crate) fn add(&mut self, file: &FileReport) {
+        self.metrics.add(file);
+        if self.durations.len() == RECENT_FILES {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(file.duration);
+    }
+
+    /// Metrics of a run of `elapsed`, percentiles being those of the latest files
+    pub(crate) fn metrics(self, elapsed: Duration) -> Metrics {
+        self.metrics.complete(self.durations.into(), elapsed)
+    }
+}
+
+// Nearest-rank percentile of sorted `durations`
+fn percentile(durations: &[Duration], percent: usize) -> Duration {
+    if durations.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (durations.len() * percent).div_ceil(100);
+    durations[rank.saturating_sub(1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::percentile;
+    use crate::hooks::Outcome;
+    use crate::report::{FileReport, Report};
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn test_percentile() {
+        let durations = (1..=20).map(Duration::from_secs).collect::<Vec<_>>();
+        assert_eq!(percentile(&durations, 50), Duration::from_secs(10));
+        assert_eq!(percentile(&durations, 95), Duration::from_secs(19));
+        assert_eq!(percentile(&durations, 100), Duration::from_secs(20));
+        assert_eq!(percentile(&[], 95), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let report = Report {
+            files: vec![
+                FileReport {
+                    bytes_in: 2_000_000,
+                    duration: Duration::from_secs(1),
+                    ..FileReport::new(Path::new("a.jpg"), Outcome::Watermarked, None)
+                },
+                FileReport::new(Path::new("b.txt"), Outcome::Copied, None),
+            ],
+            elapsed: Duration::from_secs(2),
+        };
+        let metrics = report.metrics();
+        assert_eq!(metrics.throughput_mb_s, 1.0);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("filigram_files_total{outcome=\"watermarked\"} 1\n"));
+        assert!(text.contains("filigram_files_total{outcome=\"copied\"} 1\n"));
+        assert!(text.contains("filigram_read_bytes_total 2000000\n"));
+        assert!(text.contains("filigram_file_duration_seconds{quantile=\"0.95\"} 1\n"));
+    }
+    #[cfg(feature = "walkdir")]
+    #[test]
+    fn test_totals() {
+        use super::{Totals, RECENT_FILES};
+
+        let file = |secs| FileReport {
+            bytes_in: 1000,
+            duration: Duration::from_secs(secs),
+            ..FileReport::new(Path::new("a.jpg"), Outcome::Watermarked, None)
+        };
+        let report = Report {
+            files: (1..=20).map(file).collect(),
+            elapsed: Duration::from_secs(10),
+        };
+        let mut totals = Totals::default();
+        for file in &report.files {
+            totals.add(file);
+        }
+        assert_eq!(totals.metrics(report.elapsed), report.metrics());
+
+        // percentiles of the latest files only, but the longest of all of them
+        let mut totals = Totals::default();
+        totals.add(&file(100));
+        for _ in 0..RECENT_FILES {
+            totals.add(&file(1));
+        }
+        let metrics = totals.metrics(Duration::from_secs(1));
+        assert_eq!(metrics.count(Outcome::Watermarked), RECENT_FILES + 1);
+        assert_eq!(metrics.p95, Duration::from_secs(1));
+        assert_eq!(metrics.max, Duration::from_secs(100));
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::Path;
+
+/// Naming of watermarked files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Naming {
+    /// Keep the name of the source file
+    #[default]
+    Keep,
+    /// Append a suffix to the file stem
+    /// i.e.: `Suffix("_wm")` writes "photo.jpg" as "photo_wm.jpg"
+    Suffix(String),
+    /// Build the name from a pattern, with placeholders:
+    /// `{stem}`, `{ext}` (source extension), `{width}` and `{height}` (output dimensions)
+    /// i.e.: `Pattern("{stem}-{width}px.{ext}")` writes "photo.jpg" as "photo-500px.jpg".
+    /// The output format follows the resulting extension
+    Pattern(String),
+}
+
+impl Naming {
+    /// Name of the output file of `path`, given the output dimensions
+    pub fn file_name(&self, path: &Path, width: u32, height: u32) -> String {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy())
+            .unwrap_or_default();
+
+        match self {
+            Naming::Keep => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Naming::Suffix(suffix) if ext.is_empty() => format!("{stem}{suffix}"),
+            Naming::Suffix(suffix) => format!("{stem}{suffix}.{ext}"),
+            Naming::Pattern(pattern) => pattern
+                .replace("{stem}", &stem)
+                .replace("{ext}", &ext)
+                .replace("{width}", &width.to_string())
+                .replace("{height}", &height.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Naming;
+    use std::path::Path;
+
+    #[test]
+    fn test_keep() {
+        let name = Naming::Keep.file_name(Path::new("dir/photo.JPG"), 500, 500);
+        assert_eq!(name, "photo.JPG");
+    }
+
+    #[test]
+    fn test_suffix() {
+        let naming = Naming::Suffix("_wm".to_string());
+        assert_eq!(
+            naming.file_name(Path::new("dir/photo.jpg"), 500, 500),
+            "photo_wm.jpg"
+        );
+        assert_eq!(
+            naming.file_name(Path::new("dir/archive.tar.png"), 500, 500),
+            "archive.tar_wm.png"
+        );
+    }
+
+    #[test]
+    fn test_pattern() {
+        let naming = Naming::Pattern("{stem}-{width}x{height}.{ext}".to_string());
+        assert_eq!(
+            naming.file_name(Path::new("dir/photo.jpg"), 640, 480),
+            "photo-640x480.jpg"
+        );
+    }
+}
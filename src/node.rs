@@ -0,0 +1,169 @@
+use ab_glyph::PxScale;
+use image::Rgba;
+use napi::bindgen_prelude::{AsyncTask, Buffer};
+use napi::threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, Error, JsFunction, Result, Task};
+use napi_derive::napi;
+use std::fs;
+
+use crate::config::Config;
+use crate::options::Options;
+use crate::watermark_bytes;
+
+/// Customization of the watermark, missing fields take their default value
+#[napi(object)]
+#[derive(Debug, Default)]
+pub struct WatermarkOptions {
+    pub text: Option<String>,
+    /// Red, green, blue and alpha channels, from 0 to 255
+    pub color: Option<Vec<u32>>,
+    /// Height of the watermark text, in pixels
+    pub scale: Option<f64>,
+    pub copyright: Option<String>,
+    pub artist: Option<String>,
+    pub description: Option<String>,
+}
+
+impl WatermarkOptions {
+    fn into_config(self) -> Result<Config> {
+        let default = Config::default();
+        let color = match self.color.as_deref() {
+            None => default.color,
+            Some(&[r, g, b, a]) if [r, g, b, a].iter().all(|c| *c <= 255) => {
+                Rgba([r as u8, g as u8, b as u8, a as u8])
+            }
+            Some(color) => {
+                return Err(Error::from_reason(format!(
+                    "{color:?} is not a color, as [r, g, b, a] from 0 to 255"
+                )))
+            }
+        };
+        Ok(Config {
+            text: self.text.unwrap_or(default.text),
+            color,
+            scale: self
+                .scale
+                .map_or(default.scale, |scale| PxScale::from(scale as f32)),
+            copyright: self.copyright,
+            artist: self.artist,
+            description: self.description,
+            ..default
+        })
+    }
+}
+
+// Callback notified of each stage of a task, on the JavaScript thread
+type Progress = ThreadsafeFunction<String, ErrorStrategy::Fatal>;
+
+/// Watermark of a file, run on the thread pool of libuv
+pub struct WatermarkFile {
+    src: String,
+    dst: String,
+    cfg: Config,
+    progress: Option<Progress>,
+}
+
+impl Task for WatermarkFile {
+    type Output = ();
+    type JsValue = ();
+
+    fn compute(&mut self) -> Result<()> {
+        let input = fs::read(&self.src).map_err(|e| reason(&self.src, e))?;
+        notify(&self.progress, "read");
+        let output = watermark_bytes(&input, &self.cfg, &Options::default())
+            .map_err(|e| reason(&self.src, e))?;
+        notify(&self.progress, "watermarked");
+        fs::write(&self.dst, output).map_err(|e| reason(&self.dst, e))?;
+        notify(&self.progress, "written");
+        Ok(())
+    }
+
+    fn resolve(&mut self, _env: Env, _output: ()) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Watermark of an image in memory, run on the thread pool of libuv
+pub struct WatermarkBuffer {
+    input: Buffer,
+    cfg: Config,
+    progress: Option<Progress>,
+}
+
+impl Task for WatermarkBuffer {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Vec<u8>> {
+        let output = watermark_bytes(&self.input, &self.cfg, &Options::default())
+            .map_err(|e| Error::from_reason(e.to_string()))?;
+        notify(&self.progress, "watermarked");
+        Ok(output)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Vec<u8>) -> Result<Buffer> {
+        Ok(output.into())
+    }
+}
+
+/// Watermark image `src` into `dst`, in the format of its extension.
+/// `onProgress` is called with each stage done: "read", "watermarked", "written"
+#[napi(
+    js_name = "watermarkFile",
+    ts_args_type = "src: string, dst: string, options?: WatermarkOptions, onProgress?: (stage: string) => void",
+    ts_return_type = "Promise<void>"
+)]
+pub fn watermark_file(
+    src: String,
+    dst: String,
+    options: Option<WatermarkOptions>,
+    on_progress: Option<JsFunction>,
+) -> Result<AsyncTask<WatermarkFile>> {
+    Ok(AsyncTask::new(WatermarkFile {
+        src,
+        dst,
+        cfg: options.unwrap_or_default().into_config()?,
+        progress: progress(on_progress)?,
+    }))
+}
+
+/// Watermark image `input`, resolved with the watermarked image in the same format.
+/// `onProgress` is called with "watermarked" once done
+#[napi(
+    js_name = "watermarkBuffer",
+    ts_args_type = "input: Buffer, options?: WatermarkOptions, onProgress?: (stage: string) => void",
+    ts_return_type = "Promise<Buffer>"
+)]
+pub fn watermark_buffer(
+    input: Buffer,
+    options: Option<WatermarkOptions>,
+    on_progress: Option<JsFunction>,
+) -> Result<AsyncTask<WatermarkBuffer>> {
+    Ok(AsyncTask::new(WatermarkBuffer {
+        input,
+        cfg: options.unwrap_or_default().into_config()?,
+        progress: progress(on_progress)?,
+    }))
+}
+
+fn progress(on_progress: Option<JsFunction>) -> Result<Option<Progress>> {
+    on_progress
+        .map(|callback| {
+            callback.create_threadsafe_function(0, |ctx: ThreadSafeCallContext<String>| {
+                Ok(vec![ctx.value])
+            })
+        })
+        .transpose()
+}
+
+fn notify(progress: &Option<Progress>, stage: &str) {
+    if let Some(progress) = progress {
+        progress.call(stage.to_owned(), ThreadsafeFunctionCallMode::NonBlocking);
+    }
+}
+
+fn reason(path: &str, e: impl std::fmt::Display) -> Error {
+    Error::from_reason(format!("{path}: {e}"))
+}
@@ -0,0 +1,401 @@
+use crate::buffers::BufferPool;
+use crate::color::ColorPolicy;
+use crate::config::Config;
+#[cfg(feature = "c2pa")]
+use crate::credentials::ContentCredentials;
+use crate::dedup::DuplicatePolicy;
+use crate::hooks::{Event, Hooks};
+use crate::layout::{CaseCollisions, Layout, Roots};
+use crate::metadata::MetadataPolicy;
+use crate::naming::Naming;
+use crate::processor::{default_chain, Processor};
+use crate::rendition::Rendition;
+use crate::report::Manifest;
+use image::Limits;
+use regex::Regex;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+/// What is done with files not qualified for watermarking by `Rules`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnqualifiedPolicy {
+    /// Copy the file to destination
+    #[default]
+    Copy,
+    /// Write nothing in destination
+    Skip,
+    /// Create a hard link to the source file in destination
+    Hardlink,
+    /// Create a symbolic link to the (absolute) source file in destination
+    Symlink,
+}
+
+/// What is done when the target directory is located inside an input folder,
+/// where the walk would pick up the outputs being written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NestedTargetPolicy {
+    /// Leave the target directory out of the walk, with a warning
+    #[default]
+    Exclude,
+    /// Fail before processing any file
+    Fail,
+}
+
+/// What is done with XMP sidecars, i.e. `photo.xmp` next to `photo.jpg`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SidecarPolicy {
+    /// Sidecars are processed as any other file, according to `Rules`
+    /// and `UnqualifiedPolicy`
+    #[default]
+    Ignore,
+    /// Sidecars follow their image: written next to its output, with the same name
+    Copy,
+    /// Sidecars replace the XMP packet embedded in watermarked JPEG, PNG and WebP images,
+    /// nothing else is written for them. Sidecars of images which are not watermarked
+    /// are written next to their output, as with `Copy`
+    Merge,
+}
+
+/// Subset of the images watermarked by a run,
+/// i.e. to check the look of the watermark before a full run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// At most this number of images, the first ones to be processed
+    Count(usize),
+    /// This fraction of images, between 0 and 1, picked by a hash of their relative path:
+    /// the same `seed` picks the same images
+    Ratio { ratio: f64, seed: u64 },
+}
+
+/// Threads reading files ahead of the workers and writing their outputs behind them,
+/// see `Options::read_ahead`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadAhead {
+    /// Threads reading files, next to the workers processing them
+    pub threads: usize,
+    /// Files read and waiting for a worker, at most
+    pub files: usize,
+    /// Threads writing watermarked images, next to the workers encoding them.
+    /// Workers write them themselves with none
+    pub writers: usize,
+    /// Watermarked images encoded and waiting for a writer, at most
+    pub outputs: usize,
+}
+
+impl Default for ReadAhead {
+    fn default() -> Self {
+        Self {
+            threads: 4,
+            files: 16,
+            writers: 4,
+            outputs: 16,
+        }
+    }
+}
+
+/// Limits checked before decoding an image, from its header, so that an image
+/// crafted to decompress to a huge size (a decompression bomb) fails on its own
+/// instead of exhausting the memory of the run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Largest number of pixels of an image, width times height
+    pub max_pixels: Option<u64>,
+    /// Largest memory taken by an image once decoded, in bytes,
+    /// also given to the decoders for their allocations
+    pub max_memory: Option<u64>,
+}
+
+impl Default for DecodeLimits {
+    // the default limits of the `image` crate
+    fn default() -> Self {
+        Self {
+            max_pixels: None,
+            max_memory: Some(512 * 1024 * 1024),
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// No limit at all, for trusted inputs only
+    pub fn none() -> Self {
+        Self {
+            max_pixels: None,
+            max_memory: None,
+        }
+    }
+
+    // Limits given to the decoders of the `image` crate
+    pub(crate) fn image_limits(&self) -> Limits {
+        let mut limits = Limits::no_limits();
+        limits.max_alloc = self.max_memory;
+        limits
+    }
+
+    // Error if an image of `width` by `height` pixels, taking `size` bytes
+    // once decoded, exceeds the limits
+    pub(crate) fn check(&self, (width, height): (u32, u32), size: u64) -> Result<(), String> {
+        let pixels = u64::from(width) * u64::from(height);
+        if let Some(max) = self.max_pixels.filter(|max| pixels > *max) {
+            return Err(format!(
+                "Image of {width}x{height} pixels exceeds the limit of {max} pixels"
+            ));
+        }
+        if let Some(max) = self.max_memory.filter(|max| size > *max) {
+            return Err(format!(
+                "Decoded image of {size} bytes exceeds the limit of {max} bytes"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Options of the processing pipeline.
+/// Unlike `Config` (the look of the watermark) and
+/// `Rules` (which files are watermarked), these
+/// options drive how `spread_watermark` runs.
+pub struct Options {
+    /// Callbacks invoked for each processed file
+    pub hooks: Hooks,
+    /// Chain of processors applied to each qualified image.
+    /// Defaults to `processor::default_chain()`, custom stages
+    /// can be inserted before or after the `Watermark` stage
+    pub processors: Vec<Box<dyn Processor>>,
+    /// Recopy modification/access times and permissions
+    /// of source files to their outputs, either watermarked or copied
+    pub preserve_attributes: bool,
+    /// How byte-identical images are handled
+    pub duplicates: DuplicatePolicy,
+    /// Naming of watermarked files, copied files keep their name
+    pub naming: Naming,
+    /// Layout of the files written in target directory
+    pub layout: Layout,
+    /// Where files of each input folder are written, see `spread_watermark_roots`
+    pub roots: Roots,
+    /// What is done with files not qualified for watermarking
+    pub unqualified: UnqualifiedPolicy,
+    /// What is done when the target directory is inside an input folder.
+    /// A target directory being an input folder is always an error
+    pub nested_target: NestedTargetPolicy,
+    /// Manifest of the run to write once all files are processed
+    pub manifest: Option<Manifest>,
+    /// Compute SHA-256 of each source and output file, reported in `FileReport`
+    pub checksums: bool,
+    /// Record completed files in a journal in target directory
+    /// (see `journal::JOURNAL_FILE`) and skip files already recorded there,
+    /// so that an interrupted run can be resumed with the same arguments.
+    /// The journal is removed once a run completes without failure
+    pub journal: bool,
+    /// Watermarks by regular expression on the path of files relative to the input folder,
+    /// with '/' separators: images matching one of them get the watermark of the first one,
+    /// others the watermark of the run
+    /// i.e.: `(Regex::new("^clients/acme/")?, acme_config)`
+    pub watermarks: Vec<(Regex, Config)>,
+    /// Embed a marker in watermarked JPEG and PNG images,
+    /// so that they are recognized by `Rules::skip_watermarked` on later runs.
+    /// Off by default, outputs of runs with `Rules::skip_watermarked` are marked anyway
+    pub mark_outputs: bool,
+    /// Metadata of source images written in watermarked images, by category.
+    /// All of them are kept by default
+    pub metadata: MetadataPolicy,
+    /// Rotate and flip images according to their Exif orientation before processing,
+    /// the orientation copied into watermarked images is then reset to normal
+    /// so that viewers do not rotate them twice
+    pub auto_orient: bool,
+    /// What is done with XMP sidecars of images
+    pub sidecars: SidecarPolicy,
+    /// Attach C2PA content credentials, signed with the certificate of the user,
+    /// to watermarked images
+    #[cfg(feature = "c2pa")]
+    pub credentials: Option<ContentCredentials>,
+    /// Only watermark a sample of the qualified images,
+    /// all other files are skipped and reported as such
+    pub sample: Option<Sample>,
+    /// Number of worker threads processing files, in a pool dedicated to the run.
+    /// By default, files are processed in the global pool of `rayon`,
+    /// with as many threads as logical cores.
+    /// Without the `rayon` feature, files are processed one after the other
+    pub threads: Option<usize>,
+    /// Flag to cancel the run from another thread: once set, the walk stops
+    /// and files not started yet are reported as `Outcome::Cancelled`,
+    /// files being processed are completed
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Images taking more than this many bytes once decoded, as read from their header,
+    /// are skipped instead of decoded, with the reason in `FileReport::error`,
+    /// so that a huge image doesn't exhaust the memory of the run.
+    /// Processing may take a few times this size, i.e. to resize images
+    pub max_image_memory: Option<u64>,
+    /// Walk input folders completely before processing their files,
+    /// from the largest to the smallest, so that a huge file doesn't keep
+    /// a single worker busy at the end of the run
+    pub largest_first: bool,
+    /// Buffers kept by each worker to read, decode and encode the next images,
+    /// instead of allocating them for each file
+    pub buffer_pool: BufferPool,
+    /// Read images in dedicated threads, ahead of the workers processing them,
+    /// and write watermarked images in others, behind them, so that reads and writes
+    /// overlap with decoding, watermarking and encoding, i.e. on network storages.
+    /// Workers read and write files themselves by default.
+    /// Only applies to folders walked by `spread_watermark` and `spread_watermark_roots`
+    pub read_ahead: Option<ReadAhead>,
+    /// Limits of the images decoded, checked from their header:
+    /// images beyond them fail with the reason, unlike `max_image_memory` skipping them
+    pub decode_limits: DecodeLimits,
+    /// Produce byte-identical outputs from the same inputs, run after run:
+    /// encoder parameters are pinned instead of following the defaults of `image`,
+    /// folders are walked in the order of file names, tar entries are dated
+    /// 1970-01-01 and archive entries are written in the order of their names once the run is over
+    /// (i.e. a watermarked tar stream is only written at the end), and content credentials
+    /// leave out the date of the watermark. Their signature still differs between runs,
+    /// as does the choice of images with `Sample::Count` on several workers
+    pub deterministic: bool,
+    /// Color management of watermarked images, i.e. to convert wide-gamut photos to sRGB
+    pub color: ColorPolicy,
+    /// Write a gallery (`GALLERY_INDEX`) in each output directory once the run is over:
+    /// a static HTML page with the thumbnails of its watermarked images, linking to them,
+    /// i.e. for clients to browse their proofs. Only applies to folders written by
+    /// `spread_watermark`, `spread_watermark_roots`, `watermark_files` and `watch`.
+    /// An `index.html` file of the input folder is overwritten
+    pub gallery: bool,
+    /// Flag duplicate images in the report (`FileReport::duplicate`), from perceptual hashes
+    /// computed while they are decoded: exact duplicates have the same content, near duplicates
+    /// (i.e. resized or recompressed copies) have hashes differing by at most this many bits
+    /// out of 64, 0 flags visually identical images only. Each image is compared with the first
+    /// image of each group, which takes a while over hundreds of thousands of distinct images
+    pub similar_images: Option<u32>,
+    /// Leave the pixels of qualified images untouched and only write the ownership metadata
+    /// of their watermark (Exif `copyright` and `artist` of `Config`, XMP rights) in their
+    /// copies, i.e. where a visible mark is not allowed. Metadata still follows `metadata`,
+    /// `sidecars` and `mark_outputs`, and outputs keep the format of their source whatever
+    /// their name. Formats without metadata support are copied as is,
+    /// videos follow `unqualified`
+    pub metadata_only: bool,
+    /// What is done with files whose output paths only differ by case from another one,
+    /// i.e. when the target directory is on macOS or Windows
+    pub case_collisions: CaseCollisions,
+    /// Channel on which the events of the run are sent, along with the hooks:
+    /// its start, each file processed or failed, and its end.
+    /// Events are dropped once the receiver is gone
+    pub events: Option<Sender<Event>>,
+    /// Other outputs written next to the output of each watermarked image,
+    /// i.e. a web version and a thumbnail, from the same decoded image
+    /// instead of decoding it once per output. Ignored with `metadata_only`.
+    /// Renditions run their own `Rendition::processors` on the decoded image, not `processors`
+    /// whose first stage may resize it: a processor placing the watermark elsewhere,
+    /// i.e. `SalientWatermark`, must be set on each rendition too
+    pub renditions: Vec<Rendition>,
+    /// Copy files which fail to be processed, i.e. corrupt images, into the `_failed`
+    /// directory of the target directory (see `QUARANTINE_DIR`) under their relative path,
+    /// each along with a note of its error, for them to be triaged after the run.
+    /// Doesn't apply to archives and output sinks
+    pub quarantine: bool,
+    /// Map sources of at least this many bytes in memory instead of reading them
+    /// into buffers, i.e. big TIFFs, so that their content stays in the page cache
+    /// instead of being copied for each file. Sources must not be truncated or rewritten
+    /// while they are processed. Defaults to 64 MiB
+    #[cfg(feature = "mmap")]
+    pub mmap_threshold: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            hooks: Hooks::default(),
+            processors: default_chain(),
+            preserve_attributes: false,
+            duplicates: DuplicatePolicy::default(),
+            naming: Naming::default(),
+            layout: Layout::default(),
+            roots: Roots::default(),
+            unqualified: UnqualifiedPolicy::default(),
+            nested_target: NestedTargetPolicy::default(),
+            manifest: None,
+            checksums: false,
+            journal: false,
+            watermarks: vec![],
+            mark_outputs: false,
+            metadata: MetadataPolicy::default(),
+            auto_orient: false,
+            sidecars: SidecarPolicy::default(),
+            #[cfg(feature = "c2pa")]
+            credentials: None,
+            sample: None,
+            threads: None,
+            cancel: None,
+            max_image_memory: None,
+            largest_first: false,
+            buffer_pool: BufferPool::default(),
+            read_ahead: None,
+            decode_limits: DecodeLimits::default(),
+            deterministic: false,
+            color: ColorPolicy::default(),
+            gallery: false,
+            similar_images: None,
+            metadata_only: false,
+            case_collisions: CaseCollisions::default(),
+            events: None,
+            renditions: vec![],
+            quarantine: false,
+            #[cfg(feature = "mmap")]
+            mmap_threshold: Some(64 * 1024 * 1024),
+        }
+    }
+}
+
+impl Options {
+    /// Send the event built by `event` on `events`, if any
+    pub(crate) fn emit(&self, event: impl FnOnce() -> Event) {
+        if let Some(events) = &self.events {
+            // the receiver may be gone, i.e. a closed window
+            events.send(event()).ok();
+        }
+    }
+}
+
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Options");
+        debug
+            .field("hooks", &self.hooks)
+            .field("processors", &self.processors.len())
+            .field("preserve_attributes", &self.preserve_attributes)
+            .field("duplicates", &self.duplicates)
+            .field("naming", &self.naming)
+            .field("layout", &self.layout)
+            .field("roots", &self.roots)
+            .field("unqualified", &self.unqualified)
+            .field("nested_target", &self.nested_target)
+            .field("manifest", &self.manifest)
+            .field("checksums", &self.checksums)
+            .field("journal", &self.journal)
+            .field("watermarks", &self.watermarks)
+            .field("mark_outputs", &self.mark_outputs)
+            .field("metadata", &self.metadata)
+            .field("auto_orient", &self.auto_orient)
+            .field("sidecars", &self.sidecars);
+        #[cfg(feature = "c2pa")]
+        debug.field("credentials", &self.credentials);
+        debug
+            .field("sample", &self.sample)
+            .field("threads", &self.threads)
+            .field("cancel", &self.cancel)
+            .field("max_image_memory", &self.max_image_memory)
+            .field("largest_first", &self.largest_first)
+            .field("buffer_pool", &self.buffer_pool)
+            .field("read_ahead", &self.read_ahead)
+            .field("decode_limits", &self.decode_limits)
+            .field("deterministic", &self.deterministic)
+            .field("color", &self.color)
+            .field("gallery", &self.gallery)
+            .field("similar_images", &self.similar_images)
+            .field("metadata_only", &self.metadata_only)
+            .field("case_collisions", &self.case_collisions)
+            .field("events", &self.events.is_some())
+            .field("renditions", &self.renditions)
+            .field("quarantine", &self.quarantine);
+        #[cfg(feature = "mmap")]
+        debug.field("mmap_threshold", &self.mmap_threshold);
+        debug.finish()
+    }
+}
@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::archive::relative_path;
+use crate::config::Config;
+use crate::hooks::Event;
+use crate::options::{Options, SidecarPolicy, UnqualifiedPolicy};
+use crate::report::Report;
+use crate::rules::{Rules, SymlinkPolicy};
+use crate::run::{is_sidecar, Qualification, Run};
+use crate::trace::RunSpan;
+use crate::walk_folder;
+use crate::ProgressBar;
+use crate::{check_dir, complete, handle_file, nested_targets, open_journal, record_file};
+
+/// Actions intended on the files of a folder, see `plan_watermark`.
+/// It can be reviewed, edited or stored before being applied by `apply_plan`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    /// Input folder
+    pub source: PathBuf,
+    /// Target directory
+    pub target: PathBuf,
+    /// Files of the input folder, sorted by path
+    pub files: Vec<PlannedFile>,
+}
+
+/// Action intended on a file of a plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedFile {
+    /// Path of the file, relative to the input folder
+    pub path: PathBuf,
+    /// Path of its output relative to the target directory, before `Options::naming`
+    pub output: PathBuf,
+    /// What is done with the file
+    pub action: Action,
+}
+
+/// What is done with a file of a plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// File is watermarked
+    Watermark,
+    /// File follows `Options::unqualified`, copied unless it links it
+    Copy,
+    /// File is left out
+    Skip,
+}
+
+impl From<Action> for Qualification {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Watermark => Qualification::Qualified,
+            Action::Copy => Qualification::Unqualified,
+            Action::Skip => Qualification::Skipped,
+        }
+    }
+}
+
+/// Plan the watermarking of `folder` into `target_dir`, without writing anything.
+///
+/// Files are qualified by `rules` and `options` as in `spread_watermark`,
+/// their outputs are placed according to `Options::layout`
+pub fn plan_watermark<P: AsRef<Path> + ?Sized>(
+    folder: &P,
+    target_dir: &P,
+    rules: &Rules,
+    options: &Options,
+) -> Result<Plan, Box<dyn std::error::Error>> {
+    let (folder, target_dir) = (folder.as_ref(), target_dir.as_ref());
+    check_dir(folder)?;
+
+    let cfg = Config::default();
+    let run = Run::new(&cfg, rules, options)?;
+    let nested_targets = nested_targets(
+        &[(folder.to_owned(), target_dir.to_owned())],
+        options.nested_target,
+    )?;
+
+    let mut files = vec![];
+    for entry in walk_folder(folder, rules, true, &nested_targets) {
+        let entry = entry?;
+        if entry.file_type().is_dir()
+            || (rules.symlinks == SymlinkPolicy::Skip && entry.path_is_symlink())
+        {
+            continue;
+        }
+        let path = entry.path();
+        let relative_path = path.strip_prefix(folder).expect("can't strip prefix");
+        let output = options.layout.output_path(path, relative_path)?;
+
+        let action = if options.sidecars != SidecarPolicy::Ignore && is_sidecar(path) {
+            Action::Skip
+        } else {
+            let metadata = entry.metadata()?;
+            let qualification = run.qualify(
+                path,
+                relative_path,
+                metadata.len(),
+                metadata.modified().ok(),
+                || Ok(BufReader::new(File::open(path)?)),
+            );
+            match qualification {
+                Qualification::Qualified => Action::Watermark,
+                Qualification::Unqualified if options.unqualified != UnqualifiedPolicy::Skip => {
+                    Action::Copy
+                }
+                _ => Action::Skip,
+            }
+        };
+        files.push(PlannedFile {
+            path: relative_path.to_owned(),
+            output,
+            action,
+        });
+    }
+
+    Ok(Plan {
+        source: folder.to_owned(),
+        target: target_dir.to_owned(),
+        files,
+    })
+}
+
+/// Apply a watermark as planned by `plan_watermark`.
+///
+/// Each file of `plan` is handled by its action instead of by rules,
+/// other options apply as in `spread_watermark`. Files whose path or output
+/// lead out of the input folder or of the target directory fail
+pub fn apply_plan(
+    plan: &Plan,
+    cfg: &Config,
+    options: &Options,
+    progress: Option<&ProgressBar>,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    check_dir(&plan.source)?;
+
+    let planned: HashMap<_, _> = plan
+        .files
+        .iter()
+        .map(|file| (plan.source.join(&file.path), file.action.into()))
+        .collect();
+    let rules = Rules::default();
+    let span = RunSpan::new(&plan.source, &plan.target);
+    let run = Run::new(cfg, &rules, options)?
+        .with_plan(planned)
+        .quarantined_in(&plan.target);
+    let journal = open_journal(&run, &plan.source, &plan.target)?;
+    options.emit(|| Event::Started {
+        source: plan.source.clone(),
+        target: plan.target.clone(),
+    });
+    fs::create_dir_all(&plan.target)?;
+
+    if let Some(progress) = progress {
+        progress.inc_length(plan.files.len() as u64);
+    }
+    let files = run.map(plan.files.iter(), |file| {
+        let path = plan.source.join(&file.path);
+        let report = match relative_path(&file.path).zip(relative_path(&file.output)) {
+            Some((relative_path, output)) => {
+                let target_path = plan.target.join(output);
+                // a failure surfaces when the output is written
+                if let Some(parent) = target_path.parent().filter(|_| file.action != Action::Skip) {
+                    fs::create_dir_all(parent).ok();
+                }
+                handle_file(
+                    &run,
+                    &span,
+                    journal.as_ref(),
+                    &path,
+                    &relative_path,
+                    Ok(target_path),
+                    None,
+                )
+            }
+            // an edited plan may lead out of the input folder or of the target directory:
+            // such a file is neither read nor quarantined
+            None => {
+                let error = format!("unsafe path: {:?} -> {:?}", file.path, file.output);
+                let span = span.file(&path);
+                let start = Instant::now();
+                record_file(
+                    &run,
+                    &span,
+                    journal.as_ref(),
+                    &path,
+                    start,
+                    Err(error.into()),
+                )
+            }
+        };
+        if let Some(progress) = progress {
+            progress.inc(1);
+        }
+        report
+    });
+
+    complete(options, journal, files, start)
+}
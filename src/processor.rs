@@ -0,0 +1,142 @@
+use image::imageops::{crop_imm, overlay, FilterType};
+use image::{DynamicImage, RgbaImage};
+use std::path::Path;
+
+use crate::saliency::{candidate_regions, Region};
+
+/// Data available to a `Processor` for the image being processed
+#[derive(Debug)]
+pub struct Context<'a> {
+    /// Path of the source image
+    pub path: &'a Path,
+    /// Rendered watermark, as created by `create_watermark_image`
+    pub watermark: &'a RgbaImage,
+}
+
+/// A stage of the image processing chain.
+/// Qualified images are decoded, then go through each
+/// processor of the chain in order, and are finally saved.
+pub trait Processor: Send + Sync {
+    fn process(
+        &self,
+        img: DynamicImage,
+        ctx: &Context,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>>;
+}
+
+/// Resize image to exact dimensions, ignoring aspect ratio
+#[derive(Debug, Clone, Copy)]
+pub struct Resize {
+    pub width: u32,
+    pub height: u32,
+    pub filter: FilterType,
+}
+
+impl Default for Resize {
+    fn default() -> Self {
+        Self {
+            width: 500,
+            height: 500,
+            filter: FilterType::Nearest,
+        }
+    }
+}
+
+impl Processor for Resize {
+    fn process(
+        &self,
+        img: DynamicImage,
+        _ctx: &Context,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+        #[cfg(feature = "gpu")]
+        if let Some(resized) = crate::gpu::resize(&img, self.width, self.height, self.filter) {
+            return Ok(resized);
+        }
+        Ok(img.resize_exact(self.width, self.height, self.filter))
+    }
+}
+
+/// Overlay the rendered watermark on the top left corner of the image
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Watermark;
+
+impl Processor for Watermark {
+    fn process(
+        &self,
+        mut img: DynamicImage,
+        ctx: &Context,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+        #[cfg(feature = "gpu")]
+        if crate::gpu::overlay(&mut img, ctx.watermark) {
+            return Ok(img);
+        }
+        match &mut img {
+            DynamicImage::ImageRgba8(rgba) => crate::blend::overlay(rgba, ctx.watermark),
+            _ => overlay(&mut img, ctx.watermark, 0, 0),
+        }
+        Ok(img)
+    }
+}
+
+/// Chooses the region where the watermark of image `path` is placed, by its index
+/// in the candidate regions, see `SalientWatermark`
+pub type RegionChooser = Box<dyn Fn(&Path, &[Region]) -> Option<usize> + Send + Sync>;
+
+/// Overlay the rendered watermark over the least detailed region of the image,
+/// so that it doesn't cover faces or subjects, see `saliency::candidate_regions`.
+/// The region can be picked among candidates by `choose` instead,
+/// the least detailed one is kept when it returns `None`
+#[derive(Default)]
+pub struct SalientWatermark {
+    pub choose: Option<RegionChooser>,
+}
+
+impl std::fmt::Debug for SalientWatermark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SalientWatermark")
+            .field("choose", &self.choose.is_some())
+            .finish()
+    }
+}
+
+impl Processor for SalientWatermark {
+    fn process(
+        &self,
+        mut img: DynamicImage,
+        ctx: &Context,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+        // the watermark is rendered on a larger transparent canvas
+        let Some((x, y, width, height)) = opaque_bounds(ctx.watermark) else {
+            return Ok(img);
+        };
+        let watermark = crop_imm(ctx.watermark, x, y, width, height).to_image();
+
+        let regions = candidate_regions(&img, (width, height));
+        let chosen = self
+            .choose
+            .as_ref()
+            .and_then(|choose| choose(ctx.path, &regions))
+            .unwrap_or(0);
+        if let Some(region) = regions.get(chosen) {
+            overlay(&mut img, &watermark, region.x.into(), region.y.into());
+        }
+        Ok(img)
+    }
+}
+
+// Bounds (x, y, width, height) of the pixels of `img` which aren't fully transparent
+fn opaque_bounds(img: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in img.enumerate_pixels() {
+        if pixel.0[3] > 0 {
+            (x0, y0) = (x0.min(x), y0.min(y));
+            (x1, y1) = (x1.max(x), y1.max(y));
+        }
+    }
+    (x0 <= x1).then(|| (x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+}
+
+/// Chain used by default: resize to 500x500 then apply the watermark
+pub fn default_chain() -> Vec<Box<dyn Processor>> {
+    vec![Box::new(Resize::default()), Box::new(Watermark)]
+}
@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Progress of a run, standing for the progress bar of `indicatif`
+/// without the `indicatif` feature: nothing is drawn, entries are only counted
+#[derive(Debug, Default)]
+pub struct ProgressBar {
+    position: AtomicU64,
+    length: AtomicU64,
+}
+
+impl ProgressBar {
+    /// Progress of `length` entries
+    pub fn new(length: u64) -> Self {
+        Self {
+            position: AtomicU64::new(0),
+            length: AtomicU64::new(length),
+        }
+    }
+
+    /// Count `delta` more entries processed
+    pub fn inc(&self, delta: u64) {
+        self.position.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// Count `delta` more entries to process
+    pub fn inc_length(&self, delta: u64) {
+        self.length.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// Number of entries processed so far
+    pub fn position(&self) -> u64 {
+        self.position.load(Ordering::Relaxed)
+    }
+
+    /// Number of entries to process, as `indicatif` returns it
+    pub fn length(&self) -> Option<u64> {
+        Some(self.length.load(Ordering::Relaxed))
+    }
+}
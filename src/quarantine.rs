@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Directory of the target directory where files which failed are copied,
+/// see `Options::quarantine`
+pub const QUARANTINE_DIR: &str = "_failed";
+
+/// Extension appended to the name of a quarantined file for the note of its error
+pub const ERROR_NOTE_EXTENSION: &str = "error.txt";
+
+/// Copy file `path`, at `relative_path` in its input folder, into `quarantine`
+/// along with a note of its `error`. Returns the path of the copy
+pub(crate) fn quarantine(
+    quarantine: &Path,
+    path: &Path,
+    relative_path: &Path,
+    error: &dyn std::error::Error,
+) -> std::io::Result<PathBuf> {
+    // relative paths of `watermark_files` may be absolute
+    let copy = quarantine.join(
+        relative_path
+            .components()
+            .filter(|comp| matches!(comp, Component::Normal(_)))
+            .collect::<PathBuf>(),
+    );
+    if let Some(parent) = copy.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut note = copy.clone().into_os_string();
+    note.push(".");
+    note.push(ERROR_NOTE_EXTENSION);
+    fs::write(note, format!("{}\n{error}\n", path.display()))?;
+    // the note is kept even if the file can't be read anymore
+    fs::copy(path, &copy)?;
+    Ok(copy)
+}
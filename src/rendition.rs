@@ -0,0 +1,109 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbaImage};
+use std::path::{Path, PathBuf};
+
+use crate::graphics::transform_image;
+use crate::processor::{Processor, Watermark};
+
+/// Other output of each watermarked image, i.e. a web version or a thumbnail,
+/// produced from the same decoded image as the main output, see `Options::renditions`
+pub struct Rendition {
+    /// Appended to the file stem of the main output, i.e. "web" for "photo-web.jpg"
+    pub suffix: String,
+    /// Largest width and height, larger images are shrunk to fit keeping their aspect ratio,
+    /// `None` for the full resolution
+    pub max_size: Option<u32>,
+    /// Factor applied to the size of the rendered watermark, i.e. 0.5 for a thumbnail
+    pub watermark_scale: f32,
+    /// Chain of processors run once the image is shrunk, overlaying the watermark by default.
+    /// `Options::processors` don't apply to renditions
+    pub processors: Vec<Box<dyn Processor>>,
+}
+
+impl Rendition {
+    /// Rendition named by `suffix` fitting in `max_size`, with the watermark scaled by `watermark_scale`
+    pub fn new(suffix: impl Into<String>, max_size: Option<u32>, watermark_scale: f32) -> Self {
+        Self {
+            suffix: suffix.into(),
+            max_size,
+            watermark_scale,
+            processors: vec![Box::new(Watermark)],
+        }
+    }
+
+    /// `img`, decoded from `src`, shrunk and run through the processors of the rendition,
+    /// `watermark` being already scaled by `watermark_scale`, see `WatermarkCache`
+    pub(crate) fn render(
+        &self,
+        src: &Path,
+        img: &DynamicImage,
+        watermark: &RgbaImage,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+        let img = match self.max_size {
+            Some(max) if img.width() > max || img.height() > max => {
+                img.resize(max, max, FilterType::Lanczos3)
+            }
+            _ => img.clone(),
+        };
+        transform_image(src, img, &self.processors, watermark)
+    }
+}
+
+impl std::fmt::Debug for Rendition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rendition")
+            .field("suffix", &self.suffix)
+            .field("max_size", &self.max_size)
+            .field("watermark_scale", &self.watermark_scale)
+            .field("processors", &self.processors.len())
+            .finish()
+    }
+}
+
+/// Path of the rendition named by `suffix` of main output `output`
+pub(crate) fn rendition_path(output: &Path, suffix: &str) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}-{suffix}");
+    if let Some(extension) = output.extension() {
+        name = format!("{name}.{}", extension.to_string_lossy());
+    }
+    output.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba};
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(
+            rendition_path(Path::new("out/photo.jpg"), "web"),
+            Path::new("out/photo-web.jpg")
+        );
+        assert_eq!(
+            rendition_path(Path::new("out/README"), "thumb"),
+            Path::new("out/README-thumb")
+        );
+    }
+
+    #[test]
+    fn test_rendered() {
+        let img = RgbImage::from_pixel(2000, 1000, Rgb([255, 255, 255])).into();
+        // scaled by the run
+        let watermark = RgbaImage::from_pixel(200, 200, Rgba([0, 0, 0, 255]));
+
+        let web = Rendition::new("web", Some(1600), 2.0)
+            .render(Path::new("a.png"), &img, &watermark)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(web.dimensions(), (1600, 800));
+        assert_eq!(*web.get_pixel(199, 199), Rgb([0, 0, 0]));
+        assert_eq!(*web.get_pixel(200, 200), Rgb([255, 255, 255]));
+
+        let full = Rendition::new("full", None, 1.0)
+            .render(Path::new("a.png"), &img, &watermark)
+            .unwrap();
+        assert_eq!((full.width(), full.height()), (2000, 1000));
+    }
+}
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::hooks::Outcome;
+use crate::metrics::{Metrics, Timings};
+
+/// Machine-readable manifest of a run, mapping each source file to its output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Manifest {
+    /// JSON document, see `Report` for its structure
+    Json(PathBuf),
+    /// CSV file with a header line and one line per file
+    Csv(PathBuf),
+}
+
+/// Report of the processing of a single file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileReport {
+    /// Path of the source file
+    pub source: PathBuf,
+    /// Path of the output file, if any has been written
+    pub output: Option<PathBuf>,
+    /// Action taken
+    pub outcome: Outcome,
+    /// Width and height of the watermarked output
+    pub dimensions: Option<(u32, u32)>,
+    /// Processing time of the file
+    #[serde(serialize_with = "as_secs", deserialize_with = "from_secs")]
+    pub duration: Duration,
+    /// Error message when processing failed,
+    /// or why an image was skipped, see `Options::max_image_memory`
+    pub error: Option<String>,
+    /// Hexadecimal SHA-256 of the source file, see `Options::checksums`
+    /// and `Options::similar_images`
+    pub source_sha256: Option<String>,
+    /// Hexadecimal SHA-256 of the output file, see `Options::checksums`
+    pub output_sha256: Option<String>,
+    /// Size of the source file
+    #[serde(default)]
+    pub bytes_in: u64,
+    /// Size of the output file
+    #[serde(default)]
+    pub bytes_out: u64,
+    /// Time spent in each stage, for watermarked files
+    pub timings: Option<Timings>,
+    /// Perceptual hash of the source image, in hexadecimal, see `Options::similar_images`
+    pub perceptual_hash: Option<String>,
+    /// Earlier image of the report this one duplicates, see `Options::similar_images`
+    pub duplicate: Option<Duplicate>,
+    /// Paths of the other outputs of the file, see `Options::renditions`
+    #[serde(default)]
+    pub renditions: Vec<PathBuf>,
+}
+
+/// Earlier image of a report that an image duplicates, exactly or nearly
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Duplicate {
+    /// Source of the earlier image
+    pub of: PathBuf,
+    /// Both sources have the same content, byte for byte
+    pub exact: bool,
+    /// Number of bits differing between their perceptual hashes, 0 for exact duplicates
+    pub distance: u32,
+}
+
+impl FileReport {
+    pub(crate) fn new(source: &Path, outcome: Outcome, output: Option<PathBuf>) -> Self {
+        Self {
+            source: source.to_owned(),
+            output,
+            outcome,
+            dimensions: None,
+            duration: Duration::ZERO,
+            error: None,
+            source_sha256: None,
+            output_sha256: None,
+            bytes_in: 0,
+            bytes_out: 0,
+            timings: None,
+            perceptual_hash: None,
+            duplicate: None,
+            renditions: vec![],
+        }
+    }
+}
+
+/// Report of a `spread_watermark` run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    /// One report per file met during the run
+    pub files: Vec<FileReport>,
+    /// Total duration of the run
+    #[serde(serialize_with = "as_secs")]
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Number of files processed with the given `outcome`
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.outcome == outcome)
+            .count()
+    }
+
+    /// Aggregated metrics: throughput, percentiles of processing time...
+    pub fn metrics(&self) -> Metrics {
+        Metrics::new(self)
+    }
+
+    /// Reports of the files which failed to be processed
+    pub fn failed(&self) -> impl Iterator<Item = &FileReport> {
+        self.files
+            .iter()
+            .filter(|file| file.outcome == Outcome::Failed)
+    }
+
+    /// Write the report as JSON
+    pub fn write_json<W: Write>(&self, writer: W) -> Result<(), Box<dyn std::error::Error>> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Write the report as CSV, one line per file
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "source,output,outcome,width,height,duration_secs,error,source_sha256,output_sha256,bytes_in,bytes_out"
+        )?;
+        for file in &self.files {
+            let (width, height) = file
+                .dimensions
+                .map(|(width, height)| (width.to_string(), height.to_string()))
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "{},{},{:?},{},{},{},{},{},{},{},{}",
+                csv_field(&file.source.to_string_lossy()),
+                csv_field(
+                    &file
+                        .output
+                        .as_ref()
+                        .map(|output| output.to_string_lossy())
+                        .unwrap_or_default()
+                ),
+                file.outcome,
+                width,
+                height,
+                file.duration.as_secs_f64(),
+                csv_field(file.error.as_deref().unwrap_or_default()),
+                file.source_sha256.as_deref().unwrap_or_default(),
+                file.output_sha256.as_deref().unwrap_or_default(),
+                file.bytes_in,
+                file.bytes_out,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the report to the file described by `manifest`
+    pub fn write_manifest(&self, manifest: &Manifest) -> Result<(), Box<dyn std::error::Error>> {
+        match manifest {
+            Manifest::Json(path) => self.write_json(BufWriter::new(File::create(path)?))?,
+            Manifest::Csv(path) => self.write_csv(BufWriter::new(File::create(path)?))?,
+        }
+        Ok(())
+    }
+}
+
+// Quote a CSV field when required
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+pub(crate) fn as_secs<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+pub(crate) fn from_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Ok(Duration::from_secs_f64(f64::deserialize(deserializer)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileReport, Report};
+    use crate::hooks::Outcome;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_csv() {
+        let report = Report {
+            files: vec![
+                FileReport {
+                    dimensions: Some((500, 500)),
+                    ..FileReport::new(
+                        Path::new("in/a.jpg"),
+                        Outcome::Watermarked,
+                        Some(PathBuf::from("out/a.jpg")),
+                    )
+                },
+                FileReport {
+                    error: Some("bad \"magic\", really".to_string()),
+                    ..FileReport::new(Path::new("in/b,c.png"), Outcome::Failed, None)
+                },
+            ],
+            ..Default::default()
+        };
+
+        let mut csv = vec![];
+        report.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "source,output,outcome,width,height,duration_secs,error,source_sha256,output_sha256,bytes_in,bytes_out\n\
+             in/a.jpg,out/a.jpg,Watermarked,500,500,0,,,,0,0\n\
+             \"in/b,c.png\",,Failed,,,0,\"bad \"\"magic\"\", really\",,,0,0\n"
+        );
+    }
+}
@@ -1,11 +1,37 @@
-use log::debug;
-use std::path::Path;
+use image::ImageFormat;
+use regex::Regex;
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::trace::debug;
+
+/// How symbolic links met during traversal are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Follow links: linked directories are traversed and
+    /// linked files are processed as their targets.
+    /// A link loop makes the whole traversal fail
+    #[default]
+    Follow,
+    /// Ignore links, nothing is written in destination
+    Skip,
+    /// Recreate the link itself in destination, pointing to the same target
+    CopyLink,
+}
 
 /// Rules to watermark files.
 /// Using this struct you can select which
 /// files will be watermarked or not, and
 /// which folders will be traversed.
-#[derive(Debug)]
+/// Serialized rules can be loaded with `Rules::from_file`
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Rules {
     /// Name of directories to exclude
     /// if path contains a name from this list,
@@ -19,36 +45,378 @@ pub struct Rules {
     /// i.e.: "/some/path/background.png" won't be watermarked
     /// if "back" is part of `excluded_files`
     pub excluded_files: Vec<String>,
+    /// Paths of directories to exclude, relative to the input folder,
+    /// content of these dirs will not be watermarked
+    /// i.e.: "2021/temp/pic.jpg" won't be watermarked if "2021/temp" is part of
+    /// `excluded_dir_paths`, unlike "2022/temp/pic.jpg" or "2021/temple/pic.jpg"
+    pub excluded_dir_paths: Vec<String>,
+    /// Name of directories to include: if not empty, only files
+    /// under a directory from this list are watermarked,
+    /// considering their path relative to the input folder
+    /// i.e.: "exports/2024/pic.jpg" is watermarked
+    /// if "exports" is part of `included_dirs`, "drafts/pic.jpg" is not
+    pub included_dirs: Vec<String>,
+    /// Name of files to include: if not empty, only files whose
+    /// filename starts with a name from this list are watermarked
+    /// i.e.: "final_cover.jpg" is watermarked if "final_" is part of `included_files`
+    pub included_files: Vec<String>,
     /// Extensions allowed to be watermarked
     /// i.e.: ["png", "jpg", ...]
     pub authorized_extensions: Vec<String>,
+    /// MIME types allowed to be watermarked, in addition to `authorized_extensions`,
+    /// resolved from the format given by content (see `sniff_content`) or by extension
+    /// i.e.: ["image/jpeg", "image/*"]
+    pub authorized_mime_types: Vec<String>,
+    /// How symbolic links are handled
+    pub symlinks: SymlinkPolicy,
+    /// Regular expressions on the path of files relative to the input folder,
+    /// with '/' separators: if not empty, only files matching one of them
+    /// are watermarked
+    /// i.e.: `^exports/.*\.png$`
+    #[serde(serialize_with = "as_patterns", deserialize_with = "from_patterns")]
+    pub included_paths: Vec<Regex>,
+    /// Regular expressions on the path of files relative to the input folder,
+    /// with '/' separators: files matching one of them are not watermarked
+    /// i.e.: `-proof\.[^.]+$` excludes "shoot/pic-proof.jpg"
+    #[serde(serialize_with = "as_patterns", deserialize_with = "from_patterns")]
+    pub excluded_paths: Vec<Regex>,
+    /// Minimum size of files to watermark, in bytes
+    pub min_file_size: Option<u64>,
+    /// Maximum size of files to watermark, in bytes
+    pub max_file_size: Option<u64>,
+    /// Files out of `min_file_size`..=`max_file_size` are skipped,
+    /// whatever their kind, instead of following `Options::unqualified`
+    pub skip_out_of_size: bool,
+    /// Only files modified at or after this time are processed, others are skipped
+    /// i.e.: `Some(SystemTime::now() - Duration::from_secs(30 * 24 * 3600))`
+    /// for files modified during the last 30 days
+    #[serde(serialize_with = "as_timestamp", deserialize_with = "from_timestamp")]
+    pub modified_after: Option<SystemTime>,
+    /// Only files modified before this time are processed, others are skipped
+    #[serde(serialize_with = "as_timestamp", deserialize_with = "from_timestamp")]
+    pub modified_before: Option<SystemTime>,
+    /// Minimum dimensions (width, height) of images to watermark, read from
+    /// their header: smaller images follow `Options::unqualified`
+    /// i.e.: `Some((200, 200))` leaves out a 64x64 icon or a 800x100 banner
+    pub min_dimensions: Option<(u32, u32)>,
+    /// Regular expressions on the Copyright and Artist Exif fields of images:
+    /// images with a field matching one of them, i.e. already marked by earlier tooling,
+    /// are not watermarked
+    /// i.e.: `(?i)filigram`
+    #[serde(serialize_with = "as_patterns", deserialize_with = "from_patterns")]
+    pub excluded_copyrights: Vec<Regex>,
+    /// Don't watermark images carrying the marker of filigram, i.e. outputs of a previous run
+    /// (see `Options::mark_outputs`), so that runs over mixed trees are idempotent.
+    /// Outputs of the run are marked too
+    pub skip_watermarked: bool,
+    /// Qualify files by the format given by their magic bytes instead of their extension,
+    /// which is still used if content is not recognized:
+    /// a JPEG named "photo.dat" or "photo" is watermarked if "jpg" is authorized
+    pub sniff_content: bool,
+    /// Honor `.filigramignore` files (see `IGNORE_FILE`) of the input folder and of its
+    /// subdirectories: with gitignore syntax, they list files not to watermark,
+    /// along with the other rules. Requires the `walkdir` feature
+    pub ignore_files: bool,
+    /// Skip files and directories whose name starts with a dot under the input folder
+    /// (i.e. ".DS_Store", ".thumbnails"), whatever their kind:
+    /// nothing is written for them and hidden directories are not traversed
+    pub skip_hidden: bool,
+    /// Maximum depth of directories traversal
+    /// i.e.: `Some(1)` only handles files directly in input folder,
+    /// `Some(2)` also handles files of its direct subfolders,
+    /// `None` traverses the whole tree
+    pub max_depth: Option<usize>,
+    /// Custom predicate on the path of files, for filters the other rules can't express
+    /// (i.e. a database lookup): files for which it returns `false` are not watermarked.
+    /// It can't be serialized
+    #[serde(skip)]
+    pub custom: Option<Predicate>,
+}
+
+/// Custom predicate on the path of a file, see `Rules::custom`
+pub type Predicate = Box<dyn Fn(&Path) -> bool + Send + Sync>;
+
+impl fmt::Debug for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rules")
+            .field("excluded_dirs", &self.excluded_dirs)
+            .field("excluded_files", &self.excluded_files)
+            .field("excluded_dir_paths", &self.excluded_dir_paths)
+            .field("included_dirs", &self.included_dirs)
+            .field("included_files", &self.included_files)
+            .field("authorized_extensions", &self.authorized_extensions)
+            .field("authorized_mime_types", &self.authorized_mime_types)
+            .field("symlinks", &self.symlinks)
+            .field("included_paths", &self.included_paths)
+            .field("excluded_paths", &self.excluded_paths)
+            .field("min_file_size", &self.min_file_size)
+            .field("max_file_size", &self.max_file_size)
+            .field("skip_out_of_size", &self.skip_out_of_size)
+            .field("modified_after", &self.modified_after)
+            .field("modified_before", &self.modified_before)
+            .field("min_dimensions", &self.min_dimensions)
+            .field("excluded_copyrights", &self.excluded_copyrights)
+            .field("skip_watermarked", &self.skip_watermarked)
+            .field("sniff_content", &self.sniff_content)
+            .field("ignore_files", &self.ignore_files)
+            .field("skip_hidden", &self.skip_hidden)
+            .field("max_depth", &self.max_depth)
+            .field("custom", &self.custom.is_some())
+            .finish()
+    }
+}
+
+/// Extensions authorized by `Rules::default()`,
+/// along with `VIDEO_EXTENSIONS` with the `ffmpeg` feature
+pub const DEFAULT_EXTENSIONS: [&str; 8] =
+    ["jpg", "jpeg", "png", "webp", "gif", "bmp", "tif", "tiff"];
+
+// `DEFAULT_EXTENSIONS`, and `VIDEO_EXTENSIONS` with the `ffmpeg` feature
+fn default_extensions() -> Vec<String> {
+    let extensions = DEFAULT_EXTENSIONS.iter();
+    #[cfg(feature = "ffmpeg")]
+    let extensions = extensions.chain(crate::video::VIDEO_EXTENSIONS.iter());
+    extensions.map(|ext| ext.to_string()).collect()
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            excluded_dirs: vec![],
+            excluded_files: vec![],
+            excluded_dir_paths: vec![],
+            included_dirs: vec![],
+            included_files: vec![],
+            authorized_mime_types: vec![],
+            authorized_extensions: default_extensions(),
+            symlinks: SymlinkPolicy::default(),
+            included_paths: vec![],
+            excluded_paths: vec![],
+            min_file_size: None,
+            max_file_size: None,
+            skip_out_of_size: false,
+            modified_after: None,
+            modified_before: None,
+            min_dimensions: None,
+            excluded_copyrights: vec![],
+            skip_watermarked: false,
+            sniff_content: false,
+            ignore_files: false,
+            skip_hidden: false,
+            max_depth: None,
+            custom: None,
+        }
+    }
 }
 
 impl Rules {
+    /// Rules read from `path`, a JSON, TOML (with the `toml` feature)
+    /// or YAML (with the `yaml` feature) file according to its extension.
+    /// Missing fields take the value of `Rules::default()`, regular expressions
+    /// are given as strings and modification times as seconds since the Unix epoch
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let extension = path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+
+        match extension.as_str() {
+            "json" => Ok(serde_json::from_str(&content)?),
+            #[cfg(feature = "toml")]
+            "toml" => Ok(toml::from_str(&content)?),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Ok(serde_yaml::from_str(&content)?),
+            other => Err(format!("unsupported rules format ({other}): {path:?}").into()),
+        }
+    }
+
+    /// Builder of rules, starting from `Rules::default()`
+    /// i.e.: `Rules::builder().exclude_dir(".hidden").allow_ext("jpg").build()`
+    pub fn builder() -> RulesBuilder {
+        RulesBuilder {
+            rules: Rules {
+                authorized_extensions: vec![],
+                ..Rules::default()
+            },
+        }
+    }
+
+    /// File at `path` is qualified if it is qualified by `is_file_qualified`
+    /// and if `relative_path` (`path` relative to the input folder)
+    /// passes `included_dirs`, `included_files`, `included_paths` and `excluded_paths`
+    pub fn is_qualified(&self, path: &Path, relative_path: &Path) -> bool {
+        self.is_qualified_as(path, relative_path, None)
+    }
+
+    /// Same as `is_qualified`, with the extensions of `format`, if any,
+    /// checked instead of the extension of `path`
+    pub fn is_qualified_as(
+        &self,
+        path: &Path,
+        relative_path: &Path,
+        format: Option<ImageFormat>,
+    ) -> bool {
+        let authorized = match format {
+            Some(format) => self.is_format_authorized(format),
+            None => self.is_extension_authorized(path),
+        };
+        let format = format.or_else(|| ImageFormat::from_path(path).ok());
+        if !authorized
+            && !format.is_some_and(|format| self.is_mime_authorized(format.to_mime_type()))
+        {
+            debug!("file ignored (bad extension): {path:?}");
+            return false;
+        }
+        if !self.is_name_qualified(path) {
+            return false;
+        }
+
+        if self.excluded_dir_paths.iter().any(|dir| {
+            relative_path
+                .parent()
+                .is_some_and(|parent| parent.starts_with(dir))
+        }) {
+            debug!("file ignored (dir path excluded): {path:?}");
+            return false;
+        }
+        if !self.included_dirs.is_empty()
+            && !relative_path.parent().is_some_and(|parent| {
+                parent.components().any(|comp| {
+                    self.included_dirs
+                        .iter()
+                        .any(|dir| comp.as_os_str() == dir.as_str())
+                })
+            })
+        {
+            debug!("file ignored (dir not included): {path:?}");
+            return false;
+        }
+        if !self.included_files.is_empty()
+            && !relative_path.file_name().is_some_and(|name| {
+                let name = name.to_string_lossy();
+                self.included_files
+                    .iter()
+                    .any(|included_filename| name.starts_with(included_filename))
+            })
+        {
+            debug!("file ignored (file not included): {path:?}");
+            return false;
+        }
+        if self.included_paths.is_empty() && self.excluded_paths.is_empty() {
+            return true;
+        }
+
+        let relative_path = slash_path(relative_path);
+        if !self.included_paths.is_empty()
+            && !self
+                .included_paths
+                .iter()
+                .any(|regex| regex.is_match(&relative_path))
+        {
+            debug!("file ignored (path not included): {path:?}");
+            return false;
+        }
+        if self
+            .excluded_paths
+            .iter()
+            .any(|regex| regex.is_match(&relative_path))
+        {
+            debug!("file ignored (path excluded): {path:?}");
+            return false;
+        }
+
+        true
+    }
+
+    /// Size of a file, in bytes, is within `min_file_size`..=`max_file_size`
+    pub fn is_size_qualified(&self, size: u64) -> bool {
+        self.min_file_size.is_none_or(|min| size >= min)
+            && self.max_file_size.is_none_or(|max| size <= max)
+    }
+
+    /// Modification time of a file is within `modified_after`..`modified_before`,
+    /// an unknown modification time (i.e. of an archive entry) always is
+    pub fn is_date_qualified(&self, modified: Option<SystemTime>) -> bool {
+        let Some(modified) = modified else {
+            return true;
+        };
+        self.modified_after.is_none_or(|after| modified >= after)
+            && self.modified_before.is_none_or(|before| modified < before)
+    }
+
+    /// Dimensions of an image are at least `min_dimensions`
+    pub fn is_dimension_qualified(&self, width: u32, height: u32) -> bool {
+        self.min_dimensions
+            .is_none_or(|(min_width, min_height)| width >= min_width && height >= min_height)
+    }
+
+    /// None of `fields`, the Copyright and Artist Exif fields of an image,
+    /// matches `excluded_copyrights`
+    pub fn is_copyright_qualified(&self, fields: &[String]) -> bool {
+        !fields.iter().any(|field| {
+            self.excluded_copyrights
+                .iter()
+                .any(|regex| regex.is_match(field))
+        })
+    }
+
     /// File is qualified if it is not part of excluded file list
     /// and if its extension is authorized.
+    /// Regular expressions on its relative path are checked by `is_qualified`
     pub fn is_file_qualified(&self, path: &impl AsRef<Path>) -> bool {
         let path = path.as_ref();
+        let mime_type = ImageFormat::from_path(path).map(|format| format.to_mime_type());
+        if !self.is_extension_authorized(path)
+            && !mime_type.is_ok_and(|mime_type| self.is_mime_authorized(mime_type))
+        {
+            debug!("file ignored (bad extension): {path:?}");
+            return false;
+        }
+        self.is_name_qualified(path)
+    }
 
-        if let Some(extension) = path.extension() {
-            let extension = extension
-                .to_str()
-                .expect("can't convert to str")
-                .to_lowercase();
-
-            if !self
-                .authorized_extensions
+    /// One of the extensions of `format` is authorized
+    pub fn is_format_authorized(&self, format: ImageFormat) -> bool {
+        format.extensions_str().iter().any(|format_ext| {
+            self.authorized_extensions
                 .iter()
-                .any(|ext| ext.as_str() == extension)
-            {
-                debug!("file ignored (bad extension): {path:?}");
-                return false;
-            }
-        } else {
-            debug!("file ignored (no extension): {path:?}");
+                .any(|ext| ext.eq_ignore_ascii_case(format_ext))
+        })
+    }
+
+    /// `mime_type` is authorized by `authorized_mime_types`,
+    /// either exactly or through a wildcard such as "image/*"
+    pub fn is_mime_authorized(&self, mime_type: &str) -> bool {
+        self.authorized_mime_types
+            .iter()
+            .any(|authorized| match authorized.strip_suffix("/*") {
+                Some(kind) => mime_type
+                    .split_once('/')
+                    .is_some_and(|(mime_kind, _)| mime_kind.eq_ignore_ascii_case(kind)),
+                None => authorized.eq_ignore_ascii_case(mime_type),
+            })
+    }
+
+    // Extension of `path` is authorized
+    fn is_extension_authorized(&self, path: &Path) -> bool {
+        let Some(extension) = path.extension() else {
             return false;
-        }
+        };
+        let extension = extension
+            .to_str()
+            .expect("can't convert to str")
+            .to_lowercase();
+        self.authorized_extensions
+            .iter()
+            .any(|ext| ext.as_str() == extension)
+    }
 
+    // File is not part of excluded file list, nor under an excluded directory,
+    // and is accepted by the custom predicate
+    fn is_name_qualified(&self, path: &Path) -> bool {
         let path_str = path
             .file_name()
             .expect("can't retrieve filename")
@@ -73,6 +441,381 @@ impl Rules {
             return false;
         }
 
+        if self.custom.as_ref().is_some_and(|custom| !custom(path)) {
+            debug!("file ignored (custom predicate): {path:?}");
+            return false;
+        }
+
         true
     }
 }
+
+/// File at `relative_path` is hidden, or is under a hidden directory
+pub(crate) fn is_hidden(relative_path: &Path) -> bool {
+    relative_path.components().any(
+        |comp| matches!(comp, Component::Normal(name) if name.to_string_lossy().starts_with('.')),
+    )
+}
+
+/// `relative_path` with '/' separators, as matched by regular expressions
+pub(crate) fn slash_path(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .map(|comp| comp.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Serialize regular expressions as their pattern
+fn as_patterns<S: Serializer>(regexes: &[Regex], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(regexes.iter().map(Regex::as_str))
+}
+
+fn from_patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Regex>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(D::Error::custom))
+        .collect()
+}
+
+// Serialize a time as seconds since the Unix epoch
+fn as_timestamp<S: Serializer>(
+    time: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let secs = time
+        .map(|time| time.duration_since(UNIX_EPOCH).map_err(S::Error::custom))
+        .transpose()?
+        .map(|duration| duration.as_secs());
+    secs.serialize(serializer)
+}
+
+fn from_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<SystemTime>, D::Error> {
+    Ok(
+        Option::<u64>::deserialize(deserializer)?
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+    )
+}
+
+/// Builder of `Rules`, see `Rules::builder`.
+/// Authorized extensions are `DEFAULT_EXTENSIONS` unless some extensions or MIME types
+/// are given with `allow_ext` or `allow_mime`
+#[derive(Debug)]
+pub struct RulesBuilder {
+    rules: Rules,
+}
+
+impl RulesBuilder {
+    /// Don't watermark content of directories named `dir`, see `Rules::excluded_dirs`
+    pub fn exclude_dir(mut self, dir: impl Into<String>) -> Self {
+        self.rules.excluded_dirs.push(dir.into());
+        self
+    }
+
+    /// Don't watermark files whose name starts with `prefix`, see `Rules::excluded_files`
+    pub fn exclude_file(mut self, prefix: impl Into<String>) -> Self {
+        self.rules.excluded_files.push(prefix.into());
+        self
+    }
+
+    /// Don't watermark content of directory `dir`, relative to the input folder,
+    /// see `Rules::excluded_dir_paths`
+    pub fn exclude_dir_path(mut self, dir: impl Into<String>) -> Self {
+        self.rules.excluded_dir_paths.push(dir.into());
+        self
+    }
+
+    /// Only watermark files under directories named `dir`, see `Rules::included_dirs`
+    pub fn include_dir(mut self, dir: impl Into<String>) -> Self {
+        self.rules.included_dirs.push(dir.into());
+        self
+    }
+
+    /// Only watermark files whose name starts with `prefix`, see `Rules::included_files`
+    pub fn include_file(mut self, prefix: impl Into<String>) -> Self {
+        self.rules.included_files.push(prefix.into());
+        self
+    }
+
+    /// Authorize extension `ext`, case-insensitive and with or without its leading dot
+    pub fn allow_ext(mut self, ext: &str) -> Self {
+        let ext = ext.trim_start_matches('.').to_lowercase();
+        self.rules.authorized_extensions.push(ext);
+        self
+    }
+
+    /// Authorize MIME type `mime_type`, possibly a wildcard such as "image/*"
+    pub fn allow_mime(mut self, mime_type: impl Into<String>) -> Self {
+        self.rules.authorized_mime_types.push(mime_type.into());
+        self
+    }
+
+    /// Handle symbolic links with `policy`
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.rules.symlinks = policy;
+        self
+    }
+
+    /// Only watermark files whose relative path matches `regex`, see `Rules::included_paths`
+    pub fn include_path(mut self, regex: Regex) -> Self {
+        self.rules.included_paths.push(regex);
+        self
+    }
+
+    /// Don't watermark files whose relative path matches `regex`, see `Rules::excluded_paths`
+    pub fn exclude_path(mut self, regex: Regex) -> Self {
+        self.rules.excluded_paths.push(regex);
+        self
+    }
+
+    /// Only watermark files of at least `size` bytes
+    pub fn min_file_size(mut self, size: u64) -> Self {
+        self.rules.min_file_size = Some(size);
+        self
+    }
+
+    /// Only watermark files of at most `size` bytes
+    pub fn max_file_size(mut self, size: u64) -> Self {
+        self.rules.max_file_size = Some(size);
+        self
+    }
+
+    /// Skip files out of size bounds, see `Rules::skip_out_of_size`
+    pub fn skip_out_of_size(mut self, skip: bool) -> Self {
+        self.rules.skip_out_of_size = skip;
+        self
+    }
+
+    /// Only process files modified at or after `time`
+    pub fn modified_after(mut self, time: SystemTime) -> Self {
+        self.rules.modified_after = Some(time);
+        self
+    }
+
+    /// Only process files modified before `time`
+    pub fn modified_before(mut self, time: SystemTime) -> Self {
+        self.rules.modified_before = Some(time);
+        self
+    }
+
+    /// Only watermark images of at least `width`x`height`
+    pub fn min_dimensions(mut self, width: u32, height: u32) -> Self {
+        self.rules.min_dimensions = Some((width, height));
+        self
+    }
+
+    /// Don't watermark images whose Copyright or Artist field matches `regex`,
+    /// see `Rules::excluded_copyrights`
+    pub fn exclude_copyright(mut self, regex: Regex) -> Self {
+        self.rules.excluded_copyrights.push(regex);
+        self
+    }
+
+    /// Don't watermark images already watermarked, see `Rules::skip_watermarked`
+    pub fn skip_watermarked(mut self, skip: bool) -> Self {
+        self.rules.skip_watermarked = skip;
+        self
+    }
+
+    /// Qualify files by their content, see `Rules::sniff_content`
+    pub fn sniff_content(mut self, sniff: bool) -> Self {
+        self.rules.sniff_content = sniff;
+        self
+    }
+
+    /// Honor `.filigramignore` files, see `Rules::ignore_files`
+    pub fn ignore_files(mut self, honor: bool) -> Self {
+        self.rules.ignore_files = honor;
+        self
+    }
+
+    /// Skip hidden files and directories, see `Rules::skip_hidden`
+    pub fn skip_hidden(mut self, skip: bool) -> Self {
+        self.rules.skip_hidden = skip;
+        self
+    }
+
+    /// Traverse directories down to `depth`, see `Rules::max_depth`
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.rules.max_depth = Some(depth);
+        self
+    }
+
+    /// Only watermark files for which `predicate` returns `true`, see `Rules::custom`
+    pub fn custom(mut self, predicate: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        self.rules.custom = Some(Box::new(predicate));
+        self
+    }
+
+    /// Rules as configured
+    pub fn build(mut self) -> Rules {
+        if self.rules.authorized_extensions.is_empty()
+            && self.rules.authorized_mime_types.is_empty()
+        {
+            self.rules.authorized_extensions = Rules::default().authorized_extensions;
+        }
+        self.rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rules, SymlinkPolicy};
+    use image::ImageFormat;
+    use regex::Regex;
+    use std::path::Path;
+
+    #[test]
+    fn test_path_regexes() {
+        let rules = Rules {
+            authorized_extensions: vec!["jpg".to_string()],
+            included_paths: vec![Regex::new("^exports/").unwrap()],
+            excluded_paths: vec![Regex::new(r"-proof\.[^.]+$").unwrap()],
+            ..Rules::default()
+        };
+        let qualified = |relative_path: &str| {
+            let path = Path::new("input").join(relative_path);
+            rules.is_qualified(&path, Path::new(relative_path))
+        };
+
+        assert!(qualified("exports/pic.jpg"));
+        assert!(qualified("exports/shoot/pic-proofread.jpg"));
+        assert!(!qualified("exports/shoot/pic-proof.jpg"));
+        assert!(!qualified("drafts/pic.jpg"));
+        assert!(!qualified("exports/pic.png"));
+    }
+
+    #[test]
+    fn test_included() {
+        let rules = Rules {
+            included_dirs: vec!["exports".to_string()],
+            included_files: vec!["final_".to_string()],
+            authorized_extensions: vec!["jpg".to_string()],
+            ..Rules::default()
+        };
+        let qualified = |relative_path: &str| {
+            let path = Path::new("exports").join(relative_path);
+            rules.is_qualified(&path, Path::new(relative_path))
+        };
+
+        assert!(qualified("exports/final_cover.jpg"));
+        assert!(qualified("2024/exports/shoot/final_1.jpg"));
+        assert!(!qualified("exports/draft_cover.jpg"));
+        assert!(!qualified("final_cover.jpg"));
+        assert!(!qualified("exports.jpg/final_cover.png"));
+    }
+
+    #[test]
+    fn test_builder() {
+        let rules = Rules::builder()
+            .exclude_dir(".hidden")
+            .allow_ext(".JPG")
+            .allow_ext("png")
+            .min_dimensions(200, 100)
+            .build();
+        assert_eq!(rules.excluded_dirs, [".hidden"]);
+        assert_eq!(rules.authorized_extensions, ["jpg", "png"]);
+        assert_eq!(rules.min_dimensions, Some((200, 100)));
+        assert!(rules.is_file_qualified(&"shoot/pic.JPG"));
+        assert!(!rules.is_file_qualified(&"shoot/.hidden/pic.jpg"));
+        assert!(!rules.is_file_qualified(&"shoot/pic.gif"));
+
+        let rules = Rules::builder().build();
+        assert_eq!(
+            rules.authorized_extensions,
+            Rules::default().authorized_extensions
+        );
+        assert!(rules.is_file_qualified(&"shoot/pic.gif"));
+    }
+
+    #[test]
+    fn test_from_file() {
+        let rules = Rules::builder()
+            .exclude_dir(".hidden")
+            .allow_ext("jpg")
+            .exclude_path(Regex::new(r"-proof\.[^.]+$").unwrap())
+            .modified_after(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000))
+            .build();
+        std::fs::create_dir_all("tmp").unwrap();
+        std::fs::write("tmp/rules.json", serde_json::to_string(&rules).unwrap()).unwrap();
+
+        let loaded = Rules::from_file("tmp/rules.json").unwrap();
+        assert_eq!(loaded.excluded_dirs, rules.excluded_dirs);
+        assert_eq!(loaded.authorized_extensions, ["jpg"]);
+        assert_eq!(loaded.excluded_paths[0].as_str(), r"-proof\.[^.]+$");
+        assert_eq!(loaded.modified_after, rules.modified_after);
+
+        std::fs::write("tmp/partial_rules.json", r#"{"symlinks": "copy_link"}"#).unwrap();
+        let loaded = Rules::from_file("tmp/partial_rules.json").unwrap();
+        assert_eq!(loaded.symlinks, SymlinkPolicy::CopyLink);
+        assert_eq!(
+            loaded.authorized_extensions,
+            Rules::default().authorized_extensions
+        );
+
+        std::fs::write("tmp/bad_rules.json", r#"{"max_dpth": 2}"#).unwrap();
+        assert!(Rules::from_file("tmp/bad_rules.json").is_err());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_from_toml() {
+        std::fs::create_dir_all("tmp").unwrap();
+        std::fs::write(
+            "tmp/rules.toml",
+            "authorized_extensions = [\"png\"]\nexcluded_paths = [\"^drafts/\"]\nmax_depth = 2\n",
+        )
+        .unwrap();
+        let rules = Rules::from_file("tmp/rules.toml").unwrap();
+        assert_eq!(rules.authorized_extensions, ["png"]);
+        assert!(rules.excluded_paths[0].is_match("drafts/pic.png"));
+        assert_eq!(rules.max_depth, Some(2));
+    }
+
+    #[test]
+    fn test_custom() {
+        let rules = Rules::builder()
+            .allow_ext("jpg")
+            .custom(|path| !path.ends_with("rejected.jpg"))
+            .build();
+        assert!(rules.is_file_qualified(&"shoot/pic.jpg"));
+        assert!(!rules.is_file_qualified(&"shoot/rejected.jpg"));
+        assert!(!rules.is_qualified(Path::new("shoot/rejected.jpg"), Path::new("rejected.jpg")));
+    }
+
+    #[test]
+    fn test_excluded_dir_paths() {
+        let rules = Rules::builder()
+            .allow_ext("jpg")
+            .exclude_dir_path("2021/temp")
+            .build();
+        let qualified = |relative_path: &str| {
+            let path = Path::new("input").join(relative_path);
+            rules.is_qualified(&path, Path::new(relative_path))
+        };
+
+        assert!(!qualified("2021/temp/pic.jpg"));
+        assert!(!qualified("2021/temp/raw/pic.jpg"));
+        assert!(qualified("2021/temple/pic.jpg"));
+        assert!(qualified("2022/temp/pic.jpg"));
+        assert!(qualified("2021/temp.jpg"));
+    }
+
+    #[test]
+    fn test_mime_types() {
+        let rules = Rules::builder().allow_mime("image/jpeg").build();
+        assert!(rules.authorized_extensions.is_empty());
+        assert!(rules.is_file_qualified(&"shoot/pic.JPEG"));
+        assert!(!rules.is_file_qualified(&"shoot/pic.png"));
+        assert!(!rules.is_file_qualified(&"shoot/notes.txt"));
+
+        let rules = Rules::builder().allow_mime("image/*").build();
+        assert!(rules.is_file_qualified(&"shoot/pic.png"));
+        assert!(rules.is_mime_authorized("image/webp"));
+        assert!(!rules.is_mime_authorized("video/mp4"));
+        let relative_path = Path::new("pic.dat");
+        assert!(rules.is_qualified_as(relative_path, relative_path, Some(ImageFormat::Gif)));
+    }
+}
@@ -0,0 +1,995 @@
+use bytes::Bytes;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File, FileTimes, OpenOptions};
+use std::io::{BufRead, BufReader, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+use crate::color::{to_srgb, ColorPolicy};
+use crate::config::{Config, TextRotation};
+use crate::dedup::{hash_file, DuplicatePolicy, Duplicates, Output};
+use crate::exif::{read_ascii, ARTIST, COPYRIGHT};
+use crate::graphics::{
+    decode_image, decode_oriented, encode, read_decoded_size, read_dimensions, read_exif, read_icc,
+    render_watermark, sniff_format, transform_image, WatermarkCache,
+};
+use crate::hooks::Outcome;
+#[cfg(feature = "walkdir")]
+use crate::ignores::Ignores;
+use crate::layout::{CaseCollisions, Layout, Names};
+use crate::metadata::{edit_metadata, embed_metadata, is_marked, mark, xmp_rights};
+use crate::metadata::{process_thumbnail, reset_orientation, set_exif_fields};
+use crate::metadata::{MetadataAction, MetadataPolicy};
+use crate::metrics::Timings;
+use crate::options::{Options, Sample, SidecarPolicy, UnqualifiedPolicy};
+use crate::quarantine::QUARANTINE_DIR;
+use crate::rendition::{rendition_path, Rendition};
+use crate::report::FileReport;
+use crate::rules::{is_hidden, slash_path, Rules, SymlinkPolicy};
+use crate::similar::perceptual_hash;
+use crate::trace::debug;
+#[cfg(feature = "ffmpeg")]
+use crate::video::{is_video, watermark_video};
+
+/// What is done with a file, according to rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Qualification {
+    /// File is watermarked
+    Qualified,
+    /// File follows `Options::unqualified`
+    Unqualified,
+    /// File is left out, whatever its kind
+    Skipped,
+}
+
+// Encoded rendition of an image, with its path
+pub(crate) type Encoded = (PathBuf, Bytes);
+
+// Outputs of a file left to write by `Run::complete_file`: its encoded images,
+// then the attributes of its source copied to its output
+#[derive(Default)]
+pub(crate) struct Writes {
+    files: Vec<Encoded>,
+    attributes: Option<PathBuf>,
+}
+
+// State shared by the workers of a `spread_watermark` run
+pub(crate) struct Run<'a> {
+    // watermark of the run, then those of `Options::watermarks`, shared by workers,
+    // rendered with each of their texts
+    watermarks: Vec<(&'a Config, Vec<Arc<RgbaImage>>)>,
+    // rendered watermarks scaled for renditions
+    scaled_watermarks: WatermarkCache,
+    pub(crate) rules: &'a Rules,
+    pub(crate) options: &'a Options,
+    duplicates: Duplicates,
+    names: Names,
+    #[cfg(feature = "walkdir")]
+    ignores: Ignores,
+    // images sampled so far, see `Sample::Count`
+    sampled: AtomicUsize,
+    // images given a text so far, see `TextRotation::RoundRobin`
+    rotated: AtomicUsize,
+    // qualifications decided beforehand by a plan, by source path
+    planned: HashMap<PathBuf, Qualification>,
+    // where files which failed are copied, see `Options::quarantine`
+    quarantine: Option<PathBuf>,
+    // workers of the run, the global pool of `rayon` unless `Options::threads` is set
+    #[cfg(feature = "rayon")]
+    pool: Option<rayon::ThreadPool>,
+}
+
+impl<'a> Run<'a> {
+    pub(crate) fn new(
+        cfg: &'a Config,
+        rules: &'a Rules,
+        options: &'a Options,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            watermarks: std::iter::once(cfg)
+                .chain(options.watermarks.iter().map(|(_, cfg)| cfg))
+                .map(|cfg| {
+                    let rendered = cfg
+                        .all_texts()
+                        .map(|text| render_watermark(cfg, text).map(Arc::new))
+                        .collect::<Result<_, _>>()?;
+                    Ok((cfg, rendered))
+                })
+                .collect::<Result<_, Box<dyn std::error::Error>>>()?,
+            scaled_watermarks: WatermarkCache::default(),
+            rules,
+            options,
+            duplicates: Duplicates::default(),
+            names: Names::default(),
+            #[cfg(feature = "walkdir")]
+            ignores: Ignores::default(),
+            sampled: AtomicUsize::new(0),
+            rotated: AtomicUsize::new(0),
+            planned: HashMap::new(),
+            quarantine: None,
+            #[cfg(feature = "rayon")]
+            pool: options
+                .threads
+                .map(|threads| rayon::ThreadPoolBuilder::new().num_threads(threads).build())
+                .transpose()?,
+        })
+    }
+
+    /// Map `files` with `f` on the workers of the run, while `files` is iterated.
+    /// Without the `rayon` feature, files are mapped one after the other
+    pub(crate) fn map<I, F, R>(&self, files: I, f: F) -> Vec<R>
+    where
+        I: Iterator + Send,
+        I::Item: Send,
+        F: Fn(I::Item) -> R + Sync + Send,
+        R: Send,
+    {
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::iter::{ParallelBridge, ParallelIterator};
+            let op = || files.par_bridge().map(f).collect();
+            match &self.pool {
+                Some(pool) => pool.install(op),
+                None => op(),
+            }
+        }
+        #[cfg(not(feature = "rayon"))]
+        files.map(f).collect()
+    }
+
+    /// Number of workers of the run
+    #[cfg(feature = "tar")]
+    pub(crate) fn workers(&self) -> usize {
+        #[cfg(feature = "rayon")]
+        {
+            match &self.pool {
+                Some(pool) => pool.current_num_threads(),
+                None => rayon::current_num_threads(),
+            }
+        }
+        #[cfg(not(feature = "rayon"))]
+        1
+    }
+
+    /// The run has been cancelled, see `Options::cancel`
+    pub(crate) fn cancelled(&self) -> bool {
+        self.options
+            .cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// Qualify files as decided by `planned` instead of by rules
+    #[cfg(feature = "walkdir")]
+    pub(crate) fn with_plan(mut self, planned: HashMap<PathBuf, Qualification>) -> Self {
+        self.planned = planned;
+        self
+    }
+
+    /// Copy files which fail into the quarantine of `target_dir` if enabled,
+    /// see `Options::quarantine`
+    pub(crate) fn quarantined_in(mut self, target_dir: &Path) -> Self {
+        if self.options.quarantine {
+            self.quarantine = Some(target_dir.join(QUARANTINE_DIR));
+        }
+        self
+    }
+
+    /// Directory where files which fail are copied, if any
+    pub(crate) fn quarantine(&self) -> Option<&Path> {
+        self.quarantine.as_deref()
+    }
+
+    /// Keep outputs of previous runs from being overwritten in flat layout
+    pub(crate) fn reserve_outputs<'r>(&self, reports: impl Iterator<Item = &'r FileReport>) {
+        for output in reports.filter_map(|report| report.output.as_ref()) {
+            self.names.insert(output.to_owned());
+        }
+    }
+
+    /// Final path of an output, unique in flat layout
+    pub(crate) fn output_path(&self, path: PathBuf) -> PathBuf {
+        match self.options.layout {
+            Layout::Mirror => path,
+            Layout::Flat => self.names.reserve(path),
+        }
+    }
+
+    /// Qualification of file `path`, of `size` bytes modified at `modified`, by rules.
+    /// Its content, to sniff its format or read its dimensions, is read from `open`
+    /// if required, an unreadable image is qualified to let its decoding fail.
+    /// With `Options::sample`, files out of the sample are skipped.
+    /// Files of a plan are qualified as planned, see `Run::with_plan`
+    pub(crate) fn qualify<R: BufRead + Seek>(
+        &self,
+        path: &Path,
+        relative_path: &Path,
+        size: u64,
+        modified: Option<SystemTime>,
+        open: impl Fn() -> Result<R, Box<dyn std::error::Error>>,
+    ) -> Qualification {
+        if let Some(&qualification) = self.planned.get(path) {
+            return qualification;
+        }
+        let qualification = self.qualify_by_rules(path, relative_path, size, modified, open);
+        let Some(sample) = self.options.sample else {
+            return qualification;
+        };
+        if qualification == Qualification::Qualified && self.is_sampled(sample, relative_path) {
+            return Qualification::Qualified;
+        }
+        debug!("file out of sample: {path:?}");
+        Qualification::Skipped
+    }
+
+    /// File `path` of `size` bytes is left out by its path and size alone, whatever its content,
+    /// so that it doesn't have to be read: it's skipped, or unqualified with
+    /// `UnqualifiedPolicy::Skip`. Qualification of the others is left to `Run::qualify`
+    #[cfg(any(feature = "walkdir", feature = "tar"))]
+    pub(crate) fn is_left_out(&self, path: &Path, relative_path: &Path, size: u64) -> bool {
+        let rules = self.rules;
+        let qualification = match self.planned.get(path) {
+            Some(&qualification) => qualification,
+            None if rules.skip_hidden && is_hidden(relative_path) => Qualification::Skipped,
+            None if !rules.is_size_qualified(size) && rules.skip_out_of_size => {
+                Qualification::Skipped
+            }
+            None if !rules.is_size_qualified(size) => Qualification::Unqualified,
+            // the format given by content may qualify a file whatever its extension
+            None if !rules.sniff_content && !rules.is_qualified_as(path, relative_path, None) => {
+                Qualification::Unqualified
+            }
+            None => Qualification::Qualified,
+        };
+        match qualification {
+            Qualification::Qualified => false,
+            Qualification::Unqualified => self.options.unqualified == UnqualifiedPolicy::Skip,
+            Qualification::Skipped => true,
+        }
+    }
+
+    // Qualification of file `path` by rules only
+    fn qualify_by_rules<R: BufRead + Seek>(
+        &self,
+        path: &Path,
+        relative_path: &Path,
+        size: u64,
+        modified: Option<SystemTime>,
+        open: impl Fn() -> Result<R, Box<dyn std::error::Error>>,
+    ) -> Qualification {
+        let rules = self.rules;
+        if rules.skip_hidden && is_hidden(relative_path) {
+            debug!("hidden file: {path:?}");
+            return Qualification::Skipped;
+        }
+        if !rules.is_date_qualified(modified) {
+            debug!("file out of date range: {path:?}");
+            return Qualification::Skipped;
+        }
+        if !rules.is_size_qualified(size) {
+            debug!("file out of size bounds: {path:?}");
+            if rules.skip_out_of_size {
+                return Qualification::Skipped;
+            }
+            return Qualification::Unqualified;
+        }
+        let format = match rules.sniff_content {
+            true => open().ok().and_then(sniff_format),
+            false => None,
+        };
+        if !rules.is_qualified_as(path, relative_path, format) {
+            return Qualification::Unqualified;
+        }
+        #[cfg(feature = "walkdir")]
+        if rules.ignore_files && self.ignores.is_ignored(path, relative_path) {
+            debug!("file ignored (ignore file): {path:?}");
+            return Qualification::Unqualified;
+        }
+        if rules.min_dimensions.is_some() {
+            if let Ok((width, height)) = open().and_then(|input| read_dimensions(path, input)) {
+                if !rules.is_dimension_qualified(width, height) {
+                    debug!("image too small ({width}x{height}): {path:?}");
+                    return Qualification::Unqualified;
+                }
+            }
+        }
+        if rules.skip_watermarked {
+            let marked = open()
+                .and_then(|mut input| {
+                    let mut content = vec![];
+                    input.read_to_end(&mut content)?;
+                    Ok(content)
+                })
+                .is_ok_and(|content| is_marked(content.into()));
+            if marked {
+                debug!("image already watermarked: {path:?}");
+                return Qualification::Unqualified;
+            }
+        }
+        if !rules.excluded_copyrights.is_empty() {
+            let fields = open()
+                .and_then(|input| read_exif(path, input))
+                .ok()
+                .flatten()
+                .map(|exif| {
+                    [COPYRIGHT, ARTIST]
+                        .into_iter()
+                        .filter_map(|tag| read_ascii(&exif, tag))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            if !rules.is_copyright_qualified(&fields) {
+                debug!("image already marked ({fields:?}): {path:?}");
+                return Qualification::Unqualified;
+            }
+        }
+        Qualification::Qualified
+    }
+
+    /// Content of file `path`, read into a buffer of the pool,
+    /// or mapped in memory if large enough, see `Options::mmap_threshold`
+    pub(crate) fn read(&self, path: &Path) -> std::io::Result<Bytes> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        #[cfg(feature = "mmap")]
+        if self
+            .options
+            .mmap_threshold
+            .is_some_and(|threshold| len >= threshold)
+        {
+            // SAFETY: sources are not modified while being processed, see `Options::mmap_threshold`
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            return Ok(Bytes::from_owner(map));
+        }
+        let mut input = self.options.buffer_pool.take(len as usize);
+        file.read_to_end(&mut input)?;
+        Ok(input.into())
+    }
+
+    /// File `path` is worth reading ahead of its processing, see `Options::read_ahead`:
+    /// a file which will likely be watermarked
+    #[cfg(feature = "walkdir")]
+    pub(crate) fn reads_ahead(&self, path: &Path, relative_path: &Path) -> bool {
+        if self.rules.symlinks == SymlinkPolicy::CopyLink && path.is_symlink() {
+            return false;
+        }
+        let sidecar = self.options.sidecars != SidecarPolicy::Ignore && is_sidecar(path);
+        !sidecar && self.rules.is_qualified(path, relative_path)
+    }
+
+    /// Why image `path` is skipped, if it takes more than `Options::max_image_memory` once decoded.
+    /// Its header is read from `open`, an unreadable one lets decoding fail
+    pub(crate) fn exceeds_memory<R: BufRead + Seek>(
+        &self,
+        path: &Path,
+        open: impl FnOnce() -> Result<R, Box<dyn std::error::Error>>,
+    ) -> Option<String> {
+        let max = self.options.max_image_memory?;
+        let size = open()
+            .and_then(|input| read_decoded_size(path, input))
+            .ok()?;
+        (size > max)
+            .then(|| format!("Decoded image of {size} bytes exceeds the limit of {max} bytes"))
+    }
+
+    /// Path of the watermarked output of the file to be written at `target_path`,
+    /// named after its source, given the output dimensions
+    pub(crate) fn watermarked_path(&self, target_path: &Path, width: u32, height: u32) -> PathBuf {
+        self.output_path(target_path.with_file_name(self.options.naming.file_name(
+            target_path,
+            width,
+            height,
+        )))
+    }
+
+    /// `target_path` of a file, renamed if it only differs by case from the target path
+    /// of another file (see `CaseCollisions`), or the reason the file is left out
+    pub(crate) fn claim(&self, target_path: &Path) -> Result<PathBuf, String> {
+        match self.options.case_collisions {
+            CaseCollisions::Ignore => Ok(target_path.to_owned()),
+            CaseCollisions::Suffix => Ok(self.names.reserve_folded(target_path)),
+            CaseCollisions::Fail | CaseCollisions::Skip => {
+                match self.names.claim_folded(target_path) {
+                    Some(other) => Err(format!(
+                        "{target_path:?} only differs by case from {other:?}"
+                    )),
+                    None => Ok(target_path.to_owned()),
+                }
+            }
+        }
+    }
+
+    // Qualified image at `relative_path` is part of `sample`
+    fn is_sampled(&self, sample: Sample, relative_path: &Path) -> bool {
+        match sample {
+            Sample::Count(count) => self.sampled.fetch_add(1, Ordering::Relaxed) < count,
+            Sample::Ratio { ratio, seed } => {
+                let mut hasher = Sha256::new();
+                hasher.update(seed.to_le_bytes());
+                hasher.update(slash_path(relative_path));
+                let hash = u64::from_le_bytes(hasher.finalize()[..8].try_into().unwrap());
+                (hash as f64 / u64::MAX as f64) < ratio
+            }
+        }
+    }
+
+    /// Index of the watermark of file at `relative_path`: 0 for the watermark of the run,
+    /// `i + 1` for the i-th of `Options::watermarks`, and index of its text
+    /// among `Config::all_texts`, see `TextRotation`
+    pub(crate) fn select_watermark(&self, relative_path: &Path) -> (usize, usize) {
+        let relative_path = slash_path(relative_path);
+        let watermark = self
+            .options
+            .watermarks
+            .iter()
+            .position(|(regex, _)| regex.is_match(&relative_path))
+            .map_or(0, |i| i + 1);
+
+        let (cfg, rendered) = &self.watermarks[watermark];
+        let text = match cfg.rotation {
+            _ if rendered.len() == 1 => 0,
+            TextRotation::RoundRobin => self.rotated.fetch_add(1, Ordering::Relaxed),
+            TextRotation::ByPath => {
+                let hash = Sha256::digest(&relative_path);
+                u64::from_le_bytes(hash[..8].try_into().unwrap()) as usize
+            }
+        };
+        (watermark, text % rendered.len())
+    }
+
+    /// Watermark `input`, the content of `path`, with watermark `watermark` and its text
+    /// (see `select_watermark`) and encode it
+    /// in the format of `output_path(width, height)`, or of `input` if not an image path.
+    /// `renditions` are produced from the same decoded image, encoded along with their path
+    pub(crate) fn watermark(
+        &self,
+        path: &Path,
+        watermark: (usize, usize),
+        input: Bytes,
+        renditions: &[Rendition],
+        output_path: impl FnOnce(u32, u32) -> PathBuf,
+    ) -> Result<(Output, Bytes, Vec<Encoded>), Box<dyn std::error::Error>> {
+        if self.options.metadata_only {
+            let (output, stamped) = self.stamp(path, watermark, input, output_path)?;
+            return Ok((output, stamped, vec![]));
+        }
+        let mut timings = Timings::default();
+
+        let start = Instant::now();
+        let (mut img, orientation) = if self.options.auto_orient {
+            decode_oriented(path, &input, &self.options.decode_limits)?
+        } else {
+            let buffer = self.options.buffer_pool.take(0);
+            (
+                decode_image(path, &input, buffer, &self.options.decode_limits)?,
+                Orientation::NoTransforms,
+            )
+        };
+        timings.decode = start.elapsed();
+        let perceptual_hash = self.options.similar_images.map(|_| perceptual_hash(&img));
+
+        let start = Instant::now();
+        let srgb =
+            self.options.color == ColorPolicy::Srgb && self.convert_to_srgb(path, &input, &mut img);
+        let rendered = &self.watermarks[watermark.0].1[watermark.1];
+        // before the main output, which takes the decoded image
+        let rendition_imgs = renditions
+            .iter()
+            .map(|rendition| {
+                let scaled =
+                    self.scaled_watermarks
+                        .scaled(watermark, rendered, rendition.watermark_scale);
+                rendition.render(path, &img, &scaled)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let img = transform_image(path, img, &self.options.processors, rendered)?;
+        timings.process = start.elapsed();
+
+        let start = Instant::now();
+        // converted pixels no longer match the profile of the source
+        let metadata = if srgb {
+            Cow::Owned(MetadataPolicy {
+                icc_profile: MetadataAction::Strip,
+                ..self.options.metadata.clone()
+            })
+        } else {
+            Cow::Borrowed(&self.options.metadata)
+        };
+        // `img` encoded as `output_path`, with the metadata of the source
+        let encode_output = |img: &DynamicImage, output_path: &Path| {
+            let mut encoded = Cursor::new(self.options.buffer_pool.take(input.len()));
+            // a file qualified by its content may have no image extension
+            let format =
+                ImageFormat::from_path(output_path).or_else(|_| image::guess_format(&input))?;
+            encode(img, format, &mut encoded, self.options.deterministic)?;
+            let mut encoded = embed_metadata(
+                path,
+                input.clone(),
+                output_path,
+                encoded.into_inner().into(),
+                &metadata,
+            );
+            encoded = process_thumbnail(encoded, img, self.options.metadata.thumbnail)?;
+            if orientation != Orientation::NoTransforms {
+                encoded = reset_orientation(encoded)?;
+            }
+            self.finish_metadata(path, watermark.0, output_path, encoded)
+        };
+        let output_path = output_path(img.width(), img.height());
+        let encoded = encode_output(&img, &output_path)?;
+        let mut encoded_renditions = vec![];
+        for (rendition, rendition_img) in renditions.iter().zip(rendition_imgs) {
+            let rendition_path = rendition_path(&output_path, &rendition.suffix);
+            let encoded = encode_output(&rendition_img, &rendition_path)?;
+            encoded_renditions.push((rendition_path, encoded));
+        }
+        timings.encode = start.elapsed();
+
+        let output = Output {
+            renditions: renditions
+                .iter()
+                .zip(&encoded_renditions)
+                .map(|(rendition, (path, _))| (rendition.suffix.clone(), path.clone()))
+                .collect(),
+            path: output_path,
+            width: img.width(),
+            height: img.height(),
+            timings: Some(timings),
+            perceptual_hash,
+        };
+        self.options.buffer_pool.recycle_image(img);
+        Ok((output, encoded, encoded_renditions))
+    }
+
+    // Write the ownership metadata of watermark `watermark` in `input`, the content of `path`,
+    // leaving its pixels untouched, see `Options::metadata_only`
+    fn stamp(
+        &self,
+        path: &Path,
+        watermark: (usize, usize),
+        input: Bytes,
+        output_path: impl FnOnce(u32, u32) -> PathBuf,
+    ) -> Result<(Output, Bytes), Box<dyn std::error::Error>> {
+        let start = Instant::now();
+        let (width, height) = read_dimensions(path, Cursor::new(&input))?;
+        let output_path = output_path(width, height);
+        let mut stamped = embed_metadata(
+            path,
+            input.clone(),
+            &output_path,
+            input,
+            &self.options.metadata,
+        );
+        let cfg = self.watermarks[watermark.0].0;
+        stamped = edit_metadata(&output_path, stamped, |metadata| {
+            let xmp = xmp_rights(
+                metadata.xmp(),
+                cfg.copyright.as_deref(),
+                cfg.artist.as_deref(),
+            );
+            metadata.set_xmp(Some(xmp));
+        });
+        let stamped = self.finish_metadata(path, watermark.0, &output_path, stamped)?;
+
+        let output = Output {
+            path: output_path,
+            renditions: vec![],
+            width,
+            height,
+            timings: Some(Timings {
+                encode: start.elapsed(),
+                ..Default::default()
+            }),
+            perceptual_hash: None,
+        };
+        Ok((output, stamped))
+    }
+
+    // Metadata of `encoded`, the output of `path` to be written at `output_path`, completed
+    // with its sidecar, the hook, the Exif fields of watermark `watermark`, the marker
+    // and content credentials
+    fn finish_metadata(
+        &self,
+        path: &Path,
+        watermark: usize,
+        output_path: &Path,
+        mut encoded: Bytes,
+    ) -> Result<Bytes, Box<dyn std::error::Error>> {
+        if self.options.sidecars == SidecarPolicy::Merge {
+            if let Some(sidecar) = sidecar_of(path) {
+                let xmp = fs::read(sidecar)?;
+                encoded = edit_metadata(output_path, encoded, |metadata| {
+                    metadata.set_xmp(Some(xmp));
+                });
+            }
+        }
+        if let Some(hook) = &self.options.hooks.on_metadata {
+            encoded = edit_metadata(output_path, encoded, |metadata| hook(path, metadata));
+        }
+        let fields = self.watermarks[watermark].0.exif_fields();
+        if !fields.is_empty() {
+            encoded = set_exif_fields(encoded, &fields)?;
+        }
+        if self.options.mark_outputs || self.rules.skip_watermarked {
+            encoded = mark(encoded);
+        }
+        // last, as the manifest binds the final content
+        #[cfg(feature = "c2pa")]
+        if let Some(credentials) = &self.options.credentials {
+            encoded = crate::credentials::sign(
+                credentials,
+                output_path,
+                encoded,
+                self.options.deterministic,
+            )?;
+        }
+        Ok(encoded)
+    }
+
+    // Convert `img`, decoded from `input`, to sRGB if it has an ICC profile, see `ColorPolicy::Srgb`.
+    // Returns whether it was converted
+    fn convert_to_srgb(&self, path: &Path, input: &[u8], img: &mut DynamicImage) -> bool {
+        let Ok(Some(icc)) = read_icc(path, Cursor::new(input)) else {
+            return false;
+        };
+        to_srgb(img, &icc).unwrap_or_else(|e| {
+            debug!("{path:?} not converted to sRGB: {e}");
+            false
+        })
+    }
+
+    // Watermark video `path` into `target_path`, keeping its name
+    #[cfg(feature = "ffmpeg")]
+    fn produce_video(
+        &self,
+        path: &Path,
+        relative_path: &Path,
+        target_path: &Path,
+    ) -> Result<FileReport, Box<dyn std::error::Error>> {
+        debug!("watermarking video {path:?}");
+
+        let start = Instant::now();
+        let target_path = self.output_path(target_path.to_owned());
+        let (watermark, text) = self.select_watermark(relative_path);
+        let watermark = &self.watermarks[watermark].1[text];
+        watermark_video(path, &target_path, watermark)?;
+        if self.options.preserve_attributes {
+            recopy_attributes(path, &target_path)?;
+        }
+        Ok(FileReport {
+            timings: Some(Timings {
+                process: start.elapsed(),
+                ..Default::default()
+            }),
+            ..FileReport::new(path, Outcome::Watermarked, Some(target_path))
+        })
+    }
+
+    /// Watermark `path` into `target_path` if qualified by rules, copy it otherwise,
+    /// but for its outputs to write with `complete_file`, possibly by another thread
+    /// (see `Options::read_ahead`). Its `content` is read here unless already read ahead
+    pub(crate) fn prepare_file(
+        &self,
+        path: &Path,
+        relative_path: &Path,
+        target_path: &Path,
+        content: Option<Bytes>,
+    ) -> Result<(FileReport, Writes), Box<dyn std::error::Error>> {
+        let mut writes = Writes::default();
+        let report = self.produce(path, relative_path, target_path, content, &mut writes)?;
+        Ok((report, writes))
+    }
+
+    /// Write `writes` of `path`, prepared by `prepare_file`, and complete its `report`
+    pub(crate) fn complete_file(
+        &self,
+        path: &Path,
+        mut report: FileReport,
+        writes: Writes,
+    ) -> Result<FileReport, Box<dyn std::error::Error>> {
+        let start = Instant::now();
+        self.write_files(writes.files)?;
+        if let Some(output) = writes.attributes {
+            recopy_attributes(path, &output)?;
+        }
+        if let Some(timings) = &mut report.timings {
+            timings.encode += start.elapsed();
+        }
+
+        report.bytes_in = fs::metadata(path)?.len();
+        if let Some(output) = &report.output {
+            report.bytes_out = fs::metadata(output)?.len();
+        }
+
+        if self.options.checksums || report.perceptual_hash.is_some() {
+            report.source_sha256 = Some(hex(&hash_file(path)?));
+        }
+        if self.options.checksums {
+            if let Some(output) = &report.output {
+                report.output_sha256 = Some(hex(&hash_file(output)?));
+            }
+        }
+        Ok(report)
+    }
+
+    // Write encoded `files`, recycling their buffers
+    fn write_files(&self, files: Vec<Encoded>) -> std::io::Result<()> {
+        for (path, encoded) in files {
+            fs::write(path, &encoded)?;
+            self.options.buffer_pool.recycle(encoded);
+        }
+        Ok(())
+    }
+
+    // Write the output of `path`, whatever its kind, but its encoded images
+    // and attributes left to `writes`
+    fn produce(
+        &self,
+        path: &Path,
+        relative_path: &Path,
+        target_path: &Path,
+        content: Option<Bytes>,
+        writes: &mut Writes,
+    ) -> Result<FileReport, Box<dyn std::error::Error>> {
+        let (rules, options) = (self.rules, self.options);
+
+        if rules.symlinks == SymlinkPolicy::CopyLink && path.is_symlink() {
+            debug!("linking {path:?}");
+
+            let target_path = self.output_path(target_path.to_owned());
+            copy_link(path, &target_path)?;
+            return Ok(FileReport::new(path, Outcome::Linked, Some(target_path)));
+        }
+
+        if options.sidecars != SidecarPolicy::Ignore && is_sidecar(path) {
+            debug!("skipping {path:?}, processed with its image");
+            return Ok(FileReport::new(path, Outcome::Skipped, None));
+        }
+
+        let metadata = fs::metadata(path)?;
+        let qualification = self.qualify(
+            path,
+            relative_path,
+            metadata.len(),
+            metadata.modified().ok(),
+            || Ok(BufReader::new(File::open(path)?)),
+        );
+        if qualification == Qualification::Skipped {
+            debug!("skipping {path:?}");
+            return Ok(FileReport::new(path, Outcome::Skipped, None));
+        }
+
+        // files left out don't collide with others
+        let target_path = &if qualification == Qualification::Qualified
+            || options.unqualified != UnqualifiedPolicy::Skip
+        {
+            match self.claim(target_path) {
+                Ok(target_path) => target_path,
+                Err(reason) if options.case_collisions == CaseCollisions::Skip => {
+                    debug!("skipping {path:?}: {reason}");
+                    return Ok(FileReport {
+                        error: Some(reason),
+                        ..FileReport::new(path, Outcome::Skipped, None)
+                    });
+                }
+                Err(reason) => return Err(reason.into()),
+            }
+        } else {
+            target_path.to_owned()
+        };
+
+        // videos follow `Options::unqualified` when only metadata is written
+        #[cfg(feature = "ffmpeg")]
+        let qualification = match qualification {
+            Qualification::Qualified if options.metadata_only && is_video(path) => {
+                Qualification::Unqualified
+            }
+            qualification => qualification,
+        };
+        #[cfg(feature = "ffmpeg")]
+        if qualification == Qualification::Qualified && is_video(path) {
+            return self.produce_video(path, relative_path, target_path);
+        }
+
+        if qualification == Qualification::Qualified {
+            let open = || Ok(BufReader::new(File::open(path)?));
+            if let Some(reason) = self.exceeds_memory(path, open) {
+                debug!("skipping {path:?}: {reason}");
+                return Ok(FileReport {
+                    error: Some(reason),
+                    ..FileReport::new(path, Outcome::Skipped, None)
+                });
+            }
+        }
+
+        let report = if qualification == Qualification::Qualified {
+            debug!("watermarking {path:?}");
+
+            let watermark = self.select_watermark(relative_path);
+            let output_path = |width, height| self.watermarked_path(target_path, width, height);
+            let (outcome, output) = self.duplicates.watermark_once(
+                options.duplicates,
+                path,
+                watermark,
+                output_path,
+                || {
+                    let start = Instant::now();
+                    let input = match content {
+                        Some(content) => content,
+                        None => self.read(path)?,
+                    };
+                    let read = start.elapsed();
+
+                    let (mut output, encoded, renditions) =
+                        self.watermark(path, watermark, input, &options.renditions, output_path)?;
+                    if let Some(timings) = &mut output.timings {
+                        timings.decode += read;
+                    }
+
+                    writes.files.push((output.path.clone(), encoded));
+                    writes.files.extend(renditions);
+                    // duplicates are linked to the output, written right away
+                    if options.duplicates != DuplicatePolicy::Process {
+                        let start = Instant::now();
+                        self.write_files(std::mem::take(&mut writes.files))?;
+                        if let Some(timings) = &mut output.timings {
+                            timings.encode += start.elapsed();
+                        }
+                    }
+                    Ok(output)
+                },
+            )?;
+            FileReport {
+                dimensions: Some((output.width, output.height)),
+                timings: output.timings,
+                perceptual_hash: output.perceptual_hash.map(|hash| format!("{hash:016x}")),
+                renditions: output
+                    .renditions
+                    .into_iter()
+                    .map(|(_, path)| path)
+                    .collect(),
+                ..FileReport::new(path, outcome, Some(output.path))
+            }
+        } else {
+            if options.unqualified == UnqualifiedPolicy::Skip {
+                debug!("skipping {path:?}");
+                return Ok(FileReport::new(path, Outcome::Skipped, None));
+            }
+
+            let target_path = self.output_path(target_path.to_owned());
+            match options.unqualified {
+                UnqualifiedPolicy::Copy => {
+                    debug!("copying {path:?}");
+
+                    fs::copy(path, &target_path)?;
+                    FileReport::new(path, Outcome::Copied, Some(target_path))
+                }
+                UnqualifiedPolicy::Hardlink => {
+                    debug!("hard linking {path:?}");
+
+                    remove_existing(&target_path)?;
+                    fs::hard_link(path, &target_path)?;
+                    // attributes are shared with source, leave them untouched
+                    return Ok(FileReport::new(path, Outcome::Linked, Some(target_path)));
+                }
+                UnqualifiedPolicy::Symlink => {
+                    debug!("linking {path:?}");
+
+                    remove_existing(&target_path)?;
+                    symlink(&fs::canonicalize(path)?, &target_path, false)?;
+                    return Ok(FileReport::new(path, Outcome::Linked, Some(target_path)));
+                }
+                UnqualifiedPolicy::Skip => unreachable!(),
+            }
+        };
+
+        // sidecars merged into watermarked images are not written
+        let merged = options.sidecars == SidecarPolicy::Merge
+            && matches!(report.outcome, Outcome::Watermarked | Outcome::Deduplicated);
+        if options.sidecars != SidecarPolicy::Ignore && !merged {
+            if let (Some(sidecar), Some(output)) = (sidecar_of(path), &report.output) {
+                fs::copy(sidecar, output.with_extension("xmp"))?;
+            }
+        }
+
+        if options.preserve_attributes {
+            writes.attributes.clone_from(&report.output);
+        }
+        Ok(report)
+    }
+}
+
+// XMP sidecar of the image at `path`, if any: a file with the same name
+// and an `xmp` extension
+fn sidecar_of(path: &Path) -> Option<PathBuf> {
+    if is_xmp(path) {
+        return None;
+    }
+    ["xmp", "XMP"]
+        .into_iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|sidecar| sidecar.is_file())
+}
+
+// File at `path` is the XMP sidecar of an image, see `sidecar_of`
+pub(crate) fn is_sidecar(path: &Path) -> bool {
+    if !is_xmp(path) {
+        return false;
+    }
+    let Some(entries) = path.parent().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let other = entry.path();
+        other != path
+            && other.file_stem() == path.file_stem()
+            && ImageFormat::from_path(&other).is_ok()
+    })
+}
+
+// File at `path` has an `xmp` extension, whatever its case
+fn is_xmp(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("xmp"))
+}
+
+// Lowercase hexadecimal representation of `bytes`
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// Recopy file's timestamps and permissions from original file (`from`) to output one (`to`)
+fn recopy_attributes(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = fs::metadata(from)?;
+
+    let mut times = FileTimes::new().set_modified(metadata.modified()?);
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    OpenOptions::new().write(true).open(to)?.set_times(times)?;
+
+    // last, as permissions may forbid writing
+    fs::set_permissions(to, metadata.permissions())
+}
+
+// Remove file at `path`, if any, so that a link can be created there
+fn remove_existing(path: &Path) -> std::io::Result<()> {
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// Create at `to` a symbolic link pointing to the same target as link `from`
+fn copy_link(from: &Path, to: &Path) -> std::io::Result<()> {
+    let link_target = fs::read_link(from)?;
+    symlink(&link_target, to, from.is_dir())
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path, _is_dir: bool) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path, is_dir: bool) -> std::io::Result<()> {
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(target: &Path, link: &Path, _is_dir: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("cannot create link {link:?} to {target:?} on this platform"),
+    ))
+}
@@ -0,0 +1,253 @@
+use rusty_s3::actions::ListObjectsV2;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use std::env;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::archive::{finish, local_entries, relative_path, spread_entries, with_sink, Entry};
+use crate::config::Config;
+use crate::options::Options;
+use crate::report::Report;
+use crate::rules::Rules;
+use crate::run::Run;
+use crate::sink::OutputSink;
+use crate::trace::debug;
+use crate::ProgressBar;
+
+// Validity of signed requests
+const SIGNATURE_EXPIRY: Duration = Duration::from_secs(3600);
+
+/// Objects of a bucket under a prefix, given as `s3://bucket/prefix`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct S3Uri {
+    bucket: String,
+    // empty, or ending with '/'
+    prefix: String,
+}
+
+impl S3Uri {
+    /// Parse `path`, if it is a `s3://` URI
+    pub(crate) fn parse(path: &Path) -> Option<Self> {
+        let uri = path.to_str()?.strip_prefix("s3://")?;
+        let (bucket, prefix) = uri.split_once('/').unwrap_or((uri, ""));
+        if bucket.is_empty() {
+            return None;
+        }
+        let prefix = prefix.trim_matches('/');
+        Some(Self {
+            bucket: bucket.to_string(),
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{prefix}/")
+            },
+        })
+    }
+
+    // Key of object `name`, relative to the prefix
+    fn key(&self, name: &Path) -> String {
+        let name = name
+            .components()
+            .map(|comp| comp.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        format!("{}{name}", self.prefix)
+    }
+}
+
+/// Client of a bucket, configured from the usual environment variables:
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`
+/// for credentials (anonymous requests otherwise),
+/// `AWS_REGION` (defaults to "us-east-1") and `AWS_ENDPOINT_URL`
+/// for S3-compatible storages (path-style requests are then used)
+struct Client {
+    bucket: Bucket,
+    credentials: Option<Credentials>,
+    agent: ureq::Agent,
+}
+
+impl Client {
+    fn new(bucket: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let region = env::var("AWS_REGION")
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let (endpoint, style) = match env::var("AWS_ENDPOINT_URL") {
+            Ok(endpoint) => (endpoint, UrlStyle::Path),
+            Err(_) => (
+                format!("https://s3.{region}.amazonaws.com"),
+                UrlStyle::VirtualHost,
+            ),
+        };
+
+        Ok(Self {
+            bucket: Bucket::new(endpoint.parse()?, style, bucket.to_string(), region)
+                .map_err(|e| format!("invalid bucket {bucket:?}: {e}"))?,
+            credentials: Credentials::from_env(),
+            agent: ureq::Agent::new(),
+        })
+    }
+
+    // Keys of all objects under `prefix`, with their size
+    fn list(&self, prefix: &str) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+        let mut keys = vec![];
+        let mut continuation_token = None;
+        loop {
+            let mut action = self.bucket.list_objects_v2(self.credentials.as_ref());
+            action.with_prefix(prefix);
+            if let Some(token) = continuation_token.take() {
+                action.with_continuation_token(token);
+            }
+            let url = action.sign(SIGNATURE_EXPIRY);
+            let response = self.agent.get(url.as_str()).call()?.into_string()?;
+            let response = ListObjectsV2::parse_response(response)?;
+
+            // keys ending with a slash are folder markers
+            keys.extend(
+                response
+                    .contents
+                    .into_iter()
+                    .map(|object| (object.key, object.size))
+                    .filter(|(key, _)| !key.ends_with('/')),
+            );
+            match response.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => return Ok(keys),
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let url = self
+            .bucket
+            .get_object(self.credentials.as_ref(), key)
+            .sign(SIGNATURE_EXPIRY);
+        let mut content = vec![];
+        self.agent
+            .get(url.as_str())
+            .call()?
+            .into_reader()
+            .read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let url = self
+            .bucket
+            .put_object(self.credentials.as_ref(), key)
+            .sign(SIGNATURE_EXPIRY);
+        let mut request = self.agent.put(url.as_str());
+        if let Ok(format) = image::ImageFormat::from_path(key) {
+            request = request.set("Content-Type", format.to_mime_type());
+        }
+        request.send_bytes(data)?;
+        Ok(())
+    }
+}
+
+/// Objects uploaded under a prefix
+pub(crate) struct S3Sink {
+    uri: S3Uri,
+    client: Client,
+}
+
+impl S3Sink {
+    pub(crate) fn new(uri: S3Uri) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            client: Client::new(&uri.bucket)?,
+            uri,
+        })
+    }
+}
+
+impl OutputSink for S3Sink {
+    fn write_file(&self, name: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        self.client.put(&self.uri.key(name), data)
+    }
+}
+
+/// Watermark `source` into `target`, any of them being a `s3://bucket/prefix` URI,
+/// the other one a local directory (or a ZIP archive for `target`, see `with_sink`).
+/// Objects are downloaded and uploaded by workers, in parallel
+pub(crate) fn spread_s3(
+    source: &Path,
+    target: &Path,
+    cfg: &Config,
+    rules: &Rules,
+    options: &Options,
+    progress: Option<&ProgressBar>,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let run = Run::new(cfg, rules, options)?;
+
+    let files = match S3Uri::parse(source) {
+        Some(uri) => {
+            let client = Client::new(&uri.bucket)?;
+            let keys = client.list(&uri.prefix)?;
+            debug!("{} objects listed in {source:?}", keys.len());
+
+            let client = &client;
+            // objects are qualified by their key and listed size first,
+            // and only downloaded if their content is required
+            let entries = keys.into_iter().map(|(key, size)| -> Entry {
+                match relative_path(Path::new(&key[uri.prefix.len()..])) {
+                    Some(relative_path) => (
+                        relative_path,
+                        Some(size),
+                        Box::new(move || client.get(&key)),
+                    ),
+                    None => (
+                        PathBuf::from(&key),
+                        None,
+                        Box::new(move || Err(format!("unsafe object key: {key:?}").into())),
+                    ),
+                }
+            });
+            with_sink(target, options, |sink| {
+                spread_entries(&run, source, target, entries, sink, progress)
+            })?
+        }
+        None => {
+            if !source.is_dir() {
+                return Err(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Path {source:?} is not a directory as required"),
+                )));
+            }
+            let entries = local_entries(source, rules);
+            with_sink(target, options, |sink| {
+                spread_entries(&run, source, target, entries, sink, progress)
+            })?
+        }
+    };
+    finish(options, files, start)
+}
+
+/// Whether `source` or `target` is a `s3://` URI, to be handled by `spread_s3`
+pub(crate) fn is_s3(source: &Path, target: &Path) -> bool {
+    S3Uri::parse(source).is_some() || S3Uri::parse(target).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::S3Uri;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse() {
+        let uri = S3Uri::parse(Path::new("s3://bucket/some/prefix/")).unwrap();
+        assert_eq!(uri.bucket, "bucket");
+        assert_eq!(uri.prefix, "some/prefix/");
+        assert_eq!(
+            uri.key(Path::new("dir/photo.jpg")),
+            "some/prefix/dir/photo.jpg"
+        );
+
+        let uri = S3Uri::parse(Path::new("s3://bucket")).unwrap();
+        assert_eq!(uri.prefix, "");
+        assert_eq!(uri.key(Path::new("photo.jpg")), "photo.jpg");
+
+        assert_eq!(S3Uri::parse(Path::new("s3:///prefix")), None);
+        assert_eq!(S3Uri::parse(Path::new("/tmp/bucket")), None);
+    }
+}
@@ -0,0 +1,200 @@
+use image::{DynamicImage, GrayImage};
+
+// Largest side of the thumbnail whose edges are measured
+const ANALYZED_SIZE: u32 = 128;
+
+// Positions tried along each side of the image, besides the first one
+const STEPS: u32 = 8;
+
+/// Region of an image where a watermark can be placed, see `candidate_regions`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Edge density of the region, from 0 for a flat one to 1
+    pub detail: f32,
+}
+
+/// Regions of `img` of `size` (width, height) where a watermark can be placed,
+/// from the least to the most detailed, detail being measured as the density of edges
+/// of the region. Faces and subjects of photos are detailed, skies and backgrounds aren't.
+/// Regions are kept off the borders of the image when they fit, so that the watermark
+/// can't be cropped out with a thin strip
+pub fn candidate_regions(img: &DynamicImage, size: (u32, u32)) -> Vec<Region> {
+    let (width, height) = (img.width(), img.height());
+    if width == 0 || height == 0 {
+        return vec![];
+    }
+    let size = (size.0.clamp(1, width), size.1.clamp(1, height));
+
+    let thumbnail = img.thumbnail(ANALYZED_SIZE, ANALYZED_SIZE).to_luma8();
+    let edges = EdgeDensity::new(&thumbnail);
+    let scale = (
+        thumbnail.width() as f32 / width as f32,
+        thumbnail.height() as f32 / height as f32,
+    );
+
+    let xs = positions(width, size.0);
+    let ys = positions(height, size.1);
+    let mut regions: Vec<_> = ys
+        .iter()
+        .flat_map(|&y| xs.iter().map(move |&x| (x, y)))
+        .map(|(x, y)| Region {
+            x,
+            y,
+            width: size.0,
+            height: size.1,
+            detail: edges.mean(
+                (x as f32 * scale.0) as u32,
+                (y as f32 * scale.1) as u32,
+                ((x + size.0) as f32 * scale.0).ceil() as u32,
+                ((y + size.1) as f32 * scale.1).ceil() as u32,
+            ),
+        })
+        .collect();
+    // stable, ties keep the top left region first
+    regions.sort_by(|a, b| a.detail.total_cmp(&b.detail));
+    regions
+}
+
+// Positions of a region of `size` along a side of `extent`, away from its ends
+// by a margin of 5% when the region fits
+fn positions(extent: u32, size: u32) -> Vec<u32> {
+    let margin = extent / 20;
+    let (first, last) = match extent.checked_sub(size + 2 * margin) {
+        Some(_) => (margin, extent - size - margin),
+        None => (0, extent - size),
+    };
+    let mut positions: Vec<_> = (0..=STEPS)
+        .map(|i| first + (last - first) * i / STEPS)
+        .collect();
+    positions.dedup();
+    positions
+}
+
+// Summed-area table of the gradient magnitude of a grayscale image,
+// to get the edge density of any of its rectangles in constant time
+struct EdgeDensity {
+    width: u32,
+    height: u32,
+    sums: Vec<u64>,
+}
+
+impl EdgeDensity {
+    fn new(img: &GrayImage) -> Self {
+        let (width, height) = img.dimensions();
+        let luma = |x: u32, y: u32| i32::from(img.get_pixel(x, y).0[0]);
+        let stride = width as usize + 1;
+        let mut sums = vec![0; stride * (height as usize + 1)];
+        for y in 0..height {
+            let mut row = 0;
+            for x in 0..width {
+                let dx = if x + 1 < width {
+                    luma(x + 1, y) - luma(x, y)
+                } else {
+                    0
+                };
+                let dy = if y + 1 < height {
+                    luma(x, y + 1) - luma(x, y)
+                } else {
+                    0
+                };
+                row += u64::from(dx.unsigned_abs() + dy.unsigned_abs());
+                let i = (y as usize + 1) * stride + x as usize + 1;
+                sums[i] = sums[i - stride] + row;
+            }
+        }
+        Self {
+            width,
+            height,
+            sums,
+        }
+    }
+
+    // Mean gradient magnitude of rectangle `x0..x1` x `y0..y1`, from 0 to 1
+    fn mean(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> f32 {
+        let (x1, y1) = (x1.min(self.width), y1.min(self.height));
+        if x0 >= x1 || y0 >= y1 {
+            return 0.0;
+        }
+        let stride = self.width as usize + 1;
+        let sum = |x: u32, y: u32| self.sums[y as usize * stride + x as usize];
+        let total = sum(x1, y1) + sum(x0, y0) - sum(x0, y1) - sum(x1, y0);
+        let area = u64::from(x1 - x0) * u64::from(y1 - y0);
+        // each pixel differs from its neighbours by 255 at most
+        total as f32 / (area * 510) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, Rgb, RgbImage};
+
+    #[test]
+    fn test_low_detail_first() {
+        // checkerboard on the left half, flat on the right one
+        let img = RgbImage::from_fn(400, 200, |x, y| match x < 200 && (x / 8 + y / 8) % 2 == 0 {
+            true => Rgb([0, 0, 0]),
+            false => Rgb([200, 200, 200]),
+        });
+        let regions = candidate_regions(&img.into(), (100, 80));
+
+        let best = regions[0];
+        assert_eq!((best.width, best.height), (100, 80));
+        assert!(best.x >= 200, "{best:?}");
+        assert_eq!(best.detail, 0.0);
+        assert!(regions.last().unwrap().detail > 0.1);
+        // kept off the borders
+        assert!(regions
+            .iter()
+            .all(|r| r.x >= 20 && r.y >= 10 && r.x + r.width <= 380 && r.y + r.height <= 190));
+    }
+
+    #[test]
+    fn test_chosen_region() {
+        use crate::processor::{Context, Processor, SalientWatermark};
+        use image::{Rgba, RgbaImage};
+        use std::path::Path;
+
+        let watermark = RgbaImage::from_fn(40, 40, |x, y| match (10..20).contains(&x) && y < 30 {
+            true => Rgba([255, 0, 0, 255]),
+            false => Rgba([0, 0, 0, 0]),
+        });
+        let ctx = Context {
+            path: Path::new("a.png"),
+            watermark: &watermark,
+        };
+        let img = DynamicImage::from(RgbImage::from_pixel(200, 100, Rgb([0, 0, 255])));
+        let regions = candidate_regions(&img, (10, 30));
+        let last = *regions.last().unwrap();
+
+        let processor = SalientWatermark {
+            choose: Some(Box::new(|_, regions| Some(regions.len() - 1))),
+        };
+        let output = processor.process(img, &ctx).unwrap().to_rgb8();
+        assert_eq!(*output.get_pixel(last.x, last.y), Rgb([255, 0, 0]));
+        assert_eq!(
+            *output.get_pixel(last.x + 10, last.y + 30),
+            Rgb([0, 0, 255])
+        );
+    }
+
+    #[test]
+    fn test_oversized() {
+        let img = GrayImage::from_pixel(50, 40, Luma([0]));
+        let regions = candidate_regions(&img.into(), (500, 500));
+        assert_eq!(
+            regions,
+            [Region {
+                x: 0,
+                y: 0,
+                width: 50,
+                height: 40,
+                detail: 0.0
+            }]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use axum::extract::{DefaultBodyLimit, Multipart, Path as UrlPath, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::jobs::Registry;
+use crate::metrics::Metrics;
+use crate::options::Options;
+use crate::report::Report;
+use crate::rules::Rules;
+use crate::{spread_watermark, watermark_bytes};
+
+// Largest request body, i.e. an uploaded image
+const MAX_BODY_SIZE: usize = 100 * 1024 * 1024;
+
+// State shared by the handlers
+struct Service {
+    cfg: Config,
+    jobs: Registry,
+}
+
+/// Folder job submitted to `POST /jobs`, with paths of the server
+#[derive(Deserialize)]
+pub struct JobRequest {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Rules of the run, the default ones if missing
+    #[serde(default)]
+    pub rules: Rules,
+}
+
+/// State of a folder job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Done,
+    Failed,
+}
+
+/// Status of a folder job, returned by `GET /jobs/{id}`
+#[derive(Debug, Serialize)]
+pub struct JobStatus {
+    pub state: JobState,
+    /// Entries (files and directories) processed so far
+    pub processed: u64,
+    /// Entries met so far by the walk of the input folder
+    pub total: u64,
+    /// Report of the run, once done
+    pub report: Option<Report>,
+    /// Metrics of the report, once done
+    pub metrics: Option<Metrics>,
+    /// Error of the run, if it failed
+    pub error: Option<String>,
+}
+
+/// Router of the HTTP service, watermarking with `cfg`:
+/// - `POST /watermark`: multipart form whose `image` field is an image,
+///   answered with the watermarked image, as `watermark_bytes` does
+/// - `POST /jobs`: `JobRequest` as JSON, runs `spread_watermark` in the background
+///   and answers `{"id": ...}` with status 202
+/// - `GET /jobs/{id}`: `JobStatus` of a job as JSON, to poll its progress.
+///   Jobs are forgotten an hour after they are over
+pub fn router(cfg: Config) -> Router {
+    let service = Arc::new(Service {
+        cfg,
+        jobs: Registry::default(),
+    });
+    Router::new()
+        .route("/watermark", post(watermark))
+        .route("/jobs", post(submit))
+        .route("/jobs/{id}", get(status))
+        .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+        .with_state(service)
+}
+
+/// Serve the routes of `router` on `addr`, until the process ends
+pub async fn serve(addr: SocketAddr, cfg: Config) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router(cfg)).await
+}
+
+async fn watermark(
+    State(service): State<Arc<Service>>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, String)> {
+    let mut input = None;
+    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+        if field.name() == Some("image") {
+            input = Some(field.bytes().await.map_err(bad_request)?);
+        }
+    }
+    let input = input.ok_or_else(|| bad_request("missing `image` field"))?;
+
+    // decoding and encoding are CPU-bound
+    let output = tokio::task::spawn_blocking(move || {
+        watermark_bytes(&input, &service.cfg, &Options::default()).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let content_type = image::guess_format(&output)
+        .map_or("application/octet-stream", |format| format.to_mime_type());
+    Ok(([(header::CONTENT_TYPE, content_type)], output).into_response())
+}
+
+async fn submit(
+    State(service): State<Arc<Service>>,
+    Json(request): Json<JobRequest>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let id = service.jobs.submit({
+        let service = service.clone();
+        move |job| {
+            spread_watermark(
+                &request.input,
+                &request.output,
+                &service.cfg,
+                &request.rules,
+                &Options::default(),
+                Some(&job.progress),
+            )
+        }
+    });
+    (StatusCode::ACCEPTED, Json(serde_json::json!({ "id": id })))
+}
+
+async fn status(
+    State(service): State<Arc<Service>>,
+    UrlPath(id): UrlPath<u64>,
+) -> Result<Json<JobStatus>, StatusCode> {
+    let job = service.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    let (state, report, error) = match job.result() {
+        None => (JobState::Running, None, None),
+        Some(Ok(report)) => (JobState::Done, Some(report), None),
+        Some(Err(e)) => (JobState::Failed, None, Some(e)),
+    };
+    Ok(Json(JobStatus {
+        state,
+        processed: job.progress.position(),
+        total: job.progress.length().unwrap_or_default(),
+        metrics: report.as_ref().map(Report::metrics),
+        report,
+        error,
+    }))
+}
+
+fn bad_request(e: impl ToString) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, e.to_string())
+}
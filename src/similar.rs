@@ -0,0 +1,119 @@
+use image::DynamicImage;
+use std::collections::HashMap;
+
+use crate::report::{Duplicate, FileReport};
+
+/// Perceptual hash of `img`: 64-bit difference hash of its 9x8 grayscale thumbnail,
+/// each bit telling whether a pixel is brighter than its right neighbour.
+/// Resized, recompressed or slightly edited copies of an image have close hashes
+pub(crate) fn perceptual_hash(img: &DynamicImage) -> u64 {
+    let thumbnail = img.thumbnail_exact(9, 8).to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = thumbnail.get_pixel(x, y).0[0] > thumbnail.get_pixel(x + 1, y).0[0];
+            hash = hash << 1 | u64::from(brighter);
+        }
+    }
+    hash
+}
+
+/// Flag each file of `files` duplicating an earlier one, in their order: an exact duplicate
+/// when their sources have the same SHA-256, a near duplicate when their perceptual hashes
+/// differ by `max_distance` bits at most. Files are compared to the first file of each group
+pub(crate) fn flag_duplicates(files: &mut [FileReport], max_distance: u32) {
+    // first file of each group, by source SHA-256 and by perceptual hash
+    let mut exact: HashMap<String, usize> = HashMap::new();
+    let mut originals: Vec<(u64, usize)> = vec![];
+
+    for i in 0..files.len() {
+        let Some(hash) = files[i].perceptual_hash.as_deref() else {
+            continue;
+        };
+        let Ok(hash) = u64::from_str_radix(hash, 16) else {
+            continue;
+        };
+
+        if let Some(sha256) = files[i].source_sha256.clone() {
+            if let Some(&original) = exact.get(&sha256) {
+                files[i].duplicate = Some(Duplicate {
+                    of: files[original].source.clone(),
+                    exact: true,
+                    distance: 0,
+                });
+                continue;
+            }
+            exact.insert(sha256, i);
+        }
+
+        let similar = originals
+            .iter()
+            .map(|(original_hash, original)| ((original_hash ^ hash).count_ones(), *original))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min();
+        match similar {
+            Some((distance, original)) => {
+                files[i].duplicate = Some(Duplicate {
+                    of: files[original].source.clone(),
+                    exact: false,
+                    distance,
+                });
+            }
+            None => originals.push((hash, i)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::Outcome;
+    use image::{imageops::FilterType, Rgb, RgbImage};
+    use std::path::Path;
+
+    // Horizontal gradient, with a dark square
+    fn photo() -> DynamicImage {
+        RgbImage::from_fn(300, 200, |x, y| {
+            if (100..150).contains(&x) && (50..100).contains(&y) {
+                Rgb([10, 10, 10])
+            } else {
+                let level = (x * 255 / 299) as u8;
+                Rgb([level, level / 2, (y * 255 / 199) as u8])
+            }
+        })
+        .into()
+    }
+
+    fn report(name: &str, sha256: &str, img: &DynamicImage) -> FileReport {
+        FileReport {
+            source_sha256: Some(sha256.to_owned()),
+            perceptual_hash: Some(format!("{:016x}", perceptual_hash(img))),
+            ..FileReport::new(Path::new(name), Outcome::Watermarked, None)
+        }
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let photo = photo();
+        let resized = photo.resize_exact(150, 100, FilterType::Triangle);
+        let other = photo.fliph();
+        let mut files = [
+            report("a.png", "1", &photo),
+            report("b.png", "1", &photo),
+            report("c.png", "2", &resized),
+            report("d.png", "3", &other),
+        ];
+        flag_duplicates(&mut files, 5);
+
+        assert_eq!(files[0].duplicate, None);
+        let exact = files[1].duplicate.as_ref().unwrap();
+        assert_eq!(
+            (exact.of.as_path(), exact.exact),
+            (Path::new("a.png"), true)
+        );
+        let near = files[2].duplicate.as_ref().unwrap();
+        assert_eq!((near.of.as_path(), near.exact), (Path::new("a.png"), false));
+        assert!(near.distance <= 5);
+        assert_eq!(files[3].duplicate, None);
+    }
+}
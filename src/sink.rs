@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where outputs are written, shared by workers: a directory (`FsSink`),
+/// or i.e. an object storage or an in-memory store, see `spread_watermark_to`.
+/// Paths are relative to the root of the sink
+pub trait OutputSink: Sync {
+    /// Create directory `path` and its parents, before files are written in it.
+    /// Does nothing by default, for stores without directories
+    fn create_dir_all(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let _ = path;
+        Ok(())
+    }
+
+    /// Write `data` as file `path`, replacing it if it exists
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// Files of a directory of the filesystem
+#[derive(Debug, Clone)]
+pub struct FsSink {
+    root: PathBuf,
+}
+
+impl FsSink {
+    /// Sink writing in directory `root`, created along with the first directory
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl OutputSink for FsSink {
+    fn create_dir_all(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(self.root.join(path))?;
+        Ok(())
+    }
+
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(self.root.join(path), data)?;
+        Ok(())
+    }
+}
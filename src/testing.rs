@@ -0,0 +1,202 @@
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage, RgbaImage};
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::exif::{ARTIST, COPYRIGHT, IMAGE_DESCRIPTION};
+use crate::graphics::render_watermark;
+use crate::metadata::set_exif_fields;
+
+/// Image generated for tests, of a single `color` so that a watermark stands out,
+/// see `assert_watermarked`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticImage {
+    pub width: u32,
+    pub height: u32,
+    pub format: ImageFormat,
+    pub color: Rgb<u8>,
+    /// Copyright Exif field, for formats with Exif support
+    pub copyright: Option<String>,
+    /// Artist Exif field, for formats with Exif support
+    pub artist: Option<String>,
+    /// ImageDescription Exif field, for formats with Exif support
+    pub description: Option<String>,
+}
+
+impl Default for SyntheticImage {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 480,
+            format: ImageFormat::Jpeg,
+            color: Rgb([200, 200, 200]),
+            copyright: None,
+            artist: None,
+            description: None,
+        }
+    }
+}
+
+impl SyntheticImage {
+    /// Gray JPEG image of `width` x `height`
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    /// Image encoded in its format, with its Exif fields
+    pub fn encode(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let img = RgbImage::from_pixel(self.width, self.height, self.color);
+        let mut encoded = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(img).write_to(&mut encoded, self.format)?;
+
+        let fields: Vec<_> = [
+            (COPYRIGHT, &self.copyright),
+            (ARTIST, &self.artist),
+            (IMAGE_DESCRIPTION, &self.description),
+        ]
+        .into_iter()
+        .filter_map(|(tag, value)| Some((tag, value.clone()?)))
+        .collect();
+        if fields.is_empty() {
+            return Ok(encoded.into_inner());
+        }
+        Ok(set_exif_fields(encoded.into_inner().into(), &fields)?.into())
+    }
+
+    /// Write the encoded image to `path`, creating its parent directories
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, self.encode()?)?;
+        Ok(())
+    }
+}
+
+/// Whether `output`, the encoded output of a `SyntheticImage`, carries one of the watermarks
+/// of `cfg` on its top left corner, where the default processors overlay it.
+/// Most pixels covered by the watermark must have taken its color, despite lossy encodings
+pub fn has_watermark(output: &[u8], cfg: &Config) -> Result<bool, Box<dyn std::error::Error>> {
+    let img = image::load_from_memory(output)?.to_rgb8();
+    for text in cfg.all_texts() {
+        if covers(&img, &render_watermark(cfg, text)?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Panic unless `output` carries one of the watermarks of `cfg`, see `has_watermark`
+#[track_caller]
+pub fn assert_watermarked(output: &[u8], cfg: &Config) {
+    match has_watermark(output, cfg) {
+        Ok(true) => {}
+        Ok(false) => panic!("output doesn't carry the watermark {:?}", cfg.text),
+        Err(e) => panic!("output can't be decoded: {e}"),
+    }
+}
+
+// `watermark` is overlaid on the top left corner of `img`, a single color otherwise
+fn covers(img: &RgbImage, watermark: &RgbaImage) -> bool {
+    let (width, height) = (
+        img.width().min(watermark.width()),
+        img.height().min(watermark.height()),
+    );
+    let pixels = || {
+        (0..height).flat_map(move |y| {
+            (0..width).map(move |x| (img.get_pixel(x, y).0, watermark.get_pixel(x, y).0))
+        })
+    };
+    let Some(max_alpha) = pixels().map(|(_, mark)| mark[3]).max().filter(|&a| a > 0) else {
+        return false;
+    };
+
+    // median of the pixels left uncovered, for each channel
+    let mut uncovered: [Vec<u8>; 3] = Default::default();
+    for (pixel, _) in pixels().filter(|(_, mark)| mark[3] == 0) {
+        for (channel, value) in uncovered.iter_mut().zip(pixel) {
+            channel.push(value);
+        }
+    }
+    if uncovered[0].is_empty() {
+        return false;
+    }
+    let background = uncovered.map(|mut channel| {
+        channel.sort_unstable();
+        channel[channel.len() / 2]
+    });
+
+    // pixels mostly covered are closer to the blended watermark than to the background
+    let distance = |a: [u8; 3], b: [f32; 3]| -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(&a, b)| (f32::from(a) - b).abs())
+            .sum()
+    };
+    let (mut covered, mut marked) = (0, 0);
+    for (pixel, mark) in pixels().filter(|(_, mark)| u16::from(mark[3]) * 2 >= u16::from(max_alpha))
+    {
+        let alpha = f32::from(mark[3]) / 255.0;
+        let blended = [0, 1, 2]
+            .map(|i| f32::from(mark[i]) * alpha + f32::from(background[i]) * (1.0 - alpha));
+        covered += 1;
+        if distance(pixel, blended) < distance(pixel, background.map(f32::from)) {
+            marked += 1;
+        }
+    }
+    marked * 10 >= covered * 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exif::read_ascii;
+    use crate::graphics::read_exif;
+    use crate::options::Options;
+    use crate::watermark_bytes;
+
+    #[test]
+    fn test_watermarked() {
+        let cfg = Config::default();
+        for format in [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP] {
+            let source = SyntheticImage {
+                format,
+                ..SyntheticImage::new(800, 600)
+            }
+            .encode()
+            .unwrap();
+            assert_eq!(image::guess_format(&source).unwrap(), format);
+            assert!(!has_watermark(&source, &cfg).unwrap());
+
+            let output = watermark_bytes(&source, &cfg, &Options::default()).unwrap();
+            assert_watermarked(&output, &cfg);
+            let other = Config {
+                text: "Other".to_owned(),
+                ..Config::default()
+            };
+            assert!(!has_watermark(&output, &other).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_exif() {
+        let source = SyntheticImage {
+            copyright: Some("ACME".to_owned()),
+            artist: Some("Jane".to_owned()),
+            ..Default::default()
+        }
+        .encode()
+        .unwrap();
+        let exif = read_exif(Path::new("a.jpg"), Cursor::new(&source))
+            .unwrap()
+            .unwrap();
+        assert_eq!(read_ascii(&exif, COPYRIGHT).as_deref(), Some("ACME"));
+        assert_eq!(read_ascii(&exif, ARTIST).as_deref(), Some("Jane"));
+        assert_eq!(read_ascii(&exif, IMAGE_DESCRIPTION), None);
+    }
+}
@@ -0,0 +1,77 @@
+// Instrumentation of runs: with the `tracing` feature, events are emitted
+// through `tracing` and files are processed in spans, otherwise `log` is used
+use std::path::Path;
+
+use crate::report::FileReport;
+
+// `warn` is unused without the `walkdir` feature
+#[cfg(not(feature = "tracing"))]
+#[allow(unused_imports)]
+pub(crate) use log::{debug, error, warn};
+#[cfg(feature = "tracing")]
+#[allow(unused_imports)]
+pub(crate) use tracing::{debug, error, warn};
+
+/// Root span of a `spread_watermark` run
+#[derive(Debug)]
+pub(crate) struct RunSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl RunSpan {
+    pub(crate) fn new(folder: &Path, target_dir: &Path) -> Self {
+        #[cfg(not(feature = "tracing"))]
+        let _ = (folder, target_dir);
+
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!("spread_watermark", ?folder, ?target_dir),
+        }
+    }
+
+    /// Enter the span of the processing of `path`, child of the run span
+    /// whatever the worker thread it is processed on
+    pub(crate) fn file(&self, path: &Path) -> FileSpan {
+        #[cfg(not(feature = "tracing"))]
+        let _ = path;
+
+        FileSpan {
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!(
+                parent: &self.span,
+                "file",
+                ?path,
+                size = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+                outcome = tracing::field::Empty,
+            )
+            .entered(),
+        }
+    }
+}
+
+/// Span of the processing of a file, exited when dropped
+pub(crate) struct FileSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+impl FileSpan {
+    /// Record the result of the processing in the span fields
+    pub(crate) fn record(&self, report: &FileReport) {
+        #[cfg(not(feature = "tracing"))]
+        let _ = report;
+
+        #[cfg(feature = "tracing")]
+        {
+            if let Ok(metadata) = std::fs::metadata(&report.source) {
+                self.span.record("size", metadata.len());
+            }
+            self.span
+                .record("duration_ms", report.duration.as_millis() as u64);
+            self.span
+                .record("outcome", tracing::field::debug(report.outcome));
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use image::RgbaImage;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::trace::debug;
+
+/// Extensions of the videos authorized by `Rules::default()`, with the `ffmpeg` feature
+pub const VIDEO_EXTENSIONS: [&str; 2] = ["mp4", "mov"];
+
+// Number of watermarks written for `ffmpeg` so far, to name them uniquely
+static WATERMARKS: AtomicUsize = AtomicUsize::new(0);
+
+/// File at `path` is a video, by its extension
+pub(crate) fn is_video(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        VIDEO_EXTENSIONS
+            .iter()
+            .any(|video| ext.eq_ignore_ascii_case(video))
+    })
+}
+
+/// Overlay `watermark` on the top left corner of each frame of video `src`,
+/// re-encoded into `dst` with the default codec of its container.
+/// Audio streams and metadata are copied as is.
+/// The `ffmpeg` program is run, it must be found in `PATH`
+pub(crate) fn watermark_video(
+    src: &Path,
+    dst: &Path,
+    watermark: &RgbaImage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let watermark_path = std::env::temp_dir().join(format!(
+        "filigram-{}-{}.png",
+        std::process::id(),
+        WATERMARKS.fetch_add(1, Ordering::Relaxed)
+    ));
+    watermark.save(&watermark_path)?;
+
+    debug!("running ffmpeg on {src:?}");
+    let output = Command::new("ffmpeg")
+        .args(["-nostdin", "-y", "-loglevel", "error", "-i"])
+        .arg(src)
+        .arg("-i")
+        .arg(&watermark_path)
+        .args([
+            "-filter_complex",
+            "[0:v][1:v]overlay=0:0[v]",
+            "-map",
+            "[v]",
+            "-map",
+            "0:a?",
+            "-c:a",
+            "copy",
+            "-map_metadata",
+            "0",
+        ])
+        .arg(dst)
+        .output();
+    fs::remove_file(&watermark_path).ok();
+
+    let output = output.map_err(|e| format!("Can't run ffmpeg: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffmpeg failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video() {
+        assert!(is_video(Path::new("clips/intro.mp4")));
+        assert!(is_video(Path::new("clips/INTRO.MOV")));
+        assert!(!is_video(Path::new("clips/intro.jpg")));
+        assert!(!is_video(Path::new("clips/mp4")));
+    }
+}
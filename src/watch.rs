@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use walkdir::WalkDir;
+
+use crate::config::Config;
+use crate::hooks::Event;
+use crate::metrics::{Metrics, Totals};
+use crate::options::Options;
+use crate::rules::{Rules, SymlinkPolicy};
+use crate::run::Run;
+use crate::trace::{warn, RunSpan};
+use crate::{check_dir, handle_file, is_hidden, nested_targets};
+
+// Modification time and size of a file, as met by a scan
+type FileState = (Option<SystemTime>, u64);
+
+/// Watch `folder` and apply the watermark to its files, into `target_dir`,
+/// as they appear or change, until `stop` is set (i.e. by a SIGINT handler).
+///
+/// `folder` is scanned every `interval`: a file is processed once it is unchanged
+/// between two scans, so that files being copied in are not read half-written.
+/// Files already in `folder` are processed by the second scan, modified files are
+/// processed again and overwrite their output with `Layout::Mirror`.
+/// Files are handled as in `spread_watermark`, each of them being notified through
+/// `Options::hooks` and events, `Options::journal`, `manifest`, `gallery`
+/// and `similar_images` aside: reports of files are not kept once notified.
+/// Once stopped, the `Metrics` sum up all the files processed since the start,
+/// percentiles of processing times being those of the latest files
+pub fn watch<P, T>(
+    folder: &P,
+    target_dir: &T,
+    cfg: &Config,
+    rules: &Rules,
+    options: &Options,
+    interval: Duration,
+    stop: &AtomicBool,
+) -> Result<Metrics, Box<dyn std::error::Error>>
+where
+    P: AsRef<Path> + ?Sized,
+    T: AsRef<Path> + ?Sized,
+{
+    let start = Instant::now();
+    let (folder, target_dir) = (folder.as_ref(), target_dir.as_ref());
+    check_dir(folder)?;
+
+    let span = RunSpan::new(folder, target_dir);
+    let run = Run::new(cfg, rules, options)?.quarantined_in(target_dir);
+    options.emit(|| Event::Started {
+        source: folder.to_owned(),
+        target: target_dir.to_owned(),
+    });
+    let roots = [(folder.to_owned(), target_dir.to_owned())];
+    let nested_targets = nested_targets(&roots, options.nested_target)?;
+    fs::create_dir_all(target_dir)?;
+
+    // state of files at the previous scan, and when they were processed
+    let mut scanned: HashMap<PathBuf, FileState> = HashMap::new();
+    let mut processed: HashMap<PathBuf, FileState> = HashMap::new();
+    let mut totals = Totals::default();
+    while !stop.load(Ordering::Relaxed) {
+        let scan = scan(folder, rules, &nested_targets);
+        let ready: Vec<(PathBuf, FileState)> = scan
+            .iter()
+            .filter(|(path, state)| {
+                scanned.get(*path) == Some(state) && processed.get(*path) != Some(state)
+            })
+            .map(|(path, state)| (path.clone(), *state))
+            .collect();
+
+        let reports = run.map(ready.iter(), |(path, _)| {
+            let relative_path = path.strip_prefix(folder).expect("can't strip prefix");
+            let target_path = options
+                .layout
+                .output_path(path, relative_path)
+                .map(|output| {
+                    let target_path = target_dir.join(output);
+                    // a failure surfaces when the output is written
+                    if let Some(parent) = target_path.parent() {
+                        fs::create_dir_all(parent).ok();
+                    }
+                    target_path
+                });
+            handle_file(&run, &span, None, path, relative_path, target_path, None)
+        });
+        for report in &reports {
+            totals.add(report);
+        }
+
+        processed.extend(ready);
+        // a file removed then added back is processed again
+        processed.retain(|path, _| scan.contains_key(path));
+        scanned = scan;
+        sleep(interval, stop);
+    }
+
+    let metrics = totals.metrics(start.elapsed());
+    options.emit(|| Event::Finished(metrics.clone()));
+    Ok(metrics)
+}
+
+// State of the files of `folder` to process, according to `rules`
+fn scan(folder: &Path, rules: &Rules, nested_targets: &[PathBuf]) -> HashMap<PathBuf, FileState> {
+    let mut walker = WalkDir::new(folder).follow_links(rules.symlinks == SymlinkPolicy::Follow);
+    if let Some(max_depth) = rules.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+    walker
+        .into_iter()
+        .filter_entry(|entry| {
+            if rules.skip_hidden && is_hidden(entry) {
+                return false;
+            }
+            !nested_targets.iter().any(|target| target == entry.path())
+        })
+        // files may be moved or removed while scanned, they are met again by next scans
+        .filter_map(|entry| {
+            entry
+                .and_then(|entry| Ok((entry.metadata()?, entry)))
+                .map_err(|e| warn!("Can't scan {folder:?}: {e}"))
+                .ok()
+        })
+        .filter(|(metadata, _)| !metadata.is_dir())
+        .filter(|(_, entry)| !(rules.symlinks == SymlinkPolicy::Skip && entry.path_is_symlink()))
+        .map(|(metadata, entry)| {
+            let state = (metadata.modified().ok(), metadata.len());
+            (entry.into_path(), state)
+        })
+        .collect()
+}
+
+// Sleep for `duration`, or until `stop` is set
+fn sleep(duration: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        thread::sleep(remaining.min(Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "zip")]
+
+use filigram_rs::{spread_watermark, Config, Options, Outcome, Rules};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+// Archive of test images, under "photos/"
+fn build_archive(path: &str) -> PathBuf {
+    std::fs::create_dir_all("tmp").unwrap();
+    let mut writer = ZipWriter::new(File::create(path).unwrap());
+    writer
+        .add_directory("photos/", SimpleFileOptions::default())
+        .unwrap();
+    for name in ["test.jpg", "test.bmp"] {
+        writer
+            .start_file(format!("photos/{name}"), SimpleFileOptions::default())
+            .unwrap();
+        writer
+            .write_all(&std::fs::read(format!("tests/img/{name}")).unwrap())
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    PathBuf::from(path)
+}
+
+fn jpg_only() -> Rules {
+    Rules::builder().allow_ext("jpg").build()
+}
+
+#[test]
+fn test_zip_to_dir() {
+    let source = build_archive("tmp/zip_to_dir.zip");
+    let target = PathBuf::from("tmp/zip_to_dir");
+    std::fs::remove_dir_all(&target).ok();
+
+    let report = spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Copied), 1);
+
+    let img = image::open(target.join("photos/test.jpg")).unwrap();
+    assert_eq!((img.width(), img.height()), (500, 500));
+    assert_eq!(
+        std::fs::read(target.join("photos/test.bmp")).unwrap(),
+        std::fs::read("tests/img/test.bmp").unwrap()
+    );
+}
+
+#[test]
+fn test_zip_to_zip() {
+    let source = build_archive("tmp/zip_to_zip.zip");
+    let target = PathBuf::from("tmp/zip_to_zip/out.zip");
+
+    let report = spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.failed().count(), 0);
+    assert_eq!(report.files[1].output, Some(target.join("photos/test.jpg")));
+
+    let mut archive = ZipArchive::new(File::open(&target).unwrap()).unwrap();
+    assert_eq!(archive.len(), 2);
+    let mut content = vec![];
+    archive
+        .by_name("photos/test.jpg")
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    let img = image::load_from_memory(&content).unwrap();
+    assert_eq!((img.width(), img.height()), (500, 500));
+}
+
+#[test]
+fn test_deterministic_zip() {
+    let source = build_archive("tmp/deterministic_zip.zip");
+    let options = Options {
+        deterministic: true,
+        threads: Some(4),
+        ..Default::default()
+    };
+    let run = |target: &str| {
+        let target = PathBuf::from(target);
+        spread_watermark(
+            &source,
+            &target,
+            &Config::default(),
+            &jpg_only(),
+            &options,
+            None,
+        )
+        .unwrap();
+        std::fs::read(target).unwrap()
+    };
+
+    let first = run("tmp/deterministic_zip/first.zip");
+    assert_eq!(first, run("tmp/deterministic_zip/second.zip"));
+    let archive = ZipArchive::new(std::io::Cursor::new(first)).unwrap();
+    let names: Vec<_> = archive.file_names().collect();
+    assert_eq!(names, ["photos/test.bmp", "photos/test.jpg"]);
+}
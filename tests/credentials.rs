@@ -0,0 +1,104 @@
+#![cfg(feature = "c2pa")]
+
+use filigram_rs::{spread_watermark, Config, ContentCredentials, Options, Rules, SigningAlg};
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose, PKCS_ECDSA_P256_SHA256,
+};
+use std::fs::File;
+use std::path::PathBuf;
+
+// Credentials of a test signer issued by a test root, both generated for the test
+// so that no private key is committed
+fn credentials() -> ContentCredentials {
+    let name = |common_name: &str| {
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, common_name);
+        name.push(DnType::OrganizationName, "Filigram");
+        name
+    };
+
+    let root_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+    let mut root = CertificateParams::default();
+    root.distinguished_name = name("Filigram Test Root");
+    root.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    root.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let root = root.self_signed(&root_key).unwrap();
+
+    // C2PA requires a signer certificate which is not a CA, for signing with an allowed usage
+    let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+    let mut signer = CertificateParams::default();
+    signer.distinguished_name = name("Filigram Test Signer");
+    signer.is_ca = IsCa::ExplicitNoCa;
+    signer.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    signer.extended_key_usages = vec![ExtendedKeyUsagePurpose::EmailProtection];
+    signer.use_authority_key_identifier_extension = true;
+    let signer = signer.signed_by(&key, &root, &root_key).unwrap();
+
+    ContentCredentials {
+        creator: Some("Jane Doe".into()),
+        ..ContentCredentials::new(
+            format!("{}{}", signer.pem(), root.pem()).into_bytes(),
+            key.serialize_pem().into_bytes(),
+            SigningAlg::Es256,
+        )
+    }
+}
+
+#[test]
+fn test_content_credentials() {
+    let source = PathBuf::from("tmp/credentials_src");
+    let target = PathBuf::from("tmp/credentials");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    for name in ["test.jpg", "test.bmp"] {
+        std::fs::copy(format!("tests/img/{name}"), source.join(name)).unwrap();
+    }
+
+    let options = Options {
+        credentials: Some(credentials()),
+        ..Default::default()
+    };
+    let rules = Rules::builder().allow_ext("jpg").allow_ext("bmp").build();
+    let report =
+        spread_watermark(&source, &target, &Config::default(), &rules, &options, None).unwrap();
+    assert!(report.failed().next().is_none());
+
+    let output = target.join("test.jpg");
+    let reader = c2pa::Reader::from_stream("image/jpeg", File::open(&output).unwrap()).unwrap();
+    let manifest = reader.active_manifest().unwrap();
+    assert_eq!(manifest.title(), Some("test.jpg"));
+    let json = reader.json();
+    assert!(json.contains("c2pa.watermarked"), "{json}");
+    assert!(json.contains("Jane Doe"), "{json}");
+    // the content is still a decodable image
+    let img = image::open(&output).unwrap();
+    assert_eq!((img.width(), img.height()), (500, 500));
+
+    // BMP images cannot carry content credentials, they are watermarked anyway
+    assert!(image::open(target.join("test.bmp")).is_ok());
+}
+
+#[test]
+fn test_invalid_credentials() {
+    let source = PathBuf::from("tmp/invalid_credentials_src");
+    let target = PathBuf::from("tmp/invalid_credentials");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("test.jpg")).unwrap();
+
+    let options = Options {
+        credentials: Some(ContentCredentials::new(
+            b"not a certificate".to_vec(),
+            b"not a key".to_vec(),
+            SigningAlg::Es256,
+        )),
+        ..Default::default()
+    };
+    let rules = Rules::builder().allow_ext("jpg").build();
+    let report =
+        spread_watermark(&source, &target, &Config::default(), &rules, &options, None).unwrap();
+    assert_eq!(report.failed().count(), 1);
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "grpc")]
+
+use filigram_rs::grpc::proto::jobs_client::JobsClient;
+use filigram_rs::grpc::proto::{
+    CancelJobRequest, JobState, StreamProgressRequest, SubmitJobRequest,
+};
+use filigram_rs::grpc::service;
+use filigram_rs::Config;
+use tokio_stream::wrappers::TcpListenerStream;
+
+#[test]
+fn test_folder_job() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service(Config::default()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let mut client = JobsClient::connect(format!("http://{addr}")).await.unwrap();
+        std::fs::remove_dir_all("tmp/grpc_job").ok();
+
+        let job = SubmitJobRequest {
+            input: "tests/img".into(),
+            output: "tmp/grpc_job".into(),
+            rules: String::new(),
+        };
+        let id = client.submit_job(job).await.unwrap().into_inner().id;
+
+        let mut stream = client
+            .stream_progress(StreamProgressRequest { id })
+            .await
+            .unwrap()
+            .into_inner();
+        let mut last = None;
+        while let Some(progress) = stream.message().await.unwrap() {
+            last = Some(progress);
+        }
+        let last = last.unwrap();
+        assert_eq!(last.state(), JobState::Done);
+        assert_eq!(last.processed, last.total);
+        let report: serde_json::Value = serde_json::from_str(&last.report).unwrap();
+        assert_eq!(report["files"].as_array().unwrap().len(), 4);
+
+        // the job is over
+        let cancelled = client.cancel_job(CancelJobRequest { id }).await.unwrap();
+        assert!(!cancelled.into_inner().cancelled);
+
+        let missing = client
+            .stream_progress(StreamProgressRequest { id: 1000 })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        let job = SubmitJobRequest {
+            rules: "not json".into(),
+            ..Default::default()
+        };
+        let invalid = client.submit_job(job).await.unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    });
+}
@@ -1,4 +1,8 @@
-use filigram_rs::{create_watermark_image, overlay_watermark, Config};
+use filigram_rs::processor::{Context, Resize, Watermark};
+use filigram_rs::{create_watermark_image, overlay_watermark, process_image, Config, Processor};
+use filigram_rs::{watermark_bytes, watermark_file, Options};
+use image::imageops::FilterType;
+use image::DynamicImage;
 
 macro_rules! run_test {
     ($extension:literal) => {
@@ -33,3 +37,64 @@ fn test_webp() {
 fn test_bmp() {
     run_test!("bmp");
 }
+
+struct Invert;
+
+impl Processor for Invert {
+    fn process(
+        &self,
+        mut img: DynamicImage,
+        _ctx: &Context,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+        img.invert();
+        Ok(img)
+    }
+}
+
+#[test]
+fn test_custom_chain() {
+    let cfg = Config::default();
+    std::fs::create_dir("tmp").ok();
+    let watermark_img = create_watermark_image(&cfg).unwrap();
+    let chain: Vec<Box<dyn Processor>> = vec![
+        Box::new(Resize {
+            width: 120,
+            height: 80,
+            filter: FilterType::Triangle,
+        }),
+        Box::new(Invert),
+        Box::new(Watermark),
+    ];
+    process_image(
+        "tests/img/test.jpg",
+        "tmp/test_chain.png",
+        &chain,
+        &watermark_img,
+    )
+    .unwrap();
+
+    let output = image::open("tmp/test_chain.png").unwrap();
+    assert_eq!((output.width(), output.height()), (120, 80));
+}
+
+#[test]
+fn test_single_image() {
+    std::fs::create_dir("tmp").ok();
+    let input = std::fs::read("tests/img/test.webp").unwrap();
+    let output = watermark_bytes(&input, &Config::default(), &Options::default()).unwrap();
+    assert_eq!(
+        image::guess_format(&output).unwrap(),
+        image::ImageFormat::WebP
+    );
+    assert!(watermark_bytes(b"not an image", &Config::default(), &Options::default()).is_err());
+
+    watermark_file(
+        "tests/img/test.jpg",
+        "tmp/test_single.png",
+        &Config::default(),
+        &Options::default(),
+    )
+    .unwrap();
+    let output = image::open("tmp/test_single.png").unwrap();
+    assert_eq!((output.width(), output.height()), (500, 500));
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "server")]
+
+use filigram_rs::server::router;
+use filigram_rs::Config;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+// Start the service on a free port, in the background of `runtime`
+fn start(runtime: &tokio::runtime::Runtime) -> SocketAddr {
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    runtime.spawn(async move { axum::serve(listener, router(Config::default())).await });
+    addr
+}
+
+// Send a request made of `head` (request line and headers) and `body`,
+// return the status and the body of the response
+fn request(addr: SocketAddr, head: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{head}\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).unwrap();
+
+    let status = std::str::from_utf8(&response[9..12])
+        .unwrap()
+        .parse()
+        .unwrap();
+    let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    (status, response[end + 4..].to_vec())
+}
+
+#[test]
+fn test_watermark_upload() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let addr = start(&runtime);
+
+    let mut body =
+        b"--XYZ\r\nContent-Disposition: form-data; name=\"image\"; filename=\"test.webp\"\r\n\
+                     Content-Type: image/webp\r\n\r\n"
+            .to_vec();
+    body.extend(std::fs::read("tests/img/test.webp").unwrap());
+    body.extend(b"\r\n--XYZ--\r\n");
+    let head = "POST /watermark HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XYZ";
+    let (status, output) = request(addr, head, &body);
+    assert_eq!(status, 200);
+    let output = image::load_from_memory(&output).unwrap();
+    assert_eq!((output.width(), output.height()), (500, 500));
+
+    let body =
+        b"--XYZ\r\nContent-Disposition: form-data; name=\"other\"\r\n\r\nvalue\r\n--XYZ--\r\n";
+    assert_eq!(request(addr, head, body).0, 400);
+}
+
+#[test]
+fn test_folder_job() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let addr = start(&runtime);
+    std::fs::remove_dir_all("tmp/server_job").ok();
+
+    let job = br#"{"input": "tests/img", "output": "tmp/server_job"}"#;
+    let (status, body) = request(
+        addr,
+        "POST /jobs HTTP/1.1\r\nContent-Type: application/json",
+        job,
+    );
+    assert_eq!(status, 202);
+    let id = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["id"].clone();
+
+    let start = Instant::now();
+    let status = loop {
+        let (code, body) = request(addr, &format!("GET /jobs/{id} HTTP/1.1"), b"");
+        assert_eq!(code, 200);
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        if status["state"] != "running" {
+            break status;
+        }
+        assert!(start.elapsed() < Duration::from_secs(60), "job not done");
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    assert_eq!(status["state"], "done");
+    assert_eq!(status["processed"], status["total"]);
+    assert_eq!(status["report"]["files"].as_array().unwrap().len(), 4);
+    assert!(status["metrics"]["throughput_mb_s"].is_number());
+
+    assert_eq!(request(addr, "GET /jobs/1000 HTTP/1.1", b"").0, 404);
+}
@@ -0,0 +1,1964 @@
+#![cfg(feature = "walkdir")]
+
+use filigram_rs::{
+    is_watermarked, read_metadata, regex::Regex, spread_watermark, spread_watermark_roots, watch,
+    watermark_files, CaseCollisions, ColorPolicy, Config, DecodeLimits, DuplicatePolicy, Hooks,
+    Layout, Manifest, NestedTargetPolicy, Options, Outcome, ReadAhead, Roots, Rules, Sample,
+    SymlinkPolicy, TextRotation, UnqualifiedPolicy, GALLERY_INDEX, IGNORE_FILE,
+};
+use image::codecs::png::PngEncoder;
+use image::{ImageEncoder, Rgb, RgbImage};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn jpg_only() -> Rules {
+    Rules::builder().allow_ext("jpg").build()
+}
+
+#[test]
+fn test_hooks() {
+    let target = PathBuf::from("tmp/hooks");
+    std::fs::remove_dir_all(&target).ok();
+
+    let started = Arc::new(Mutex::new(vec![]));
+    let done = Arc::new(Mutex::new(vec![]));
+    let reports = Arc::new(Mutex::new(vec![]));
+    let hooks = Hooks {
+        on_file_start: Some(Box::new({
+            let started = started.clone();
+            move |path| started.lock().unwrap().push(path.to_owned())
+        })),
+        on_file_done: Some(Box::new({
+            let done = done.clone();
+            move |path, outcome| done.lock().unwrap().push((path.to_owned(), outcome))
+        })),
+        on_error: None,
+        on_report: Some(Box::new({
+            let reports = reports.clone();
+            move |report| reports.lock().unwrap().push(report.clone())
+        })),
+        on_metadata: None,
+    };
+    let options = Options {
+        hooks,
+        ..Default::default()
+    };
+
+    spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(started.lock().unwrap().len(), 4);
+    let done = done.lock().unwrap();
+    assert_eq!(done.len(), 4);
+    for (path, outcome) in done.iter() {
+        let expected = if path.extension().unwrap() == "jpg" {
+            Outcome::Watermarked
+        } else {
+            Outcome::Copied
+        };
+        assert_eq!(*outcome, expected, "{path:?}");
+    }
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 4);
+    for report in reports.iter() {
+        assert!(done.contains(&(report.source.clone(), report.outcome)));
+        assert!(report.output.is_some());
+    }
+}
+
+#[test]
+#[cfg(feature = "rayon")]
+fn test_threads() {
+    let target = PathBuf::from("tmp/threads");
+    std::fs::remove_dir_all(&target).ok();
+
+    let pools = Arc::new(Mutex::new(vec![]));
+    let options = Options {
+        hooks: Hooks {
+            on_file_start: Some(Box::new({
+                let pools = pools.clone();
+                move |_| {
+                    pools
+                        .lock()
+                        .unwrap()
+                        .push((rayon::current_thread_index(), rayon::current_num_threads()))
+                }
+            })),
+            ..Default::default()
+        },
+        threads: Some(1),
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(*pools.lock().unwrap(), [(Some(0), 1); 4]);
+}
+
+#[test]
+fn test_events() {
+    use filigram_rs::Event;
+    use std::sync::mpsc;
+
+    let target = PathBuf::from("tmp/events");
+    std::fs::remove_dir_all(&target).ok();
+
+    let (sender, receiver) = mpsc::channel();
+    let options = Options {
+        events: Some(sender),
+        ..Default::default()
+    };
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            spread_watermark(
+                &PathBuf::from("tests/img"),
+                &target,
+                &Config::default(),
+                &jpg_only(),
+                &options,
+                None,
+            )
+            .unwrap()
+        });
+    });
+    drop(options);
+
+    let events = receiver.iter().collect::<Vec<_>>();
+    assert!(matches!(&events[0], Event::Started { source, .. } if source.ends_with("img")));
+    let done = events
+        .iter()
+        .filter(|event| matches!(event, Event::FileDone(_)))
+        .count();
+    assert_eq!(done, 4);
+    match events.last().unwrap() {
+        Event::Finished(metrics) => assert_eq!(metrics.outcomes.len(), 2),
+        event => panic!("{event:?}"),
+    }
+}
+
+#[test]
+fn test_cancel() {
+    let target = PathBuf::from("tmp/cancel");
+    std::fs::remove_dir_all(&target).ok();
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let options = Options {
+        hooks: Hooks {
+            on_file_done: Some(Box::new({
+                let cancel = cancel.clone();
+                move |_, _| cancel.store(true, Ordering::Relaxed)
+            })),
+            ..Default::default()
+        },
+        threads: Some(1),
+        cancel: Some(cancel),
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &Rules::default(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    let processed = report.files.len() - report.count(Outcome::Cancelled);
+    assert_eq!(processed, 1);
+}
+
+#[test]
+fn test_largest_first() {
+    let target = PathBuf::from("tmp/largest_first");
+    std::fs::remove_dir_all(&target).ok();
+
+    let sizes = Arc::new(Mutex::new(vec![]));
+    let options = Options {
+        hooks: Hooks {
+            on_file_start: Some(Box::new({
+                let sizes = sizes.clone();
+                move |path| sizes.lock().unwrap().push(path.metadata().unwrap().len())
+            })),
+            ..Default::default()
+        },
+        threads: Some(1),
+        largest_first: true,
+        ..Default::default()
+    };
+    spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &Rules::default(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    let sizes = sizes.lock().unwrap();
+    assert_eq!(sizes.len(), 4);
+    assert!(sizes.windows(2).all(|pair| pair[0] >= pair[1]));
+}
+
+#[test]
+fn test_read_ahead() {
+    // outputs written behind the workers by writer threads, or by the workers
+    for writers in [2, 0] {
+        let target = PathBuf::from(format!("tmp/read_ahead_{writers}"));
+        std::fs::remove_dir_all(&target).ok();
+
+        let options = Options {
+            read_ahead: Some(ReadAhead {
+                threads: 2,
+                files: 1,
+                writers,
+                outputs: 1,
+            }),
+            mark_outputs: true,
+            checksums: true,
+            preserve_attributes: true,
+            ..Default::default()
+        };
+        let report = spread_watermark(
+            &PathBuf::from("tests/img"),
+            &target,
+            &Config::default(),
+            &Rules::default(),
+            &options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(report.files.len(), 4);
+        assert_eq!(report.count(Outcome::Watermarked), 4);
+        assert!(is_watermarked(&target.join("test.jpg")).unwrap());
+        for file in &report.files {
+            let output = file.output.as_ref().unwrap();
+            assert_eq!(file.bytes_out, std::fs::metadata(output).unwrap().len());
+            assert!(file.output_sha256.is_some());
+            let modified = |path| std::fs::metadata(path).unwrap().modified().unwrap();
+            assert_eq!(modified(output), modified(&file.source));
+        }
+    }
+}
+
+#[test]
+fn test_max_image_memory() {
+    let target = PathBuf::from("tmp/max_image_memory");
+    std::fs::remove_dir_all(&target).ok();
+
+    let options = Options {
+        max_image_memory: Some(1_000),
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(report.count(Outcome::Watermarked), 0);
+    let skipped = report
+        .files
+        .iter()
+        .find(|file| file.source.extension().unwrap() == "jpg")
+        .unwrap();
+    assert_eq!(skipped.outcome, Outcome::Skipped);
+    assert!(skipped.error.as_ref().unwrap().contains("exceeds"));
+    assert!(!target.join("test.jpg").exists());
+}
+
+#[test]
+fn test_deterministic() {
+    let options = Options {
+        deterministic: true,
+        ..Default::default()
+    };
+    let run = |target: &str| {
+        let target = PathBuf::from(target);
+        std::fs::remove_dir_all(&target).ok();
+        spread_watermark(
+            &PathBuf::from("tests/img"),
+            &target,
+            &Config::default(),
+            &Rules::default(),
+            &options,
+            None,
+        )
+        .unwrap();
+        target
+    };
+
+    let (first, second) = (run("tmp/deterministic_1"), run("tmp/deterministic_2"));
+    for name in ["test.jpg", "test.bmp", "test.gif", "test.webp"] {
+        let output = std::fs::read(first.join(name)).unwrap();
+        assert_eq!(output, std::fs::read(second.join(name)).unwrap());
+    }
+}
+
+#[test]
+fn test_srgb() {
+    let source = PathBuf::from("tmp/srgb_src");
+    let target = PathBuf::from("tmp/srgb");
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    // Display P3 photo
+    let icc = moxcms::ColorProfile::new_display_p3().encode().unwrap();
+    let img = RgbImage::from_pixel(600, 400, Rgb([200, 60, 40]));
+    let mut encoder = PngEncoder::new(File::create(source.join("p3.png")).unwrap());
+    encoder.set_icc_profile(icc).unwrap();
+    img.write_with_encoder(encoder).unwrap();
+
+    let options = Options {
+        color: ColorPolicy::Srgb,
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &Rules::default(),
+        &options,
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+
+    let output = target.join("p3.png");
+    assert_eq!(read_metadata(&output).unwrap().icc_name, None);
+    // in a corner, out of the watermark, the red is more saturated in sRGB
+    let Rgb([r, g, b]) = *image::open(&output).unwrap().to_rgb8().get_pixel(0, 0);
+    assert!(r > 200 && g < 60 && b < 40);
+}
+
+#[test]
+fn test_gallery() {
+    let target = PathBuf::from("tmp/gallery");
+    std::fs::remove_dir_all(&target).ok();
+
+    let options = Options {
+        gallery: true,
+        ..Default::default()
+    };
+    spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    let gallery = std::fs::read_to_string(target.join(GALLERY_INDEX)).unwrap();
+    assert!(gallery.contains("<a href=\"test.jpg\"><img src=\"data:image/jpeg;base64,"));
+    // copied files are left out
+    assert!(!gallery.contains("test.bmp"));
+}
+
+#[test]
+fn test_similar_images() {
+    let source = PathBuf::from("tmp/similar_images_src");
+    let target = PathBuf::from("tmp/similar_images");
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("a.jpg")).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("b.jpg")).unwrap();
+    std::fs::copy("tests/img/test.bmp", source.join("c.bmp")).unwrap();
+
+    let options = Options {
+        similar_images: Some(5),
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &Rules::default(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    assert!(report
+        .files
+        .iter()
+        .all(|file| file.perceptual_hash.is_some()));
+    assert_eq!(report.files[0].duplicate, None);
+    let duplicate = report.files[1].duplicate.as_ref().unwrap();
+    assert_eq!(duplicate.of, source.join("a.jpg"));
+    assert!(duplicate.exact);
+}
+
+#[test]
+fn test_metadata_only() {
+    use filigram_rs::metadata::{Metadata, ARTIST, COPYRIGHT};
+
+    let target = PathBuf::from("tmp/metadata_only");
+    std::fs::remove_dir_all(&target).ok();
+
+    let cfg = Config {
+        copyright: Some("© ACME".to_owned()),
+        artist: Some("Jane Doe".to_owned()),
+        ..Config::default()
+    };
+    let options = Options {
+        metadata_only: true,
+        mark_outputs: true,
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("data/exif"),
+        &target,
+        &cfg,
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+
+    let output = target.join("notes.jpg");
+    let metadata = Metadata::read(&output).unwrap();
+    assert_eq!(metadata.field(COPYRIGHT).as_deref(), Some("© ACME"));
+    assert_eq!(metadata.field(ARTIST).as_deref(), Some("Jane Doe"));
+    let xmp = String::from_utf8_lossy(metadata.xmp().unwrap()).into_owned();
+    assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">© ACME</rdf:li>"));
+    assert!(xmp.contains("<rdf:li>Jane Doe</rdf:li>"));
+    assert!(is_watermarked(&output).unwrap());
+    // pixels are those of the source
+    assert_eq!(
+        image::open(&output).unwrap(),
+        image::open("data/exif/notes.jpg").unwrap()
+    );
+}
+
+#[test]
+fn test_output_sink() {
+    use filigram_rs::{spread_watermark_to, OutputSink};
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    #[derive(Default)]
+    struct MemorySink(Mutex<BTreeMap<PathBuf, Vec<u8>>>);
+
+    impl OutputSink for MemorySink {
+        fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(path.to_owned(), data.to_vec());
+            Ok(())
+        }
+    }
+
+    let sink = MemorySink::default();
+    let report = spread_watermark_to(
+        Path::new("tests/img"),
+        &sink,
+        &Config::default(),
+        &jpg_only(),
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Copied), 3);
+    let files = sink.0.into_inner().unwrap();
+    assert_eq!(files.len(), 4);
+    let watermarked = image::load_from_memory(&files[Path::new("test.jpg")]).unwrap();
+    assert_eq!(
+        report.files.iter().find_map(|file| file.dimensions),
+        Some((watermarked.width(), watermarked.height()))
+    );
+    assert!(!PathBuf::from("test.jpg").exists());
+}
+
+#[test]
+fn test_text_rotation() {
+    let source = PathBuf::from("tmp/text_rotation_src");
+    std::fs::create_dir_all(&source).unwrap();
+    for name in ["a.jpg", "b.jpg", "c.jpg"] {
+        std::fs::copy("tests/img/test.jpg", source.join(name)).unwrap();
+    }
+    let run = |target: &str, rotation| {
+        let target = PathBuf::from(target);
+        std::fs::remove_dir_all(&target).ok();
+        let cfg = Config {
+            texts: vec!["www.acme.com".to_owned(), "Client".to_owned()],
+            rotation,
+            ..Config::default()
+        };
+        let options = Options {
+            threads: Some(1),
+            ..Default::default()
+        };
+        let report = spread_watermark(&source, &target, &cfg, &jpg_only(), &options, None).unwrap();
+        assert_eq!(report.count(Outcome::Watermarked), 3);
+        ["a.jpg", "b.jpg", "c.jpg"].map(|name| std::fs::read(target.join(name)).unwrap())
+    };
+
+    // each copy of the same image gets a different text
+    let [a, b, c] = run("tmp/text_rotation", TextRotation::RoundRobin);
+    assert!(a != b && b != c && a != c);
+
+    let first = run("tmp/text_rotation_1", TextRotation::ByPath);
+    assert_eq!(first, run("tmp/text_rotation_2", TextRotation::ByPath));
+}
+
+#[test]
+fn test_case_collisions() {
+    let source = PathBuf::from("tmp/case_collisions_src");
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("Photo.JPG")).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("photo.jpg")).unwrap();
+
+    let run = |case_collisions| {
+        let target = PathBuf::from("tmp/case_collisions");
+        std::fs::remove_dir_all(&target).ok();
+        let options = Options {
+            case_collisions,
+            threads: Some(1),
+            ..Default::default()
+        };
+        spread_watermark(
+            &source,
+            &target,
+            &Config::default(),
+            &Rules::default(),
+            &options,
+            None,
+        )
+        .unwrap()
+    };
+
+    let report = run(CaseCollisions::Ignore);
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+
+    let report = run(CaseCollisions::Skip);
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    let skipped = report
+        .files
+        .iter()
+        .find(|file| file.outcome == Outcome::Skipped)
+        .unwrap();
+    assert!(skipped.error.as_ref().unwrap().contains("differs by case"));
+
+    let report = run(CaseCollisions::Fail);
+    assert_eq!(report.count(Outcome::Failed), 1);
+
+    let report = run(CaseCollisions::Suffix);
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+    let mut outputs = report
+        .files
+        .iter()
+        .map(|file| {
+            file.output
+                .as_ref()
+                .unwrap()
+                .to_string_lossy()
+                .to_lowercase()
+        })
+        .collect::<Vec<_>>();
+    outputs.sort();
+    outputs.dedup();
+    assert_eq!(outputs.len(), 2);
+    assert!(outputs.iter().any(|output| output.ends_with("-1.jpg")));
+}
+
+#[test]
+fn test_decode_limits() {
+    let target = PathBuf::from("tmp/decode_limits");
+    std::fs::remove_dir_all(&target).ok();
+
+    let options = Options {
+        decode_limits: DecodeLimits {
+            max_pixels: Some(100),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(report.count(Outcome::Watermarked), 0);
+    let failed = report
+        .files
+        .iter()
+        .find(|file| file.source.extension().unwrap() == "jpg")
+        .unwrap();
+    assert_eq!(failed.outcome, Outcome::Failed);
+    assert!(failed.error.as_ref().unwrap().contains("exceeds the limit"));
+    assert!(!target.join("test.jpg").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks() {
+    let source = PathBuf::from("tmp/symlinks_src");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::create_dir_all(source.join("dir")).unwrap();
+    std::fs::copy("tests/img/test.bmp", source.join("dir/test.bmp")).unwrap();
+    std::os::unix::fs::symlink("dir", source.join("linked_dir")).unwrap();
+    std::os::unix::fs::symlink("dir/test.bmp", source.join("linked.bmp")).unwrap();
+
+    let run = |symlinks, target: &str| {
+        let target = PathBuf::from(target);
+        std::fs::remove_dir_all(&target).ok();
+        let rules = Rules {
+            symlinks,
+            ..jpg_only()
+        };
+        spread_watermark(
+            &source,
+            &target,
+            &Config::default(),
+            &rules,
+            &Options::default(),
+            None,
+        )
+        .unwrap();
+        target
+    };
+
+    let target = run(SymlinkPolicy::Follow, "tmp/symlinks_follow");
+    assert!(target.join("linked_dir/test.bmp").is_file());
+    assert!(!target.join("linked_dir").is_symlink());
+    assert!(target.join("linked.bmp").is_file());
+    assert!(!target.join("linked.bmp").is_symlink());
+
+    let target = run(SymlinkPolicy::Skip, "tmp/symlinks_skip");
+    assert!(target.join("dir/test.bmp").is_file());
+    assert!(!target.join("linked_dir").exists());
+    assert!(!target.join("linked.bmp").exists());
+
+    let target = run(SymlinkPolicy::CopyLink, "tmp/symlinks_copy");
+    assert!(target.join("linked_dir").is_symlink());
+    assert!(target.join("linked_dir/test.bmp").is_file());
+    assert_eq!(
+        std::fs::read_link(target.join("linked.bmp")).unwrap(),
+        PathBuf::from("dir/test.bmp")
+    );
+}
+
+#[test]
+fn test_max_depth() {
+    let source = PathBuf::from("tmp/depth_src");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::create_dir_all(source.join("a/b")).unwrap();
+    for file in ["test.bmp", "a/test.bmp", "a/b/test.bmp"] {
+        std::fs::copy("tests/img/test.bmp", source.join(file)).unwrap();
+    }
+
+    let target = PathBuf::from("tmp/depth");
+    std::fs::remove_dir_all(&target).ok();
+    let rules = Rules {
+        max_depth: Some(2),
+        ..jpg_only()
+    };
+    spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+
+    assert!(target.join("test.bmp").is_file());
+    assert!(target.join("a/test.bmp").is_file());
+    assert!(!target.join("a/b").exists());
+}
+
+#[test]
+fn test_preserve_attributes() {
+    let source = PathBuf::from("tmp/attributes_src");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    let modified =
+        std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    for file in ["test.jpg", "test.bmp"] {
+        let path = source.join(file);
+        std::fs::copy(PathBuf::from("tests/img").join(file), &path).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    let target = PathBuf::from("tmp/attributes");
+    std::fs::remove_dir_all(&target).ok();
+    let options = Options {
+        preserve_attributes: true,
+        ..Default::default()
+    };
+    spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    for file in ["test.jpg", "test.bmp"] {
+        let metadata = std::fs::metadata(target.join(file)).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified, "{file}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_duplicates_hardlink() {
+    use std::os::unix::fs::MetadataExt;
+
+    let source = PathBuf::from("tmp/duplicates_src");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::create_dir_all(source.join("a")).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("test.jpg")).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("a/copy.jpg")).unwrap();
+
+    let target = PathBuf::from("tmp/duplicates");
+    std::fs::remove_dir_all(&target).ok();
+    let outcomes = Arc::new(Mutex::new(vec![]));
+    let options = Options {
+        hooks: Hooks {
+            on_file_done: Some(Box::new({
+                let outcomes = outcomes.clone();
+                move |_, outcome| outcomes.lock().unwrap().push(outcome)
+            })),
+            ..Default::default()
+        },
+        duplicates: DuplicatePolicy::Hardlink,
+        ..Default::default()
+    };
+    spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    let mut outcomes = outcomes.lock().unwrap().clone();
+    outcomes.sort_by_key(|outcome| format!("{outcome:?}"));
+    assert_eq!(outcomes, [Outcome::Deduplicated, Outcome::Watermarked]);
+    let inode = |path: &str| std::fs::metadata(target.join(path)).unwrap().ino();
+    assert_eq!(inode("test.jpg"), inode("a/copy.jpg"));
+}
+
+#[test]
+fn test_flat_layout() {
+    let source = PathBuf::from("tmp/flat_src");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::create_dir_all(source.join("a/b")).unwrap();
+    for file in ["test.bmp", "a/test.bmp", "a/b/test.bmp"] {
+        std::fs::copy("tests/img/test.bmp", source.join(file)).unwrap();
+    }
+
+    let target = PathBuf::from("tmp/flat");
+    std::fs::remove_dir_all(&target).ok();
+    let options = Options {
+        layout: Layout::Flat,
+        ..Default::default()
+    };
+    spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    let mut names = std::fs::read_dir(&target)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["test-1.bmp", "test-2.bmp", "test.bmp"]);
+}
+
+#[test]
+fn test_unqualified_skip() {
+    let target = PathBuf::from("tmp/unqualified_skip");
+    std::fs::remove_dir_all(&target).ok();
+    let options = Options {
+        unqualified: UnqualifiedPolicy::Skip,
+        ..Default::default()
+    };
+    spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    let names = std::fs::read_dir(&target)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["test.jpg"]);
+}
+
+#[test]
+fn test_manifest() {
+    let target = PathBuf::from("tmp/manifest");
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all("tmp").unwrap();
+    let manifest = PathBuf::from("tmp/manifest.json");
+    let options = Options {
+        manifest: Some(Manifest::Json(manifest.clone())),
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Copied), 3);
+
+    let json: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(manifest).unwrap()).unwrap();
+    let files = json["files"].as_array().unwrap();
+    assert_eq!(files.len(), 4);
+    let jpg = files
+        .iter()
+        .find(|file| file["source"] == "tests/img/test.jpg")
+        .unwrap();
+    assert_eq!(jpg["outcome"], "watermarked");
+    assert_eq!(jpg["output"], "tmp/manifest/test.jpg");
+    assert_eq!(jpg["dimensions"], serde_json::json!([500, 500]));
+}
+
+#[test]
+fn test_checksums() {
+    let target = PathBuf::from("tmp/checksums");
+    std::fs::remove_dir_all(&target).ok();
+    let options = Options {
+        checksums: true,
+        ..Default::default()
+    };
+    let rules = Rules {
+        included_dirs: vec![],
+        included_files: vec![],
+        authorized_extensions: vec![],
+        ..jpg_only()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &rules,
+        &options,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(report.files.len(), 4);
+    for file in &report.files {
+        let checksum = file.source_sha256.as_ref().unwrap();
+        assert_eq!(checksum.len(), 64);
+        // copied files are identical
+        assert_eq!(file.output_sha256.as_ref(), Some(checksum));
+    }
+}
+
+#[test]
+fn test_journal() {
+    let source = PathBuf::from("tmp/journal_src");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::copy("tests/img/test.bmp", source.join("test.bmp")).unwrap();
+    std::fs::write(source.join("broken.jpg"), b"not an image").unwrap();
+
+    let target = PathBuf::from("tmp/journal");
+    std::fs::remove_dir_all(&target).ok();
+    let started = Arc::new(Mutex::new(vec![]));
+    let options = Options {
+        hooks: Hooks {
+            on_file_start: Some(Box::new({
+                let started = started.clone();
+                move |path| started.lock().unwrap().push(path.to_owned())
+            })),
+            ..Default::default()
+        },
+        journal: true,
+        ..Default::default()
+    };
+    let run = || {
+        spread_watermark(
+            &source,
+            &target,
+            &Config::default(),
+            &jpg_only(),
+            &options,
+            None,
+        )
+        .unwrap()
+    };
+
+    let report = run();
+    assert_eq!(report.count(Outcome::Failed), 1);
+    assert!(target.join(filigram_rs::journal::JOURNAL_FILE).exists());
+
+    // only the failed file is processed again
+    std::fs::copy("tests/img/test.jpg", source.join("broken.jpg")).unwrap();
+    started.lock().unwrap().clear();
+    let report = run();
+    assert_eq!(*started.lock().unwrap(), [source.join("broken.jpg")]);
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Copied), 1);
+    assert!(!target.join(filigram_rs::journal::JOURNAL_FILE).exists());
+}
+
+#[test]
+fn test_watermark_files() {
+    let target = PathBuf::from("tmp/files");
+    std::fs::remove_dir_all(&target).ok();
+
+    let report = watermark_files(
+        ["tests/img/test.jpg", "tests/img/test.bmp"],
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.files.len(), 2);
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Copied), 1);
+    assert!(target.join("tests/img/test.jpg").exists());
+    assert!(target.join("tests/img/test.bmp").exists());
+    assert!(!target.join("tests/img/test.gif").exists());
+
+    let target = PathBuf::from("tmp/files_flat");
+    std::fs::remove_dir_all(&target).ok();
+    let options = Options {
+        layout: Layout::Flat,
+        ..Default::default()
+    };
+    let report = watermark_files(
+        [
+            PathBuf::from("tests/img/test.jpg"),
+            PathBuf::from(".."),
+            PathBuf::from("/"),
+        ],
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+    assert!(target.join("test.jpg").exists());
+    // paths without file name fail instead of panicking
+    assert_eq!(report.count(Outcome::Failed), 2);
+    assert!(report
+        .files
+        .iter()
+        .filter(|file| file.outcome == Outcome::Failed)
+        .all(|file| file.error.as_ref().unwrap().contains("filename")));
+}
+
+#[test]
+fn test_roots() {
+    let other = PathBuf::from("tmp/roots_src/other");
+    std::fs::create_dir_all(&other).unwrap();
+    std::fs::copy("tests/img/test.jpg", other.join("other.jpg")).unwrap();
+    let folders = [PathBuf::from("tests/img"), other];
+
+    let target = PathBuf::from("tmp/roots");
+    std::fs::remove_dir_all(&target).ok();
+    let report = spread_watermark_roots(
+        &folders,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.files.len(), 5);
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+    assert!(target.join("img/test.jpg").exists());
+    assert!(target.join("other/other.jpg").exists());
+
+    let target = PathBuf::from("tmp/roots_merged");
+    std::fs::remove_dir_all(&target).ok();
+    let options = Options {
+        roots: Roots::Merged,
+        ..Default::default()
+    };
+    spread_watermark_roots(
+        &folders,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+    assert!(target.join("test.jpg").exists());
+    assert!(target.join("other.jpg").exists());
+
+    // both folders would be written under "img"
+    let folders = [PathBuf::from("tests/img"), PathBuf::from("tests/img/")];
+    assert!(spread_watermark_roots(
+        &folders,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &Options::default(),
+        None,
+    )
+    .is_err());
+}
+
+#[test]
+fn test_nested_target() {
+    let source = PathBuf::from("tmp/nested");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("test.jpg")).unwrap();
+    let target = source.join("out");
+
+    // outputs of the first run are not picked up by the second one
+    for _ in 0..2 {
+        let report = spread_watermark(
+            &source,
+            &target,
+            &Config::default(),
+            &jpg_only(),
+            &Options::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.files.len(), 1);
+    }
+    assert!(!target.join("out").exists());
+
+    let options = Options {
+        nested_target: NestedTargetPolicy::Fail,
+        ..Default::default()
+    };
+    let rules = jpg_only();
+    let cfg = Config::default();
+    assert!(spread_watermark(&source, &target, &cfg, &rules, &options, None).is_err());
+    assert!(spread_watermark(&source, &source, &cfg, &rules, &Options::default(), None).is_err());
+}
+
+#[test]
+fn test_file_size() {
+    let target = PathBuf::from("tmp/file_size");
+    std::fs::remove_dir_all(&target).ok();
+    let jpg_size = std::fs::metadata("tests/img/test.jpg").unwrap().len();
+
+    let rules = Rules {
+        min_file_size: Some(jpg_size + 1),
+        ..jpg_only()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 0);
+    assert_eq!(report.count(Outcome::Copied), 4);
+
+    let rules = Rules {
+        max_file_size: Some(jpg_size),
+        skip_out_of_size: true,
+        ..jpg_only()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    for file in &report.files {
+        let size = std::fs::metadata(&file.source).unwrap().len();
+        assert_eq!(file.outcome == Outcome::Skipped, size > jpg_size);
+    }
+}
+
+#[test]
+fn test_min_dimensions() {
+    let target = PathBuf::from("tmp/min_dimensions");
+    std::fs::remove_dir_all(&target).ok();
+    let (width, height) = image::image_dimensions("tests/img/test.jpg").unwrap();
+
+    for (min_dimensions, watermarked) in [((width, height), 1), ((width, height + 1), 0)] {
+        let rules = Rules {
+            min_dimensions: Some(min_dimensions),
+            ..jpg_only()
+        };
+        let report = spread_watermark(
+            &PathBuf::from("tests/img"),
+            &target,
+            &Config::default(),
+            &rules,
+            &Options::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.count(Outcome::Watermarked), watermarked);
+    }
+}
+
+#[test]
+fn test_modified_range() {
+    let target = PathBuf::from("tmp/modified_range");
+    std::fs::remove_dir_all(&target).ok();
+    let modified = std::fs::metadata("tests/img/test.jpg")
+        .unwrap()
+        .modified()
+        .unwrap();
+
+    let rules = Rules {
+        modified_after: Some(modified),
+        modified_before: Some(modified + std::time::Duration::from_secs(1)),
+        ..jpg_only()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+
+    let rules = Rules {
+        modified_before: Some(modified),
+        ..jpg_only()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 0);
+    assert!(report.count(Outcome::Skipped) >= 1);
+}
+
+#[test]
+fn test_sniff_content() {
+    let folder = PathBuf::from("tmp/sniff_content/in");
+    let target = PathBuf::from("tmp/sniff_content/out");
+    std::fs::remove_dir_all("tmp/sniff_content").ok();
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join("photo.dat")).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join("photo")).unwrap();
+
+    for (sniff_content, watermarked) in [(false, 0), (true, 2)] {
+        let rules = Rules {
+            sniff_content,
+            ..jpg_only()
+        };
+        let report = spread_watermark(
+            &folder,
+            &target,
+            &Config::default(),
+            &rules,
+            &Options::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.count(Outcome::Watermarked), watermarked);
+    }
+
+    let output = std::fs::read(target.join("photo.dat")).unwrap();
+    assert_eq!(
+        image::guess_format(&output).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+    assert_ne!(output, std::fs::read("tests/img/test.jpg").unwrap());
+}
+
+#[test]
+fn test_ignore_files() {
+    let folder = PathBuf::from("tmp/ignore_files/in");
+    let target = PathBuf::from("tmp/ignore_files/out");
+    std::fs::remove_dir_all("tmp/ignore_files").ok();
+    std::fs::create_dir_all(folder.join("raw")).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join("pic.jpg")).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join("raw/pic.jpg")).unwrap();
+    std::fs::write(folder.join(IGNORE_FILE), "raw/\n").unwrap();
+
+    for (ignore_files, watermarked) in [(false, 2), (true, 1)] {
+        let rules = Rules {
+            ignore_files,
+            ..jpg_only()
+        };
+        let report = spread_watermark(
+            &folder,
+            &target,
+            &Config::default(),
+            &rules,
+            &Options::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.count(Outcome::Watermarked), watermarked);
+    }
+}
+
+#[test]
+fn test_watermarks() {
+    let folder = PathBuf::from("tmp/watermarks/in");
+    let target = PathBuf::from("tmp/watermarks/out");
+    std::fs::remove_dir_all("tmp/watermarks").ok();
+    std::fs::create_dir_all(folder.join("clients/acme")).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join("pic.jpg")).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join("clients/acme/pic.jpg")).unwrap();
+
+    let options = Options {
+        duplicates: DuplicatePolicy::Hardlink,
+        watermarks: vec![(
+            Regex::new("^clients/acme/").unwrap(),
+            Config {
+                text: "© Acme".to_string(),
+                ..Config::default()
+            },
+        )],
+        ..Options::default()
+    };
+    let report = spread_watermark(
+        &folder,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+    assert_ne!(
+        std::fs::read(target.join("pic.jpg")).unwrap(),
+        std::fs::read(target.join("clients/acme/pic.jpg")).unwrap()
+    );
+}
+
+#[test]
+fn test_skip_hidden() {
+    let folder = PathBuf::from("tmp/skip_hidden/in");
+    let target = PathBuf::from("tmp/skip_hidden/out");
+    std::fs::remove_dir_all("tmp/skip_hidden").ok();
+    std::fs::create_dir_all(folder.join(".thumbnails")).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join("pic.jpg")).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join(".thumbnails/pic.jpg")).unwrap();
+    std::fs::write(folder.join(".DS_Store"), "").unwrap();
+
+    let rules = Rules {
+        skip_hidden: true,
+        ..jpg_only()
+    };
+    let report = spread_watermark(
+        &folder,
+        &target,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.files.len(), 1);
+    assert!(!target.join(".DS_Store").exists());
+    assert!(!target.join(".thumbnails").exists());
+}
+
+#[test]
+fn test_excluded_copyrights() {
+    use img_parts::jpeg::Jpeg;
+    use img_parts::ImageEXIF;
+
+    let folder = PathBuf::from("tmp/excluded_copyrights/in");
+    let target = PathBuf::from("tmp/excluded_copyrights/out");
+    std::fs::remove_dir_all("tmp/excluded_copyrights").ok();
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::copy("tests/img/test.jpg", folder.join("plain.jpg")).unwrap();
+
+    // Exif with a single Copyright field
+    let mut exif = b"II\x2a\x00\x08\x00\x00\x00\x01\x00".to_vec();
+    exif.extend(b"\x98\x82\x02\x00\x0e\x00\x00\x00\x1a\x00\x00\x00\x00\x00\x00\x00");
+    exif.extend(b"(c) Filigram\x00\x00");
+    let mut jpeg = Jpeg::from_bytes(std::fs::read("tests/img/test.jpg").unwrap().into()).unwrap();
+    jpeg.set_exif(Some(exif.into()));
+    std::fs::write(folder.join("marked.jpg"), jpeg.encoder().bytes()).unwrap();
+
+    let rules = Rules {
+        excluded_copyrights: vec![Regex::new("(?i)filigram").unwrap()],
+        ..jpg_only()
+    };
+    let report = spread_watermark(
+        &folder,
+        &target,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Copied), 1);
+    assert_eq!(
+        std::fs::read(target.join("marked.jpg")).unwrap(),
+        std::fs::read(folder.join("marked.jpg")).unwrap()
+    );
+}
+
+#[test]
+fn test_skip_watermarked() {
+    let first = PathBuf::from("tmp/skip_watermarked/first");
+    let second = PathBuf::from("tmp/skip_watermarked/second");
+    std::fs::remove_dir_all("tmp/skip_watermarked").ok();
+
+    let rules = Rules {
+        skip_watermarked: true,
+        ..jpg_only()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &first,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert!(is_watermarked(&first.join("test.jpg")).unwrap());
+    assert!(!is_watermarked(&first.join("test.bmp")).unwrap());
+    assert!(!is_watermarked(&PathBuf::from("tests/img/test.jpg")).unwrap());
+
+    let report = spread_watermark(
+        &first,
+        &second,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 0);
+    assert_eq!(
+        std::fs::read(first.join("test.jpg")).unwrap(),
+        std::fs::read(second.join("test.jpg")).unwrap()
+    );
+}
+
+#[test]
+fn test_sample() {
+    let target = PathBuf::from("tmp/sample");
+    std::fs::remove_dir_all(&target).ok();
+
+    let run = |sample| {
+        let options = Options {
+            sample: Some(sample),
+            ..Options::default()
+        };
+        spread_watermark(
+            &PathBuf::from("tests/img"),
+            &target,
+            &Config::default(),
+            &Rules::default(),
+            &options,
+            None,
+        )
+        .unwrap()
+    };
+    let images = std::fs::read_dir("tests/img").unwrap().count();
+
+    let report = run(Sample::Count(2));
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+    assert_eq!(report.count(Outcome::Skipped), images - 2);
+
+    let sampled = |report: &filigram_rs::Report| {
+        report
+            .files
+            .iter()
+            .filter(|file| file.outcome == Outcome::Watermarked)
+            .map(|file| file.source.clone())
+            .collect::<Vec<_>>()
+    };
+    let ratio = Sample::Ratio {
+        ratio: 0.5,
+        seed: 42,
+    };
+    assert_eq!(sampled(&run(ratio)), sampled(&run(ratio)));
+    let all = Sample::Ratio {
+        ratio: 1.0,
+        seed: 42,
+    };
+    assert_eq!(run(all).count(Outcome::Watermarked), images);
+}
+
+#[test]
+fn test_exif_fields() {
+    use img_parts::jpeg::Jpeg;
+    use img_parts::ImageEXIF;
+
+    let target = PathBuf::from("tmp/exif_fields");
+    std::fs::remove_dir_all(&target).ok();
+
+    let cfg = Config {
+        copyright: Some("(c) Filigram Studio".to_string()),
+        artist: Some("Jane Doe".to_string()),
+        ..Config::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("tests/img"),
+        &target,
+        &cfg,
+        &jpg_only(),
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+
+    let output = Jpeg::from_bytes(std::fs::read(target.join("test.jpg")).unwrap().into()).unwrap();
+    let exif = output.exif().unwrap();
+    let contains = |value: &[u8]| exif.windows(value.len()).any(|window| window == value);
+    assert!(contains(b"(c) Filigram Studio\0"));
+    assert!(contains(b"Jane Doe\0"));
+    image::load_from_memory(&std::fs::read(target.join("test.jpg")).unwrap()).unwrap();
+}
+
+#[test]
+fn test_strip_gps() {
+    use filigram_rs::{MetadataAction, MetadataPolicy};
+    use img_parts::jpeg::Jpeg;
+    use img_parts::ImageEXIF;
+
+    // entry of the pointer to the GPS IFD in the first IFD of little endian Exif data
+    fn gps_entry(exif: &[u8]) -> Option<&[u8]> {
+        let u16_at = |offset: usize| u16::from_le_bytes([exif[offset], exif[offset + 1]]);
+        let ifd = u32::from_le_bytes(exif[4..8].try_into().unwrap()) as usize;
+        (0..u16_at(ifd) as usize)
+            .map(|i| &exif[ifd + 2 + i * 12..ifd + 14 + i * 12])
+            .find(|entry| entry[..2] == [0x25, 0x88])
+    }
+    let exif_of = |path: &std::path::Path| {
+        let jpeg = Jpeg::from_bytes(std::fs::read(path).unwrap().into()).unwrap();
+        jpeg.exif().unwrap()
+    };
+
+    let target = PathBuf::from("tmp/strip_gps");
+    std::fs::remove_dir_all(&target).ok();
+
+    let options = Options {
+        metadata: MetadataPolicy {
+            gps: MetadataAction::Strip,
+            ..MetadataPolicy::default()
+        },
+        ..Options::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("data/exif"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+
+    let source = exif_of("data/exif/notes.jpg".as_ref());
+    let gps = gps_entry(&source).unwrap();
+    let gps = u32::from_le_bytes(gps[8..].try_into().unwrap()) as usize;
+    let output = exif_of(&target.join("notes.jpg"));
+    assert!(gps_entry(&output).is_none());
+    // GPS IFD is zeroed, other data is kept
+    assert!(output[gps..gps + 2].iter().all(|byte| *byte == 0));
+    let contains = |value: &[u8]| output.windows(value.len()).any(|window| window == value);
+    assert!(contains(b"NIKON\0COOLPIX P6000"));
+    image::load_from_memory(&std::fs::read(target.join("notes.jpg")).unwrap()).unwrap();
+}
+
+#[test]
+fn test_metadata_hook() {
+    use filigram_rs::metadata::{Metadata, SOFTWARE};
+
+    let target = PathBuf::from("tmp/metadata_hook");
+    std::fs::remove_dir_all(&target).ok();
+
+    let options = Options {
+        hooks: Hooks {
+            on_metadata: Some(Box::new(|_, metadata| {
+                let software = metadata.field(SOFTWARE).unwrap_or_default();
+                metadata.set_field(SOFTWARE, &format!("{software} + filigram"));
+            })),
+            ..Hooks::default()
+        },
+        ..Options::default()
+    };
+    let report = spread_watermark(
+        &PathBuf::from("data/exif"),
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+
+    let metadata = Metadata::read(&target.join("notes.jpg")).unwrap();
+    assert_eq!(
+        metadata.field(SOFTWARE).as_deref(),
+        Some("Nikon Transfer 1.1 W + filigram")
+    );
+    image::open(target.join("notes.jpg")).unwrap();
+}
+
+#[test]
+fn test_auto_orient() {
+    use image::metadata::Orientation;
+    use image::{ImageDecoder, ImageReader, Rgb, RgbImage};
+    use img_parts::jpeg::Jpeg;
+    use img_parts::ImageEXIF;
+
+    let input = PathBuf::from("tmp/auto_orient_src");
+    std::fs::remove_dir_all(&input).ok();
+    std::fs::create_dir_all(&input).unwrap();
+
+    // left half red, right half blue, with Exif orientation 6 (rotated 90° clockwise)
+    let img = RgbImage::from_fn(64, 32, |x, _| match x < 32 {
+        true => Rgb([255, 0, 0]),
+        false => Rgb([0, 0, 255]),
+    });
+    let mut encoded = std::io::Cursor::new(vec![]);
+    img.write_to(&mut encoded, image::ImageFormat::Jpeg)
+        .unwrap();
+    let mut jpeg = Jpeg::from_bytes(encoded.into_inner().into()).unwrap();
+    let mut exif = b"MM\x00\x2a\x00\x00\x00\x08\x00\x01".to_vec();
+    exif.extend(b"\x01\x12\x00\x03\x00\x00\x00\x01\x00\x06\x00\x00\x00\x00\x00\x00");
+    jpeg.set_exif(Some(exif.into()));
+    std::fs::write(input.join("portrait.jpg"), jpeg.encoder().bytes()).unwrap();
+
+    let is_red = |pixel: Rgb<u8>| pixel[0] > 200 && pixel[2] < 60;
+    for auto_orient in [false, true] {
+        let target = PathBuf::from(format!("tmp/auto_orient_{auto_orient}"));
+        std::fs::remove_dir_all(&target).ok();
+        let options = Options {
+            auto_orient,
+            ..Options::default()
+        };
+        let report = spread_watermark(
+            &input,
+            &target,
+            &Config::default(),
+            &jpg_only(),
+            &options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.count(Outcome::Watermarked), 1);
+
+        let output = target.join("portrait.jpg");
+        let mut decoder = ImageReader::open(&output).unwrap().into_decoder().unwrap();
+        let orientation = decoder.orientation().unwrap();
+        let output = image::open(&output).unwrap().to_rgb8();
+        if auto_orient {
+            // red half on top, not to be rotated again
+            assert_eq!(orientation, Orientation::NoTransforms);
+            assert!(is_red(*output.get_pixel(250, 10)));
+            assert!(!is_red(*output.get_pixel(250, 490)));
+        } else {
+            assert_eq!(orientation, Orientation::Rotate90);
+            assert!(is_red(*output.get_pixel(10, 250)));
+            assert!(!is_red(*output.get_pixel(490, 250)));
+        }
+    }
+}
+
+#[test]
+fn test_sidecars() {
+    use filigram_rs::{Metadata, Naming, SidecarPolicy};
+
+    let input = PathBuf::from("tmp/sidecars_src");
+    std::fs::remove_dir_all(&input).ok();
+    std::fs::create_dir_all(&input).unwrap();
+    std::fs::copy("tests/img/test.jpg", input.join("photo.jpg")).unwrap();
+    let xmp = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>";
+    std::fs::write(input.join("photo.xmp"), xmp).unwrap();
+    // sidecar without image, processed as any other file
+    std::fs::write(input.join("orphan.xmp"), xmp).unwrap();
+
+    for sidecars in [
+        SidecarPolicy::Ignore,
+        SidecarPolicy::Copy,
+        SidecarPolicy::Merge,
+    ] {
+        let target = PathBuf::from(format!("tmp/sidecars_{sidecars:?}"));
+        std::fs::remove_dir_all(&target).ok();
+        let options = Options {
+            sidecars,
+            naming: Naming::Suffix("_wm".to_string()),
+            ..Options::default()
+        };
+        let report = spread_watermark(
+            &input,
+            &target,
+            &Config::default(),
+            &jpg_only(),
+            &options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.count(Outcome::Watermarked), 1);
+        assert!(target.join("orphan.xmp").exists());
+
+        let embedded = Metadata::read(&target.join("photo_wm.jpg")).unwrap();
+        match sidecars {
+            SidecarPolicy::Ignore => {
+                assert_eq!(report.count(Outcome::Copied), 2);
+                assert!(target.join("photo.xmp").exists());
+                assert_eq!(embedded.xmp(), None);
+            }
+            SidecarPolicy::Copy => {
+                assert_eq!(report.count(Outcome::Skipped), 1);
+                assert_eq!(
+                    std::fs::read(target.join("photo_wm.xmp")).unwrap(),
+                    xmp.to_vec()
+                );
+                assert!(!target.join("photo.xmp").exists());
+                assert_eq!(embedded.xmp(), None);
+            }
+            SidecarPolicy::Merge => {
+                assert_eq!(report.count(Outcome::Skipped), 1);
+                assert!(!target.join("photo_wm.xmp").exists());
+                assert!(!target.join("photo.xmp").exists());
+                assert_eq!(embedded.xmp(), Some(&xmp[..]));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_watch() {
+    let source = PathBuf::from("tmp/watch_src");
+    let target = PathBuf::from("tmp/watch");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::copy("tests/img/test.bmp", source.join("before.bmp")).unwrap();
+
+    // files are added while watching, the watch is stopped once they are all written
+    let stop = AtomicBool::new(false);
+    let metrics = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let wait_for = |path: PathBuf| {
+                let start = Instant::now();
+                while !path.exists() && start.elapsed() < Duration::from_secs(30) {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+            };
+            wait_for(target.join("before.bmp"));
+            std::fs::create_dir_all(source.join("dir")).unwrap();
+            std::fs::copy("tests/img/test.bmp", source.join("dir/after.bmp")).unwrap();
+            wait_for(target.join("dir/after.bmp"));
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        watch(
+            &source,
+            &target,
+            &Config::default(),
+            &Rules::default(),
+            &Options::default(),
+            Duration::from_millis(100),
+            &stop,
+        )
+        .unwrap()
+    });
+
+    assert_eq!(metrics.count(Outcome::Watermarked), 2);
+    assert_eq!(metrics.outcomes, [(Outcome::Watermarked, 2)]);
+}
+
+#[test]
+fn test_plan() {
+    use filigram_rs::{apply_plan, plan_watermark, Action, Plan};
+    use std::path::Path;
+
+    let target = PathBuf::from("tmp/plan");
+    std::fs::remove_dir_all(&target).ok();
+
+    let options = Options::default();
+    let mut plan = plan_watermark(Path::new("tests/img"), &target, &jpg_only(), &options).unwrap();
+    assert!(!target.exists());
+    let actions: Vec<_> = plan
+        .files
+        .iter()
+        .map(|file| (file.path.to_str().unwrap(), file.action))
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            ("test.bmp", Action::Copy),
+            ("test.gif", Action::Copy),
+            ("test.jpg", Action::Watermark),
+            ("test.webp", Action::Copy),
+        ]
+    );
+
+    // reviewed and edited as JSON
+    let json = serde_json::to_string(&plan).unwrap();
+    assert!(json.contains(r#""action":"watermark""#));
+    plan = serde_json::from_str::<Plan>(&json).unwrap();
+    plan.files[1].action = Action::Skip;
+    plan.files[3].action = Action::Watermark;
+
+    let report = apply_plan(&plan, &Config::default(), &options, None).unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+    assert_eq!(report.count(Outcome::Copied), 1);
+    assert_eq!(report.count(Outcome::Skipped), 1);
+    assert!(!target.join("test.gif").exists());
+    let webp = report
+        .files
+        .iter()
+        .find(|file| file.source.ends_with("test.webp"));
+    assert_eq!(webp.unwrap().outcome, Outcome::Watermarked);
+}
+
+#[test]
+fn test_plan_unsafe_paths() {
+    use filigram_rs::{apply_plan, plan_watermark, QUARANTINE_DIR};
+    use std::path::Path;
+
+    let target = PathBuf::from("tmp/plan_unsafe/target");
+    std::fs::remove_dir_all("tmp/plan_unsafe").ok();
+
+    let options = Options {
+        quarantine: true,
+        ..Default::default()
+    };
+    let mut plan = plan_watermark(Path::new("tests/img"), &target, &jpg_only(), &options).unwrap();
+    plan.files[0].path = PathBuf::from("../Cargo.toml");
+    plan.files[1].output = PathBuf::from("../escaped.gif");
+    plan.files[2].output = PathBuf::from("/tmp/escaped.jpg");
+
+    let report = apply_plan(&plan, &Config::default(), &options, None).unwrap();
+    assert_eq!(report.count(Outcome::Failed), 3);
+    assert_eq!(report.count(Outcome::Copied), 1);
+    for file in report.failed() {
+        assert!(file.error.as_ref().unwrap().starts_with("unsafe path"));
+    }
+    assert!(!Path::new("tmp/plan_unsafe/escaped.gif").exists());
+    assert!(!target.join(QUARANTINE_DIR).exists());
+}
+
+#[test]
+fn test_renditions() {
+    use filigram_rs::Rendition;
+
+    let source = PathBuf::from("tmp/renditions_src");
+    let target = PathBuf::from("tmp/renditions");
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    for name in ["a.jpg", "b.jpg"] {
+        std::fs::copy("tests/img/test.jpg", source.join(name)).unwrap();
+    }
+
+    let options = Options {
+        renditions: vec![
+            Rendition::new("web", Some(300), 1.0),
+            Rendition::new("thumb", Some(100), 0.25),
+        ],
+        duplicates: DuplicatePolicy::Hardlink,
+        threads: Some(1),
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Deduplicated), 1);
+    for file in &report.files {
+        let name = file.source.file_stem().unwrap().to_str().unwrap();
+        assert_eq!(
+            file.renditions,
+            [
+                target.join(format!("{name}-web.jpg")),
+                target.join(format!("{name}-thumb.jpg"))
+            ]
+        );
+        let (width, height) = image::image_dimensions(&file.renditions[0]).unwrap();
+        assert_eq!(width.max(height), 300);
+        let (width, height) = image::image_dimensions(&file.renditions[1]).unwrap();
+        assert_eq!(width.max(height), 100);
+    }
+    assert_eq!(
+        image::image_dimensions(target.join("a.jpg")).unwrap(),
+        (500, 500)
+    );
+}
+
+#[test]
+fn test_rendition_processors() {
+    use filigram_rs::image::{DynamicImage, RgbImage as Image};
+    use filigram_rs::processor::{Context, Processor};
+    use filigram_rs::Rendition;
+
+    // replaces the image by a small red square
+    struct Red;
+    impl Processor for Red {
+        fn process(
+            &self,
+            _img: DynamicImage,
+            _ctx: &Context,
+        ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+            Ok(Image::from_pixel(20, 20, Rgb([255, 0, 0])).into())
+        }
+    }
+
+    let source = PathBuf::from("tmp/rendition_processors_src");
+    let target = PathBuf::from("tmp/rendition_processors");
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("a.jpg")).unwrap();
+
+    let mut own = Rendition::new("own", None, 1.0);
+    own.processors = vec![Box::new(Red)];
+    let options = Options {
+        processors: vec![Box::new(Red)],
+        renditions: vec![Rendition::new("web", None, 1.0), own],
+        ..Default::default()
+    };
+    spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    // `Options::processors` only apply to the main output
+    assert_eq!(
+        image::image_dimensions(target.join("a.jpg")).unwrap(),
+        (20, 20)
+    );
+    assert_eq!(
+        image::image_dimensions(target.join("a-web.jpg")).unwrap(),
+        image::image_dimensions("tests/img/test.jpg").unwrap()
+    );
+    assert_eq!(
+        image::image_dimensions(target.join("a-own.jpg")).unwrap(),
+        (20, 20)
+    );
+}
+
+#[test]
+fn test_quarantine() {
+    use filigram_rs::QUARANTINE_DIR;
+
+    let source = PathBuf::from("tmp/quarantine_src");
+    let target = PathBuf::from("tmp/quarantine");
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(source.join("sub")).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("good.jpg")).unwrap();
+    std::fs::write(source.join("sub/bad.jpg"), b"not a jpeg").unwrap();
+
+    let options = Options {
+        quarantine: true,
+        ..Default::default()
+    };
+    let report = spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &jpg_only(),
+        &options,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Failed), 1);
+    let quarantine = target.join(QUARANTINE_DIR);
+    assert_eq!(
+        std::fs::read(quarantine.join("sub/bad.jpg")).unwrap(),
+        b"not a jpeg"
+    );
+    let note = std::fs::read_to_string(quarantine.join("sub/bad.jpg.error.txt")).unwrap();
+    assert!(note.starts_with(&source.join("sub/bad.jpg").display().to_string()));
+    assert!(note.lines().count() >= 2, "{note}");
+    assert!(!quarantine.join("good.jpg").exists());
+    assert!(!target.join("sub/bad.jpg").exists());
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "tar")]
+
+use filigram_rs::{watermark_tar, Config, Options, Outcome, Rules};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[test]
+fn test_tar_stream() {
+    let mut input = tar::Builder::new(vec![]);
+    input.append_dir("./photos", "tests/img").unwrap();
+    for name in ["test.jpg", "test.bmp"] {
+        input
+            .append_path_with_name(format!("tests/img/{name}"), format!("./photos/{name}"))
+            .unwrap();
+    }
+    let input = input.into_inner().unwrap();
+
+    let rules = Rules::builder().allow_ext("jpg").build();
+    let mut output = vec![];
+    let report = watermark_tar(
+        input.as_slice(),
+        &mut output,
+        &Config::default(),
+        &rules,
+        &Options::default(),
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 1);
+    assert_eq!(report.count(Outcome::Copied), 1);
+    assert_eq!(report.files[1].source, Path::new("photos/test.jpg"));
+
+    let mut entries = vec![];
+    for entry in tar::Archive::new(output.as_slice()).entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let mut content = vec![];
+        entry.read_to_end(&mut content).unwrap();
+        entries.push((entry.path().unwrap().into_owned(), content));
+    }
+    entries.sort();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].0, PathBuf::from("photos/test.bmp"));
+    assert_eq!(entries[0].1, std::fs::read("tests/img/test.bmp").unwrap());
+    assert_eq!(entries[1].0, PathBuf::from("photos/test.jpg"));
+    let img = image::load_from_memory(&entries[1].1).unwrap();
+    assert_eq!((img.width(), img.height()), (500, 500));
+}
+
+#[test]
+fn test_tar_entry_size() {
+    // a header announcing a terabyte, followed by a few bytes only
+    let mut input = tar::Builder::new(vec![]);
+    input
+        .append_path_with_name("tests/img/test.jpg", "photos/test.jpg")
+        .unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_path("photos/huge.jpg").unwrap();
+    header.set_size(1 << 40);
+    header.set_cksum();
+    input.get_mut().extend_from_slice(header.as_bytes());
+    input.get_mut().extend_from_slice(&[0; 512]);
+    let input = input.get_ref().clone();
+
+    for rules in [
+        Rules::builder().allow_ext("jpg").build(),
+        Rules::builder()
+            .allow_ext("jpg")
+            .max_file_size(1 << 20)
+            .skip_out_of_size(true)
+            .build(),
+    ] {
+        let result = watermark_tar(
+            input.as_slice(),
+            vec![],
+            &Config::default(),
+            &rules,
+            &Options::default(),
+        );
+        assert!(result.is_err());
+    }
+}
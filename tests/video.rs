@@ -0,0 +1,48 @@
+#![cfg(all(feature = "ffmpeg", feature = "walkdir"))]
+
+use filigram_rs::{spread_watermark, Config, Options, Outcome, Rules};
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn test_video() {
+    let source = PathBuf::from("tmp/video_src");
+    let target = PathBuf::from("tmp/video");
+    std::fs::remove_dir_all(&source).ok();
+    std::fs::remove_dir_all(&target).ok();
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::copy("tests/img/test.jpg", source.join("test.jpg")).unwrap();
+
+    // one second of a test pattern with a tone
+    let status = Command::new("ffmpeg")
+        .args(["-nostdin", "-loglevel", "error", "-f", "lavfi", "-i"])
+        .arg("testsrc=duration=1:size=320x240:rate=10")
+        .args(["-f", "lavfi", "-i", "sine=duration=1", "-shortest"])
+        .arg(source.join("clip.mp4"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let report = spread_watermark(
+        &source,
+        &target,
+        &Config::default(),
+        &Rules::default(),
+        &Options::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(report.count(Outcome::Watermarked), 2);
+
+    let output = Command::new("ffprobe")
+        .args(["-loglevel", "error", "-show_entries", "stream=codec_type"])
+        .args(["-of", "csv=p=0"])
+        .arg(target.join("clip.mp4"))
+        .output()
+        .unwrap();
+    let streams = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        streams.split_whitespace().collect::<Vec<_>>(),
+        ["video", "audio"]
+    );
+}